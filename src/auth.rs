@@ -0,0 +1,217 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tonic::{Request, Response, Status};
+use crate::job_service::job::auth_service_server::AuthService;
+use crate::job_service::job::{ApiToken, CreateApiTokenRequest, ListApiTokensRequest, ListApiTokensResponse, LoginRequest};
+use crate::job_service::job::{LogoutRequest, LogoutResponse, RevokeApiTokenRequest, Session};
+use crate::job_service::job::{BeginOidcLoginRequest, CompleteOidcLoginRequest, OidcLogin};
+use crate::job_service::job::{DeviceLogin, DeviceLoginStatus, PollDeviceLoginRequest, StartDeviceLoginRequest};
+use crate::job_service::job::{Identity, WhoAmIRequest};
+use crate::oidc::{DevicePoll, ExternalIdentity, OidcClient};
+use crate::user_manager::{self, Principal, Scope, User, UserManager};
+
+// Metadata key carrying "Bearer <token>"
+pub const AUTHORIZATION: &str = "authorization";
+
+// Checks the bearer token on client RPCs against the accounts
+#[derive(Clone)]
+pub struct Authenticator {
+    accounts: Arc<Mutex<UserManager>>,
+    required: bool, // false lets requests without a token through (accounts.require_auth)
+}
+
+impl Authenticator {
+    pub fn new(accounts: Arc<Mutex<UserManager>>, required: bool) -> Self {
+        Authenticator { accounts, required }
+    }
+
+    // The caller, or None for an anonymous request while authentication is optional.
+    // A token that is present is always checked.
+    pub fn authenticate<T>(&self, request: &Request<T>) -> Result<Option<Principal>, Status> {
+        match bearer(request)? {
+            Some(token) => self.accounts.lock().unwrap().authenticate(token).map(Some).map_err(Status::unauthenticated),
+            None if self.required => Err(Status::unauthenticated("Log in first (dcctl login) or pass an API token")),
+            None => Ok(None),
+        }
+    }
+}
+
+fn bearer<T>(request: &Request<T>) -> Result<Option<&str>, Status> {
+    let value = match request.metadata().get(AUTHORIZATION) {
+        Some(value) => value.to_str().map_err(|_| Status::unauthenticated("Malformed authorization header"))?,
+        None => return Ok(None),
+    };
+    value
+        .strip_prefix("Bearer ")
+        .map(|token| Some(token.trim()))
+        .ok_or_else(|| Status::unauthenticated("Expected authorization: Bearer <token>"))
+}
+
+// Client side: attach `token` to an outgoing request
+pub fn with_token<T>(message: T, token: Option<&str>) -> Result<Request<T>, String> {
+    let mut request = Request::new(message);
+    if let Some(token) = token {
+        let value = format!("Bearer {}", token).parse().map_err(|_| "Token contains invalid characters".to_string())?;
+        request.metadata_mut().insert(AUTHORIZATION, value);
+    }
+    Ok(request)
+}
+
+// AuthService: sessions and API tokens for dcctl and other clients, served next to JobService
+pub struct AuthApi {
+    accounts: Arc<Mutex<UserManager>>,
+    oidc: Option<Arc<OidcClient>>, // Single sign-on, when configured
+}
+
+impl AuthApi {
+    pub fn new(accounts: Arc<Mutex<UserManager>>) -> Self {
+        AuthApi { accounts, oidc: None }
+    }
+
+    pub fn with_oidc(mut self, oidc: Arc<OidcClient>) -> Self {
+        self.oidc = Some(oidc);
+        self
+    }
+
+    fn oidc(&self) -> Result<&OidcClient, Status> {
+        self.oidc.as_deref().ok_or_else(|| Status::failed_precondition("Single sign-on is not configured on this controller"))
+    }
+
+    // Session for someone the provider vouched for
+    fn external_session(&self, oidc: &OidcClient, identity: &ExternalIdentity) -> Result<Session, Status> {
+        let sign_up_role = oidc.sign_up_role(identity);
+        let (token, user, expires_at) = self.accounts.lock().unwrap().login_external(identity, oidc.issuer(), sign_up_role).map_err(Status::permission_denied)?;
+        Ok(session(token, user, expires_at))
+    }
+
+    // Every AuthService call except Login needs a valid token
+    fn caller<T>(&self, request: &Request<T>) -> Result<Principal, Status> {
+        let token = bearer(request)?.ok_or_else(|| Status::unauthenticated("Log in first"))?;
+        self.accounts.lock().unwrap().authenticate(token).map_err(Status::unauthenticated)
+    }
+}
+
+fn session(token: String, user: User, expires_at: u64) -> Session {
+    Session { token, user_id: user.user_id, role: user.role.as_str().to_string(), expires_at: expires_at as i64 }
+}
+
+fn token_to_proto(token: &user_manager::ApiToken, secret: String) -> ApiToken {
+    ApiToken {
+        token_id: token.token_id.clone(),
+        token: secret,
+        name: token.name.clone(),
+        scopes: token.scopes.iter().map(|scope| scope.as_str().to_string()).collect(),
+        created_at: token.created_at as i64,
+        expires_at: token.expires_at as i64,
+    }
+}
+
+#[tonic::async_trait]
+impl AuthService for AuthApi {
+    async fn login(&self, request: Request<LoginRequest>) -> Result<Response<Session>, Status> {
+        let request = request.into_inner();
+        // Argon2 is slow on purpose: check the password without holding the accounts, off the runtime
+        let password_hash = self.accounts.lock().unwrap().password_hash_for(&request.email);
+        let checked_hash = password_hash.clone();
+        let password = request.password;
+        let matched = tokio::task::spawn_blocking(move || user_manager::verify_password(&password, &checked_hash))
+            .await
+            .map_err(|_| Status::internal("Password check failed"))?;
+        let (token, user, expires_at) =
+            self.accounts.lock().unwrap().finish_login(&request.email, &password_hash, matched).map_err(Status::unauthenticated)?;
+        Ok(Response::new(session(token, user, expires_at)))
+    }
+
+    async fn logout(&self, request: Request<LogoutRequest>) -> Result<Response<LogoutResponse>, Status> {
+        let principal = self.caller(&request)?;
+        if principal.token_id.is_some() {
+            return Err(Status::invalid_argument("API tokens are revoked, not logged out"));
+        }
+        let token = bearer(&request)?.unwrap_or_default();
+        self.accounts.lock().unwrap().logout(token).map_err(Status::unauthenticated)?;
+        Ok(Response::new(LogoutResponse {}))
+    }
+
+    async fn create_api_token(&self, request: Request<CreateApiTokenRequest>) -> Result<Response<ApiToken>, Status> {
+        let principal = self.caller(&request)?;
+        if principal.token_id.is_some() {
+            return Err(Status::permission_denied("API tokens are created from a login session"));
+        }
+        let request = request.into_inner();
+        let scopes = request.scopes.iter().map(|scope| Scope::parse(scope)).collect::<Result<Vec<_>, _>>().map_err(Status::invalid_argument)?;
+        let ttl = if request.ttl_secs == 0 { None } else { Some(Duration::from_secs(request.ttl_secs)) };
+        let (secret, token) = self
+            .accounts
+            .lock()
+            .unwrap()
+            .create_api_token(&principal.user_id, &request.name, scopes, ttl)
+            .map_err(Status::permission_denied)?;
+        Ok(Response::new(token_to_proto(&token, secret)))
+    }
+
+    async fn list_api_tokens(&self, request: Request<ListApiTokensRequest>) -> Result<Response<ListApiTokensResponse>, Status> {
+        let principal = self.caller(&request)?;
+        let accounts = self.accounts.lock().unwrap();
+        let tokens = accounts.api_tokens_of(&principal.user_id).into_iter().map(|token| token_to_proto(token, String::new())).collect();
+        Ok(Response::new(ListApiTokensResponse { tokens }))
+    }
+
+    async fn revoke_api_token(&self, request: Request<RevokeApiTokenRequest>) -> Result<Response<ApiToken>, Status> {
+        let principal = self.caller(&request)?;
+        let token_id = request.into_inner().token_id;
+        let revoked = self.accounts.lock().unwrap().revoke_api_token(&principal.user_id, &token_id).map_err(Status::not_found)?;
+        Ok(Response::new(token_to_proto(&revoked, String::new())))
+    }
+
+    async fn who_am_i(&self, request: Request<WhoAmIRequest>) -> Result<Response<Identity>, Status> {
+        let principal = self.caller(&request)?;
+        let email = self.accounts.lock().unwrap().get_user(&principal.user_id).map(|user| user.email.clone()).unwrap_or_default();
+        Ok(Response::new(Identity {
+            user_id: principal.user_id,
+            email,
+            role: principal.role.as_str().to_string(),
+            scopes: principal.scopes.iter().map(|scope| scope.as_str().to_string()).collect(),
+            token_id: principal.token_id.unwrap_or_default(),
+        }))
+    }
+
+    async fn begin_oidc_login(&self, _request: Request<BeginOidcLoginRequest>) -> Result<Response<OidcLogin>, Status> {
+        let (authorize_url, state) = self.oidc()?.begin_login().map_err(Status::internal)?;
+        Ok(Response::new(OidcLogin { authorize_url, state }))
+    }
+
+    async fn complete_oidc_login(&self, request: Request<CompleteOidcLoginRequest>) -> Result<Response<Session>, Status> {
+        let oidc = self.oidc()?;
+        let request = request.into_inner();
+        let identity = oidc.complete_login(&request.code, &request.state).await.map_err(Status::unauthenticated)?;
+        Ok(Response::new(self.external_session(oidc, &identity)?))
+    }
+
+    async fn start_device_login(&self, _request: Request<StartDeviceLoginRequest>) -> Result<Response<DeviceLogin>, Status> {
+        let device = self.oidc()?.start_device_login().await.map_err(Status::unavailable)?;
+        Ok(Response::new(DeviceLogin {
+            device_code: device.device_code,
+            user_code: device.user_code,
+            verification_uri: device.verification_uri,
+            verification_uri_complete: device.verification_uri_complete.unwrap_or_default(),
+            expires_in: device.expires_in,
+            interval: device.interval,
+        }))
+    }
+
+    async fn poll_device_login(&self, request: Request<PollDeviceLoginRequest>) -> Result<Response<DeviceLoginStatus>, Status> {
+        let oidc = self.oidc()?;
+        let device_code = request.into_inner().device_code;
+        match oidc.poll_device_login(&device_code).await.map_err(Status::unauthenticated)? {
+            // RFC 8628: back off by five seconds each time the provider asks to slow down
+            DevicePoll::Pending { slow_down } => {
+                let back_off_secs = if slow_down { 5 } else { 0 };
+                Ok(Response::new(DeviceLoginStatus { pending: true, back_off_secs, session: None }))
+            }
+            DevicePoll::Done(identity) => {
+                let session = self.external_session(oidc, &identity)?;
+                Ok(Response::new(DeviceLoginStatus { pending: false, back_off_secs: 0, session: Some(session) }))
+            }
+        }
+    }
+}
@@ -0,0 +1,304 @@
+use std::sync::{Arc, Mutex};
+use clap::Parser;
+use log::{info, warn};
+use tokio::time::Duration;
+use tonic::transport::Server;
+use distributed_computing::auth::{AuthApi, Authenticator};
+use distributed_computing::certificate_authority::{CertificateAuthority, SharedRegistry};
+use distributed_computing::config::{parse_override, Config, ConfigSource, WATCH_INTERVAL};
+use distributed_computing::controller_grpc_client::{self, node::controller_service_server::ControllerServiceServer};
+use distributed_computing::controller_grpc_client::node::enrollment_service_server::EnrollmentServiceServer;
+use distributed_computing::enrollment::{EnrollmentApi, NodeOwnerApi};
+use distributed_computing::job_service::job::auth_service_server::AuthServiceServer;
+use distributed_computing::job_service::job::job_service_server::JobServiceServer;
+use distributed_computing::job_service::job::node_owner_service_server::NodeOwnerServiceServer;
+use distributed_computing::job_service::JobApi;
+use distributed_computing::leader_election::{LeaderElector, LeaderInfo, Leadership};
+use distributed_computing::load_balancer::{LoadBalancer, StrategyRegistry};
+use distributed_computing::node::Node;
+use distributed_computing::node_controller::{ControllerNodeService, HeartbeatTracker, NodeController};
+use distributed_computing::oidc::OidcClient;
+use distributed_computing::redis_client::RedisClient;
+use distributed_computing::result_store::ResultStore;
+use distributed_computing::state_store::StateStore;
+use distributed_computing::strategy_switcher::StrategySwitcher;
+use distributed_computing::task_scheduler::TaskScheduler;
+use distributed_computing::tls::PeerRole;
+use distributed_computing::user_manager::UserManager;
+
+// Runs scheduling, task tracking and the failure detector for a cluster of node agents
+#[derive(Parser)]
+#[command(name = "controller")]
+struct Args {
+    /// Config file (default: $DC_CONFIG or config/config.toml)
+    #[arg(long)]
+    config: Option<String>,
+
+    /// Override a config value, e.g. --set scheduler.tick_ms=500 (repeatable)
+    #[arg(long = "set", value_parser = parse_override)]
+    overrides: Vec<(String, String)>,
+
+    /// Address for ControllerService (network.controller_listen)
+    #[arg(long)]
+    listen: Option<String>,
+
+    /// Address for the public JobService (network.jobs_listen)
+    #[arg(long)]
+    jobs_listen: Option<String>,
+
+    /// NodeService endpoint of a node agent, replaces network.nodes (repeatable)
+    #[arg(long = "node")]
+    nodes: Vec<String>,
+
+    /// Initial placement strategy (scheduler.strategy)
+    #[arg(long)]
+    strategy: Option<String>,
+
+    /// Create the first operator account with this email, unless there already is an operator
+    #[arg(long, requires = "operator_password")]
+    bootstrap_operator: Option<String>,
+
+    /// Password for --bootstrap-operator
+    #[arg(long, env = "CONTROLLER_OPERATOR_PASSWORD", hide_env_values = true)]
+    operator_password: Option<String>,
+}
+
+impl Args {
+    // Shorthand flags are applied after --set
+    fn overrides(&self) -> Vec<(String, String)> {
+        let mut overrides = self.overrides.clone();
+        let flags = [
+            ("network.controller_listen", &self.listen),
+            ("network.jobs_listen", &self.jobs_listen),
+            ("scheduler.strategy", &self.strategy),
+        ];
+        for (key, value) in flags {
+            if let Some(value) = value {
+                overrides.push((key.to_string(), format!("{:?}", value)));
+            }
+        }
+        if !self.nodes.is_empty() {
+            overrides.push(("network.nodes".to_string(), format!("{:?}", self.nodes)));
+        }
+        overrides
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    env_logger::init();
+    let args = Args::parse();
+    let config_path = args.config.clone().unwrap_or_else(Config::default_path);
+    let source = ConfigSource::new(config_path, args.overrides());
+    let config = source.load()?;
+
+    // Load balancer with dynamic strategy switching
+    let load_balancer = LoadBalancer::new(StrategyRegistry::with_defaults(), &config.scheduler.strategy)?;
+    let switcher = StrategySwitcher::new(config.scheduler.switching.clone(), load_balancer.strategy_name());
+
+    // Heartbeats feed the failure detector, pushed task events feed the scheduler
+    let heartbeats = Arc::new(Mutex::new(HeartbeatTracker::new(config.heartbeat.failure_detector.clone())));
+    let mut scheduler = TaskScheduler::new(NodeController::new(), load_balancer, switcher)
+        .with_tick(Duration::from_millis(config.scheduler.tick_ms))
+        .with_aging(config.scheduler.aging.clone())
+        .with_results(ResultStore::with_limits(config.ipfs.inline_output_limit, config.ipfs.output_tail_bytes).with_ipfs(&config.ipfs.api_url))
+        .with_max_retries(config.retries.max_retries)
+        .with_failure_detector(heartbeats.clone())
+        .with_config(source, config.clone());
+    // Apply config file edits on the fly (ReloadConfig does the same on demand)
+    scheduler.watch_config(WATCH_INTERVAL);
+
+    // Journal state changes so queued and running tasks survive a controller crash
+    let (mut journal, mut recovered) = if config.state.dir.is_empty() {
+        (None, None)
+    } else {
+        let (journal, state) = StateStore::open(&config.state.dir, config.state.snapshot_every, config.state.fsync)?;
+        (Some(journal), Some(state))
+    };
+
+    // Share queue, assignments and node status with other replicas through Redis
+    let redis = if config.redis.enabled { Some(RedisClient::connect(&config.redis).await?) } else { None };
+
+    // Mutual TLS with node agents; the built-in CA issues their certificates at enrollment
+    let replica = replica_id(&config.ha.replica_id);
+    let (authority, tls) = if config.tls.enabled {
+        let mut authority = CertificateAuthority::open(&config.tls.ca_dir, config.tls.cert_days, config.tls.renew_before_days)?;
+        let tls = authority.issue_local(&replica, PeerRole::Controller)?;
+        authority.ensure_operator_credentials()?;
+        (Some(Arc::new(Mutex::new(authority))), Some(tls))
+    } else {
+        (None, None)
+    };
+
+    // Buyers log in to submit jobs and sellers' node agents register with join tokens;
+    // the accounts file records users, their tokens and who owns which node
+    let mut accounts = UserManager::open(&config.accounts.file)?.with_session_ttl(Duration::from_secs(config.accounts.session_ttl_secs));
+    // Operators manage users over ControllerService, so the first one comes from the command line
+    if let (Some(email), Some(password)) = (&args.bootstrap_operator, &args.operator_password) {
+        if accounts.has_operator() {
+            info!("An operator account already exists; ignoring --bootstrap-operator");
+        } else {
+            accounts.add_first_operator(email, password)?;
+        }
+    }
+    let accounts = Arc::new(Mutex::new(accounts));
+    // Every API decides what a caller may do through the same roles and ownership rules (see policy.rs)
+    let authenticator = Authenticator::new(accounts.clone(), config.accounts.require_auth);
+    let join_token_ttl = Duration::from_secs(config.accounts.join_token_ttl_secs);
+
+    // Replicas campaign for leadership; only the leader schedules, followers redirect node agents to it
+    let leadership = match &redis {
+        Some(redis) if config.ha.enabled => {
+            let me = LeaderInfo {
+                replica_id: replica.clone(),
+                controller_url: advertised(&config.ha.advertise_url, &config.network.controller_listen, tls.is_some()),
+                jobs_url: advertised(&config.ha.advertise_jobs_url, &config.network.jobs_listen, tls.is_some()),
+            };
+            info!("Replica {} campaigning for leadership, advertising {}", me.replica_id, me.controller_url);
+            LeaderElector::new(redis.clone(), me, Duration::from_millis(config.ha.lease_ms)).spawn()
+        }
+        _ => Leadership::always(),
+    };
+    // The leader keeps the CA's issued and revoked certificates in Redis for whoever leads next
+    let shared_registry = match (&redis, &authority) {
+        (Some(redis), Some(_)) if config.ha.enabled => Some(SharedRegistry::new(redis.clone(), leadership.clone())),
+        _ => None,
+    };
+
+    let mut controller_server = Server::builder();
+    let mut jobs_server = Server::builder();
+    if let Some(tls) = &tls {
+        // Node agents and operators must present a certificate from our CA; buyers need none
+        controller_server = controller_server.tls_config(tls.server_config()?)?;
+        jobs_server = jobs_server.tls_config(tls.public_server_config()?)?;
+    }
+
+    let mut controller_service = ControllerNodeService::new(heartbeats, scheduler.event_sender(), scheduler.result_store())
+        .with_leadership(leadership.clone())
+        .with_accounts(accounts.clone(), tls.clone(), join_token_ttl)
+        .with_auth(authenticator.clone());
+    if let Some(authority) = &authority {
+        controller_service = controller_service.with_authority(authority.clone());
+    }
+    if let Some(registry) = &shared_registry {
+        controller_service = controller_service.with_shared_registry(registry.clone());
+    }
+    let addr = config.network.controller_listen.parse()?;
+    tokio::spawn(async move {
+        if let Err(err) = controller_server.add_service(ControllerServiceServer::new(controller_service)).serve(addr).await {
+            log::error!("Controller gRPC server stopped: {}", err);
+        }
+    });
+
+    // Public job API for buyers of compute, on its own port, next to sign-in, node enrollment and the sellers' API
+    let job_api = JobApi::new(scheduler.event_sender(), scheduler.job_updates())
+        .with_leadership(leadership.clone())
+        .with_auth(authenticator.clone());
+    let node_owner_api =
+        NodeOwnerApi::new(accounts.clone(), authenticator, scheduler.event_sender(), join_token_ttl).with_leadership(leadership.clone());
+    let mut auth_api = AuthApi::new(accounts.clone());
+    if config.oidc.enabled {
+        // Password logins and API tokens keep working while the provider is unreachable
+        match OidcClient::discover(&config.oidc).await {
+            Ok(oidc) => auth_api = auth_api.with_oidc(Arc::new(oidc)),
+            Err(err) => warn!("Single sign-on is unavailable: {}", err),
+        }
+    }
+    let mut enrollment = EnrollmentApi::new(accounts).with_leadership(leadership.clone());
+    if let Some(authority) = &authority {
+        enrollment = enrollment.with_authority(authority.clone(), &config.tls.enrollment_token);
+    }
+    if let Some(registry) = &shared_registry {
+        enrollment = enrollment.with_shared_registry(registry.clone());
+    }
+    let jobs_addr = config.network.jobs_listen.parse()?;
+    tokio::spawn(async move {
+        let router = jobs_server
+            .add_service(JobServiceServer::new(job_api))
+            .add_service(AuthServiceServer::new(auth_api))
+            .add_service(NodeOwnerServiceServer::new(node_owner_api))
+            .add_service(EnrollmentServiceServer::new(enrollment));
+        if let Err(err) = router.serve(jobs_addr).await {
+            log::error!("Job gRPC server stopped: {}", err);
+        }
+    });
+
+    info!("Controller listening on {} (jobs on {})", config.network.controller_listen, config.network.jobs_listen);
+
+    if let (Some(redis), true) = (&redis, config.ha.enabled) {
+        let mut waiting = leadership.clone();
+        waiting.wait_until_leader().await;
+        // Take over where the previous leader left off; its journal lives on another host
+        let state = redis.load_state().await?;
+        info!("Took over as leader with {} unfinished tasks and {} jobs", state.tasks.len(), state.jobs.len());
+        if let Some(journal) = journal.as_mut() {
+            journal.reset(state.clone())?;
+        }
+        recovered = Some(state);
+        // Refuse the certificates earlier leaders revoked
+        if let (Some(registry), Some(authority)) = (&shared_registry, &authority) {
+            registry.load_into(authority).await?;
+        }
+    }
+    if let Some(journal) = journal {
+        scheduler = scheduler.with_journal(journal);
+    }
+    // Only the leader writes the shared state, and only while it holds the lease
+    if let Some(redis) = &redis {
+        scheduler = scheduler.with_shared_state(redis.clone().spawn_mirror(leadership.clone()));
+    }
+
+    // Learn each node agent's sold capacity and subscribe to its task events. A follower waits
+    // until it leads, or events from its standby would be replayed against the state it takes over.
+    for addr in &config.network.nodes {
+        let mut client = match controller_grpc_client::NodeController::connect(addr.clone(), tls.as_ref()).await {
+            Ok(client) => client,
+            Err(err) => {
+                warn!("Skipping node agent at {}: {}", addr, err);
+                continue;
+            }
+        };
+        let status = match client.get_node_status().await {
+            Ok(status) => status,
+            Err(err) => {
+                warn!("Skipping node agent at {}: {}", addr, err);
+                continue;
+            }
+        };
+        let node = Node::new(&status.node_id, status.available_ram, status.available_storage, status.available_cpu, status.available_bandwidth);
+        info!("Node {} at {} sells {}MB RAM, {}% CPU, {}Mbps", status.node_id, addr, status.available_ram, status.available_cpu, status.available_bandwidth);
+        scheduler.add_node(node, client);
+    }
+
+    // Reconcile with the node agents before placing anything
+    if let Some(state) = recovered {
+        scheduler.recover(state).await;
+    }
+
+    scheduler.with_leadership(leadership.clone()).run().await;
+    if !leadership.is_leader() {
+        // Start over as a follower; the scheduler's in-memory state is stale by now
+        return Err("Lost controller leadership".into());
+    }
+    Ok(())
+}
+
+// Configured replica ID, or <hostname>-<pid>
+fn replica_id(configured: &str) -> String {
+    if !configured.is_empty() {
+        return configured.to_string();
+    }
+    let host = std::env::var("HOSTNAME").unwrap_or_else(|_| "controller".to_string());
+    format!("{}-{}", host, std::process::id())
+}
+
+// Configured URL, or one built from the bind address
+fn advertised(configured: &str, listen: &str, tls: bool) -> String {
+    if !configured.is_empty() {
+        configured.to_string()
+    } else if tls {
+        format!("https://{}", listen)
+    } else {
+        format!("http://{}", listen)
+    }
+}
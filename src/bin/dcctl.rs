@@ -0,0 +1,877 @@
+// dcctl: command-line client for the controller's JobService and ControllerService
+use std::io::Read;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use serde_json::{json, Value};
+use tonic::transport::Channel;
+use distributed_computing::controller_grpc_client::node::controller_service_client::ControllerServiceClient;
+use distributed_computing::controller_grpc_client::node::node_service_client::NodeServiceClient;
+use distributed_computing::controller_grpc_client::node::{DrainNodeRequest, ListNodesRequest, NodeInfo, TaskResultRequest, TaskResultResponse, TaskState};
+use distributed_computing::controller_grpc_client::node::{ReloadConfigRequest, ReloadConfigResponse, RevokeNodeRequest};
+use distributed_computing::controller_grpc_client::node::{CreateJoinTokenRequest, CreateUserRequest};
+use distributed_computing::auth;
+use distributed_computing::job_service::job::auth_service_client::AuthServiceClient;
+use distributed_computing::job_service::job::job_service_client::JobServiceClient;
+use distributed_computing::job_service::job::node_owner_service_client::NodeOwnerServiceClient;
+use distributed_computing::job_service::job::{Job, JobRequest, JobState, ListJobsRequest, SubmitJobRequest};
+use distributed_computing::job_service::job::{ApiToken, CreateApiTokenRequest, ListApiTokensRequest, LoginRequest, LogoutRequest, RevokeApiTokenRequest};
+use distributed_computing::job_service::job::{PollDeviceLoginRequest, Session, StartDeviceLoginRequest};
+use distributed_computing::job_service::job::{ListEarningsRequest, NodeEarnings, NodeJoinTokenRequest};
+use distributed_computing::tls::{self, TlsSettings, CONTROLLER_SERVER_NAME, NODE_SERVER_NAME};
+
+#[derive(Parser)]
+#[command(name = "dcctl", about = "Submit and inspect jobs on a distributed computing cluster")]
+struct Cli {
+    /// Controller endpoint serving ControllerService (results, nodes)
+    #[arg(long, env = "DCCTL_CONTROLLER", default_value = "http://[::1]:50050", global = true)]
+    controller: String,
+
+    /// Endpoint serving the public JobService
+    #[arg(long, env = "DCCTL_JOBS", default_value = "http://[::1]:50060", global = true)]
+    jobs: String,
+
+    /// Directory with ca.pem, cert.pem and key.pem for clusters running TLS
+    /// (the controller writes operator credentials to <tls.ca_dir>/operator)
+    #[arg(long, env = "DCCTL_TLS_DIR", global = true)]
+    tls_dir: Option<String>,
+
+    /// Session token from `dcctl login`, or an API token
+    #[arg(long, env = "DCCTL_TOKEN", global = true, hide_env_values = true)]
+    token: Option<String>,
+
+    #[arg(short, long, value_enum, default_value_t = Output::Table, global = true)]
+    output: Output,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum Output {
+    Table,
+    Json,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Submit a job
+    Submit(SubmitArgs),
+    /// Show one job, or list jobs when no ID is given
+    Status {
+        job_id: Option<String>,
+        #[arg(long)]
+        owner: Option<String>,
+        #[arg(long, value_enum)]
+        state: Option<StateFilter>,
+    },
+    /// Cancel a pending or running job
+    Cancel { job_id: String },
+    /// Print a finished job's output
+    Logs {
+        job_id: String,
+        /// Print stderr instead of stdout
+        #[arg(long)]
+        stderr: bool,
+        /// Wait for the job to finish first, printing its state changes
+        #[arg(short, long)]
+        follow: bool,
+    },
+    /// Inspect and manage nodes
+    Nodes {
+        #[command(subcommand)]
+        command: NodesCommand,
+    },
+    /// Fetch task results
+    Results {
+        #[command(subcommand)]
+        command: ResultsCommand,
+    },
+    /// Manage the running configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Manage user accounts
+    Users {
+        #[command(subcommand)]
+        command: UsersCommand,
+    },
+    /// Create tokens node agents register with
+    JoinToken {
+        #[command(subcommand)]
+        command: JoinTokenCommand,
+    },
+    /// Start a session; prints a token to export as DCCTL_TOKEN
+    Login {
+        #[arg(long, required_unless_present = "sso")]
+        email: Option<String>,
+        /// Read from stdin when not given
+        #[arg(long, env = "DCCTL_PASSWORD", hide_env_values = true)]
+        password: Option<String>,
+        /// Log in with your organization's single sign-on instead, approving dcctl in a browser
+        #[arg(long, conflicts_with = "email")]
+        sso: bool,
+    },
+    /// End the session in DCCTL_TOKEN
+    Logout,
+    /// Manage API tokens for scripts
+    Tokens {
+        #[command(subcommand)]
+        command: TokensCommand,
+    },
+}
+
+#[derive(Args)]
+struct SubmitArgs {
+    #[arg(long)]
+    job_id: Option<String>,
+    #[arg(long, default_value = "")]
+    owner: String,
+    #[arg(long, default_value_t = 0)]
+    priority: u8,
+    /// UNIX time the job must finish by
+    #[arg(long)]
+    deadline: Option<u64>,
+    /// RAM in MB
+    #[arg(long)]
+    ram: u64,
+    /// CPU in %
+    #[arg(long)]
+    cpu: u64,
+    /// Bandwidth in Mbps
+    #[arg(long, default_value_t = 0)]
+    bandwidth: u64,
+    /// Storage in GB
+    #[arg(long, default_value_t = 0)]
+    storage: u64,
+    /// subprocess, wasm or in_process
+    #[arg(long, default_value = "in_process")]
+    executor: String,
+    /// Program for subprocess jobs, entry function for wasm jobs
+    #[arg(long, default_value = "")]
+    command: String,
+    #[arg(long = "arg")]
+    args: Vec<String>,
+    /// File sent as the job payload ("-" for stdin)
+    #[arg(long)]
+    payload: Option<String>,
+    #[arg(long, default_value_t = 0)]
+    max_retries: u32,
+    /// Wait for the job to finish, printing its state changes
+    #[arg(short, long)]
+    watch: bool,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum StateFilter {
+    Pending,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Subcommand)]
+enum NodesCommand {
+    /// List every known node
+    List,
+    /// Stop placing jobs on a node; running jobs finish normally
+    Drain { node_id: String },
+    /// Revoke a node's certificates; it has to enroll again to rejoin
+    Revoke { node_id: String },
+    /// Show your registered nodes and the jobs they completed
+    Earnings {
+        /// Another seller's nodes (operators only)
+        #[arg(long)]
+        owner: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ResultsCommand {
+    /// Show a finished job's result
+    Get {
+        job_id: String,
+        /// Write the full stdout to this file
+        #[arg(long)]
+        out: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Make the controller (or a node agent) re-read its config file
+    Reload {
+        /// NodeService endpoint of a node agent to reload instead of the controller
+        #[arg(long)]
+        node: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum UsersCommand {
+    /// Add a buyer, seller or operator account
+    Add {
+        #[arg(long)]
+        email: String,
+        /// buyer, seller or operator
+        #[arg(long, default_value = "seller")]
+        role: String,
+        /// Lets the user log in; sellers who only own nodes don't need one
+        #[arg(long, default_value = "")]
+        password: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum TokensCommand {
+    /// Create an API token; it is printed once
+    Create {
+        #[arg(long)]
+        name: String,
+        /// jobs:read, jobs:submit or jobs:cancel (repeatable; default: everything your role allows)
+        #[arg(long = "scope")]
+        scopes: Vec<String>,
+        /// Lifetime of the token (default: never expires)
+        #[arg(long, default_value_t = 0)]
+        ttl_secs: u64,
+    },
+    /// List your API tokens
+    List,
+    /// Revoke one of your API tokens
+    Revoke { token_id: String },
+}
+
+#[derive(Subcommand)]
+enum JoinTokenCommand {
+    /// Create a one-time token; the node that registers with it belongs to the owner
+    Create {
+        /// User ID of the seller or operator who owns the node (default: you)
+        #[arg(long)]
+        owner: Option<String>,
+        /// Lifetime of the token (default: accounts.join_token_ttl_secs on the controller)
+        #[arg(long, default_value_t = 0)]
+        ttl_secs: u64,
+    },
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    if let Err(err) = run(cli).await {
+        eprintln!("error: {}", err);
+        std::process::exit(1);
+    }
+}
+
+// Where the controller's services are, and the credentials to reach them with
+struct Endpoints {
+    controller: String,
+    jobs: String,
+    tls: Option<TlsSettings>,
+    token: Option<String>,
+}
+
+impl Endpoints {
+    // A request carrying the token; operators with a certificate don't need one for ControllerService
+    fn request<T>(&self, message: T) -> Result<tonic::Request<T>, String> {
+        auth::with_token(message, self.token.as_deref())
+    }
+}
+
+async fn run(cli: Cli) -> Result<(), String> {
+    let tls = cli.tls_dir.as_deref().map(|dir| TlsSettings::load(Path::new(dir))).transpose()?;
+    let endpoints = Endpoints { controller: cli.controller.clone(), jobs: cli.jobs.clone(), tls, token: cli.token.clone() };
+    match cli.command {
+        Command::Submit(args) => submit(&endpoints, cli.output, args).await,
+        Command::Status { job_id: Some(job_id), .. } => {
+            let job = job_client(&endpoints).await?.get_job(endpoints.request(JobRequest { job_id })?).await.map_err(status_error)?.into_inner();
+            print_jobs(cli.output, &[job]);
+            Ok(())
+        }
+        Command::Status { job_id: None, owner, state } => {
+            let request = ListJobsRequest {
+                owner: owner.unwrap_or_default(),
+                state: state.map_or(JobState::Unknown, job_state) as i32,
+            };
+            let jobs = job_client(&endpoints).await?.list_jobs(endpoints.request(request)?).await.map_err(status_error)?.into_inner().jobs;
+            print_jobs(cli.output, &jobs);
+            Ok(())
+        }
+        Command::Cancel { job_id } => {
+            let job = job_client(&endpoints).await?.cancel_job(endpoints.request(JobRequest { job_id })?).await.map_err(status_error)?.into_inner();
+            print_jobs(cli.output, &[job]);
+            Ok(())
+        }
+        Command::Logs { job_id, stderr, follow } => {
+            if follow {
+                watch(&endpoints, Output::Table, &job_id).await?;
+            }
+            let result = get_result(&endpoints, &job_id).await?;
+            let (data, cid) = if stderr { (&result.stderr, &result.stderr_cid) } else { (&result.stdout, &result.stdout_cid) };
+            if cid.is_empty() {
+                print!("{}", String::from_utf8_lossy(data));
+            } else {
+                // Large outputs live in IPFS; only the tail is kept on the controller
+                let tail = if stderr { &result.stderr_tail } else { &result.stdout_tail };
+                eprintln!("(output stored in IPFS as {}, showing the last {} bytes)", cid, tail.len());
+                print!("{}", String::from_utf8_lossy(tail));
+            }
+            Ok(())
+        }
+        Command::Nodes { command: NodesCommand::List } => {
+            let nodes = controller_client(&endpoints).await?.list_nodes(endpoints.request(ListNodesRequest {})?).await.map_err(status_error)?.into_inner().nodes;
+            print_nodes(cli.output, &nodes);
+            Ok(())
+        }
+        Command::Nodes { command: NodesCommand::Drain { node_id } } => {
+            let response = controller_client(&endpoints)
+                .await?
+                .drain_node(endpoints.request(DrainNodeRequest { node_id })?)
+                .await
+                .map_err(status_error)?
+                .into_inner();
+            print_nodes(cli.output, &response.node.into_iter().collect::<Vec<_>>());
+            Ok(())
+        }
+        Command::Nodes { command: NodesCommand::Revoke { node_id } } => {
+            let response = controller_client(&endpoints)
+                .await?
+                .revoke_node(endpoints.request(RevokeNodeRequest { node_id: node_id.clone() })?)
+                .await
+                .map_err(status_error)?
+                .into_inner();
+            match cli.output {
+                Output::Json => println!("{}", json!({ "node_id": node_id, "revoked": response.revoked })),
+                Output::Table => println!("Revoked {} certificates of {}", response.revoked, node_id),
+            }
+            Ok(())
+        }
+        Command::Nodes { command: NodesCommand::Earnings { owner } } => {
+            let request = endpoints.request(ListEarningsRequest { owner_id: owner.unwrap_or_default() })?;
+            let nodes = node_owner_client(&endpoints).await?.list_earnings(request).await.map_err(status_error)?.into_inner().nodes;
+            print_earnings(cli.output, &nodes);
+            Ok(())
+        }
+        Command::Results { command: ResultsCommand::Get { job_id, out } } => {
+            let result = get_result(&endpoints, &job_id).await?;
+            if let Some(path) = out {
+                if !result.stdout_cid.is_empty() {
+                    return Err(format!("stdout is stored in IPFS as {}, fetch it from there", result.stdout_cid));
+                }
+                std::fs::write(&path, &result.stdout).map_err(|e| format!("Could not write {}: {}", path, e))?;
+            }
+            print_result(cli.output, &result);
+            Ok(())
+        }
+        Command::Config { command: ConfigCommand::Reload { node: Some(addr) } } => {
+            let channel = tls::connect(&addr, endpoints.tls.as_ref(), NODE_SERVER_NAME)
+                .await
+                .map_err(|e| format!("Could not connect to {}: {}", addr, e))?;
+            let mut client = NodeServiceClient::new(channel);
+            let response = client.reload_config(ReloadConfigRequest {}).await.map_err(status_error)?.into_inner();
+            print_reload(cli.output, &response);
+            Ok(())
+        }
+        Command::Config { command: ConfigCommand::Reload { node: None } } => {
+            let response = controller_client(&endpoints)
+                .await?
+                .reload_config(endpoints.request(ReloadConfigRequest {})?)
+                .await
+                .map_err(status_error)?
+                .into_inner();
+            print_reload(cli.output, &response);
+            Ok(())
+        }
+        Command::Users { command: UsersCommand::Add { email, role, password } } => {
+            let user = controller_client(&endpoints)
+                .await?
+                .create_user(endpoints.request(CreateUserRequest { email, role, password })?)
+                .await
+                .map_err(status_error)?
+                .into_inner();
+            match cli.output {
+                Output::Json => println!("{}", json!({ "user_id": user.user_id, "email": user.email, "role": user.role })),
+                Output::Table => println!("Added {} {} as {}", user.role, user.email, user.user_id),
+            }
+            Ok(())
+        }
+        Command::JoinToken { command: JoinTokenCommand::Create { owner, ttl_secs } } => {
+            let owner_id = owner.unwrap_or_default();
+            // Sellers go through the public NodeOwnerService; operators may use their certificate instead
+            let (token, owner_id, expires_at) = if endpoints.token.is_some() {
+                let request = endpoints.request(NodeJoinTokenRequest { owner_id, ttl_secs })?;
+                let token = node_owner_client(&endpoints).await?.create_join_token(request).await.map_err(status_error)?.into_inner();
+                (token.token, token.owner_id, token.expires_at)
+            } else {
+                if owner_id.is_empty() {
+                    return Err("--owner is required without a token".to_string());
+                }
+                let request = CreateJoinTokenRequest { owner_id, ttl_secs };
+                let token = controller_client(&endpoints).await?.create_join_token(request).await.map_err(status_error)?.into_inner();
+                (token.token, token.owner_id, token.expires_at)
+            };
+            match cli.output {
+                Output::Json => println!("{}", json!({ "token": token, "owner_id": owner_id, "expires_at": expires_at })),
+                Output::Table => {
+                    println!("{}", token);
+                    eprintln!("Single use, for a node of {}, valid until UNIX time {}", owner_id, expires_at);
+                }
+            }
+            Ok(())
+        }
+        Command::Login { sso: true, .. } => {
+            let session = device_login(&endpoints).await?;
+            print_session(cli.output, &session);
+            Ok(())
+        }
+        Command::Login { email, password, .. } => {
+            let email = email.unwrap_or_default();
+            let password = match password {
+                Some(password) => password,
+                None => {
+                    eprint!("Password: ");
+                    let mut line = String::new();
+                    std::io::stdin().read_line(&mut line).map_err(|e| format!("Could not read the password: {}", e))?;
+                    line.trim_end_matches(['\r', '\n']).to_string()
+                }
+            };
+            let session = auth_client(&endpoints).await?.login(LoginRequest { email, password }).await.map_err(status_error)?.into_inner();
+            print_session(cli.output, &session);
+            Ok(())
+        }
+        Command::Logout => {
+            auth_client(&endpoints).await?.logout(endpoints.request(LogoutRequest {})?).await.map_err(status_error)?;
+            Ok(())
+        }
+        Command::Tokens { command: TokensCommand::Create { name, scopes, ttl_secs } } => {
+            let token = auth_client(&endpoints)
+                .await?
+                .create_api_token(endpoints.request(CreateApiTokenRequest { name, scopes, ttl_secs })?)
+                .await
+                .map_err(status_error)?
+                .into_inner();
+            match cli.output {
+                Output::Json => println!("{}", token_json(&token)),
+                Output::Table => {
+                    println!("{}", token.token);
+                    eprintln!("Token {} ({}); it is not shown again", token.token_id, token.scopes.join(", "));
+                }
+            }
+            Ok(())
+        }
+        Command::Tokens { command: TokensCommand::List } => {
+            let tokens = auth_client(&endpoints)
+                .await?
+                .list_api_tokens(endpoints.request(ListApiTokensRequest {})?)
+                .await
+                .map_err(status_error)?
+                .into_inner()
+                .tokens;
+            match cli.output {
+                Output::Json => println!("{}", Value::Array(tokens.iter().map(token_json).collect())),
+                Output::Table => {
+                    println!("{:<12} {:<20} {:<36} {:>12}", "TOKEN", "NAME", "SCOPES", "EXPIRES");
+                    for token in &tokens {
+                        let expires = if token.expires_at == 0 { "never".to_string() } else { token.expires_at.to_string() };
+                        println!("{:<12} {:<20} {:<36} {:>12}", token.token_id, token.name, token.scopes.join(","), expires);
+                    }
+                }
+            }
+            Ok(())
+        }
+        Command::Tokens { command: TokensCommand::Revoke { token_id } } => {
+            auth_client(&endpoints)
+                .await?
+                .revoke_api_token(endpoints.request(RevokeApiTokenRequest { token_id: token_id.clone() })?)
+                .await
+                .map_err(status_error)?;
+            if cli.output == Output::Table {
+                println!("Revoked {}", token_id);
+            }
+            Ok(())
+        }
+    }
+}
+
+async fn job_client(endpoints: &Endpoints) -> Result<JobServiceClient<Channel>, String> {
+    let addr = &endpoints.jobs;
+    let channel = tls::connect(addr, endpoints.tls.as_ref(), CONTROLLER_SERVER_NAME)
+        .await
+        .map_err(|e| format!("Could not connect to {}: {}", addr, e))?;
+    Ok(JobServiceClient::new(channel))
+}
+
+async fn auth_client(endpoints: &Endpoints) -> Result<AuthServiceClient<Channel>, String> {
+    let addr = &endpoints.jobs;
+    let channel = tls::connect(addr, endpoints.tls.as_ref(), CONTROLLER_SERVER_NAME)
+        .await
+        .map_err(|e| format!("Could not connect to {}: {}", addr, e))?;
+    Ok(AuthServiceClient::new(channel))
+}
+
+async fn node_owner_client(endpoints: &Endpoints) -> Result<NodeOwnerServiceClient<Channel>, String> {
+    let addr = &endpoints.jobs;
+    let channel = tls::connect(addr, endpoints.tls.as_ref(), CONTROLLER_SERVER_NAME)
+        .await
+        .map_err(|e| format!("Could not connect to {}: {}", addr, e))?;
+    Ok(NodeOwnerServiceClient::new(channel))
+}
+
+async fn controller_client(endpoints: &Endpoints) -> Result<ControllerServiceClient<Channel>, String> {
+    let addr = &endpoints.controller;
+    let channel = tls::connect(addr, endpoints.tls.as_ref(), CONTROLLER_SERVER_NAME)
+        .await
+        .map_err(|e| format!("Could not connect to {}: {}", addr, e))?;
+    Ok(ControllerServiceClient::new(channel))
+}
+
+fn status_error(status: tonic::Status) -> String {
+    format!("{:?}: {}", status.code(), status.message())
+}
+
+async fn get_result(endpoints: &Endpoints, job_id: &str) -> Result<TaskResultResponse, String> {
+    let request = endpoints.request(TaskResultRequest { task_id: job_id.to_string() })?;
+    Ok(controller_client(endpoints).await?.get_task_result(request).await.map_err(status_error)?.into_inner())
+}
+
+async fn submit(endpoints: &Endpoints, output: Output, args: SubmitArgs) -> Result<(), String> {
+    let payload = match args.payload.as_deref() {
+        None => Vec::new(),
+        Some("-") => {
+            let mut buf = Vec::new();
+            std::io::stdin().read_to_end(&mut buf).map_err(|e| format!("Could not read stdin: {}", e))?;
+            buf
+        }
+        Some(path) => std::fs::read(path).map_err(|e| format!("Could not read {}: {}", path, e))?,
+    };
+
+    let request = SubmitJobRequest {
+        job_id: args.job_id.unwrap_or_default(),
+        owner: args.owner,
+        priority: args.priority as u32,
+        deadline: args.deadline.unwrap_or(0),
+        required_ram: args.ram,
+        required_cpu: args.cpu,
+        required_bandwidth: args.bandwidth,
+        required_storage: args.storage,
+        payload,
+        executor: args.executor,
+        command: args.command,
+        args: args.args,
+        max_retries: args.max_retries,
+    };
+    let job = job_client(endpoints).await?.submit_job(endpoints.request(request)?).await.map_err(status_error)?.into_inner();
+    print_jobs(output, std::slice::from_ref(&job));
+
+    if args.watch {
+        watch(endpoints, output, &job.job_id).await?;
+    }
+    Ok(())
+}
+
+// Device flow: the user approves dcctl at the provider while we poll the controller
+async fn device_login(endpoints: &Endpoints) -> Result<Session, String> {
+    let mut client = auth_client(endpoints).await?;
+    let device = client.start_device_login(StartDeviceLoginRequest {}).await.map_err(status_error)?.into_inner();
+    if device.verification_uri_complete.is_empty() {
+        eprintln!("Open {} and enter the code {}", device.verification_uri, device.user_code);
+    } else {
+        eprintln!("Open {} and check that it shows the code {}", device.verification_uri_complete, device.user_code);
+    }
+
+    let deadline = Instant::now() + Duration::from_secs(device.expires_in);
+    let mut interval = Duration::from_secs(device.interval.max(1));
+    while Instant::now() < deadline {
+        tokio::time::sleep(interval).await;
+        let request = PollDeviceLoginRequest { device_code: device.device_code.clone() };
+        let status = client.poll_device_login(request).await.map_err(status_error)?.into_inner();
+        if let Some(session) = status.session {
+            return Ok(session);
+        }
+        interval += Duration::from_secs(status.back_off_secs);
+    }
+    Err("The login code expired before it was approved".to_string())
+}
+
+// Print state changes until the job finishes
+async fn watch(endpoints: &Endpoints, output: Output, job_id: &str) -> Result<(), String> {
+    let mut stream = job_client(endpoints)
+        .await?
+        .watch_job(endpoints.request(JobRequest { job_id: job_id.to_string() })?)
+        .await
+        .map_err(status_error)?
+        .into_inner();
+    while let Some(job) = stream.message().await.map_err(status_error)? {
+        match output {
+            Output::Json => println!("{}", job_json(&job)),
+            Output::Table => eprintln!(
+                "{} {} {}%{}",
+                job.job_id,
+                job_state_name(job.state),
+                job.progress_percent,
+                if job.message.is_empty() { String::new() } else { format!(" ({})", job.message) }
+            ),
+        }
+    }
+    Ok(())
+}
+
+fn print_session(output: Output, session: &Session) {
+    match output {
+        Output::Json => println!(
+            "{}",
+            json!({ "token": session.token, "user_id": session.user_id, "role": session.role, "expires_at": session.expires_at })
+        ),
+        Output::Table => {
+            println!("{}", session.token);
+            eprintln!("Logged in as {} ({}) until UNIX time {}; export DCCTL_TOKEN to use it", session.user_id, session.role, session.expires_at);
+        }
+    }
+}
+
+fn token_json(token: &ApiToken) -> Value {
+    json!({
+        "token_id": token.token_id,
+        "token": if token.token.is_empty() { Value::Null } else { json!(token.token) },
+        "name": token.name,
+        "scopes": token.scopes,
+        "created_at": token.created_at,
+        "expires_at": token.expires_at,
+    })
+}
+
+fn job_state(filter: StateFilter) -> JobState {
+    match filter {
+        StateFilter::Pending => JobState::Pending,
+        StateFilter::Running => JobState::Running,
+        StateFilter::Succeeded => JobState::Succeeded,
+        StateFilter::Failed => JobState::Failed,
+        StateFilter::Cancelled => JobState::Cancelled,
+    }
+}
+
+fn job_state_name(state: i32) -> &'static str {
+    match JobState::from_i32(state) {
+        Some(JobState::Pending) => "pending",
+        Some(JobState::Running) => "running",
+        Some(JobState::Succeeded) => "succeeded",
+        Some(JobState::Failed) => "failed",
+        Some(JobState::Cancelled) => "cancelled",
+        _ => "unknown",
+    }
+}
+
+fn format_time(unix_ms: u64) -> String {
+    if unix_ms == 0 {
+        return "-".to_string();
+    }
+    let age = SystemTime::now()
+        .duration_since(UNIX_EPOCH + Duration::from_millis(unix_ms))
+        .unwrap_or_default()
+        .as_secs();
+    match age {
+        0..=59 => format!("{}s ago", age),
+        60..=3599 => format!("{}m ago", age / 60),
+        _ => format!("{}h ago", age / 3600),
+    }
+}
+
+// Print rows as left-aligned columns sized to their widest cell
+fn print_table(headers: &[&str], rows: Vec<Vec<String>>) {
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.len()).collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    let line = |cells: Vec<String>| {
+        let padded: Vec<String> = cells.iter().zip(&widths).map(|(cell, width)| format!("{:<width$}", cell, width = width)).collect();
+        println!("{}", padded.join("  ").trim_end());
+    };
+    line(headers.iter().map(|h| h.to_string()).collect());
+    for row in rows {
+        line(row);
+    }
+}
+
+fn job_json(job: &Job) -> Value {
+    json!({
+        "job_id": job.job_id,
+        "owner": job.owner,
+        "state": job_state_name(job.state),
+        "priority": job.priority,
+        "deadline": if job.deadline == 0 { Value::Null } else { json!(job.deadline) },
+        "required_ram": job.required_ram,
+        "required_cpu": job.required_cpu,
+        "required_bandwidth": job.required_bandwidth,
+        "required_storage": job.required_storage,
+        "executor": job.executor,
+        "node_id": if job.node_id.is_empty() { Value::Null } else { json!(job.node_id) },
+        "progress_percent": job.progress_percent,
+        "retries": job.retries,
+        "preemptions": job.preemptions,
+        "wasted_work_ms": job.wasted_work_ms,
+        "message": job.message,
+        "submitted_at_ms": job.submitted_at_ms,
+        "updated_at_ms": job.updated_at_ms,
+    })
+}
+
+fn print_jobs(output: Output, jobs: &[Job]) {
+    if output == Output::Json {
+        println!("{}", Value::Array(jobs.iter().map(job_json).collect()));
+        return;
+    }
+    let rows = jobs
+        .iter()
+        .map(|job| {
+            vec![
+                job.job_id.clone(),
+                job_state_name(job.state).to_string(),
+                job.owner.clone(),
+                job.priority.to_string(),
+                if job.node_id.is_empty() { "-".to_string() } else { job.node_id.clone() },
+                format!("{}%", job.progress_percent),
+                job.retries.to_string(),
+                match job.preemptions {
+                    0 => "0".to_string(),
+                    n => format!("{} ({:.1}s lost)", n, job.wasted_work_ms as f64 / 1000.0),
+                },
+                format_time(job.submitted_at_ms),
+                job.message.clone(),
+            ]
+        })
+        .collect();
+    print_table(&["JOB", "STATE", "OWNER", "PRIORITY", "NODE", "PROGRESS", "RETRIES", "PREEMPTED", "SUBMITTED", "MESSAGE"], rows);
+}
+
+fn print_reload(output: Output, response: &ReloadConfigResponse) {
+    if output == Output::Json {
+        println!("{}", json!({ "applied": response.applied, "restart_required": response.restart_required }));
+        return;
+    }
+    if response.applied.is_empty() && response.restart_required.is_empty() {
+        println!("No changes");
+    }
+    for key in &response.applied {
+        println!("applied           {}", key);
+    }
+    for key in &response.restart_required {
+        println!("restart required  {}", key);
+    }
+}
+
+fn print_nodes(output: Output, nodes: &[NodeInfo]) {
+    if output == Output::Json {
+        let nodes: Vec<Value> = nodes
+            .iter()
+            .map(|node| {
+                json!({
+                    "node_id": node.node_id,
+                    "health": node.health,
+                    "draining": node.draining,
+                    "phi": node.phi,
+                    "capacity": { "ram": node.capacity_ram, "cpu": node.capacity_cpu, "bandwidth": node.capacity_bandwidth, "storage": node.capacity_storage },
+                    "allocated": { "ram": node.allocated_ram, "cpu": node.allocated_cpu, "bandwidth": node.allocated_bandwidth, "storage": node.allocated_storage },
+                    "running_tasks": node.running_tasks,
+                })
+            })
+            .collect();
+        println!("{}", Value::Array(nodes));
+        return;
+    }
+    let rows = nodes
+        .iter()
+        .map(|node| {
+            vec![
+                node.node_id.clone(),
+                if node.draining { format!("{},draining", node.health) } else { node.health.clone() },
+                format!("{:.1}", node.phi),
+                format!("{}/{}MB", node.allocated_ram, node.capacity_ram),
+                format!("{}/{}%", node.allocated_cpu, node.capacity_cpu),
+                format!("{}/{}Mbps", node.allocated_bandwidth, node.capacity_bandwidth),
+                format!("{}/{}GB", node.allocated_storage, node.capacity_storage),
+                node.running_tasks.to_string(),
+            ]
+        })
+        .collect();
+    print_table(&["NODE", "HEALTH", "PHI", "RAM", "CPU", "BANDWIDTH", "STORAGE", "TASKS"], rows);
+}
+
+fn print_earnings(output: Output, nodes: &[NodeEarnings]) {
+    if output == Output::Json {
+        let nodes: Vec<Value> = nodes
+            .iter()
+            .map(|node| {
+                json!({
+                    "node_id": node.node_id,
+                    "owner_id": node.owner_id,
+                    "node_url": node.node_url,
+                    "sells": { "ram": node.sell_ram_mb, "cpu": node.sell_cpu_percent, "bandwidth": node.sell_bandwidth_mbps, "storage": node.sell_storage_gb },
+                    "registered_at": node.registered_at,
+                    "jobs_completed": node.jobs_completed,
+                })
+            })
+            .collect();
+        println!("{}", Value::Array(nodes));
+        return;
+    }
+    let rows = nodes
+        .iter()
+        .map(|node| {
+            vec![
+                node.node_id.clone(),
+                node.node_url.clone(),
+                format!("{}MB", node.sell_ram_mb),
+                format!("{}%", node.sell_cpu_percent),
+                format!("{}Mbps", node.sell_bandwidth_mbps),
+                format!("{}GB", node.sell_storage_gb),
+                node.jobs_completed.to_string(),
+            ]
+        })
+        .collect();
+    print_table(&["NODE", "URL", "RAM", "CPU", "BANDWIDTH", "STORAGE", "JOBS DONE"], rows);
+}
+
+fn print_result(output: Output, result: &TaskResultResponse) {
+    let state = match TaskState::from_i32(result.state) {
+        Some(TaskState::Succeeded) => "succeeded",
+        Some(TaskState::Failed) => "failed",
+        _ => "unknown",
+    };
+    if output == Output::Json {
+        let json = json!({
+            "task_id": result.task_id,
+            "node_id": result.node_id,
+            "state": state,
+            "exit_code": result.exit_code,
+            "error": result.error,
+            "stdout_cid": if result.stdout_cid.is_empty() { Value::Null } else { json!(result.stdout_cid) },
+            "stderr_cid": if result.stderr_cid.is_empty() { Value::Null } else { json!(result.stderr_cid) },
+            "output_size": result.output_size,
+            "stdout_tail": String::from_utf8_lossy(&result.stdout_tail),
+            "stderr_tail": String::from_utf8_lossy(&result.stderr_tail),
+            "runtime_ms": result.runtime_ms,
+            "peak_memory_mb": result.peak_memory_mb,
+            "cpu_time_ms": result.cpu_time_ms,
+            "completed_at_ms": result.completed_at_ms,
+        });
+        println!("{}", json);
+        return;
+    }
+    let stored = if result.stdout_cid.is_empty() { "inline".to_string() } else { format!("ipfs://{}", result.stdout_cid) };
+    let rows = vec![vec![
+        result.task_id.clone(),
+        state.to_string(),
+        result.exit_code.to_string(),
+        result.node_id.clone(),
+        format!("{:.1}s", result.runtime_ms as f64 / 1000.0),
+        format!("{}MB", result.peak_memory_mb),
+        format!("{}ms", result.cpu_time_ms),
+        format!("{}B {}", result.output_size, stored),
+        result.error.clone(),
+    ]];
+    print_table(&["TASK", "STATE", "EXIT", "NODE", "RUNTIME", "PEAK MEM", "CPU TIME", "OUTPUT", "ERROR"], rows);
+}
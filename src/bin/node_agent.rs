@@ -0,0 +1,187 @@
+// The interceptor returns tonic::Status as is
+#![allow(clippy::result_large_err)]
+
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use clap::Parser;
+use log::info;
+use tonic::transport::Server;
+use tonic::Request;
+use distributed_computing::config::{parse_override, Config, ConfigSource, WATCH_INTERVAL};
+use distributed_computing::controller_grpc_client::node::node_service_server::NodeServiceServer;
+use distributed_computing::enrollment::{register_node, Registration};
+use distributed_computing::host_metrics::HostSampler;
+use distributed_computing::leader_election::ControllerEndpoint;
+use distributed_computing::node::Node;
+use distributed_computing::node_grpc_server::{report_progress, MyNodeService};
+use distributed_computing::resource_enforcer::CgroupEnforcer;
+use distributed_computing::tls::{require_role, NodeCredentials, PeerRole};
+
+// Runs on a seller's machine: serves NodeService and reports to the controller
+#[derive(Parser)]
+#[command(name = "node-agent")]
+struct Args {
+    /// Config file (default: $DC_CONFIG or config/config.toml)
+    #[arg(long)]
+    config: Option<String>,
+
+    /// Override a config value, e.g. --set sell_limits.ram_mb=2048 (repeatable)
+    #[arg(long = "set", value_parser = parse_override)]
+    overrides: Vec<(String, String)>,
+
+    /// Unique ID of this node in the cluster (node.id); normally assigned at registration
+    #[arg(long, env = "NODE_ID")]
+    node_id: Option<String>,
+
+    /// One-time token to register this node with (node.join_token)
+    #[arg(long, env = "JOIN_TOKEN")]
+    join_token: Option<String>,
+
+    /// Address to serve NodeService on (network.node_listen)
+    #[arg(long)]
+    listen: Option<String>,
+
+    /// Controller's ControllerService endpoint (network.controller_url)
+    #[arg(long)]
+    controller: Option<String>,
+
+    /// RAM to sell in MB (sell_limits.ram_mb)
+    #[arg(long)]
+    sell_ram: Option<u64>,
+
+    /// CPU to sell in % of one core; 200 = two cores (sell_limits.cpu_percent)
+    #[arg(long)]
+    sell_cpu: Option<u64>,
+
+    /// Bandwidth to sell in Mbps (sell_limits.bandwidth_mbps)
+    #[arg(long)]
+    sell_bandwidth: Option<u64>,
+
+    /// Storage to sell in GB (sell_limits.storage_gb)
+    #[arg(long)]
+    sell_storage: Option<u64>,
+
+    /// Filesystem whose free space is sold as storage (node.storage_path)
+    #[arg(long)]
+    storage_path: Option<String>,
+}
+
+impl Args {
+    // Shorthand flags are applied after --set
+    fn overrides(&self) -> Vec<(String, String)> {
+        let mut overrides = self.overrides.clone();
+        let strings = [
+            ("node.id", &self.node_id),
+            ("node.join_token", &self.join_token),
+            ("network.node_listen", &self.listen),
+            ("network.controller_url", &self.controller),
+            ("node.storage_path", &self.storage_path),
+        ];
+        for (key, value) in strings {
+            if let Some(value) = value {
+                overrides.push((key.to_string(), format!("{:?}", value)));
+            }
+        }
+        let numbers = [
+            ("sell_limits.ram_mb", self.sell_ram),
+            ("sell_limits.cpu_percent", self.sell_cpu),
+            ("sell_limits.bandwidth_mbps", self.sell_bandwidth),
+            ("sell_limits.storage_gb", self.sell_storage),
+        ];
+        for (key, value) in numbers {
+            if let Some(value) = value {
+                overrides.push((key.to_string(), value.to_string()));
+            }
+        }
+        overrides
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    env_logger::init();
+    let args = Args::parse();
+    let config_path = args.config.clone().unwrap_or_else(Config::default_path);
+    let source = ConfigSource::new(config_path, args.overrides());
+    let config = source.load()?;
+    let limits = &config.sell_limits;
+
+    // Enforce sold limits when cgroups v2 is available, otherwise run tasks unconfined
+    let enforcer = match CgroupEnforcer::new("distributed_computing", limits.max_throttled_percent) {
+        Ok(enforcer) => Some(Arc::new(enforcer)),
+        Err(err) => {
+            println!("Resource enforcement disabled: {}", err);
+            None
+        }
+    };
+
+    // Configured ID, else the one assigned when this node registered, else register now
+    let node_id = if !config.node.id.is_empty() {
+        config.node.id.clone()
+    } else if let Some(registration) = Registration::load(Path::new(&config.node.registration_file))? {
+        registration.node_id
+    } else if !config.node.join_token.is_empty() {
+        let node_url = if !config.node.advertise_url.is_empty() {
+            config.node.advertise_url.clone()
+        } else if config.tls.enabled {
+            format!("https://{}", config.network.node_listen)
+        } else {
+            format!("http://{}", config.network.node_listen)
+        };
+        register_node(&config, &node_url, enforcer.is_some()).await?.node_id
+    } else {
+        return Err("Not registered: pass a join token (--join-token, JOIN_TOKEN) or set node.id".into());
+    };
+
+    let mut node = Node::new(&node_id, limits.ram_mb, limits.storage_gb, limits.cpu_percent, limits.bandwidth_mbps);
+    if let Some(enforcer) = enforcer {
+        node = node.with_enforcer(enforcer);
+    }
+
+    let heartbeat_node = node.clone();
+    let node_service = MyNodeService::new(node).with_config(source, config.clone());
+    // Apply sell limit edits on the fly (ReloadConfig does the same on demand)
+    node_service.watch_config(WATCH_INTERVAL);
+    // Offer the host's spare resources, capped by the sell limits above
+    let sample_interval = Duration::from_secs(config.heartbeat.sample_interval_secs);
+    node_service.monitor_host(HostSampler::new(&config.node.storage_path), sample_interval);
+
+    // Mutual TLS with a certificate from the controller's CA, enrolling on first start
+    let credentials = if config.tls.enabled {
+        let credentials =
+            NodeCredentials::load_or_enroll(&config.tls.dir, &node_id, &config.network.enroll_url, &config.tls.enrollment_token).await?;
+        Some(credentials)
+    } else {
+        None
+    };
+
+    // Heartbeats feed the controller's failure detector; a follower replica answers with the leader's URL
+    let mut controller = ControllerEndpoint::new(&config.network.controller_url, &config.network.standby_controllers);
+    if let Some(credentials) = &credentials {
+        controller = controller.with_credentials(credentials.clone());
+    }
+    let interval = Duration::from_secs(config.heartbeat.interval_secs);
+    let heartbeat_controller = controller.clone();
+    tokio::spawn(async move { heartbeat_node.send_heartbeat(heartbeat_controller, interval).await });
+
+    // Also push events to the controller, for nodes it cannot dial (e.g. behind NAT)
+    let retry = Duration::from_secs(config.heartbeat.progress_retry_secs);
+    tokio::spawn(report_progress(controller, node_service.events(), retry));
+
+    let addr = config.network.node_listen.parse()?;
+    info!("Node {} serving NodeService on {}, reporting to {}", node_id, config.network.node_listen, config.network.controller_url);
+    let mut server = Server::builder();
+    if let Some(credentials) = &credentials {
+        // Picks up a renewed certificate on the next restart; the old one stays valid until it expires
+        server = server.tls_config(credentials.settings().server_config()?)?;
+    }
+    // Only the controller and operators may call this node; certificates of other nodes are refused
+    let node_service = NodeServiceServer::with_interceptor(node_service, |request: Request<()>| {
+        require_role(&request, &[PeerRole::Controller, PeerRole::Operator])?;
+        Ok(request)
+    });
+    server.add_service(node_service).serve(addr).await?;
+
+    Ok(())
+}
@@ -0,0 +1,279 @@
+use std::collections::{HashMap, VecDeque};
+use crate::load_balancer::{Placement, PlacementStrategy};
+use crate::node::Node;
+use crate::task::Task;
+
+// RAM (MB), CPU (%), bandwidth (Mbps) and storage (GB) considered together
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ResourceVector {
+    pub ram: f64,
+    pub cpu: f64,
+    pub bandwidth: f64,
+    pub storage: f64,
+}
+
+impl ResourceVector {
+    // Resources a task asks for
+    pub fn demand(task: &Task) -> Self {
+        ResourceVector {
+            ram: task.required_ram as f64,
+            cpu: task.required_cpu as f64,
+            bandwidth: task.required_bandwidth as f64,
+            storage: task.required_storage as f64,
+        }
+    }
+
+    // Total capacity a node sells
+    pub fn capacity(node: &Node) -> Self {
+        ResourceVector {
+            ram: node.available_ram as f64,
+            cpu: node.available_cpu as f64,
+            bandwidth: node.available_bandwidth as f64,
+            storage: node.available_storage as f64,
+        }
+    }
+
+    // Capacity not yet allocated on a node
+    pub fn free(node: &Node) -> Self {
+        ResourceVector {
+            ram: node.available_ram.saturating_sub(node.allocated_ram) as f64,
+            cpu: node.available_cpu.saturating_sub(node.allocated_cpu) as f64,
+            bandwidth: node.available_bandwidth.saturating_sub(node.allocated_bandwidth) as f64,
+            storage: node.available_storage.saturating_sub(node.allocated_storage) as f64,
+        }
+    }
+
+    // Summed capacity of every node in the cluster
+    pub fn cluster_capacity(nodes: &[Node]) -> Self {
+        nodes.iter().fold(ResourceVector::default(), |total, node| total.add(&ResourceVector::capacity(node)))
+    }
+
+    pub fn add(&self, other: &ResourceVector) -> Self {
+        ResourceVector {
+            ram: self.ram + other.ram,
+            cpu: self.cpu + other.cpu,
+            bandwidth: self.bandwidth + other.bandwidth,
+            storage: self.storage + other.storage,
+        }
+    }
+
+    pub fn sub(&self, other: &ResourceVector) -> Self {
+        ResourceVector {
+            ram: self.ram - other.ram,
+            cpu: self.cpu - other.cpu,
+            bandwidth: self.bandwidth - other.bandwidth,
+            storage: self.storage - other.storage,
+        }
+    }
+
+    // Each dimension as a fraction of `capacity` (dimensions with no capacity are skipped)
+    pub fn shares(&self, capacity: &ResourceVector) -> Vec<f64> {
+        [
+            (self.ram, capacity.ram),
+            (self.cpu, capacity.cpu),
+            (self.bandwidth, capacity.bandwidth),
+            (self.storage, capacity.storage),
+        ]
+        .iter()
+        .filter(|(_, cap)| *cap > 0.0)
+        .map(|(value, cap)| value / cap)
+        .collect()
+    }
+
+    // Largest share across all dimensions
+    pub fn dominant_share(&self, capacity: &ResourceVector) -> f64 {
+        self.shares(capacity).into_iter().fold(0.0, f64::max)
+    }
+}
+
+// Place a task on the node it fills most tightly
+fn tightest_fit(task: &Task, nodes: &[Node]) -> Option<Placement> {
+    let demand = ResourceVector::demand(task);
+
+    nodes
+        .iter()
+        .filter(|node| node.can_handle_task(task))
+        .map(|node| {
+            // Leftover capacity after placement, normalized per node; less leftover = tighter fit
+            let leftover = ResourceVector::free(node).sub(&demand).shares(&ResourceVector::capacity(node));
+            let waste = leftover.iter().sum::<f64>() / leftover.len().max(1) as f64;
+            Placement { node_id: node.node_id.clone(), score: 1.0 - waste }
+        })
+        .max_by(|a, b| a.score.partial_cmp(&b.score).unwrap_or(std::cmp::Ordering::Equal))
+}
+
+// Best-fit decreasing: largest tasks first, each onto the tightest node
+pub struct BestFitDecreasingStrategy;
+
+impl PlacementStrategy for BestFitDecreasingStrategy {
+    fn name(&self) -> &'static str {
+        "best_fit_decreasing"
+    }
+
+    fn order_tasks(&self, tasks: &mut Vec<Task>, nodes: &[Node]) {
+        // Sort tasks by dominant share of cluster capacity (descending)
+        let capacity = ResourceVector::cluster_capacity(nodes);
+        tasks.sort_by(|a, b| {
+            let size_a = ResourceVector::demand(a).dominant_share(&capacity);
+            let size_b = ResourceVector::demand(b).dominant_share(&capacity);
+            size_b.partial_cmp(&size_a).unwrap_or(std::cmp::Ordering::Equal)
+        });
+    }
+
+    fn place(&self, task: &Task, nodes: &[Node]) -> Option<Placement> {
+        tightest_fit(task, nodes)
+    }
+}
+
+// Dominant Resource Fairness: interleave users so the one with the smallest dominant share goes next
+pub struct DominantResourceFairnessStrategy;
+
+impl PlacementStrategy for DominantResourceFairnessStrategy {
+    fn name(&self) -> &'static str {
+        "drf"
+    }
+
+    fn order_tasks(&self, tasks: &mut Vec<Task>, nodes: &[Node]) {
+        let capacity = ResourceVector::cluster_capacity(nodes);
+
+        // Per-user queues, keeping each user's own ordering by priority
+        let mut per_user: HashMap<String, VecDeque<Task>> = HashMap::new();
+        tasks.sort_by_key(|task| std::cmp::Reverse(task.priority));
+        for task in tasks.drain(..) {
+            per_user.entry(task.owner.clone()).or_default().push_back(task);
+        }
+
+        let mut usage: HashMap<String, ResourceVector> = HashMap::new();
+        while !per_user.is_empty() {
+            // User with the lowest dominant share (ties broken by name for determinism)
+            let next_user = per_user
+                .keys()
+                .map(|user| {
+                    let share = usage.get(user).map(|u| u.dominant_share(&capacity)).unwrap_or(0.0);
+                    (share, user.clone())
+                })
+                .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal).then_with(|| a.1.cmp(&b.1)))
+                .map(|(_, user)| user)
+                .unwrap();
+
+            let queue = per_user.get_mut(&next_user).unwrap();
+            let task = queue.pop_front().unwrap();
+            if queue.is_empty() {
+                per_user.remove(&next_user);
+            }
+
+            let used = usage.entry(next_user).or_default();
+            *used = used.add(&ResourceVector::demand(&task));
+            tasks.push(task);
+        }
+    }
+
+    fn place(&self, task: &Task, nodes: &[Node]) -> Option<Placement> {
+        tightest_fit(task, nodes)
+    }
+}
+
+// Packing quality of a single scheduling batch
+#[derive(Debug, Clone)]
+pub struct PackingReport {
+    pub tasks_placed: usize,
+    pub tasks_unplaced: usize,
+    pub nodes_used: usize,
+    pub efficiency: f64,    // Mean utilisation across all dimensions of the nodes in use (0.0 to 1.0)
+    pub fragmentation: f64, // Share of free cluster capacity stranded on partially used nodes (0.0 to 1.0)
+}
+
+// Compute packing efficiency after a batch has been placed
+pub fn packing_report(nodes: &[Node], assignments: &[(String, String)], batch_size: usize) -> PackingReport {
+    let used_nodes: Vec<&Node> = nodes
+        .iter()
+        .filter(|node| node.allocated_ram > 0 || node.allocated_cpu > 0 || node.allocated_bandwidth > 0 || node.allocated_storage > 0)
+        .collect();
+
+    let utilisation: Vec<f64> = used_nodes
+        .iter()
+        .flat_map(|node| {
+            let capacity = ResourceVector::capacity(node);
+            capacity.sub(&ResourceVector::free(node)).shares(&capacity)
+        })
+        .collect();
+    let efficiency = if utilisation.is_empty() { 0.0 } else { utilisation.iter().sum::<f64>() / utilisation.len() as f64 };
+
+    let cluster = ResourceVector::cluster_capacity(nodes);
+    let total_free = nodes
+        .iter()
+        .map(|node| ResourceVector::free(node).shares(&cluster).iter().sum::<f64>())
+        .sum::<f64>();
+    let stranded_free = used_nodes
+        .iter()
+        .map(|node| ResourceVector::free(node).shares(&cluster).iter().sum::<f64>())
+        .sum::<f64>();
+    let fragmentation = if total_free > 0.0 { stranded_free / total_free } else { 0.0 };
+
+    PackingReport {
+        tasks_placed: assignments.len(),
+        tasks_unplaced: batch_size.saturating_sub(assignments.len()),
+        nodes_used: used_nodes.len(),
+        efficiency,
+        fragmentation,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load_balancer::{LoadBalancer, StrategyRegistry};
+
+    fn owned(task_id: &str, owner: &str, ram: u64, cpu: u64) -> Task {
+        let mut task = Task::new(task_id, 0, ram, cpu, 1, Vec::new());
+        task.owner = owner.to_string();
+        task
+    }
+
+    fn ids(tasks: &[Task]) -> Vec<&str> {
+        tasks.iter().map(|task| task.task_id.as_str()).collect()
+    }
+
+    #[test]
+    fn best_fit_decreasing_places_large_tasks_first_on_the_tightest_node() {
+        let nodes = vec![Node::new("big", 4096, 10, 400, 100), Node::new("small", 1024, 10, 100, 100)];
+        let mut tasks = vec![owned("tiny", "a", 128, 10), owned("large", "a", 2048, 100), owned("medium", "a", 900, 50)];
+        BestFitDecreasingStrategy.order_tasks(&mut tasks, &nodes);
+        assert_eq!(ids(&tasks), ["large", "medium", "tiny"]);
+
+        assert_eq!(BestFitDecreasingStrategy.place(&tasks[1], &nodes).unwrap().node_id, "small");
+        assert_eq!(BestFitDecreasingStrategy.place(&tasks[0], &nodes).unwrap().node_id, "big");
+        assert!(BestFitDecreasingStrategy.place(&owned("huge", "a", 8192, 10), &nodes).is_none());
+    }
+
+    #[test]
+    fn drf_serves_the_user_with_the_smallest_dominant_share_next() {
+        let nodes = vec![Node::new("node_1", 1000, 10, 100, 100)];
+        // Alice's tasks are RAM heavy, Bob's CPU heavy; each of Bob's takes a bigger dominant share
+        let mut tasks = vec![
+            owned("a1", "alice", 100, 1),
+            owned("a2", "alice", 100, 1),
+            owned("a3", "alice", 100, 1),
+            owned("b1", "bob", 10, 30),
+            owned("b2", "bob", 10, 30),
+        ];
+        DominantResourceFairnessStrategy.order_tasks(&mut tasks, &nodes);
+        assert_eq!(ids(&tasks), ["a1", "b1", "a2", "a3", "b2"]);
+    }
+
+    #[test]
+    fn packing_reports_count_placements_and_utilisation() {
+        let mut balancer = LoadBalancer::new(StrategyRegistry::with_defaults(), "best_fit_decreasing").unwrap();
+        let mut nodes = vec![Node::new("node_1", 1000, 10, 100, 100), Node::new("node_2", 1000, 10, 100, 100)];
+        let mut tasks = vec![owned("t1", "a", 500, 50), owned("t2", "a", 500, 50), owned("t3", "a", 5000, 50)];
+        let assignments = balancer.assign_tasks(&mut tasks, &mut nodes);
+
+        // Both fit on one node, leaving the other free rather than stranding capacity on two
+        assert_eq!(assignments.len(), 2);
+        assert!(assignments.iter().all(|(_, node_id)| node_id == &assignments[0].1));
+        let report = balancer.last_packing_report().unwrap();
+        assert_eq!((report.tasks_placed, report.tasks_unplaced, report.nodes_used), (2, 1, 1));
+        assert!(report.efficiency > 0.5);
+        assert!(report.fragmentation < 0.5);
+    }
+}
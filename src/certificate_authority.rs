@@ -0,0 +1,341 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use log::{info, warn};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use rcgen::{BasicConstraints, Certificate, CertificateParams, CertificateSigningRequest, DistinguishedName, DnType};
+use rcgen::{ExtendedKeyUsagePurpose, IsCa, KeyPair, KeyUsagePurpose, SanType};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use crate::controller_grpc_client::node::NodeCertificate;
+use crate::leader_election::Leadership;
+use crate::redis_client::RedisClient;
+use crate::tls::{write_file, PeerIdentity, PeerRole, TlsSettings};
+
+const CA_CERT_FILE: &str = "ca.pem";
+const CA_KEY_FILE: &str = "ca.key";
+const REGISTRY_FILE: &str = "issued.json";
+// Credentials for dcctl, written once next to the CA
+const OPERATOR_DIR: &str = "operator";
+
+// The CA certificate itself outlives everything it signs by a wide margin
+const CA_VALIDITY_DAYS: i64 = 3650;
+// Allow for clocks that run a little behind ours
+const CLOCK_SKEW: time::Duration = time::Duration::minutes(5);
+
+// A certificate this CA signed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IssuedCert {
+    pub name: String,
+    pub role: PeerRole,
+    pub not_after: i64,
+    pub revoked: bool,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Registry {
+    issued: BTreeMap<u64, IssuedCert>, // By serial number
+}
+
+// Small CA run by the controller: signs node certificates at enrollment and renewal,
+// and remembers what it signed so certificates can be revoked per node. Serials are
+// random, so replicas sharing the CA never hand out the same one.
+pub struct CertificateAuthority {
+    dir: PathBuf,
+    ca: Certificate,
+    ca_pem: String,
+    registry: Registry,
+    validity: time::Duration,     // Lifetime of issued certificates
+    renew_before: time::Duration, // Nodes are asked to renew once less than this is left
+}
+
+impl CertificateAuthority {
+    // Load the CA from `dir`, creating a new one on first start
+    pub fn open(dir: impl Into<PathBuf>, cert_days: u32, renew_before_days: u32) -> Result<Self, String> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir).map_err(|e| format!("Could not create {}: {}", dir.display(), e))?;
+        let (cert_path, key_path) = (dir.join(CA_CERT_FILE), dir.join(CA_KEY_FILE));
+
+        let (ca, ca_pem) = if key_path.exists() {
+            let key = fs::read_to_string(&key_path).map_err(|e| format!("Could not read {}: {}", key_path.display(), e))?;
+            let ca_pem = fs::read_to_string(&cert_path).map_err(|e| format!("Could not read {}: {}", cert_path.display(), e))?;
+            let key_pair = KeyPair::from_pem(&key).map_err(|e| format!("Invalid CA key {}: {}", key_path.display(), e))?;
+            let params = CertificateParams::from_ca_cert_pem(&ca_pem, key_pair)
+                .map_err(|e| format!("Invalid CA certificate {}: {}", cert_path.display(), e))?;
+            let ca = Certificate::from_params(params).map_err(|e| e.to_string())?;
+            (ca, ca_pem)
+        } else {
+            let mut params = CertificateParams::default();
+            params.distinguished_name = DistinguishedName::new();
+            params.distinguished_name.push(DnType::CommonName, "distributed-computing CA");
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign];
+            params.not_before = OffsetDateTime::now_utc() - CLOCK_SKEW;
+            params.not_after = OffsetDateTime::now_utc() + time::Duration::days(CA_VALIDITY_DAYS);
+            let ca = Certificate::from_params(params).map_err(|e| format!("Could not create a CA: {}", e))?;
+            let ca_pem = ca.serialize_pem().map_err(|e| e.to_string())?;
+            write_file(&key_path, &ca.serialize_private_key_pem(), 0o600)?;
+            write_file(&cert_path, &ca_pem, 0o644)?;
+            info!("Created a new CA in {}; give node agents a copy of {}", dir.display(), cert_path.display());
+            (ca, ca_pem)
+        };
+
+        let registry_path = dir.join(REGISTRY_FILE);
+        let registry = match fs::read_to_string(&registry_path) {
+            Ok(contents) => serde_json::from_str(&contents).map_err(|e| format!("Corrupt {}: {}", registry_path.display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Registry::default(),
+            Err(e) => return Err(format!("Could not read {}: {}", registry_path.display(), e)),
+        };
+
+        Ok(CertificateAuthority {
+            dir,
+            ca,
+            ca_pem,
+            registry,
+            validity: time::Duration::days(cert_days as i64),
+            renew_before: time::Duration::days(renew_before_days as i64),
+        })
+    }
+
+    pub fn ca_pem(&self) -> &str {
+        &self.ca_pem
+    }
+
+    // Check a signing request before anything is committed to it
+    pub fn check_request(&self, csr_pem: &str) -> Result<(), String> {
+        CertificateSigningRequest::from_pem(csr_pem).map(|_| ()).map_err(|e| format!("Invalid signing request: {}", e))
+    }
+
+    // Sign a node's request. The subject comes from `node_id`, not from what the request asks for.
+    pub fn issue_node(&mut self, node_id: &str, csr_pem: &str) -> Result<NodeCertificate, String> {
+        let mut csr = CertificateSigningRequest::from_pem(csr_pem).map_err(|e| format!("Invalid signing request: {}", e))?;
+        let not_after = self.prepare(&mut csr.params, node_id, PeerRole::Node)?;
+        let certificate_pem = csr.serialize_pem_with_signer(&self.ca).map_err(|e| format!("Could not sign: {}", e))?;
+        Ok(NodeCertificate { certificate_pem, ca_certificate_pem: self.ca_pem.clone(), not_after })
+    }
+
+    // Certificate and key generated here, for the controller itself or an operator
+    pub fn issue_local(&mut self, name: &str, role: PeerRole) -> Result<TlsSettings, String> {
+        let mut params = CertificateParams::default();
+        self.prepare(&mut params, name, role)?;
+        let cert = Certificate::from_params(params).map_err(|e| e.to_string())?;
+        let cert_pem = cert.serialize_pem_with_signer(&self.ca).map_err(|e| format!("Could not sign: {}", e))?;
+        Ok(TlsSettings::new(self.ca_pem.clone(), Some((cert_pem, cert.serialize_private_key_pem()))))
+    }
+
+    // Write credentials for dcctl (`--tls-dir <ca_dir>/operator`) unless they exist already
+    pub fn ensure_operator_credentials(&mut self) -> Result<(), String> {
+        let dir = self.dir.join(OPERATOR_DIR);
+        if dir.exists() {
+            return Ok(());
+        }
+        self.issue_local("operator", PeerRole::Operator)?.save(&dir)?;
+        info!("Wrote operator credentials for dcctl to {}", dir.display());
+        Ok(())
+    }
+
+    fn prepare(&mut self, params: &mut CertificateParams, name: &str, role: PeerRole) -> Result<i64, String> {
+        // Positive as a signed 64-bit number, so it reads back the same from the certificate
+        let serial = loop {
+            let serial = OsRng.next_u64() >> 1;
+            if serial != 0 && !self.registry.issued.contains_key(&serial) {
+                break serial;
+            }
+        };
+        let now = OffsetDateTime::now_utc();
+        params.serial_number = Some(serial);
+        params.not_before = now - CLOCK_SKEW;
+        params.not_after = now + self.validity;
+        params.distinguished_name = DistinguishedName::new();
+        params.distinguished_name.push(DnType::CommonName, name);
+        params.distinguished_name.push(DnType::OrganizationalUnitName, role.as_str());
+        params.subject_alt_names = vec![SanType::DnsName(role.server_name().to_string())];
+        params.is_ca = IsCa::NoCa;
+        params.key_usages = vec![KeyUsagePurpose::DigitalSignature, KeyUsagePurpose::KeyEncipherment];
+        params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth, ExtendedKeyUsagePurpose::ClientAuth];
+
+        let not_after = params.not_after.unix_timestamp();
+        self.registry.issued.insert(serial, IssuedCert { name: name.to_string(), role, not_after, revoked: false });
+        self.save()?;
+        Ok(not_after)
+    }
+
+    // Accept only unrevoked certificates we issued to this peer; a serial alone proves nothing
+    pub fn verify(&self, peer: &PeerIdentity) -> Result<(), String> {
+        match self.registry.issued.get(&peer.serial) {
+            Some(issued) if issued.revoked => Err(format!("Certificate {} for {} is revoked", peer.serial, peer.name)),
+            Some(issued) if issued.name == peer.name && issued.role == peer.role => Ok(()),
+            Some(_) => Err(format!("Certificate {} was not issued to {}", peer.serial, peer.name)),
+            None => Err(format!("Certificate {} for {} was not issued by this controller", peer.serial, peer.name)),
+        }
+    }
+
+    // Whether the peer should swap its certificate for a new one
+    pub fn needs_renewal(&self, peer: &PeerIdentity) -> bool {
+        peer.not_after - OffsetDateTime::now_utc().unix_timestamp() < self.renew_before.whole_seconds()
+    }
+
+    // Revoke every certificate issued to `node_id`; returns how many were still valid
+    pub fn revoke_node(&mut self, node_id: &str) -> Result<u32, String> {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let mut revoked = 0;
+        for issued in self.registry.issued.values_mut() {
+            if issued.role == PeerRole::Node && issued.name == node_id && !issued.revoked {
+                issued.revoked = true;
+                if issued.not_after > now {
+                    revoked += 1;
+                }
+            }
+        }
+        // Expired certificates can't be used anyway; keep the registry from growing forever
+        self.registry.issued.retain(|_, issued| issued.not_after > now);
+        self.save()?;
+        if revoked > 0 {
+            warn!("Revoked {} certificates of node {}", revoked, node_id);
+        }
+        Ok(revoked)
+    }
+
+    // Everything this CA has issued and not yet seen expire, for other replicas
+    pub fn issued(&self) -> &BTreeMap<u64, IssuedCert> {
+        &self.registry.issued
+    }
+
+    // Take in what another replica issued and revoked; a revocation on either side wins
+    pub fn merge(&mut self, issued: BTreeMap<u64, IssuedCert>) -> Result<(), String> {
+        for (serial, theirs) in issued {
+            let ours = self.registry.issued.entry(serial).or_insert_with(|| theirs.clone());
+            ours.revoked |= theirs.revoked;
+        }
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        self.registry.issued.retain(|_, issued| issued.not_after > now);
+        self.save()
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let contents = serde_json::to_string_pretty(&self.registry).map_err(|e| e.to_string())?;
+        write_file(&self.dir.join(REGISTRY_FILE), &contents, 0o600)
+    }
+}
+
+// The leader's record of issued and revoked certificates, kept in Redis so that a replica
+// taking over refuses the same certificates the old leader did
+#[derive(Clone)]
+pub struct SharedRegistry {
+    redis: RedisClient,
+    leadership: Leadership,
+    publishing: Arc<tokio::sync::Mutex<()>>, // Keeps an older copy from overwriting a newer one
+}
+
+impl SharedRegistry {
+    pub fn new(redis: RedisClient, leadership: Leadership) -> Self {
+        SharedRegistry { redis, leadership, publishing: Arc::new(tokio::sync::Mutex::new(())) }
+    }
+
+    // Write the registry after every issue or revocation, only while this replica's term holds
+    pub async fn publish(&self, authority: &Mutex<CertificateAuthority>) -> Result<(), String> {
+        let _publishing = self.publishing.lock().await;
+        let issued = authority.lock().unwrap().issued().clone();
+        let redis = match self.leadership.fence() {
+            Some(fence) => self.redis.clone().with_fence(fence),
+            None => self.redis.clone(),
+        };
+        redis.share_certificates(&issued).await.map_err(|e| format!("Could not share the certificate registry: {}", e))
+    }
+
+    // Merge what earlier leaders issued and revoked into our own registry
+    pub async fn load_into(&self, authority: &Mutex<CertificateAuthority>) -> Result<(), String> {
+        let issued = self.redis.load_certificates().await.map_err(|e| format!("Could not load the certificate registry: {}", e))?;
+        info!("Loaded {} certificates issued by earlier leaders", issued.len());
+        authority.lock().unwrap().merge(issued)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use crate::tls::certificate_request;
+
+    // Fresh CA directory for each test
+    fn ca_dir() -> PathBuf {
+        static NEXT: AtomicU32 = AtomicU32::new(0);
+        let name = format!("dc_ca_{}_{}", std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed));
+        let dir = std::env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn identity(issued: &NodeCertificate) -> PeerIdentity {
+        let pem = x509_parser::pem::parse_x509_pem(issued.certificate_pem.as_bytes()).unwrap().1;
+        PeerIdentity::from_der(&pem.contents).unwrap()
+    }
+
+    #[test]
+    fn node_certificates_are_named_by_the_controller() {
+        let mut ca = CertificateAuthority::open(ca_dir(), 30, 7).unwrap();
+        assert!(ca.check_request("not a signing request").is_err());
+
+        let (csr, _) = certificate_request("node_someone_else").unwrap();
+        ca.check_request(&csr).unwrap();
+        let issued = ca.issue_node("node_1", &csr).unwrap();
+        assert_eq!(issued.ca_certificate_pem, ca.ca_pem());
+
+        let peer = identity(&issued);
+        assert_eq!((peer.name.as_str(), peer.role), ("node_1", PeerRole::Node));
+        assert_eq!(peer.not_after, issued.not_after);
+        assert!(!ca.needs_renewal(&peer));
+        assert!(ca.needs_renewal(&PeerIdentity { not_after: OffsetDateTime::now_utc().unix_timestamp() + 3600, ..peer }));
+    }
+
+    #[test]
+    fn revocation_covers_every_certificate_of_the_node_and_survives_a_restart() {
+        let dir = ca_dir();
+        let mut ca = CertificateAuthority::open(&dir, 30, 7).unwrap();
+        let (csr, _) = certificate_request("node_1").unwrap();
+        let first = identity(&ca.issue_node("node_1", &csr).unwrap());
+        let renewed = identity(&ca.issue_node("node_1", &csr).unwrap());
+        let other = identity(&ca.issue_node("node_2", &csr).unwrap());
+        assert_ne!(first.serial, renewed.serial);
+
+        assert_eq!(ca.revoke_node("node_1").unwrap(), 2);
+        assert_eq!(ca.revoke_node("node_1").unwrap(), 0);
+
+        let reopened = CertificateAuthority::open(&dir, 30, 7).unwrap();
+        assert_eq!(reopened.ca_pem(), ca.ca_pem());
+        assert!(reopened.verify(&first).is_err() && reopened.verify(&renewed).is_err());
+        assert!(reopened.verify(&other).is_ok());
+    }
+
+    #[test]
+    fn serials_only_vouch_for_the_node_they_were_issued_to() {
+        let mut ca = CertificateAuthority::open(ca_dir(), 30, 7).unwrap();
+        let (csr, _) = certificate_request("node_1").unwrap();
+        let node_1 = identity(&ca.issue_node("node_1", &csr).unwrap());
+        assert!(ca.verify(&node_1).is_ok());
+        assert!(ca.verify(&PeerIdentity { name: "node_2".to_string(), ..node_1.clone() }).is_err());
+        assert!(ca.verify(&PeerIdentity { role: PeerRole::Operator, ..node_1.clone() }).is_err());
+        // Unknown to this CA's registry
+        assert!(ca.verify(&PeerIdentity { serial: node_1.serial ^ 1, ..node_1 }).is_err());
+    }
+
+    #[test]
+    fn replicas_taking_over_keep_the_revocations_of_earlier_leaders() {
+        let (csr, _) = certificate_request("node_1").unwrap();
+        let mut leader = CertificateAuthority::open(ca_dir(), 30, 7).unwrap();
+        let revoked = identity(&leader.issue_node("node_1", &csr).unwrap());
+        let kept = identity(&leader.issue_node("node_2", &csr).unwrap());
+        leader.revoke_node("node_1").unwrap();
+
+        // The next leader revoked nothing itself but has an older record of node_1
+        let mut next = CertificateAuthority::open(ca_dir(), 30, 7).unwrap();
+        let mut stale = leader.issued().clone();
+        stale.values_mut().for_each(|issued| issued.revoked = false);
+        next.merge(stale).unwrap();
+        next.merge(leader.issued().clone()).unwrap();
+        assert!(next.verify(&revoked).is_err());
+        assert!(next.verify(&kept).is_ok());
+    }
+}
@@ -1,3 +1,6 @@
+use crate::node::Node;
+use crate::task::Task;
+
 pub struct CommunicationLayer;
 
 impl CommunicationLayer {
@@ -15,7 +18,7 @@ impl CommunicationLayer {
     }
 
     // Send task/data to another node
-    pub fn send_task(node_id: &str, task_data: Vec<u8>) {
+    pub fn send_task(node_id: &str, _task_data: Vec<u8>) {
         println!("Sending task to Node ID: {}", node_id);
         // Implement gRPC/WebSocket communication logic to send tasks
     }
@@ -24,8 +27,8 @@ impl CommunicationLayer {
     pub fn receive_task() -> Result<Vec<u8>, String> {
         // Implement gRPC/WebSocket receiving logic
         println!("Receiving task...");
-        let task_data = ...; // Replace with actual receiving code
-        Ok(task_data)
+        // Tasks reach nodes through NodeService::AssignTask instead
+        Err("Receiving tasks from other nodes is not supported".to_string())
     }
 
     // Notify the system that a task has been completed
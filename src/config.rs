@@ -0,0 +1,769 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use crate::controller_grpc_client::node::ReloadConfigResponse;
+use crate::node_controller::FailureDetectorConfig;
+use crate::strategy_switcher::SwitchingRules;
+use crate::task_queue::AgingPolicy;
+
+// Default location of the config file, relative to the working directory
+pub const DEFAULT_CONFIG_PATH: &str = "config/config.toml";
+// Environment overrides look like DC_SCHEDULER__TICK_MS=500 (sections separated by "__")
+const ENV_PREFIX: &str = "DC_";
+
+// How often running processes check the config file for changes
+pub const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+// Keys a running process applies on reload; changes to anything else need a restart
+const LIVE_KEYS: &[&str] = &[
+    "scheduler",
+    "heartbeat.failure_detector",
+    "retries",
+    "ipfs.inline_output_limit",
+    "ipfs.output_tail_bytes",
+    "executor.allowed_commands",
+    "sell_limits.ram_mb",
+    "sell_limits.cpu_percent",
+    "sell_limits.bandwidth_mbps",
+    "sell_limits.storage_gb",
+];
+
+// Keys each binary reads; changes outside them are ignored on reload
+pub const CONTROLLER_KEYS: &[&str] = &[
+    "network.controller_listen",
+    "network.jobs_listen",
+    "network.nodes",
+    "redis",
+    "ha",
+    "tls",
+    "accounts",
+    "oidc",
+    "ipfs",
+    "scheduler",
+    "heartbeat.failure_detector",
+    "retries",
+    "state",
+];
+pub const NODE_AGENT_KEYS: &[&str] = &[
+    "network.controller_url",
+    "network.standby_controllers",
+    "network.enroll_url",
+    "network.node_listen",
+    "node",
+    "heartbeat.interval_secs",
+    "heartbeat.progress_retry_secs",
+    "heartbeat.sample_interval_secs",
+    "sell_limits",
+    "executor",
+    "tls",
+];
+
+// Whole system configuration: file, then DC_* environment variables, then CLI overrides
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub network: NetworkConfig,
+    pub node: NodeConfig,
+    pub redis: RedisConfig,
+    pub ipfs: IpfsConfig,
+    pub scheduler: SchedulerConfig,
+    pub heartbeat: HeartbeatConfig,
+    pub retries: RetryConfig,
+    pub sell_limits: SellLimitsConfig,
+    pub executor: ExecutorConfig,
+    pub state: StateConfig,
+    pub ha: HaConfig,
+    pub tls: TlsConfig,
+    pub accounts: AccountsConfig,
+    pub oidc: OidcConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkConfig {
+    pub controller_listen: String,        // ControllerService bind address
+    pub jobs_listen: String,              // Public JobService bind address
+    pub controller_url: String,           // Where node agents reach the controller
+    pub standby_controllers: Vec<String>, // Other controller replicas, tried when controller_url is down
+    pub enroll_url: String,               // Controller's EnrollmentService (on jobs_listen), for registration and certificates
+    pub node_listen: String,              // NodeService bind address on a node agent
+    pub nodes: Vec<String>,               // NodeService endpoints the controller connects to
+    pub websocket_port: u16,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        NetworkConfig {
+            controller_listen: "[::1]:50050".to_string(),
+            jobs_listen: "[::1]:50060".to_string(),
+            controller_url: "http://[::1]:50050".to_string(),
+            standby_controllers: Vec::new(),
+            enroll_url: "http://[::1]:50060".to_string(),
+            node_listen: "[::1]:50051".to_string(),
+            nodes: vec![
+                "http://[::1]:50051".to_string(),
+                "http://[::1]:50052".to_string(),
+                "http://[::1]:50053".to_string(),
+            ],
+            websocket_port: 9001,
+        }
+    }
+}
+
+// Identity of a node agent
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NodeConfig {
+    pub id: String,                // Empty: register with join_token and use the ID the controller assigns
+    pub storage_path: String,      // Filesystem whose free space is sold as storage
+    pub join_token: String,        // One-time token from `dcctl join-token create`
+    pub advertise_url: String,     // Where the controller reaches this node; empty derives it from node_listen
+    pub registration_file: String, // Node ID assigned at registration, reused on restart
+}
+
+impl Default for NodeConfig {
+    fn default() -> Self {
+        NodeConfig {
+            id: String::new(),
+            storage_path: "/".to_string(),
+            join_token: String::new(),
+            advertise_url: String::new(),
+            registration_file: "data/node/registration.json".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RedisConfig {
+    pub enabled: bool,      // Mirror controller state into Redis so replicas can share it
+    pub url: String,
+    pub key_prefix: String, // Lets several clusters share one Redis
+    pub pool_size: usize,
+    pub node_ttl_secs: u64, // Node status expires unless refreshed within this time
+}
+
+impl Default for RedisConfig {
+    fn default() -> Self {
+        RedisConfig {
+            enabled: false,
+            url: "redis://127.0.0.1/".to_string(),
+            key_prefix: "dc:".to_string(),
+            pool_size: 4,
+            node_ttl_secs: 15,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IpfsConfig {
+    pub api_url: String,
+    pub inline_output_limit: usize, // Task outputs above this many bytes are stored in IPFS
+    pub output_tail_bytes: usize,   // Tail of stdout/stderr kept with every result
+}
+
+impl Default for IpfsConfig {
+    fn default() -> Self {
+        IpfsConfig { api_url: "http://localhost:5001".to_string(), inline_output_limit: 64 * 1024, output_tail_bytes: 4 * 1024 }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SchedulerConfig {
+    pub tick_ms: u64,      // How often queued tasks are placed
+    pub strategy: String,  // Initial placement strategy
+    pub switching: SwitchingRules,
+    pub aging: AgingPolicy,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        SchedulerConfig {
+            tick_ms: 1000,
+            strategy: "priority".to_string(),
+            switching: SwitchingRules::default(),
+            aging: AgingPolicy::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HeartbeatConfig {
+    pub interval_secs: u64,        // How often node agents send heartbeats
+    pub progress_retry_secs: u64,  // Delay before a node agent reconnects its progress stream
+    pub sample_interval_secs: u64, // How often node agents sample host resources
+    pub failure_detector: FailureDetectorConfig,
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        HeartbeatConfig {
+            interval_secs: 5,
+            progress_retry_secs: 5,
+            sample_interval_secs: 5,
+            failure_detector: FailureDetectorConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryConfig {
+    pub max_retries: u32, // For jobs that don't set their own
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        RetryConfig { max_retries: 3 }
+    }
+}
+
+// What a node agent offers at most
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SellLimitsConfig {
+    pub ram_mb: u64,
+    pub cpu_percent: u64, // % of one core; 200 = two cores
+    pub bandwidth_mbps: u64,
+    pub storage_gb: u64,
+    pub max_throttled_percent: f64, // Kill tasks throttled more than this (100 = never kill for CPU)
+}
+
+impl Default for SellLimitsConfig {
+    fn default() -> Self {
+        SellLimitsConfig { ram_mb: 1024, cpu_percent: 100, bandwidth_mbps: 10, storage_gb: 0, max_throttled_percent: 100.0 }
+    }
+}
+
+// Node agent: what assigned tasks may run on the host
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExecutorConfig {
+    pub allowed_commands: Vec<String>, // Programs subprocess tasks may run; empty runs only wasm and in-process tasks
+}
+
+// Controller journal and snapshots, used to recover queued and running tasks after a crash
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StateConfig {
+    pub dir: String,         // Empty keeps state in memory only
+    pub snapshot_every: u64, // Journal entries between snapshots
+    pub fsync: bool,         // Sync each entry to disk before acting on it
+}
+
+impl Default for StateConfig {
+    fn default() -> Self {
+        StateConfig { dir: "data/controller".to_string(), snapshot_every: 1000, fsync: true }
+    }
+}
+
+// Controller replicas: one leader schedules, the others stand by (needs Redis)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HaConfig {
+    pub enabled: bool,
+    pub replica_id: String,         // Empty uses <hostname>-<pid>
+    pub lease_ms: u64,              // A dead leader is replaced within about this long
+    pub advertise_url: String,      // ControllerService URL followers hand out; empty derives it from controller_listen
+    pub advertise_jobs_url: String, // JobService URL, likewise from jobs_listen
+}
+
+impl Default for HaConfig {
+    fn default() -> Self {
+        HaConfig {
+            enabled: false,
+            replica_id: String::new(),
+            lease_ms: 5000,
+            advertise_url: String::new(),
+            advertise_jobs_url: String::new(),
+        }
+    }
+}
+
+// Mutual TLS between controller and node agents, with certificates from the controller's CA
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    pub enabled: bool,
+    pub ca_dir: String,           // Controller: CA key and certificate, and the certificates it issued
+    pub dir: String,              // Node agent: the CA certificate (copied from ca_dir), its own key and certificate
+    pub enrollment_token: String, // Shared secret a node agent presents to get its first certificate
+    pub cert_days: u32,           // Lifetime of issued certificates
+    pub renew_before_days: u32,   // Node agents renew once less than this is left
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        TlsConfig {
+            enabled: false,
+            ca_dir: "data/ca".to_string(),
+            dir: "data/tls".to_string(),
+            enrollment_token: String::new(),
+            cert_days: 30,
+            renew_before_days: 10,
+        }
+    }
+}
+
+// Controller: user accounts, sessions, API and join tokens, and registered nodes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccountsConfig {
+    pub file: String,
+    pub join_token_ttl_secs: u64, // Default lifetime of a join token
+    pub session_ttl_secs: u64,    // Lifetime of a login session
+    pub require_auth: bool,       // Reject JobService calls without a session or API token
+}
+
+impl Default for AccountsConfig {
+    fn default() -> Self {
+        AccountsConfig { file: "data/accounts.json".to_string(), join_token_ttl_secs: 86400, session_ttl_secs: 43200, require_auth: true }
+    }
+}
+
+// Controller: single sign-on with an external OpenID Connect provider
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OidcConfig {
+    pub enabled: bool,
+    pub issuer_url: String,           // Discovery document is read from <issuer_url>/.well-known/openid-configuration
+    pub client_id: String,
+    pub client_secret: String,        // Empty for public clients
+    pub redirect_url: String,         // Where the provider sends the dashboard back to after login
+    pub scopes: Vec<String>,
+    pub auto_create_role: String,     // Role for unknown users on first login ("buyer" or "seller"); empty = no sign-up
+    pub allowed_domains: Vec<String>, // Email domains allowed to sign up; empty = any
+}
+
+impl Default for OidcConfig {
+    fn default() -> Self {
+        OidcConfig {
+            enabled: false,
+            issuer_url: String::new(),
+            client_id: String::new(),
+            client_secret: String::new(),
+            redirect_url: "http://localhost:8400/callback".to_string(),
+            scopes: vec!["openid".to_string(), "email".to_string(), "profile".to_string()],
+            auto_create_role: String::new(),
+            allowed_domains: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    // Load the file (if it exists), apply DC_* environment variables, then `overrides`
+    // ("section.key" = "value" pairs from the command line), and validate the result
+    pub fn load(path: &Path, overrides: &[(String, String)]) -> Result<Config, String> {
+        let mut root = match fs::read_to_string(path) {
+            Ok(contents) => contents.parse::<toml::Table>().map_err(|e| format!("{}: {}", path.display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => toml::Table::new(),
+            Err(e) => return Err(format!("Could not read {}: {}", path.display(), e)),
+        };
+
+        for (name, value) in std::env::vars() {
+            if let Some(key) = name.strip_prefix(ENV_PREFIX) {
+                if key == "CONFIG" {
+                    continue;
+                }
+                let key = key.to_lowercase().replace("__", ".");
+                set_value(&mut root, &key, &value).map_err(|e| format!("{}: {}", name, e))?;
+            }
+        }
+        for (key, value) in overrides {
+            set_value(&mut root, key, value).map_err(|e| format!("--set {}: {}", key, e))?;
+        }
+
+        let config = Config::deserialize(toml::Value::Table(root)).map_err(|e| format!("Invalid configuration: {}", e))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    // Config file path from DC_CONFIG, falling back to config/config.toml
+    pub fn default_path() -> String {
+        std::env::var("DC_CONFIG").unwrap_or_else(|_| DEFAULT_CONFIG_PATH.to_string())
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, addr) in [
+            ("network.controller_listen", &self.network.controller_listen),
+            ("network.jobs_listen", &self.network.jobs_listen),
+            ("network.node_listen", &self.network.node_listen),
+        ] {
+            addr.parse::<SocketAddr>().map_err(|_| format!("{} is not a socket address: {}", name, addr))?;
+        }
+        for url in std::iter::once(&self.network.controller_url)
+            .chain(&self.network.standby_controllers)
+            .chain(&self.network.nodes)
+        {
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                return Err(format!("gRPC endpoint must start with http:// or https://: {}", url));
+            }
+        }
+        if !self.redis.url.starts_with("redis://") && !self.redis.url.starts_with("rediss://") {
+            return Err(format!("redis.url must start with redis:// or rediss://: {}", self.redis.url));
+        }
+        if self.redis.pool_size == 0 || self.redis.node_ttl_secs == 0 {
+            return Err("redis.pool_size and redis.node_ttl_secs must be positive".to_string());
+        }
+        if !self.network.enroll_url.starts_with("http://") && !self.network.enroll_url.starts_with("https://") {
+            return Err(format!("gRPC endpoint must start with http:// or https://: {}", self.network.enroll_url));
+        }
+        if self.tls.enabled {
+            let plaintext = std::iter::once(&self.network.controller_url)
+                .chain(&self.network.standby_controllers)
+                .chain(&self.network.nodes)
+                .chain(std::iter::once(&self.network.enroll_url))
+                .find(|url| !url.starts_with("https://"));
+            if let Some(url) = plaintext {
+                return Err(format!("tls.enabled needs https:// endpoints: {}", url));
+            }
+            if self.tls.renew_before_days >= self.tls.cert_days {
+                return Err("tls.renew_before_days must be less than tls.cert_days".to_string());
+            }
+        }
+        let advertise_url = &self.node.advertise_url;
+        if !advertise_url.is_empty() && !advertise_url.starts_with("http://") && !advertise_url.starts_with("https://") {
+            return Err(format!("node.advertise_url must start with http:// or https://: {}", advertise_url));
+        }
+        if self.accounts.join_token_ttl_secs == 0 || self.accounts.session_ttl_secs == 0 {
+            return Err("accounts.join_token_ttl_secs and accounts.session_ttl_secs must be positive".to_string());
+        }
+        if self.oidc.enabled {
+            if !self.oidc.issuer_url.starts_with("http://") && !self.oidc.issuer_url.starts_with("https://") {
+                return Err(format!("oidc.issuer_url must start with http:// or https://: {}", self.oidc.issuer_url));
+            }
+            if self.oidc.client_id.is_empty() {
+                return Err("oidc.client_id is required".to_string());
+            }
+            if !self.oidc.scopes.iter().any(|scope| scope == "openid") {
+                return Err("oidc.scopes must include openid".to_string());
+            }
+            match self.oidc.auto_create_role.as_str() {
+                "" | "buyer" | "seller" => {}
+                other => return Err(format!("oidc.auto_create_role must be buyer, seller or empty: {}", other)),
+            }
+        }
+        if self.ha.enabled && !self.redis.enabled {
+            return Err("ha.enabled needs redis.enabled; replicas share state through Redis".to_string());
+        }
+        if self.ha.lease_ms < 300 {
+            return Err("ha.lease_ms must be at least 300".to_string());
+        }
+        for (name, url) in [("ha.advertise_url", &self.ha.advertise_url), ("ha.advertise_jobs_url", &self.ha.advertise_jobs_url)] {
+            if !url.is_empty() && !url.starts_with("http://") && !url.starts_with("https://") {
+                return Err(format!("{} must start with http:// or https://: {}", name, url));
+            }
+        }
+        if self.ipfs.output_tail_bytes > self.ipfs.inline_output_limit {
+            return Err("ipfs.output_tail_bytes cannot exceed ipfs.inline_output_limit".to_string());
+        }
+        if self.scheduler.tick_ms == 0 {
+            return Err("scheduler.tick_ms must be positive".to_string());
+        }
+        if self.heartbeat.interval_secs == 0 || self.heartbeat.sample_interval_secs == 0 {
+            return Err("heartbeat intervals must be positive".to_string());
+        }
+        let detector = &self.heartbeat.failure_detector;
+        if detector.suspect_phi <= 0.0 || detector.failed_phi <= detector.suspect_phi {
+            return Err("heartbeat.failure_detector needs 0 < suspect_phi < failed_phi".to_string());
+        }
+        if detector.window == 0 {
+            return Err("heartbeat.failure_detector.window must be positive".to_string());
+        }
+        if self.retries.max_retries == 0 {
+            return Err("retries.max_retries must be at least 1".to_string());
+        }
+        if self.state.snapshot_every == 0 {
+            return Err("state.snapshot_every must be positive".to_string());
+        }
+        if self.sell_limits.ram_mb == 0 || self.sell_limits.cpu_percent == 0 {
+            return Err("sell_limits.ram_mb and sell_limits.cpu_percent must be positive".to_string());
+        }
+        Ok(())
+    }
+}
+
+// Changed keys between two configurations, split by whether they apply without a restart
+#[derive(Debug, Clone, Default)]
+pub struct ConfigDiff {
+    pub applied: Vec<String>,
+    pub restart_required: Vec<String>,
+}
+
+impl ConfigDiff {
+    // Compare `old` and `new`, considering only keys under `relevant` (e.g. CONTROLLER_KEYS)
+    pub fn between(old: &Config, new: &Config, relevant: &[&str]) -> Self {
+        let (old, new) = (flatten(old), flatten(new));
+        let mut diff = ConfigDiff::default();
+        for (key, value) in &new {
+            if old.get(key) == Some(value) || !matches_any(key, relevant) {
+                continue;
+            }
+            if matches_any(key, LIVE_KEYS) {
+                diff.applied.push(key.clone());
+            } else {
+                diff.restart_required.push(key.clone());
+            }
+        }
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.applied.is_empty() && self.restart_required.is_empty()
+    }
+
+    // Whether an applied key is `prefix` or inside it
+    pub fn touches(&self, prefix: &str) -> bool {
+        self.applied.iter().any(|key| matches_any(key, &[prefix]))
+    }
+
+    pub fn to_response(&self) -> ReloadConfigResponse {
+        ReloadConfigResponse { applied: self.applied.clone(), restart_required: self.restart_required.clone() }
+    }
+}
+
+// `key` is `prefix` itself or inside it
+fn matches_any(key: &str, prefixes: &[&str]) -> bool {
+    prefixes.iter().any(|prefix| key == *prefix || key.strip_prefix(prefix).is_some_and(|rest| rest.starts_with('.')))
+}
+
+// Dotted key -> value for every setting; arrays (nodes, switching rules) are compared whole
+fn flatten(config: &Config) -> BTreeMap<String, toml::Value> {
+    fn walk(prefix: &str, table: &toml::Table, out: &mut BTreeMap<String, toml::Value>) {
+        for (key, value) in table {
+            let key = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+            match value {
+                toml::Value::Table(table) => walk(&key, table, out),
+                value => {
+                    out.insert(key, value.clone());
+                }
+            }
+        }
+    }
+    let mut out = BTreeMap::new();
+    if let Ok(toml::Value::Table(root)) = toml::Value::try_from(config) {
+        walk("", &root, &mut out);
+    }
+    out
+}
+
+// Where a process's configuration came from, so it can be read again on reload
+#[derive(Debug, Clone)]
+pub struct ConfigSource {
+    path: PathBuf,
+    overrides: Vec<(String, String)>, // Command-line overrides keep winning over the file
+}
+
+impl ConfigSource {
+    pub fn new(path: impl Into<PathBuf>, overrides: Vec<(String, String)>) -> Self {
+        ConfigSource { path: path.into(), overrides }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn load(&self) -> Result<Config, String> {
+        Config::load(&self.path, &self.overrides)
+    }
+
+    // Write numbers to "section.key"s in the file, keeping its comments and layout. The edited
+    // file has to load before it replaces the original; processes watching it then apply it.
+    pub fn update(&self, values: &[(&str, u64)]) -> Result<Config, String> {
+        let contents = match fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(format!("Could not read {}: {}", self.path.display(), e)),
+        };
+        let mut document = contents.parse::<toml_edit::Document>().map_err(|e| format!("{}: {}", self.path.display(), e))?;
+        for (key, value) in values {
+            let (section, name) = key.split_once('.').ok_or_else(|| format!("Expected section.key, got {}", key))?;
+            let value = i64::try_from(*value).map_err(|_| format!("{} is too large for {}", value, key))?;
+            document[section][name] = toml_edit::value(value);
+        }
+
+        let edited = self.path.with_extension("toml.new");
+        fs::write(&edited, document.to_string()).map_err(|e| format!("Could not write {}: {}", edited.display(), e))?;
+        let config = match Config::load(&edited, &self.overrides) {
+            Ok(config) => config,
+            Err(err) => {
+                let _ = fs::remove_file(&edited);
+                return Err(err);
+            }
+        };
+        fs::rename(&edited, &self.path).map_err(|e| format!("Could not replace {}: {}", self.path.display(), e))?;
+        Ok(config)
+    }
+
+    // Poll the file's modification time and send a notification whenever it changes
+    pub fn watch(&self, every: Duration) -> mpsc::Receiver<()> {
+        let (tx, rx) = mpsc::channel(1);
+        let path = self.path.clone();
+        tokio::spawn(async move {
+            let modified = |path: &Path| fs::metadata(path).and_then(|meta| meta.modified()).ok();
+            let mut last: Option<SystemTime> = modified(&path);
+            let mut ticker = tokio::time::interval(every);
+            loop {
+                ticker.tick().await;
+                let current = modified(&path);
+                if current == last {
+                    continue;
+                }
+                last = current;
+                // A full channel already has a reload pending
+                if let Err(mpsc::error::TrySendError::Closed(_)) = tx.try_send(()) {
+                    break;
+                }
+            }
+            warn!("Stopped watching {}", path.display());
+        });
+        rx
+    }
+}
+
+// Set a dotted key, creating tables on the way; the value is parsed as TOML and
+// falls back to a plain string (so both `500` and `[::1]:50051` work unquoted)
+fn set_value(root: &mut toml::Table, key: &str, raw: &str) -> Result<(), String> {
+    let value = format!("v = {}", raw)
+        .parse::<toml::Table>()
+        .ok()
+        .and_then(|mut table| table.remove("v"))
+        .unwrap_or_else(|| toml::Value::String(raw.to_string()));
+
+    let mut parts: Vec<&str> = key.split('.').collect();
+    let last = parts.pop().filter(|last| !last.is_empty()).ok_or("empty key")?;
+    let mut table = root;
+    for part in parts {
+        table = table
+            .entry(part.to_string())
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+            .as_table_mut()
+            .ok_or_else(|| format!("{} is not a section", part))?;
+    }
+    table.insert(last.to_string(), value);
+    Ok(())
+}
+
+// Parse "section.key=value" command-line overrides
+pub fn parse_override(arg: &str) -> Result<(String, String), String> {
+    arg.split_once('=')
+        .map(|(key, value)| (key.trim().to_string(), value.to_string()))
+        .ok_or_else(|| format!("expected section.key=value, got {}", arg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    // Fresh config file path for each test
+    fn config_path() -> PathBuf {
+        static NEXT: AtomicU32 = AtomicU32::new(0);
+        let name = format!("dc_config_{}_{}.toml", std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed));
+        let path = std::env::temp_dir().join(name);
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn set(key: &str, value: &str) -> (String, String) {
+        (key.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn a_missing_file_loads_the_defaults() {
+        let config = Config::load(&config_path(), &[]).unwrap();
+        assert_eq!(config.scheduler.tick_ms, 1000);
+        assert_eq!(config.network.nodes.len(), 3);
+    }
+
+    #[test]
+    fn overrides_win_over_the_file() {
+        let path = config_path();
+        fs::write(&path, "[scheduler]\ntick_ms = 250\nstrategy = \"first_fit\"\n\n[network]\ncontroller_listen = \"0.0.0.0:7000\"\n").unwrap();
+
+        let config = Config::load(&path, &[set("scheduler.tick_ms", "500"), set("network.jobs_listen", "[::1]:7001")]).unwrap();
+        assert_eq!(config.scheduler.tick_ms, 500);
+        assert_eq!(config.scheduler.strategy, "first_fit");
+        assert_eq!(config.network.controller_listen, "0.0.0.0:7000");
+        // Values that are not valid TOML are taken as strings
+        assert_eq!(config.network.jobs_listen, "[::1]:7001");
+    }
+
+    #[test]
+    fn unknown_keys_and_invalid_values_are_rejected() {
+        let path = config_path();
+        fs::write(&path, "[scheduler]\ntick_msec = 250\n").unwrap();
+        assert!(Config::load(&path, &[]).is_err());
+
+        let none = config_path();
+        assert!(Config::load(&none, &[set("scheduler.tick_ms", "0")]).is_err());
+        assert!(Config::load(&none, &[set("network.controller_listen", "localhost")]).is_err());
+        assert!(Config::load(&none, &[set("ha.enabled", "true")]).is_err());
+        assert!(Config::load(&none, &[set("scheduler.tick_ms.fast", "1")]).is_err());
+    }
+
+    #[test]
+    fn diffs_split_live_keys_from_restart_keys() {
+        let old = Config::default();
+        let mut new = old.clone();
+        new.scheduler.tick_ms = 250;
+        new.sell_limits.ram_mb = 4096;
+        new.redis.pool_size = 8;
+
+        let controller = ConfigDiff::between(&old, &new, CONTROLLER_KEYS);
+        assert_eq!(controller.applied, ["scheduler.tick_ms"]);
+        assert_eq!(controller.restart_required, ["redis.pool_size"]);
+        assert!(controller.touches("scheduler") && !controller.touches("sell_limits"));
+
+        // Node agents ignore scheduler and Redis settings
+        let node_agent = ConfigDiff::between(&old, &new, NODE_AGENT_KEYS);
+        assert_eq!(node_agent.applied, ["sell_limits.ram_mb"]);
+        assert!(node_agent.restart_required.is_empty());
+        assert!(ConfigDiff::between(&old, &old.clone(), CONTROLLER_KEYS).is_empty());
+    }
+
+    #[test]
+    fn updates_keep_comments_and_refuse_invalid_values() {
+        let path = config_path();
+        fs::write(&path, "# Sold to the cluster\n[sell_limits]\nram_mb = 1024 # half the host\n").unwrap();
+        let source = ConfigSource::new(&path, Vec::new());
+
+        let config = source.update(&[("sell_limits.ram_mb", 2048), ("sell_limits.cpu_percent", 50)]).unwrap();
+        assert_eq!((config.sell_limits.ram_mb, config.sell_limits.cpu_percent), (2048, 50));
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(contents.contains("# Sold to the cluster") && contents.contains("ram_mb = 2048"), "{}", contents);
+
+        assert!(source.update(&[("sell_limits.ram_mb", 0)]).is_err());
+        assert!(source.update(&[("sell_limits", 1)]).is_err());
+        assert_eq!(source.load().unwrap().sell_limits.ram_mb, 2048);
+        assert!(!path.with_extension("toml.new").exists());
+    }
+
+    #[tokio::test]
+    async fn the_watcher_notices_changes_to_the_file() {
+        let path = config_path();
+        fs::write(&path, "[scheduler]\ntick_ms = 250\n").unwrap();
+        let source = ConfigSource::new(&path, Vec::new());
+        let mut changes = source.watch(Duration::from_millis(20));
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert!(changes.try_recv().is_err());
+
+        fs::remove_file(&path).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), changes.recv()).await.expect("no change noticed").unwrap();
+    }
+
+    #[test]
+    fn overrides_are_parsed_from_key_equals_value() {
+        assert_eq!(parse_override("retries.max_retries=5").unwrap(), set("retries.max_retries", "5"));
+        assert_eq!(parse_override("network.controller_url=http://a:1/?x=y").unwrap(), set("network.controller_url", "http://a:1/?x=y"));
+        assert!(parse_override("retries.max_retries").is_err());
+    }
+}
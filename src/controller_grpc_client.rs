@@ -1,11 +1,16 @@
+use std::time::Duration;
 use tonic::transport::Channel;
+use tonic::Streaming;
 use node::node_service_client::NodeServiceClient;
-use node::{HeartbeatRequest, TaskRequest};
+use node::{CancelTaskRequest, HeartbeatRequest, NodeStatusRequest, NodeStatusResponse, PreemptTaskRequest, TaskEvent, TaskRequest, TaskStatusRequest, TaskStatusResponse, WatchTasksRequest};
+use crate::executor::ExecutorKind;
+use crate::tls::{self, TlsSettings, NODE_SERVER_NAME};
 
 pub mod node {
     tonic::include_proto!("node");
 }
 
+#[derive(Debug, Clone)]
 pub struct NodeController {
     client: NodeServiceClient<Channel>,
 }
@@ -16,14 +21,26 @@ impl NodeController {
         Self { client }
     }
 
+    // Connect without panicking when the node is unreachable; over mutual TLS when `tls` is set
+    pub async fn connect(addr: String, tls: Option<&TlsSettings>) -> Result<Self, tonic::transport::Error> {
+        let channel = tls::connect(&addr, tls, NODE_SERVER_NAME).await?;
+        Ok(Self { client: NodeServiceClient::new(channel) })
+    }
+
+    // Capacity the node sells and what is currently allocated
+    pub async fn get_node_status(&mut self) -> Result<NodeStatusResponse, tonic::Status> {
+        let response = self.client.get_node_status(tonic::Request::new(NodeStatusRequest {})).await?;
+        Ok(response.into_inner())
+    }
+
     // Check node health
-    pub async fn check_node_health(&mut self, node_id: String) -> bool {
+    pub async fn check_node_health(&mut self, node_id: String) -> Result<bool, tonic::Status> {
         let request = tonic::Request::new(HeartbeatRequest { node_id });
-        let response = self.client.heartbeat(request).await.unwrap();
-        response.into_inner().healthy
+        let response = self.client.heartbeat(request).await?;
+        Ok(response.into_inner().healthy)
     }
 
-    // Assign task to node
+    // Assign task to node; Ok(false) when the node turns it down
     pub async fn assign_task_to_node(
         &mut self,
         task_id: String,
@@ -31,15 +48,70 @@ impl NodeController {
         cpu: u64,
         bandwidth: u64,
         data: Vec<u8>,
-    ) -> bool {
+        executor: &ExecutorKind,
+    ) -> Result<bool, tonic::Status> {
+        let (executor, command, args) = executor.to_request();
         let request = tonic::Request::new(TaskRequest {
             task_id,
             required_ram: ram,
             required_cpu: cpu,
             required_bandwidth: bandwidth,
             data,
+            executor,
+            command,
+            args,
         });
-        let response = self.client.assign_task(request).await.unwrap();
-        response.into_inner().success
+        let response = self.client.assign_task(request).await?;
+        Ok(response.into_inner().success)
+    }
+
+    // Get the state and exit status of a task running on the node
+    pub async fn get_task_status(&mut self, task_id: String) -> Result<TaskStatusResponse, tonic::Status> {
+        let request = tonic::Request::new(TaskStatusRequest { task_id });
+        let response = self.client.get_task_status(request).await?;
+        Ok(response.into_inner())
+    }
+
+    // Subscribe to state transitions of every task on the node
+    pub async fn watch_tasks(&mut self) -> Result<Streaming<TaskEvent>, tonic::Status> {
+        let request = tonic::Request::new(WatchTasksRequest { task_ids: Vec::new() });
+        let response = self.client.watch_tasks(request).await?;
+        Ok(response.into_inner())
+    }
+
+    // Stop a task running on the node
+    pub async fn cancel_task(&mut self, task_id: String) -> Result<bool, tonic::Status> {
+        let request = tonic::Request::new(CancelTaskRequest { task_id });
+        let response = self.client.cancel_task(request).await?;
+        Ok(response.into_inner().success)
+    }
+
+    // Ask the node to suspend a task; the run time lost, or None if it was no longer running there
+    pub async fn preempt_task(&mut self, task_id: String, reason: String) -> Result<Option<Duration>, tonic::Status> {
+        let request = tonic::Request::new(PreemptTaskRequest { task_id, reason });
+        let response = self.client.preempt_task(request).await?.into_inner();
+        Ok(response.success.then(|| Duration::from_millis(response.wasted_work_ms)))
+    }
+}
+
+#[cfg(test)]
+impl NodeController {
+    // Client for a node that refuses every connection
+    pub(crate) fn unreachable() -> Self {
+        let channel = tonic::transport::Endpoint::from_static("http://127.0.0.1:1").connect_lazy();
+        Self { client: NodeServiceClient::new(channel) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn unreachable_nodes_are_errors_not_panics() {
+        let mut client = NodeController::unreachable();
+        assert!(client.check_node_health("node_1".to_string()).await.is_err());
+        let assigned = client.assign_task_to_node("t1".to_string(), 1, 1, 0, Vec::new(), &ExecutorKind::InProcess).await;
+        assert_eq!(assigned.unwrap_err().code(), tonic::Code::Unavailable);
     }
 }
@@ -0,0 +1,296 @@
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, oneshot};
+use tonic::{Request, Response, Status};
+use crate::auth::Authenticator;
+use crate::certificate_authority::{CertificateAuthority, SharedRegistry};
+use crate::config::Config;
+use crate::controller_grpc_client::node::enrollment_service_client::EnrollmentServiceClient;
+use crate::controller_grpc_client::node::enrollment_service_server::EnrollmentService;
+use crate::controller_grpc_client::node::{EnrollRequest, NodeCertificate, RegisterNodeRequest, RegisterNodeResponse};
+use crate::executor::ExecutorKind;
+use crate::job_service::job::node_owner_service_server::NodeOwnerService;
+use crate::job_service::job::{ListEarningsRequest, ListEarningsResponse, NodeEarnings, NodeJoinToken, NodeJoinTokenRequest};
+use crate::job_service::{JobCommand, JobRecord, JobState};
+use crate::leader_election::Leadership;
+use crate::policy::{self, Action, Resource};
+use crate::task_scheduler::SchedulerEvent;
+use crate::tls::{self, certificate_request, write_file, NodeCredentials, TlsSettings, CONTROLLER_SERVER_NAME};
+use crate::user_manager::{NodeRegistration, Principal, UserManager};
+
+// EnrollmentService: lets node agents join the cluster. RegisterNode trades a seller's one-time
+// join token for a new node ID; Enroll certifies a node with an operator-assigned ID.
+// Only the leader signs certificates, so both are refused on followers.
+pub struct EnrollmentApi {
+    accounts: Arc<Mutex<UserManager>>,
+    authority: Option<Arc<Mutex<CertificateAuthority>>>, // Set when the controller runs TLS
+    shared_registry: Option<SharedRegistry>,             // Where issued certificates go for other replicas
+    token: String,                                       // Shared secret for Enroll
+    leadership: Leadership,
+}
+
+impl EnrollmentApi {
+    pub fn new(accounts: Arc<Mutex<UserManager>>) -> Self {
+        EnrollmentApi { accounts, authority: None, shared_registry: None, token: String::new(), leadership: Leadership::always() }
+    }
+
+    pub fn with_authority(mut self, authority: Arc<Mutex<CertificateAuthority>>, token: &str) -> Self {
+        self.authority = Some(authority);
+        self.token = token.to_string();
+        self
+    }
+
+    pub fn with_shared_registry(mut self, registry: SharedRegistry) -> Self {
+        self.shared_registry = Some(registry);
+        self
+    }
+
+    pub fn with_leadership(mut self, leadership: Leadership) -> Self {
+        self.leadership = leadership;
+        self
+    }
+
+    async fn publish_certificates(&self, authority: &Mutex<CertificateAuthority>) -> Result<(), Status> {
+        match &self.shared_registry {
+            Some(registry) => registry.publish(authority).await.map_err(Status::unavailable),
+            None => Ok(()),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl EnrollmentService for EnrollmentApi {
+    async fn enroll(&self, request: Request<EnrollRequest>) -> Result<Response<NodeCertificate>, Status> {
+        self.leadership.require_leader()?;
+        let request = request.into_inner();
+        let authority = self.authority.as_ref().ok_or_else(|| Status::failed_precondition("TLS is not enabled on this controller"))?;
+        if self.token.is_empty() {
+            return Err(Status::failed_precondition("Enrollment is disabled: tls.enrollment_token is not set"));
+        }
+        if !constant_time_eq(request.enrollment_token.as_bytes(), self.token.as_bytes()) {
+            warn!("Rejected enrollment of node {} with a wrong token", request.node_id);
+            return Err(Status::unauthenticated("Wrong enrollment token"));
+        }
+        if request.node_id.is_empty() {
+            return Err(Status::invalid_argument("node_id is required"));
+        }
+        let issued = authority.lock().unwrap().issue_node(&request.node_id, &request.csr_pem).map_err(Status::invalid_argument)?;
+        self.publish_certificates(authority).await?;
+        info!("Enrolled node {}", request.node_id);
+        Ok(Response::new(issued))
+    }
+
+    async fn register_node(&self, request: Request<RegisterNodeRequest>) -> Result<Response<RegisterNodeResponse>, Status> {
+        self.leadership.require_leader()?;
+        let request = request.into_inner();
+        if !request.node_url.starts_with("http://") && !request.node_url.starts_with("https://") {
+            return Err(Status::invalid_argument(format!("node_url must start with http:// or https://: {}", request.node_url)));
+        }
+        if request.sell_ram_mb == 0 || request.sell_cpu_percent == 0 {
+            return Err(Status::invalid_argument("A node must sell some RAM and CPU"));
+        }
+        for executor in &request.executors {
+            ExecutorKind::from_request(executor, String::new(), Vec::new()).map_err(Status::invalid_argument)?;
+        }
+        // Reject a bad signing request before the join token is spent on it
+        if let Some(authority) = &self.authority {
+            if request.csr_pem.is_empty() {
+                return Err(Status::invalid_argument("csr_pem is required, this controller runs TLS"));
+            }
+            authority.lock().unwrap().check_request(&request.csr_pem).map_err(Status::invalid_argument)?;
+        }
+
+        let declared = NodeRegistration {
+            node_id: String::new(),
+            owner_id: String::new(),
+            node_url: request.node_url,
+            executors: request.executors,
+            enforced: request.enforced,
+            ram_mb: request.sell_ram_mb,
+            cpu_percent: request.sell_cpu_percent,
+            bandwidth_mbps: request.sell_bandwidth_mbps,
+            storage_gb: request.sell_storage_gb,
+            registered_at: 0,
+        };
+        let registration = self.accounts.lock().unwrap().register_node(&request.join_token, declared).map_err(|err| {
+            warn!("Rejected node registration: {}", err);
+            Status::permission_denied(err)
+        })?;
+
+        let certificate = match &self.authority {
+            Some(authority) => {
+                let issued = authority.lock().unwrap().issue_node(&registration.node_id, &request.csr_pem).map_err(Status::internal)?;
+                // The join token is spent by now; if the leader changes before the next publish, the node enrolls again
+                if let Err(status) = self.publish_certificates(authority).await {
+                    warn!("Node {} registered, but {}", registration.node_id, status.message());
+                }
+                Some(issued)
+            }
+            None => None,
+        };
+        Ok(Response::new(RegisterNodeResponse { node_id: registration.node_id, owner_id: registration.owner_id, certificate }))
+    }
+}
+
+// NodeOwnerService: sellers create join tokens for their own nodes and see what those nodes earned
+pub struct NodeOwnerApi {
+    accounts: Arc<Mutex<UserManager>>,
+    auth: Authenticator,
+    scheduler: mpsc::Sender<SchedulerEvent>, // Knows which node ran each job
+    leadership: Leadership,
+    join_token_ttl: Duration,
+}
+
+impl NodeOwnerApi {
+    pub fn new(accounts: Arc<Mutex<UserManager>>, auth: Authenticator, scheduler: mpsc::Sender<SchedulerEvent>, join_token_ttl: Duration) -> Self {
+        NodeOwnerApi { accounts, auth, scheduler, leadership: Leadership::always(), join_token_ttl }
+    }
+
+    pub fn with_leadership(mut self, leadership: Leadership) -> Self {
+        self.leadership = leadership;
+        self
+    }
+
+    async fn succeeded_jobs(&self) -> Result<Vec<JobRecord>, Status> {
+        self.leadership.require_leader()?;
+        let (reply, response) = oneshot::channel();
+        self.scheduler
+            .send(SchedulerEvent::Job(JobCommand::List { owner: None, state: Some(JobState::Succeeded), reply }))
+            .await
+            .map_err(|_| Status::unavailable("Scheduler is shut down"))?;
+        response.await.map_err(|_| Status::internal("Scheduler dropped the request"))
+    }
+}
+
+// The account a request is about: `owner_id`, or the caller when that is empty
+fn owner_or_caller(principal: &Option<Principal>, owner_id: String) -> Result<String, Status> {
+    match principal {
+        _ if !owner_id.is_empty() => Ok(owner_id),
+        Some(principal) => Ok(principal.user_id.clone()),
+        None => Err(Status::invalid_argument("owner_id is required for anonymous requests")),
+    }
+}
+
+#[tonic::async_trait]
+impl NodeOwnerService for NodeOwnerApi {
+    async fn create_join_token(&self, request: Request<NodeJoinTokenRequest>) -> Result<Response<NodeJoinToken>, Status> {
+        let principal = self.auth.authenticate(&request)?;
+        let request = request.into_inner();
+        let owner_id = owner_or_caller(&principal, request.owner_id)?;
+        policy::enforce(principal.as_ref(), Action::CreateJoinToken, Resource::Account { user_id: &owner_id })?;
+        let ttl = if request.ttl_secs == 0 { self.join_token_ttl } else { Duration::from_secs(request.ttl_secs) };
+        let (token, expires_at) = self.accounts.lock().unwrap().create_join_token(&owner_id, ttl).map_err(Status::failed_precondition)?;
+        info!("Created a join token for user {}", owner_id);
+        Ok(Response::new(NodeJoinToken { token, owner_id, expires_at: expires_at as i64 }))
+    }
+
+    async fn list_earnings(&self, request: Request<ListEarningsRequest>) -> Result<Response<ListEarningsResponse>, Status> {
+        let principal = self.auth.authenticate(&request)?;
+        let owner_id = owner_or_caller(&principal, request.into_inner().owner_id)?;
+        policy::enforce(principal.as_ref(), Action::ReadEarnings, Resource::Node { owner: &owner_id })?;
+        let succeeded = self.succeeded_jobs().await?;
+        let accounts = self.accounts.lock().unwrap();
+        let nodes = accounts
+            .nodes_of(&owner_id)
+            .into_iter()
+            .map(|node| NodeEarnings {
+                node_id: node.node_id.clone(),
+                owner_id: node.owner_id.clone(),
+                node_url: node.node_url.clone(),
+                sell_ram_mb: node.ram_mb,
+                sell_cpu_percent: node.cpu_percent,
+                sell_bandwidth_mbps: node.bandwidth_mbps,
+                sell_storage_gb: node.storage_gb,
+                registered_at: node.registered_at as i64,
+                jobs_completed: succeeded.iter().filter(|job| job.node_id.as_deref() == Some(node.node_id.as_str())).count() as u64,
+            })
+            .collect();
+        Ok(Response::new(ListEarningsResponse { nodes }))
+    }
+}
+
+// Compare secrets without leaking how much of them matched through timing
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+// Saved by a node agent once registered, so it keeps its ID across restarts
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Registration {
+    pub node_id: String,
+    pub owner_id: String,
+}
+
+impl Registration {
+    pub fn load(path: &Path) -> Result<Option<Self>, String> {
+        match fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents).map(Some).map_err(|e| format!("Corrupt {}: {}", path.display(), e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("Could not read {}: {}", path.display(), e)),
+        }
+    }
+
+    fn save(&self, path: &Path) -> Result<(), String> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).map_err(|e| format!("Could not create {}: {}", dir.display(), e))?;
+        }
+        let contents = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        write_file(path, &contents, 0o644)
+    }
+}
+
+// Executor kinds this node runs; subprocess only when some command is allowed
+fn executors(config: &Config) -> Vec<String> {
+    let subprocess = if config.executor.allowed_commands.is_empty() { None } else { Some("subprocess") };
+    subprocess.into_iter().chain(["wasm", "in_process"]).map(|name| name.to_string()).collect()
+}
+
+// Register this node agent with node.join_token, declaring what it sells. Stores the assigned
+// node ID in node.registration_file and, with TLS, the issued certificate in tls.dir.
+pub async fn register_node(config: &Config, node_url: &str, enforced: bool) -> Result<Registration, String> {
+    let (ca, key_pem, csr_pem) = if config.tls.enabled {
+        let dir = Path::new(&config.tls.dir);
+        let ca = TlsSettings::load(dir).map_err(|e| format!("{} (copy the controller's CA certificate there first)", e))?;
+        // The controller names the node; the subject asked for here is replaced
+        let (csr_pem, key_pem) = certificate_request("unregistered")?;
+        (Some(ca), key_pem, csr_pem)
+    } else {
+        (None, String::new(), String::new())
+    };
+
+    let limits = &config.sell_limits;
+    let request = RegisterNodeRequest {
+        join_token: config.node.join_token.clone(),
+        node_url: node_url.to_string(),
+        executors: executors(config),
+        enforced,
+        sell_ram_mb: limits.ram_mb,
+        sell_cpu_percent: limits.cpu_percent,
+        sell_bandwidth_mbps: limits.bandwidth_mbps,
+        sell_storage_gb: limits.storage_gb,
+        csr_pem,
+    };
+    let enroll_url = &config.network.enroll_url;
+    let channel = tls::connect(enroll_url, ca.as_ref(), CONTROLLER_SERVER_NAME)
+        .await
+        .map_err(|e| format!("Could not reach {} to register: {}", enroll_url, e))?;
+    let response = EnrollmentServiceClient::new(channel)
+        .register_node(request)
+        .await
+        .map_err(|status| format!("Registration rejected: {}", status.message()))?
+        .into_inner();
+
+    if let Some(certificate) = response.certificate {
+        NodeCredentials::from_issued(&config.tls.dir, certificate, key_pem)?;
+    } else if config.tls.enabled {
+        return Err("The controller issued no certificate; is TLS enabled there?".to_string());
+    }
+    let registration = Registration { node_id: response.node_id, owner_id: response.owner_id };
+    registration.save(Path::new(&config.node.registration_file))?;
+    info!("Registered as node {} of user {}", registration.node_id, registration.owner_id);
+    Ok(registration)
+}
@@ -0,0 +1,360 @@
+use std::os::unix::io::AsRawFd;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+use log::warn;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::process::Command;
+use tokio::time::interval;
+use wasmtime::{Config, Engine, Instance, Module, Store, StoreLimits, StoreLimitsBuilder};
+use crate::resource_enforcer::{CgroupEnforcer, ResourceUsage};
+use crate::task::Task;
+
+// WASM fuel granted per 1% of required CPU
+const FUEL_PER_CPU_PERCENT: u64 = 10_000_000;
+// How often enforced subprocesses are checked against their limits
+const ENFORCEMENT_INTERVAL: Duration = Duration::from_millis(500);
+
+// How a task is run on the node
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ExecutorKind {
+    // Local program, task payload written to stdin
+    Subprocess { command: String, args: Vec<String> },
+    // WebAssembly module carried in the task payload; `entry` is an exported `() -> i32` function
+    Wasm { entry: String },
+    // Runs inside the node process (tests and simulations)
+    InProcess,
+}
+
+impl ExecutorKind {
+    // Name used on the wire in TaskRequest.executor
+    pub fn name(&self) -> &'static str {
+        match self {
+            ExecutorKind::Subprocess { .. } => "subprocess",
+            ExecutorKind::Wasm { .. } => "wasm",
+            ExecutorKind::InProcess => "in_process",
+        }
+    }
+
+    // Rebuild from TaskRequest fields (`command` is the WASM entry for wasm tasks)
+    pub fn from_request(executor: &str, command: String, args: Vec<String>) -> Result<Self, String> {
+        match executor {
+            "subprocess" => Ok(ExecutorKind::Subprocess { command, args }),
+            "wasm" => Ok(ExecutorKind::Wasm { entry: if command.is_empty() { "run".to_string() } else { command } }),
+            "in_process" | "" => Ok(ExecutorKind::InProcess),
+            other => Err(format!("Unknown executor kind: {}", other)),
+        }
+    }
+
+    // Split into TaskRequest fields (executor, command, args)
+    pub fn to_request(&self) -> (String, String, Vec<String>) {
+        match self {
+            ExecutorKind::Subprocess { command, args } => (self.name().to_string(), command.clone(), args.clone()),
+            ExecutorKind::Wasm { entry } => (self.name().to_string(), entry.clone(), Vec::new()),
+            ExecutorKind::InProcess => (self.name().to_string(), String::new(), Vec::new()),
+        }
+    }
+}
+
+// What a task produced
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct TaskOutput {
+    pub exit_code: i32,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    pub runtime: Duration,
+    pub usage: Option<ResourceUsage>, // Last measured usage, when limits were enforced
+}
+
+impl TaskOutput {
+    pub fn success(&self) -> bool {
+        self.exit_code == 0
+    }
+}
+
+#[tonic::async_trait]
+pub trait TaskExecutor: Send + Sync {
+    // Run the task to completion; Err means the executor itself failed (not a non-zero exit)
+    async fn execute(&self, task: &Task) -> Result<TaskOutput, String>;
+}
+
+// Pick the executor declared by the task; subprocesses run under the enforcer when one is given
+pub fn executor_for(kind: &ExecutorKind, enforcer: Option<Arc<CgroupEnforcer>>) -> Box<dyn TaskExecutor> {
+    match kind {
+        ExecutorKind::Subprocess { command, args } => Box::new(SubprocessExecutor {
+            command: command.clone(),
+            args: args.clone(),
+            enforcer,
+        }),
+        ExecutorKind::Wasm { entry } => Box::new(WasmExecutor { entry: entry.clone() }),
+        ExecutorKind::InProcess => Box::new(InProcessExecutor::echo()),
+    }
+}
+
+pub struct SubprocessExecutor {
+    pub command: String,
+    pub args: Vec<String>,
+    pub enforcer: Option<Arc<CgroupEnforcer>>,
+}
+
+#[tonic::async_trait]
+impl TaskExecutor for SubprocessExecutor {
+    async fn execute(&self, task: &Task) -> Result<TaskOutput, String> {
+        let started = Instant::now();
+        let mut command = Command::new(&self.command);
+        command
+            .args(&self.args)
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .kill_on_drop(true);
+
+        // Join the task's cgroup before exec so no allocation escapes the limits
+        let mut cgroup = match &self.enforcer {
+            Some(enforcer) => Some(enforcer.create_task_cgroup(task).await.map_err(|e| e.to_string())?),
+            None => None,
+        };
+        if let Some(cgroup) = &cgroup {
+            // Opened with O_CLOEXEC, so the program never sees it
+            let procs = cgroup.open_procs().map_err(|e| e.to_string())?;
+            unsafe {
+                command.pre_exec(move || {
+                    if libc::write(procs.as_raw_fd(), b"0".as_ptr() as *const libc::c_void, 1) == 1 {
+                        Ok(())
+                    } else {
+                        Err(std::io::Error::last_os_error())
+                    }
+                });
+            }
+        }
+
+        let mut child = command
+            .spawn()
+            .map_err(|e| format!("Failed to start {} for Task {}: {}", self.command, task.task_id, e))?;
+
+        // Drain the output before feeding stdin, or a program that writes a lot before reading
+        // all of its input blocks on a full pipe while we block on its stdin
+        let mut stdout = child.stdout.take().unwrap();
+        let mut stderr = child.stderr.take().unwrap();
+        let stdout_reader = tokio::spawn(async move {
+            let mut buf = Vec::new();
+            let _ = stdout.read_to_end(&mut buf).await;
+            buf
+        });
+        let stderr_reader = tokio::spawn(async move {
+            let mut buf = Vec::new();
+            let _ = stderr.read_to_end(&mut buf).await;
+            buf
+        });
+
+        // Feed the payload on stdin, then close it so the program sees EOF. A program may exit
+        // without reading all of it, which is not an error.
+        if let Some(mut stdin) = child.stdin.take() {
+            let data = task.data.clone();
+            let task_id = task.task_id.clone();
+            tokio::spawn(async move {
+                match stdin.write_all(&data).await {
+                    Ok(()) => {}
+                    Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => {}
+                    Err(e) => warn!("Failed to write payload for Task {}: {}", task_id, e),
+                }
+            });
+        }
+
+        // Wait for the process, checking its cgroup against the sold limits meanwhile
+        let mut usage = None;
+        let mut violation = None;
+        let mut ticker = interval(ENFORCEMENT_INTERVAL);
+        let status = loop {
+            tokio::select! {
+                status = child.wait() => break status.map_err(|e| format!("Failed to wait for Task {}: {}", task.task_id, e))?,
+                _ = ticker.tick() => {
+                    if let (Some(enforcer), Some(cgroup)) = (&self.enforcer, cgroup.as_mut()) {
+                        match enforcer.check(cgroup) {
+                            Ok(sample) => usage = Some(sample),
+                            Err(err) if violation.is_none() => {
+                                cgroup.kill();
+                                violation = Some(err);
+                            }
+                            Err(_) => {}
+                        }
+                    }
+                }
+            }
+        };
+
+        // The kernel may OOM-kill between samples; check once more after exit
+        if let (Some(enforcer), Some(mut cgroup)) = (&self.enforcer, cgroup.take()) {
+            if violation.is_none() {
+                match enforcer.check(&mut cgroup) {
+                    Ok(sample) => usage = Some(sample),
+                    Err(err) => violation = Some(err),
+                }
+            }
+            enforcer.release(cgroup).await;
+        }
+        if let Some(err) = violation {
+            return Err(err.to_string());
+        }
+
+        Ok(TaskOutput {
+            exit_code: status.code().unwrap_or(-1), // -1 = killed by a signal
+            stdout: stdout_reader.await.unwrap_or_default(),
+            stderr: stderr_reader.await.unwrap_or_default(),
+            runtime: started.elapsed(),
+            usage,
+        })
+    }
+}
+
+pub struct WasmExecutor {
+    pub entry: String,
+}
+
+struct WasmState {
+    limits: StoreLimits,
+}
+
+// Interrupts the module when execute is dropped: aborting the task that awaits a blocking
+// thread leaves the thread running, so the store must be told to trap
+struct InterruptOnDrop(Engine);
+
+impl Drop for InterruptOnDrop {
+    fn drop(&mut self) {
+        self.0.increment_epoch();
+    }
+}
+
+#[tonic::async_trait]
+impl TaskExecutor for WasmExecutor {
+    async fn execute(&self, task: &Task) -> Result<TaskOutput, String> {
+        let module_bytes = task.data.clone();
+        let entry = self.entry.clone();
+        let task_id = task.task_id.clone();
+        // Fuel from the CPU share, linear memory capped at the RAM requirement (MB)
+        let fuel = task.required_cpu.max(1) * FUEL_PER_CPU_PERCENT;
+        let memory_limit = (task.required_ram as usize).saturating_mul(1024 * 1024);
+
+        let mut config = Config::new();
+        config.consume_fuel(true);
+        config.epoch_interruption(true);
+        let engine = Engine::new(&config).map_err(|e| e.to_string())?;
+        let _interrupt = InterruptOnDrop(engine.clone());
+
+        tokio::task::spawn_blocking(move || {
+            let started = Instant::now();
+            let module = Module::new(&engine, &module_bytes).map_err(|e| format!("Invalid WASM module for Task {}: {}", task_id, e))?;
+
+            let limits = StoreLimitsBuilder::new().memory_size(memory_limit).build();
+            let mut store = Store::new(&engine, WasmState { limits });
+            store.limiter(|state| &mut state.limits);
+            store.set_fuel(fuel).map_err(|e| e.to_string())?;
+            // Trap as soon as the epoch moves, which only happens when execute is dropped
+            store.set_epoch_deadline(1);
+
+            let instance = Instance::new(&mut store, &module, &[]).map_err(|e| format!("Failed to instantiate Task {}: {}", task_id, e))?;
+            let run = instance
+                .get_typed_func::<(), i32>(&mut store, &entry)
+                .map_err(|e| format!("Task {} has no `{}` export: {}", task_id, entry, e))?;
+
+            match run.call(&mut store, ()) {
+                Ok(exit_code) => Ok(TaskOutput { exit_code, runtime: started.elapsed(), ..Default::default() }),
+                Err(trap) => {
+                    // Out-of-fuel and memory traps are reported as a failed run, not an executor error
+                    let message = if store.get_fuel().unwrap_or(0) == 0 {
+                        format!("Task {} exceeded its CPU fuel limit", task_id)
+                    } else {
+                        format!("Task {} trapped: {}", task_id, trap)
+                    };
+                    Ok(TaskOutput { exit_code: 1, stderr: message.into_bytes(), runtime: started.elapsed(), ..Default::default() })
+                }
+            }
+        })
+        .await
+        .map_err(|e| format!("WASM executor panicked: {}", e))?
+    }
+}
+
+type Handler = dyn Fn(&Task) -> Result<TaskOutput, String> + Send + Sync;
+
+// Runs a closure in-process; used by tests and simulations
+pub struct InProcessExecutor {
+    handler: Arc<Handler>,
+}
+
+impl InProcessExecutor {
+    pub fn new<F>(handler: F) -> Self
+    where
+        F: Fn(&Task) -> Result<TaskOutput, String> + Send + Sync + 'static,
+    {
+        InProcessExecutor { handler: Arc::new(handler) }
+    }
+
+    // Succeeds immediately, echoing the payload as stdout
+    pub fn echo() -> Self {
+        InProcessExecutor::new(|task| Ok(TaskOutput { exit_code: 0, stdout: task.data.clone(), ..Default::default() }))
+    }
+}
+
+#[tonic::async_trait]
+impl TaskExecutor for InProcessExecutor {
+    async fn execute(&self, task: &Task) -> Result<TaskOutput, String> {
+        let started = Instant::now();
+        let mut output = (self.handler)(task)?;
+        output.runtime = started.elapsed();
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shell(script: &str) -> SubprocessExecutor {
+        SubprocessExecutor { command: "sh".to_string(), args: vec!["-c".to_string(), script.to_string()], enforcer: None }
+    }
+
+    async fn run(executor: SubprocessExecutor, payload: Vec<u8>) -> TaskOutput {
+        let task = Task::new("t1", 0, 64, 10, 0, payload);
+        tokio::time::timeout(Duration::from_secs(20), executor.execute(&task)).await.expect("executor hung").unwrap()
+    }
+
+    #[tokio::test]
+    async fn large_output_before_reading_stdin_does_not_deadlock() {
+        // Both pipes overflow their buffers unless output is drained while stdin is written
+        let output = run(shell("head -c 300000 /dev/zero; wc -c"), vec![b'x'; 300_000]).await;
+        assert!(output.success());
+        assert_eq!(output.stdout.len(), 300_000 + "300000\n".len());
+        assert_eq!(String::from_utf8_lossy(&output.stdout[300_000..]).trim(), "300000");
+    }
+
+    #[tokio::test]
+    async fn programs_may_ignore_their_input() {
+        let output = run(shell("echo done"), vec![b'x'; 1_000_000]).await;
+        assert!(output.success());
+        assert_eq!(output.stdout, b"done\n");
+    }
+
+    #[test]
+    fn cancelling_a_wasm_task_stops_the_module() {
+        // With a single blocking thread the second module only runs once the first has stopped
+        let runtime = tokio::runtime::Builder::new_multi_thread().max_blocking_threads(1).enable_all().build().unwrap();
+        let executor = WasmExecutor { entry: "run".to_string() };
+        let looping = Task::new("t1", 0, 64, 100_000, 0, br#"(module (func (export "run") (result i32) (loop (br 0)) (i32.const 0)))"#.to_vec());
+        let quick = Task::new("t2", 0, 64, 10, 0, br#"(module (func (export "run") (result i32) (i32.const 7)))"#.to_vec());
+
+        let output = runtime.block_on(async {
+            assert!(tokio::time::timeout(Duration::from_millis(200), executor.execute(&looping)).await.is_err());
+            tokio::time::timeout(Duration::from_secs(10), executor.execute(&quick)).await
+        });
+        runtime.shutdown_background();
+        assert_eq!(output.expect("the cancelled module kept running").unwrap().exit_code, 7);
+    }
+
+    #[tokio::test]
+    async fn exit_codes_are_reported() {
+        let output = run(shell("echo oops >&2; exit 3"), Vec::new()).await;
+        assert_eq!(output.exit_code, 3);
+        assert_eq!(output.stderr, b"oops\n");
+    }
+}
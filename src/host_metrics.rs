@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use serde::Serialize;
+
+// Snapshot of the host's resources, read from /proc and /sys
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct HostMetrics {
+    pub total_ram_mb: u64,
+    pub free_ram_mb: u64,          // MemAvailable: what can be allocated without swapping
+    pub cpu_count: u64,
+    pub cpu_utilisation: f64,      // % of all cores busy since the previous sample
+    pub disk_total_gb: u64,
+    pub disk_free_gb: u64,
+    pub link_speed_mbps: Option<u64>, // Sum over interfaces that report a speed
+    pub network_rx_mbps: f64,      // Measured since the previous sample
+    pub network_tx_mbps: f64,
+}
+
+impl HostMetrics {
+    // CPU not in use, in % of one core (200 = two idle cores)
+    pub fn idle_cpu_percent(&self) -> u64 {
+        ((100.0 - self.cpu_utilisation).max(0.0) * self.cpu_count as f64).round() as u64
+    }
+
+    // Link capacity not currently in use; None when the link speed is unknown
+    pub fn free_bandwidth_mbps(&self) -> Option<u64> {
+        let used = self.network_rx_mbps.max(self.network_tx_mbps);
+        self.link_speed_mbps.map(|speed| (speed as f64 - used).max(0.0) as u64)
+    }
+}
+
+// Samples host metrics; CPU and network figures are rates between consecutive samples
+pub struct HostSampler {
+    disk_path: PathBuf, // Filesystem whose free space is sold as storage
+    last_cpu: Option<(u64, u64)>, // (busy jiffies, total jiffies)
+    last_net: Option<(Instant, u64, u64)>, // (time, rx bytes, tx bytes)
+}
+
+impl HostSampler {
+    pub fn new(disk_path: impl Into<PathBuf>) -> Self {
+        HostSampler { disk_path: disk_path.into(), last_cpu: None, last_net: None }
+    }
+
+    pub fn sample(&mut self) -> Result<HostMetrics, String> {
+        let meminfo = read_meminfo()?;
+        let (cpu_count, busy, total) = read_cpu_stat()?;
+        let (disk_total_gb, disk_free_gb) = disk_space(&self.disk_path)?;
+        let (rx_bytes, tx_bytes, link_speed_mbps) = read_network()?;
+
+        // The first sample has nothing to compare against and reports zero usage
+        let cpu_utilisation = match self.last_cpu {
+            Some((last_busy, last_total)) if total > last_total => {
+                busy.saturating_sub(last_busy) as f64 / (total - last_total) as f64 * 100.0
+            }
+            _ => 0.0,
+        };
+        self.last_cpu = Some((busy, total));
+
+        let now = Instant::now();
+        let (network_rx_mbps, network_tx_mbps) = match self.last_net {
+            Some((at, last_rx, last_tx)) => {
+                let secs = (now - at).as_secs_f64().max(0.001);
+                (
+                    rx_bytes.saturating_sub(last_rx) as f64 * 8.0 / 1_000_000.0 / secs,
+                    tx_bytes.saturating_sub(last_tx) as f64 * 8.0 / 1_000_000.0 / secs,
+                )
+            }
+            None => (0.0, 0.0),
+        };
+        self.last_net = Some((now, rx_bytes, tx_bytes));
+
+        Ok(HostMetrics {
+            total_ram_mb: meminfo.get("MemTotal").copied().unwrap_or(0) / 1024,
+            free_ram_mb: meminfo.get("MemAvailable").or_else(|| meminfo.get("MemFree")).copied().unwrap_or(0) / 1024,
+            cpu_count,
+            cpu_utilisation,
+            disk_total_gb,
+            disk_free_gb,
+            link_speed_mbps,
+            network_rx_mbps,
+            network_tx_mbps,
+        })
+    }
+}
+
+fn read_proc(path: &str) -> Result<String, String> {
+    fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path, e))
+}
+
+// "Key:   value kB" lines, values in kB
+fn read_meminfo() -> Result<HashMap<String, u64>, String> {
+    Ok(read_proc("/proc/meminfo")?
+        .lines()
+        .filter_map(|line| {
+            let (key, rest) = line.split_once(':')?;
+            Some((key.to_string(), rest.split_whitespace().next()?.parse().ok()?))
+        })
+        .collect())
+}
+
+// CPU count plus aggregate (busy, total) jiffies from /proc/stat
+fn read_cpu_stat() -> Result<(u64, u64, u64), String> {
+    let stat = read_proc("/proc/stat")?;
+    let cpu_count = stat
+        .lines()
+        .filter(|line| line.starts_with("cpu") && line.as_bytes().get(3).is_some_and(|b| b.is_ascii_digit()))
+        .count() as u64;
+
+    let aggregate = stat.lines().next().filter(|line| line.starts_with("cpu ")).ok_or("Malformed /proc/stat")?;
+    // user nice system idle iowait irq softirq steal
+    let fields: Vec<u64> = aggregate.split_whitespace().skip(1).take(8).filter_map(|v| v.parse().ok()).collect();
+    let total: u64 = fields.iter().sum();
+    let idle = fields.get(3).copied().unwrap_or(0) + fields.get(4).copied().unwrap_or(0);
+    Ok((cpu_count.max(1), total - idle, total))
+}
+
+// (total, free) space of the filesystem holding `path`, in GB
+fn disk_space(path: &Path) -> Result<(u64, u64), String> {
+    let c_path = CString::new(path.to_string_lossy().as_bytes()).map_err(|e| e.to_string())?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(format!("statvfs {} failed: {}", path.display(), std::io::Error::last_os_error()));
+    }
+    let gb = 1024 * 1024 * 1024;
+    Ok((
+        stat.f_blocks as u64 * stat.f_frsize as u64 / gb,
+        stat.f_bavail as u64 * stat.f_frsize as u64 / gb,
+    ))
+}
+
+// Total (rx, tx) bytes and summed link speed over every interface except loopback
+fn read_network() -> Result<(u64, u64, Option<u64>), String> {
+    let mut rx = 0;
+    let mut tx = 0;
+    let mut speed = None;
+    for line in read_proc("/proc/net/dev")?.lines().skip(2) {
+        let (iface, counters) = match line.split_once(':') {
+            Some((iface, counters)) => (iface.trim(), counters),
+            None => continue,
+        };
+        if iface == "lo" {
+            continue;
+        }
+        let counters: Vec<u64> = counters.split_whitespace().filter_map(|v| v.parse().ok()).collect();
+        rx += counters.first().copied().unwrap_or(0);
+        tx += counters.get(8).copied().unwrap_or(0);
+
+        // Virtual interfaces report -1 or fail to read
+        if let Some(mbps) = fs::read_to_string(format!("/sys/class/net/{}/speed", iface))
+            .ok()
+            .and_then(|s| s.trim().parse::<i64>().ok())
+            .filter(|mbps| *mbps > 0)
+        {
+            speed = Some(speed.unwrap_or(0) + mbps as u64);
+        }
+    }
+    Ok((rx, tx, speed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn idle_cpu_and_free_bandwidth_are_derived_from_the_sample() {
+        let metrics = HostMetrics { cpu_count: 4, cpu_utilisation: 25.0, link_speed_mbps: Some(100), network_rx_mbps: 30.0, network_tx_mbps: 10.0, ..Default::default() };
+        assert_eq!(metrics.idle_cpu_percent(), 300);
+        assert_eq!(metrics.free_bandwidth_mbps(), Some(70));
+
+        let saturated = HostMetrics { cpu_count: 2, cpu_utilisation: 100.0, link_speed_mbps: Some(10), network_tx_mbps: 50.0, ..Default::default() };
+        assert_eq!(saturated.idle_cpu_percent(), 0);
+        assert_eq!(saturated.free_bandwidth_mbps(), Some(0));
+        assert_eq!(HostMetrics::default().free_bandwidth_mbps(), None);
+    }
+
+    #[test]
+    fn the_first_sample_reports_no_usage() {
+        let mut sampler = HostSampler::new(std::env::temp_dir());
+        let first = sampler.sample().unwrap();
+        assert!(first.total_ram_mb > 0 && first.free_ram_mb <= first.total_ram_mb);
+        assert!(first.cpu_count >= 1);
+        assert_eq!(first.cpu_utilisation, 0.0);
+        assert_eq!((first.network_rx_mbps, first.network_tx_mbps), (0.0, 0.0));
+        assert!(first.disk_free_gb <= first.disk_total_gb);
+
+        let second = sampler.sample().unwrap();
+        assert!((0.0..=100.0).contains(&second.cpu_utilisation));
+        assert!(HostSampler::new("/no/such/path").sample().is_err());
+    }
+}
@@ -1,37 +1,43 @@
-use rust_ipfs::{Ipfs, IpfsOptions, Types};
-use std::error::Error;
+use std::time::Duration;
+use serde::Deserialize;
 
-// IPFS Initialization
-async fn init_ipfs() -> Result<Ipfs<Types>, Box<dyn Error>> {
-    let ipfs_opts = IpfsOptions::inmemory_with_generated_keys();
-    let (ipfs, _) = Ipfs::new(ipfs_opts).await?;
-    Ok(ipfs)
-}
+// Uploads of large task outputs may take a while on a slow IPFS node
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(120);
 
-// Set bandwidth limit for IPFS upload/download
-pub async fn set_bandwidth_limit(node_bandwidth: u64) {
-    // Simulate bandwidth throttling logic, such as limiting upload/download speed
-    println!("Setting bandwidth limit to {} Mbps for IPFS operations", node_bandwidth);
+// Answer of /api/v0/add
+#[derive(Deserialize)]
+struct AddResponse {
+    #[serde(rename = "Hash")]
+    hash: String,
 }
 
-// Store data to IPFS with bandwidth control
-pub async fn store_data_with_bandwidth_control(data: Vec<u8>, bandwidth: u64) -> Result<String, Box<dyn Error>> {
-    set_bandwidth_limit(bandwidth).await;
-
-    let ipfs_opts = IpfsOptions::inmemory_with_generated_keys();
-    let (ipfs, _) = Ipfs::new(ipfs_opts).await?;
-
-    let cid = ipfs.put_block(data.into(), None).await?;
-    Ok(cid.to_string())
+// Client for the HTTP API of an IPFS node (Kubo's /api/v0), at ipfs.api_url
+#[derive(Debug, Clone)]
+pub struct IpfsClient {
+    api_url: String,
+    http: reqwest::Client,
 }
 
-// Retrieve data from IPFS with bandwidth control
-pub async fn retrieve_data_with_bandwidth_control(cid: String, bandwidth: u64) -> Result<Vec<u8>, Box<dyn Error>> {
-    set_bandwidth_limit(bandwidth).await;
-
-    let ipfs_opts = IpfsOptions::inmemory_with_generated_keys();
-    let (ipfs, _) = Ipfs::new(ipfs_opts).await?;
-
-    let block = ipfs.get_block(&cid.parse()?).await?;
-    Ok(block.data().to_vec())
+impl IpfsClient {
+    pub fn new(api_url: &str) -> Self {
+        let http = reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build().unwrap_or_default();
+        IpfsClient { api_url: api_url.trim_end_matches('/').to_string(), http }
+    }
+
+    // Store `data` on the node, pinned, and return its CID
+    pub async fn add(&self, data: Vec<u8>) -> Result<String, String> {
+        let url = format!("{}/api/v0/add?pin=true&cid-version=1", self.api_url);
+        let form = reqwest::multipart::Form::new().part("file", reqwest::multipart::Part::bytes(data));
+        let response = self.http.post(&url).multipart(form).send().await.map_err(|e| format!("Could not reach {}: {}", self.api_url, e))?;
+        let response = response.error_for_status().map_err(|e| format!("{}: {}", url, e))?;
+        let added: AddResponse = response.json().await.map_err(|e| format!("Unexpected answer from {}: {}", url, e))?;
+        Ok(added.hash)
+    }
+
+    pub async fn cat(&self, cid: &str) -> Result<Vec<u8>, String> {
+        let url = format!("{}/api/v0/cat", self.api_url);
+        let response = self.http.post(&url).query(&[("arg", cid)]).send().await.map_err(|e| format!("Could not reach {}: {}", self.api_url, e))?;
+        let response = response.error_for_status().map_err(|e| format!("{}: {}", url, e))?;
+        Ok(response.bytes().await.map_err(|e| format!("Could not read {} from {}: {}", cid, url, e))?.to_vec())
+    }
 }
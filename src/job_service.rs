@@ -0,0 +1,396 @@
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::Stream;
+use tonic::{Request, Response, Status};
+use job::job_service_server::JobService;
+use job::{Job, JobRequest, ListJobsRequest, ListJobsResponse, SubmitJobRequest};
+use crate::auth::Authenticator;
+use crate::executor::ExecutorKind;
+use crate::leader_election::Leadership;
+use crate::policy::{self, Action, Resource};
+use crate::task::Task;
+use crate::task_scheduler::SchedulerEvent;
+use crate::user_manager::{Principal, UserRole};
+
+pub mod job {
+    tonic::include_proto!("job");
+}
+
+// Longest job ID a client may choose; IDs also name files and cgroups on nodes
+const MAX_JOB_ID_LEN: usize = 64;
+// Largest requirements a job may state, far beyond any single node
+const MAX_RAM_MB: u64 = 1 << 20; // 1 TiB
+const MAX_CPU_PERCENT: u64 = 100 * 1024; // 1024 cores
+const MAX_BANDWIDTH_MBPS: u64 = 1_000_000;
+const MAX_STORAGE_GB: u64 = 1 << 20;
+
+// Lifecycle of a submitted job (one job = one task)
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum JobState {
+    Pending,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+impl JobState {
+    // Finished jobs never change state again
+    pub fn is_terminal(&self) -> bool {
+        matches!(self, JobState::Succeeded | JobState::Failed | JobState::Cancelled)
+    }
+
+    fn to_proto(self) -> job::JobState {
+        match self {
+            JobState::Pending => job::JobState::Pending,
+            JobState::Running => job::JobState::Running,
+            JobState::Succeeded => job::JobState::Succeeded,
+            JobState::Failed => job::JobState::Failed,
+            JobState::Cancelled => job::JobState::Cancelled,
+        }
+    }
+
+    fn from_proto(state: i32) -> Option<Self> {
+        match job::JobState::from_i32(state)? {
+            job::JobState::Pending => Some(JobState::Pending),
+            job::JobState::Running => Some(JobState::Running),
+            job::JobState::Succeeded => Some(JobState::Succeeded),
+            job::JobState::Failed => Some(JobState::Failed),
+            job::JobState::Cancelled => Some(JobState::Cancelled),
+            job::JobState::Unknown => None,
+        }
+    }
+}
+
+// What the scheduler knows about a job, kept after it finishes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobRecord {
+    pub job_id: String,
+    pub owner: String,
+    pub priority: u8,
+    pub deadline: u64,
+    pub required_ram: u64,
+    pub required_cpu: u64,
+    pub required_bandwidth: u64,
+    pub required_storage: u64,
+    pub executor: ExecutorKind,
+    pub state: JobState,
+    pub node_id: Option<String>,
+    pub progress_percent: u32,
+    pub retries: u32,
+    pub preemptions: u32,
+    pub wasted_work: Duration, // Run time lost to preemption
+    pub message: String, // Last error or cancellation reason
+    pub submitted_at: SystemTime,
+    pub updated_at: SystemTime,
+}
+
+impl JobRecord {
+    pub fn from_task(task: &Task) -> Self {
+        let now = SystemTime::now();
+        JobRecord {
+            job_id: task.task_id.clone(),
+            owner: task.owner.clone(),
+            priority: task.priority,
+            deadline: task.deadline,
+            required_ram: task.required_ram,
+            required_cpu: task.required_cpu,
+            required_bandwidth: task.required_bandwidth,
+            required_storage: task.required_storage,
+            executor: task.executor.clone(),
+            state: JobState::Pending,
+            node_id: None,
+            progress_percent: 0,
+            retries: task.retries,
+            preemptions: task.preemptions,
+            wasted_work: task.wasted_work,
+            message: String::new(),
+            submitted_at: now,
+            updated_at: now,
+        }
+    }
+
+    pub fn to_proto(&self) -> Job {
+        Job {
+            job_id: self.job_id.clone(),
+            owner: self.owner.clone(),
+            state: self.state.to_proto() as i32,
+            priority: self.priority as u32,
+            deadline: if self.deadline == u64::MAX { 0 } else { self.deadline },
+            required_ram: self.required_ram,
+            required_cpu: self.required_cpu,
+            required_bandwidth: self.required_bandwidth,
+            required_storage: self.required_storage,
+            executor: self.executor.name().to_string(),
+            node_id: self.node_id.clone().unwrap_or_default(),
+            progress_percent: self.progress_percent,
+            retries: self.retries,
+            preemptions: self.preemptions,
+            wasted_work_ms: self.wasted_work.as_millis() as u64,
+            message: self.message.clone(),
+            submitted_at_ms: unix_millis(self.submitted_at),
+            updated_at_ms: unix_millis(self.updated_at),
+        }
+    }
+}
+
+// Job IDs are 1 to 64 ASCII letters, digits, '_' or '-'
+pub fn valid_job_id(job_id: &str) -> bool {
+    !job_id.is_empty()
+        && job_id.len() <= MAX_JOB_ID_LEN
+        && job_id.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-')
+}
+
+// RAM and CPU must be asked for; bandwidth and storage may be zero. Nothing may be absurdly large.
+fn check_requirements(request: &SubmitJobRequest) -> Result<(), Status> {
+    let limits = [
+        ("required_ram", request.required_ram, 1, MAX_RAM_MB),
+        ("required_cpu", request.required_cpu, 1, MAX_CPU_PERCENT),
+        ("required_bandwidth", request.required_bandwidth, 0, MAX_BANDWIDTH_MBPS),
+        ("required_storage", request.required_storage, 0, MAX_STORAGE_GB),
+    ];
+    for (name, value, min, max) in limits {
+        if value < min || value > max {
+            return Err(Status::invalid_argument(format!("{} must be between {} and {}", name, min, max)));
+        }
+    }
+    Ok(())
+}
+
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64)
+}
+
+// Requests from the job API, answered by the scheduler loop
+#[derive(Debug)]
+pub enum JobCommand {
+    Submit { task: Box<Task>, reply: oneshot::Sender<Result<JobRecord, String>> },
+    Cancel { job_id: String, reply: oneshot::Sender<Result<JobRecord, String>> },
+    Get { job_id: String, reply: oneshot::Sender<Option<JobRecord>> },
+    List { owner: Option<String>, state: Option<JobState>, reply: oneshot::Sender<Vec<JobRecord>> },
+}
+
+// Public JobService, a thin front over the scheduler's event channel
+pub struct JobApi {
+    scheduler: mpsc::Sender<SchedulerEvent>,
+    updates: broadcast::Sender<JobRecord>, // Every job state change, published by the scheduler
+    next_id: AtomicU64,
+    leadership: Leadership, // Followers turn clients away to the leader
+    auth: Option<Authenticator>, // Without one every request is anonymous and unrestricted
+}
+
+impl JobApi {
+    pub fn new(scheduler: mpsc::Sender<SchedulerEvent>, updates: broadcast::Sender<JobRecord>) -> Self {
+        JobApi { scheduler, updates, next_id: AtomicU64::new(0), leadership: Leadership::always(), auth: None }
+    }
+
+    pub fn with_leadership(mut self, leadership: Leadership) -> Self {
+        self.leadership = leadership;
+        self
+    }
+
+    pub fn with_auth(mut self, auth: Authenticator) -> Self {
+        self.auth = Some(auth);
+        self
+    }
+
+    // The caller; None when the request is anonymous
+    fn authenticate<T>(&self, request: &Request<T>) -> Result<Option<Principal>, Status> {
+        match &self.auth {
+            Some(auth) => auth.authenticate(request),
+            None => Ok(None),
+        }
+    }
+
+    // Fetch a job the caller may `action`. Other users' jobs look like missing ones.
+    async fn visible_job(&self, principal: &Option<Principal>, action: Action, job_id: &str) -> Result<JobRecord, Status> {
+        // Role and token scope first, so lacking those is denied rather than not found
+        if let Some(principal) = principal {
+            policy::enforce(Some(principal), action, Resource::Job { owner: &principal.user_id })?;
+        }
+        let job_id = job_id.to_string();
+        self.ask(|reply| JobCommand::Get { job_id: job_id.clone(), reply })
+            .await?
+            .filter(|record| match principal {
+                Some(principal) => policy::check(principal, action, Resource::Job { owner: &record.owner }).is_ok(),
+                None => true,
+            })
+            .ok_or_else(|| Status::not_found(format!("Job {} not found", job_id)))
+    }
+
+    fn generate_job_id(&self) -> String {
+        format!("job_{}_{}", unix_millis(SystemTime::now()), self.next_id.fetch_add(1, Ordering::Relaxed))
+    }
+
+    // Send a command to the scheduler and wait for its answer; only the leader runs one
+    async fn ask<T>(&self, command: impl FnOnce(oneshot::Sender<T>) -> JobCommand) -> Result<T, Status> {
+        self.leadership.require_leader()?;
+        let (reply, response) = oneshot::channel();
+        self.scheduler
+            .send(SchedulerEvent::Job(command(reply)))
+            .await
+            .map_err(|_| Status::unavailable("Scheduler is shut down"))?;
+        response.await.map_err(|_| Status::internal("Scheduler dropped the request"))
+    }
+}
+
+#[tonic::async_trait]
+impl JobService for JobApi {
+    async fn submit_job(&self, request: Request<SubmitJobRequest>) -> Result<Response<Job>, Status> {
+        let principal = self.authenticate(&request)?;
+        let mut request = request.into_inner();
+        // Jobs belong to the caller; only operators submit on someone else's behalf
+        if let Some(principal) = &principal {
+            if request.owner.is_empty() {
+                request.owner = principal.user_id.clone();
+            }
+        }
+        policy::enforce(principal.as_ref(), Action::SubmitJob, Resource::Job { owner: &request.owner })?;
+        if request.priority > u8::MAX as u32 {
+            return Err(Status::invalid_argument("priority must be between 0 and 255"));
+        }
+        check_requirements(&request)?;
+        if !request.job_id.is_empty() && !valid_job_id(&request.job_id) {
+            return Err(Status::invalid_argument("job_id must be 1 to 64 letters, digits, '_' or '-'"));
+        }
+        let executor = ExecutorKind::from_request(&request.executor, request.command, request.args)
+            .map_err(Status::invalid_argument)?;
+        if let ExecutorKind::Subprocess { .. } = executor {
+            policy::enforce(principal.as_ref(), Action::RunCommand, Resource::Cluster)?;
+        }
+        let job_id = if request.job_id.is_empty() { self.generate_job_id() } else { request.job_id };
+
+        let mut task = Task::new(
+            &job_id,
+            request.priority as u8,
+            request.required_ram,
+            request.required_cpu,
+            request.required_bandwidth,
+            request.payload,
+        )
+        .with_storage(request.required_storage)
+        .with_owner(&request.owner)
+        .with_executor(executor);
+        if request.deadline > 0 {
+            task = task.with_deadline(request.deadline);
+        }
+        // Zero leaves the scheduler's configured default in place
+        task.max_retries = request.max_retries;
+
+        let record = self.ask(|reply| JobCommand::Submit { task: Box::new(task), reply }).await?.map_err(Status::already_exists)?;
+        Ok(Response::new(record.to_proto()))
+    }
+
+    async fn cancel_job(&self, request: Request<JobRequest>) -> Result<Response<Job>, Status> {
+        let principal = self.authenticate(&request)?;
+        let job_id = request.into_inner().job_id;
+        self.visible_job(&principal, Action::CancelJob, &job_id).await?;
+        let record = self.ask(|reply| JobCommand::Cancel { job_id, reply }).await?.map_err(Status::failed_precondition)?;
+        Ok(Response::new(record.to_proto()))
+    }
+
+    async fn get_job(&self, request: Request<JobRequest>) -> Result<Response<Job>, Status> {
+        let principal = self.authenticate(&request)?;
+        let record = self.visible_job(&principal, Action::ReadJob, &request.into_inner().job_id).await?;
+        Ok(Response::new(record.to_proto()))
+    }
+
+    async fn list_jobs(&self, request: Request<ListJobsRequest>) -> Result<Response<ListJobsResponse>, Status> {
+        let principal = self.authenticate(&request)?;
+        let request = request.into_inner();
+        let mut owner = if request.owner.is_empty() { None } else { Some(request.owner) };
+        if let Some(principal) = &principal {
+            // Everyone but operators only ever sees their own jobs
+            if principal.role != UserRole::Operator {
+                owner.get_or_insert_with(|| principal.user_id.clone());
+            }
+            let resource = owner.as_deref().map_or(Resource::Cluster, |owner| Resource::Job { owner });
+            policy::enforce(Some(principal), Action::ReadJob, resource)?;
+        }
+        let state = JobState::from_proto(request.state);
+        let records = self.ask(|reply| JobCommand::List { owner, state, reply }).await?;
+        Ok(Response::new(ListJobsResponse { jobs: records.iter().map(JobRecord::to_proto).collect() }))
+    }
+
+    type WatchJobStream = Pin<Box<dyn Stream<Item = Result<Job, Status>> + Send + 'static>>;
+
+    async fn watch_job(&self, request: Request<JobRequest>) -> Result<Response<Self::WatchJobStream>, Status> {
+        let principal = self.authenticate(&request)?;
+        let job_id = request.into_inner().job_id;
+        // Subscribe before reading the current state so no change falls in between
+        let mut updates = self.updates.subscribe();
+        let current = self.visible_job(&principal, Action::ReadJob, &job_id).await?;
+
+        let (tx, rx) = mpsc::channel(16);
+        tokio::spawn(async move {
+            let mut done = current.state.is_terminal();
+            if tx.send(Ok(current.to_proto())).await.is_err() {
+                return;
+            }
+            while !done {
+                match updates.recv().await {
+                    Ok(record) if record.job_id == job_id => {
+                        done = record.state.is_terminal();
+                        if tx.send(Ok(record.to_proto())).await.is_err() {
+                            return;
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        let _ = tx.send(Err(Status::data_loss(format!("Missed {} job updates, call GetJob to resync", missed)))).await;
+                        return;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return,
+                }
+            }
+        });
+        Ok(Response::new(Box::pin(ReceiverStream::new(rx))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(ram: u64, cpu: u64) -> SubmitJobRequest {
+        SubmitJobRequest { required_ram: ram, required_cpu: cpu, ..Default::default() }
+    }
+
+    #[test]
+    fn job_ids_are_plain_names() {
+        assert!(valid_job_id("job_1700000000000_3"));
+        assert!(valid_job_id("render-frame-42"));
+        assert!(valid_job_id(&"a".repeat(64)));
+        assert!(!valid_job_id(""));
+        assert!(!valid_job_id(&"a".repeat(65)));
+        assert!(!valid_job_id("../../etc"));
+        assert!(!valid_job_id("a/b"));
+        assert!(!valid_job_id("job 1"));
+        assert!(!valid_job_id("jöb"));
+    }
+
+    #[test]
+    fn requirements_are_bounded() {
+        assert!(check_requirements(&request(512, 20)).is_ok());
+        assert!(check_requirements(&request(0, 20)).is_err());
+        assert!(check_requirements(&request(512, 0)).is_err());
+        assert!(check_requirements(&request(u64::MAX, 20)).is_err());
+        assert!(check_requirements(&SubmitJobRequest { required_storage: u64::MAX, ..request(512, 20) }).is_err());
+        assert!(check_requirements(&SubmitJobRequest { required_bandwidth: MAX_BANDWIDTH_MBPS + 1, ..request(512, 20) }).is_err());
+    }
+
+    #[tokio::test]
+    async fn submit_rejects_bad_ids_before_reaching_the_scheduler() {
+        let (scheduler, mut events) = mpsc::channel(1);
+        let (updates, _) = broadcast::channel(1);
+        let api = JobApi::new(scheduler, updates);
+        let request = SubmitJobRequest { job_id: "../x".to_string(), ..request(512, 20) };
+        let status = api.submit_job(Request::new(request)).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        assert!(events.try_recv().is_err());
+    }
+}
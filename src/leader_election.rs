@@ -0,0 +1,265 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+use tonic::transport::Channel;
+use tonic::Status;
+use crate::controller_grpc_client::node::controller_service_client::ControllerServiceClient;
+use crate::redis_client::{Fence, RedisClient};
+use crate::tls::{self, NodeCredentials, CONTROLLER_SERVER_NAME};
+
+// Redis lease the controller replicas compete for
+const LEADER_LEASE: &str = "controller-leader";
+
+// Who a replica is and where clients reach it; stored as the lease value
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LeaderInfo {
+    pub replica_id: String,
+    pub controller_url: String, // ControllerService, for node agents and dcctl
+    pub jobs_url: String,       // JobService
+}
+
+// This replica's part in the cluster
+#[derive(Debug, Clone, PartialEq)]
+pub enum Role {
+    Leader { fence: Fence },   // The lease term this replica leads in
+    Follower { leader: Option<LeaderInfo> }, // None while no replica holds the lease
+}
+
+// Campaigns for the leader lease and keeps renewing it while held
+pub struct LeaderElector {
+    redis: RedisClient,
+    me: LeaderInfo,
+    lease: Duration,
+}
+
+impl LeaderElector {
+    pub fn new(redis: RedisClient, me: LeaderInfo, lease: Duration) -> Self {
+        LeaderElector { redis, me, lease }
+    }
+
+    // Run the election in the background; the returned handle follows this replica's role
+    pub fn spawn(self) -> Leadership {
+        let (tx, rx) = watch::channel(Role::Follower { leader: None });
+        tokio::spawn(async move {
+            let me = serde_json::to_string(&self.me).unwrap_or_default();
+            // Renew well before the lease runs out so one slow round trip doesn't cost leadership
+            let every = self.lease / 3;
+            let mut term: Option<(Fence, Instant)> = None; // Lease held and when it was last renewed
+            loop {
+                let role = if let Some((fence, last_renewed)) = term.clone() {
+                    match self.redis.renew_lease(LEADER_LEASE, &me, self.lease).await {
+                        Ok(true) => {
+                            term = Some((fence.clone(), Instant::now()));
+                            Role::Leader { fence }
+                        }
+                        Ok(false) => {
+                            warn!("Replica {} lost the leader lease", self.me.replica_id);
+                            term = None;
+                            self.follower_role().await
+                        }
+                        // Redis unreachable: keep leading only while our lease can't have expired yet
+                        Err(err) if last_renewed.elapsed() < self.lease - every => {
+                            warn!("Could not renew the leader lease: {}", err);
+                            Role::Leader { fence }
+                        }
+                        Err(err) => {
+                            warn!("Stepping down, leader lease could not be renewed: {}", err);
+                            term = None;
+                            Role::Follower { leader: None }
+                        }
+                    }
+                } else {
+                    match self.redis.acquire_lease(LEADER_LEASE, &me, self.lease).await {
+                        Ok(Some(fence)) => {
+                            info!("Replica {} became the leader (term {})", self.me.replica_id, fence.epoch);
+                            term = Some((fence.clone(), Instant::now()));
+                            Role::Leader { fence }
+                        }
+                        Ok(None) => self.follower_role().await,
+                        Err(err) => {
+                            warn!("Could not campaign for the leader lease: {}", err);
+                            Role::Follower { leader: None }
+                        }
+                    }
+                };
+
+                tx.send_if_modified(|current| {
+                    let changed = *current != role;
+                    *current = role;
+                    changed
+                });
+                if tx.is_closed() {
+                    break;
+                }
+                tokio::time::sleep(every).await;
+            }
+            // Nobody follows the role any more; let another replica take over right away
+            let _ = self.redis.release_lease(LEADER_LEASE, &me).await;
+        });
+        Leadership { role: Some(rx) }
+    }
+
+    async fn follower_role(&self) -> Role {
+        let leader = match self.redis.lease_holder(LEADER_LEASE).await {
+            Ok(holder) => holder.and_then(|holder| serde_json::from_str(&holder).ok()),
+            Err(err) => {
+                warn!("Could not look up the leader: {}", err);
+                None
+            }
+        };
+        Role::Follower { leader }
+    }
+}
+
+// Cheap, cloneable view of this replica's role, used to gate scheduling and RPCs
+#[derive(Clone, Default)]
+pub struct Leadership {
+    role: Option<watch::Receiver<Role>>, // None: single controller, always the leader
+}
+
+impl Leadership {
+    // A controller running without replicas
+    pub fn always() -> Self {
+        Leadership { role: None }
+    }
+
+    pub fn is_leader(&self) -> bool {
+        self.role.as_ref().is_none_or(|role| matches!(*role.borrow(), Role::Leader { .. }))
+    }
+
+    // The lease term this replica leads in; None for a single controller or a follower
+    pub fn fence(&self) -> Option<Fence> {
+        match self.role.as_ref().map(|role| role.borrow().clone()) {
+            Some(Role::Leader { fence }) => Some(fence),
+            _ => None,
+        }
+    }
+
+    // The current leader's ControllerService URL, when another replica leads
+    pub fn leader_url(&self) -> Option<String> {
+        match self.role.as_ref().map(|role| role.borrow().clone()) {
+            Some(Role::Follower { leader: Some(leader) }) => Some(leader.controller_url),
+            _ => None,
+        }
+    }
+
+    // Reject requests that only the leader may serve, pointing the caller to it
+    pub fn require_leader(&self) -> Result<(), Status> {
+        if self.is_leader() {
+            return Ok(());
+        }
+        Err(match self.role.as_ref().map(|role| role.borrow().clone()) {
+            Some(Role::Follower { leader: Some(leader) }) => Status::unavailable(format!(
+                "Not the leader; controller {} leads at {} (jobs at {})",
+                leader.replica_id, leader.controller_url, leader.jobs_url
+            )),
+            _ => Status::unavailable("Not the leader; no controller leads right now"),
+        })
+    }
+
+    // Wait until this replica leads
+    pub async fn wait_until_leader(&mut self) {
+        if let Some(role) = self.role.as_mut() {
+            while !matches!(*role.borrow_and_update(), Role::Leader { .. }) {
+                if role.changed().await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+
+    // Wait until this replica stops leading; never returns for a single controller
+    pub async fn lost(&mut self) {
+        match self.role.as_mut() {
+            Some(role) => {
+                while matches!(*role.borrow_and_update(), Role::Leader { .. }) {
+                    if role.changed().await.is_err() {
+                        return;
+                    }
+                }
+            }
+            None => std::future::pending().await,
+        }
+    }
+}
+
+// Where a node agent reaches the controller. Follows leader redirects in heartbeat
+// responses and falls back to the other known replicas when the current one is down.
+#[derive(Clone)]
+pub struct ControllerEndpoint {
+    urls: Arc<Mutex<(Vec<String>, usize)>>, // Known replicas and the index of the current one
+    credentials: Option<NodeCredentials>,   // Client certificate for mutual TLS
+}
+
+impl ControllerEndpoint {
+    pub fn new(primary: &str, standbys: &[String]) -> Self {
+        let mut urls = vec![primary.to_string()];
+        urls.extend(standbys.iter().filter(|url| url.as_str() != primary).cloned());
+        ControllerEndpoint { urls: Arc::new(Mutex::new((urls, 0))), credentials: None }
+    }
+
+    pub fn with_credentials(mut self, credentials: NodeCredentials) -> Self {
+        self.credentials = Some(credentials);
+        self
+    }
+
+    pub fn credentials(&self) -> Option<&NodeCredentials> {
+        self.credentials.as_ref()
+    }
+
+    // Connect to `addr` with the node's current certificate, if it has one
+    pub async fn connect(&self, addr: &str) -> Result<ControllerServiceClient<Channel>, tonic::transport::Error> {
+        let settings = self.credentials.as_ref().map(NodeCredentials::settings);
+        let channel = tls::connect(addr, settings.as_ref(), CONTROLLER_SERVER_NAME).await?;
+        Ok(ControllerServiceClient::new(channel))
+    }
+
+    pub fn current(&self) -> String {
+        let urls = self.urls.lock().unwrap();
+        urls.0[urls.1].clone()
+    }
+
+    // Switch to the replica a follower pointed us to; false if we were already there
+    pub fn follow(&self, leader_url: &str) -> bool {
+        let mut urls = self.urls.lock().unwrap();
+        let index = match urls.0.iter().position(|url| url == leader_url) {
+            Some(index) => index,
+            None => {
+                urls.0.push(leader_url.to_string());
+                urls.0.len() - 1
+            }
+        };
+        if urls.1 == index {
+            return false;
+        }
+        info!("Following the controller leader at {}", leader_url);
+        urls.1 = index;
+        true
+    }
+
+    // Try the next known replica after the current one failed
+    pub fn rotate(&self) {
+        let mut urls = self.urls.lock().unwrap();
+        urls.1 = (urls.1 + 1) % urls.0.len();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn the_fence_follows_the_leader_term() {
+        let fence = Fence { lease: LEADER_LEASE.to_string(), holder: "replica-1".to_string(), epoch: 7 };
+        let (tx, rx) = watch::channel(Role::Leader { fence: fence.clone() });
+        let mut leadership = Leadership { role: Some(rx) };
+        assert_eq!(leadership.fence(), Some(fence));
+        assert!(Leadership::always().fence().is_none());
+
+        tx.send(Role::Follower { leader: None }).unwrap();
+        tokio::time::timeout(Duration::from_secs(1), leadership.lost()).await.unwrap();
+        assert!(leadership.fence().is_none());
+    }
+}
@@ -0,0 +1,35 @@
+// Shared library for the controller, node-agent and dcctl binaries
+
+// RPC handlers and their helpers return tonic::Status as is
+#![allow(clippy::result_large_err)]
+
+pub mod config;
+pub mod node;
+pub mod resource_manager;
+pub mod ipfs_storage;
+pub mod communication_layer;
+pub mod task;
+pub mod executor;
+pub mod resource_enforcer;
+pub mod host_metrics;
+pub mod task_queue;
+pub mod task_tracker;
+pub mod node_controller;
+pub mod controller_grpc_client;
+pub mod node_grpc_server;
+pub mod load_balancer;
+pub mod bin_packing;
+pub mod strategy_switcher;
+pub mod task_scheduler;
+pub mod result_store;
+pub mod job_service;
+pub mod state_store;
+pub mod redis_client;
+pub mod leader_election;
+pub mod tls;
+pub mod certificate_authority;
+pub mod user_manager;
+pub mod enrollment;
+pub mod auth;
+pub mod oidc;
+pub mod policy;
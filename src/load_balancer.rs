@@ -1,91 +1,274 @@
-use futures::future::join_all;
-use crate::task_queue::Task;
+use std::collections::HashMap;
+use std::sync::Arc;
+use log::{info, warn, error};
+use crate::task::Task;
 use crate::node::Node;
+use crate::task_tracker::TaskTracker;
+use crate::bin_packing::{self, BestFitDecreasingStrategy, DominantResourceFairnessStrategy, PackingReport};
+
+// Scored placement decision returned by a strategy
+#[derive(Debug, Clone)]
+pub struct Placement {
+    pub node_id: String,
+    pub score: f64, // Higher score = better fit
+}
 
-struct LoadBalancer;
+// Pluggable placement logic, selected by name from config
+pub trait PlacementStrategy: Send + Sync {
+    // Name used to look the strategy up in the registry
+    fn name(&self) -> &'static str;
 
-impl LoadBalancer {
+    // Order a batch of tasks before placement (default keeps arrival order)
+    fn order_tasks(&self, _tasks: &mut Vec<Task>, _nodes: &[Node]) {}
 
-    // Check node capacity before assigning task
-    pub fn assign_task_to_node(&mut self, task: &mut Task, available_nodes: &mut Vec<Node>) -> Option<String> {
-        for node in available_nodes.iter_mut() {
-            if node.can_handle_task(task) {
-                // Assign task to node
-                node.allocated_ram += task.required_ram;
-                node.allocated_cpu += task.required_cpu;
-                node.allocated_bandwidth += task.required_bandwidth;
-                return Some(node.node_id.clone());
-            }
-        }
+    // Score candidate nodes for a task and return the best placement
+    fn place(&self, task: &Task, nodes: &[Node]) -> Option<Placement>;
+}
 
-        None
+// Pick the highest scoring node that can handle the task
+pub fn best_placement<F>(task: &Task, nodes: &[Node], score: F) -> Option<Placement>
+where
+    F: Fn(&Node) -> f64,
+{
+    nodes
+        .iter()
+        .filter(|node| node.can_handle_task(task))
+        .map(|node| Placement { node_id: node.node_id.clone(), score: score(node) })
+        .max_by(|a, b| a.score.partial_cmp(&b.score).unwrap_or(std::cmp::Ordering::Equal))
+}
+
+// First node with enough capacity wins
+pub struct FirstFitStrategy;
+
+impl PlacementStrategy for FirstFitStrategy {
+    fn name(&self) -> &'static str {
+        "first_fit"
     }
 
-    // Differentiate between CPU-bound and memory-bound tasks for better load distribution
-    pub fn assign_task_to_best_node(&mut self, task: &Task, available_nodes: &mut Vec<Node>) -> Option<String> {
-        if task.is_cpu_bound() {
-            // Prioritize nodes with more CPU availability
-            available_nodes.sort_by_key(|node| node.available_cpu - node.allocated_cpu);
-        } else {
-            // Prioritize nodes with more RAM availability
-            available_nodes.sort_by_key(|node| node.available_ram - node.allocated_ram);
-        }
+    fn place(&self, task: &Task, nodes: &[Node]) -> Option<Placement> {
+        nodes
+            .iter()
+            .find(|node| node.can_handle_task(task))
+            .map(|node| Placement { node_id: node.node_id.clone(), score: 1.0 })
+    }
+}
+
+// Differentiate between CPU-bound and memory-bound tasks for better load distribution
+pub struct ResourceFitStrategy;
+
+impl PlacementStrategy for ResourceFitStrategy {
+    fn name(&self) -> &'static str {
+        "resource_fit"
+    }
 
-        // Assign task to the best-fit node
-        for node in available_nodes.iter_mut() {
-            if node.can_handle_task(task) {
-                node.allocate_resources(task);
-                return Some(node.node_id.clone());
+    // Best fit: the node with the least CPU (CPU-bound tasks) or RAM left that still fits the
+    // task, so large nodes stay free for large tasks. Ties go to the earlier node.
+    fn place(&self, task: &Task, nodes: &[Node]) -> Option<Placement> {
+        let free = |node: &Node| {
+            if task.is_cpu_bound() {
+                node.available_cpu - node.allocated_cpu
+            } else {
+                node.available_ram - node.allocated_ram
             }
-        }
+        };
+        nodes
+            .iter()
+            .filter(|node| node.can_handle_task(task))
+            .min_by_key(|node| free(node))
+            .map(|node| Placement { node_id: node.node_id.clone(), score: -(free(node) as f64) })
+    }
+}
+
+// Prioritize higher-priority tasks
+pub struct PriorityStrategy;
 
-        None
+impl PlacementStrategy for PriorityStrategy {
+    fn name(&self) -> &'static str {
+        "priority"
     }
 
+    fn order_tasks(&self, tasks: &mut Vec<Task>, _nodes: &[Node]) {
+        // Sort tasks by priority (descending)
+        tasks.sort_by_key(|task| std::cmp::Reverse(task.priority));
+    }
 
+    fn place(&self, task: &Task, nodes: &[Node]) -> Option<Placement> {
+        ResourceFitStrategy.place(task, nodes)
+    }
+}
 
-    // Parallel task assignment to multiple nodes
-    pub async fn assign_tasks_in_parallel(
-        &mut self,
-        tasks: Vec<Task>,
-        available_nodes: &mut Vec<Node>,
-    ) {
-        let futures: Vec<_> = tasks
-            .into_iter()
-            .map(|mut task| async {
-                let node_id = self.assign_task_to_best_node(&mut task, available_nodes);
-                if let Some(node_id) = node_id {
-                    println!("Task {} assigned to Node {}", task.task_id, node_id);
-                } else {
-                    println!("No suitable node found for Task {}", task.task_id);
-                }
-            })
-            .collect();
+// Earliest deadline first
+pub struct DeadlineStrategy;
+
+impl PlacementStrategy for DeadlineStrategy {
+    fn name(&self) -> &'static str {
+        "deadline"
+    }
+
+    fn order_tasks(&self, tasks: &mut Vec<Task>, _nodes: &[Node]) {
+        // Sort tasks by deadline (ascending)
+        tasks.sort_by_key(|task| task.deadline);
+    }
 
-        // Wait for all tasks to be assigned in parallel
-        join_all(futures).await;
+    fn place(&self, task: &Task, nodes: &[Node]) -> Option<Placement> {
+        ResourceFitStrategy.place(task, nodes)
+    }
+}
+
+// Assign tasks to higher-weighted nodes first
+pub struct WeightStrategy;
+
+impl PlacementStrategy for WeightStrategy {
+    fn name(&self) -> &'static str {
+        "weight"
+    }
+
+    fn place(&self, task: &Task, nodes: &[Node]) -> Option<Placement> {
+        best_placement(task, nodes, |node| node.weight as f64)
+    }
+}
+
+// Assign tasks to the node with the lowest average load
+pub struct LeastLoadedStrategy;
+
+impl PlacementStrategy for LeastLoadedStrategy {
+    fn name(&self) -> &'static str {
+        "least_loaded"
+    }
+
+    fn place(&self, task: &Task, nodes: &[Node]) -> Option<Placement> {
+        best_placement(task, nodes, |node| 100.0 - node.calculate_load())
+    }
+}
+
+// Named strategies available to the load balancer
+pub struct StrategyRegistry {
+    strategies: HashMap<String, Arc<dyn PlacementStrategy>>,
+}
+
+impl Default for StrategyRegistry {
+    fn default() -> Self {
+        StrategyRegistry::new()
+    }
+}
+
+impl StrategyRegistry {
+    pub fn new() -> Self {
+        StrategyRegistry { strategies: HashMap::new() }
+    }
+
+    // Registry preloaded with the built-in heuristics
+    pub fn with_defaults() -> Self {
+        let mut registry = StrategyRegistry::new();
+        registry.register(Arc::new(FirstFitStrategy));
+        registry.register(Arc::new(ResourceFitStrategy));
+        registry.register(Arc::new(PriorityStrategy));
+        registry.register(Arc::new(DeadlineStrategy));
+        registry.register(Arc::new(WeightStrategy));
+        registry.register(Arc::new(LeastLoadedStrategy));
+        registry.register(Arc::new(BestFitDecreasingStrategy));
+        registry.register(Arc::new(DominantResourceFairnessStrategy));
+        registry
+    }
+
+    // Add or replace a strategy (site-specific strategies go through here)
+    pub fn register(&mut self, strategy: Arc<dyn PlacementStrategy>) {
+        self.strategies.insert(strategy.name().to_string(), strategy);
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<dyn PlacementStrategy>> {
+        self.strategies.get(name).cloned()
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.strategies.keys().cloned().collect();
+        names.sort();
+        names
+    }
+}
+
+pub struct LoadBalancer {
+    registry: StrategyRegistry,
+    strategy: Arc<dyn PlacementStrategy>,
+    last_packing_report: Option<PackingReport>,
+}
+
+impl LoadBalancer {
+    // Create a load balancer using the named strategy from the registry
+    pub fn new(registry: StrategyRegistry, strategy_name: &str) -> Result<Self, String> {
+        let strategy = registry
+            .get(strategy_name)
+            .ok_or_else(|| format!("Unknown placement strategy: {}", strategy_name))?;
+        Ok(LoadBalancer { registry, strategy, last_packing_report: None })
+    }
+
+    // Switch the active strategy at runtime
+    pub fn set_strategy(&mut self, strategy_name: &str) -> Result<(), String> {
+        self.strategy = self
+            .registry
+            .get(strategy_name)
+            .ok_or_else(|| format!("Unknown placement strategy: {}", strategy_name))?;
+        Ok(())
+    }
+
+    pub fn strategy_name(&self) -> &'static str {
+        self.strategy.name()
+    }
+
+    pub fn registry_mut(&mut self) -> &mut StrategyRegistry {
+        &mut self.registry
+    }
+
+    // Packing efficiency of the most recent batch
+    pub fn last_packing_report(&self) -> Option<&PackingReport> {
+        self.last_packing_report.as_ref()
+    }
+
+    // Place a single task with the active strategy and reserve its resources
+    pub fn assign_task(&mut self, task: &Task, available_nodes: &mut [Node]) -> Option<String> {
+        let placement = self.strategy.place(task, available_nodes)?;
+        let node = available_nodes.iter_mut().find(|node| node.node_id == placement.node_id)?;
+        node.allocate_resources(task);
+        Some(placement.node_id)
+    }
+
+    // Order and place a batch of tasks, returning task_id -> node_id assignments
+    pub fn assign_tasks(&mut self, tasks: &mut Vec<Task>, available_nodes: &mut [Node]) -> Vec<(String, String)> {
+        self.strategy.order_tasks(tasks, available_nodes);
+
+        let mut assignments = Vec::new();
+        for task in tasks.iter() {
+            if let Some(node_id) = self.assign_task(task, available_nodes) {
+                info!("Task {} assigned to Node {} ({} strategy)", task.task_id, node_id, self.strategy.name());
+                assignments.push((task.task_id.clone(), node_id));
+            } else {
+                warn!("No suitable node found for Task {}", task.task_id);
+            }
+        }
+
+        let report = bin_packing::packing_report(available_nodes, &assignments, tasks.len());
+        info!(
+            "Batch packed with {} strategy: {}/{} tasks on {} nodes, efficiency {:.1}%, fragmentation {:.1}%",
+            self.strategy.name(), report.tasks_placed, report.tasks_placed + report.tasks_unplaced,
+            report.nodes_used, report.efficiency * 100.0, report.fragmentation * 100.0
+        );
+        self.last_packing_report = Some(report);
+        assignments
     }
 
     pub async fn assign_task_with_retry(
         &mut self,
         task: &mut Task,
-        available_nodes: &mut Vec<Node>,
-        task_queue: &mut TaskQueue,
+        available_nodes: &mut [Node],
         task_tracker: &mut TaskTracker,
-        controller: &mut NodeController,
     ) {
         while !task.exceeded_retry_limit() {
-            if let Some(node_id) = self.assign_task_to_node(task, available_nodes) {
+            if let Some(node_id) = self.assign_task(task, available_nodes) {
                 task_tracker.assign_task_to_node(&task.task_id, &node_id);
                 info!("Task {} assigned to Node {}", task.task_id, node_id);
 
-                let task_result = if node_id == "node_1" {
-                    node1.execute_task(task).await
-                } else {
-                    node2.execute_task(task).await
-                };
-
-                match task_result {
+                let node = available_nodes.iter_mut().find(|node| node.node_id == node_id).unwrap();
+                match node.execute_task(task).await {
                     Ok(_) => {
                         info!("Task {} completed successfully on retry #{}", task.task_id, task.retries);
                         task_tracker.remove_task_assignment(&task.task_id);
@@ -106,164 +289,70 @@ impl LoadBalancer {
             error!("Task {} permanently failed after {} retries", task.task_id, task.max_retries);
         }
     }
+}
 
-    // Enhanced logic for assigning tasks to the best available node
-    pub fn assign_task_to_best_node(&mut self, task: &Task, available_nodes: &mut Vec<Node>) -> Option<String> {
-        // Sort nodes based on available resources (e.g., CPU, RAM)
-        available_nodes.sort_by_key(|node| node.available_ram - node.allocated_ram);
-        
-        for node in available_nodes.iter_mut() {
-            // Check if node has enough resources to handle the task
-            if node.allocated_ram + task.required_ram <= node.available_ram &&
-               node.allocated_cpu + task.required_cpu <= node.available_cpu &&
-               node.allocated_bandwidth + task.required_bandwidth <= node.available_bandwidth {
-                // Assign the task to this node
-                node.allocated_ram += task.required_ram;
-                node.allocated_cpu += task.required_cpu;
-                node.allocated_bandwidth += task.required_bandwidth;
-                return Some(node.node_id.clone());
-            }
-        }
-
-        // No suitable node found
-        None
-    }
-
-    // Check node capacity before assigning task
-    pub fn assign_task_to_node(&mut self, task: &mut Task, available_nodes: &mut Vec<Node>) -> Option<String> {
-        for node in available_nodes.iter_mut() {
-            if node.can_handle_task(task) {
-                // Assign task to node
-                node.allocated_ram += task.required_ram;
-                node.allocated_cpu += task.required_cpu;
-                node.allocated_bandwidth += task.required_bandwidth;
-                return Some(node.node_id.clone());
-            }
-        }
-
-        None
-    }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    // Differentiate between CPU-bound and memory-bound tasks for better load distribution
-    pub fn assign_task_to_best_node(&mut self, task: &Task, available_nodes: &mut Vec<Node>) -> Option<String> {
-        if task.is_cpu_bound() {
-            // Prioritize nodes with more CPU availability
-            available_nodes.sort_by_key(|node| node.available_cpu - node.allocated_cpu);
-        } else {
-            // Prioritize nodes with more RAM availability
-            available_nodes.sort_by_key(|node| node.available_ram - node.allocated_ram);
-        }
+    // Always picks the last node that fits
+    struct LastFit;
 
-        // Assign task to the best-fit node
-        for node in available_nodes.iter_mut() {
-            if node.can_handle_task(task) {
-                node.allocate_resources(task);
-                return Some(node.node_id.clone());
-            }
+    impl PlacementStrategy for LastFit {
+        fn name(&self) -> &'static str {
+            "last_fit"
         }
 
-        None
-    }
-    // Prioritize higher-priority tasks
-    pub fn assign_tasks_with_priority(
-        &mut self,
-        tasks: &mut Vec<Task>,
-        available_nodes: &mut Vec<Node>,
-    ) {
-        // Sort tasks by priority (descending)
-        tasks.sort_by(|a, b| b.priority.cmp(&a.priority));
-
-        for task in tasks {
-            if let Some(node_id) = self.assign_task_to_best_node(task, available_nodes) {
-                println!("Task {} (priority {}) assigned to Node {}", task.task_id, task.priority, node_id);
-            } else {
-                println!("No suitable node found for Task {}", task.task_id);
-            }
+        fn place(&self, task: &Task, nodes: &[Node]) -> Option<Placement> {
+            nodes.iter().rev().find(|node| node.can_handle_task(task)).map(|node| Placement { node_id: node.node_id.clone(), score: 1.0 })
         }
     }
 
-    // Parallel task assignment to multiple nodes
-    pub async fn assign_tasks_in_parallel(
-        &mut self,
-        tasks: Vec<Task>,
-        available_nodes: &mut Vec<Node>,
-    ) {
-        let futures: Vec<_> = tasks
-            .into_iter()
-            .map(|mut task| async {
-                let node_id = self.assign_task_to_best_node(&mut task, available_nodes);
-                if let Some(node_id) = node_id {
-                    println!("Task {} assigned to Node {}", task.task_id, node_id);
-                } else {
-                    println!("No suitable node found for Task {}", task.task_id);
-                }
-            })
-            .collect();
-
-        // Wait for all tasks to be assigned in parallel
-        join_all(futures).await;
-    }
-
-    pub fn assign_tasks_based_on_weight(
-        &mut self,
-        tasks: &mut Vec<Task>,
-        available_nodes: &mut Vec<Node>,
-    ) {
-        // Sort nodes by weight (descending)
-        available_nodes.sort_by(|a, b| b.weight.cmp(&a.weight));
-
-        // Assign tasks to higher-weighted nodes first
-        for task in tasks {
-            if let Some(node_id) = self.assign_task_to_best_node(task, available_nodes) {
-                println!("Task {} assigned to Node {} (weight {})", task.task_id, node_id, available_nodes.iter().find(|n| n.node_id == node_id).unwrap().weight);
-            } else {
-                println!("No suitable node found for Task {}", task.task_id);
-            }
-        }
+    fn nodes() -> Vec<Node> {
+        vec![Node::new("node_1", 1024, 10, 100, 10), Node::new("node_2", 2048, 10, 100, 10)]
     }
-    pub fn assign_tasks_by_deadline(
-        &mut self,
-        tasks: &mut Vec<Task>,
-        available_nodes: &mut Vec<Node>,
-    ) {
-        // Sort tasks by deadline (ascending)
-        tasks.sort_by(|a, b| a.deadline.cmp(&b.deadline));
 
-        for task in tasks {
-            if let Some(node_id) = self.assign_task_to_best_node(task, available_nodes) {
-                println!("Task {} with deadline {} assigned to Node {}", task.task_id, task.deadline, node_id);
-            } else {
-                println!("No suitable node found for Task {}", task.task_id);
-            }
-        }
+    #[test]
+    fn strategies_are_looked_up_by_name() {
+        assert!(LoadBalancer::new(StrategyRegistry::with_defaults(), "no_such_strategy").is_err());
+        let mut balancer = LoadBalancer::new(StrategyRegistry::with_defaults(), "first_fit").unwrap();
+        assert!(balancer.set_strategy("no_such_strategy").is_err());
+        assert_eq!(balancer.strategy_name(), "first_fit");
+
+        balancer.registry_mut().register(Arc::new(LastFit));
+        balancer.set_strategy("last_fit").unwrap();
+        let mut nodes = nodes();
+        assert_eq!(balancer.assign_task(&Task::new("t1", 0, 512, 10, 1, Vec::new()), &mut nodes).as_deref(), Some("node_2"));
+        assert_eq!(nodes[1].allocated_ram, 512);
     }
-    pub fn assign_tasks_to_least_loaded_node(
-        &mut self,
-        tasks: &mut Vec<Task>,
-        available_nodes: &mut Vec<Node>,
-    ) {
-        // Sort nodes by load (ascending)
-        available_nodes.sort_by(|a, b| a.calculate_load().partial_cmp(&b.calculate_load()).unwrap());
 
-        for task in tasks {
-            if let Some(node_id) = self.assign_task_to_best_node(task, available_nodes) {
-                println!("Task {} assigned to least loaded Node {}", task.task_id, node_id);
-            } else {
-                println!("No suitable node found for Task {}", task.task_id);
-            }
+    #[test]
+    fn resource_fit_picks_the_tightest_node_that_fits() {
+        let mut nodes = vec![
+            Node::new("large", 4096, 10, 100, 10),
+            Node::new("small", 512, 10, 100, 10),
+            Node::new("medium", 1024, 10, 100, 10),
+            Node::new("also_medium", 1024, 10, 100, 10),
+        ];
+        let mut balancer = LoadBalancer::new(StrategyRegistry::with_defaults(), "resource_fit").unwrap();
+        let task = Task::new("t1", 0, 768, 10, 1, Vec::new());
+        assert_eq!(balancer.assign_task(&task, &mut nodes).as_deref(), Some("medium"));
+        // Priority and deadline place the same way
+        for strategy in ["priority", "deadline"] {
+            balancer.set_strategy(strategy).unwrap();
+            assert_eq!(balancer.assign_task(&task, &mut nodes).as_deref(), Some("also_medium"));
+            nodes[3].free_resources(&task);
         }
     }
 
-    impl Task {
-        // Check if a task is CPU-bound
-        pub fn is_cpu_bound(&self) -> bool {
-            self.required_cpu > self.required_ram / 10 // Example heuristic
-        }
-    
+    #[test]
+    fn batches_reserve_capacity_as_they_go() {
+        let mut balancer = LoadBalancer::new(StrategyRegistry::with_defaults(), "priority").unwrap();
+        let mut nodes = nodes();
+        let mut tasks = vec![Task::new("low", 1, 2048, 10, 1, Vec::new()), Task::new("high", 9, 2048, 10, 1, Vec::new())];
+        let assignments = balancer.assign_tasks(&mut tasks, &mut nodes);
+        // Only one task fits; the higher priority one goes first and takes it
+        assert_eq!(assignments, [("high".to_string(), "node_2".to_string())]);
+        assert_eq!(nodes[1].allocated_ram, 2048);
     }
 }
-
-
-
-
-    
@@ -1,7 +1,33 @@
-use tokio::task;
-use tokio::time::{sleep, Duration};
+use tokio::time::{sleep, timeout, Duration, Instant};
+use tonic::transport::Channel;
+use log::{info, warn};
 use std::collections::HashMap;
+use std::sync::Arc;
+use crate::task::Task;
+use crate::executor::{executor_for, TaskOutput};
+use crate::resource_enforcer::CgroupEnforcer;
+use crate::host_metrics::HostMetrics;
+use crate::controller_grpc_client::node::controller_service_client::ControllerServiceClient;
+use crate::controller_grpc_client::node::HeartbeatRequest;
+use crate::leader_election::ControllerEndpoint;
 
+// Most the seller is willing to sell; the node never offers more even if the host has it
+#[derive(Clone, Debug)]
+pub struct SellLimits {
+    pub ram: u64,       // MB
+    pub storage: u64,   // GB
+    pub cpu: u64,       // % of one core
+    pub bandwidth: u64, // Mbps
+}
+
+// Task currently holding resources on this node
+#[derive(Clone)]
+pub struct RunningTask {
+    pub task: Task,
+    pub started_at: Instant,
+}
+
+#[derive(Clone)]
 pub struct Node {
     pub node_id: String,
     pub weight: u8, // Weight of the node (higher value = more capable)
@@ -13,13 +39,10 @@ pub struct Node {
     pub allocated_cpu: u64, // CPU percentage allocated to the system
     pub available_bandwidth: u64, // Available network bandwidth (in Mbps)
     pub allocated_bandwidth: u64, // Allocated bandwidth to the system (in Mbps)
-
-    
-}
-
-
-pub struct Node {
     task_cache: HashMap<String, Vec<u8>>, // Task ID -> Cached data
+    pub running_tasks: HashMap<String, RunningTask>, // Task ID -> Running task
+    pub enforcer: Option<Arc<CgroupEnforcer>>, // Enforces sold CPU/RAM per task (Linux cgroups v2)
+    pub sell_limits: SellLimits, // Caps on available_* set by the seller
 }
 
 impl Node {
@@ -36,83 +59,126 @@ impl Node {
             available_bandwidth,
             allocated_bandwidth: 0,
             weight: 0,
+            task_cache: HashMap::new(),
+            running_tasks: HashMap::new(),
+            enforcer: None,
+            sell_limits: SellLimits {
+                ram: available_ram,
+                storage: available_storage,
+                cpu: available_cpu,
+                bandwidth: available_bandwidth,
+            },
         }
     }
 
+    // Enforce sold CPU/RAM limits on subprocess tasks
+    pub fn with_enforcer(mut self, enforcer: Arc<CgroupEnforcer>) -> Self {
+        self.enforcer = Some(enforcer);
+        self
+    }
+
     // Set CPU and Bandwidth Limits
     pub fn set_cpu_bandwidth_limits(&mut self, cpu_limit: u64, bandwidth_limit: u64) {
-        if cpu_limit <= self.available_cpu {
-            self.allocated_cpu = cpu_limit;
-        } else {
-            println!("Error: CPU limit exceeds available CPU capacity!");
-        }
-        
-        if bandwidth_limit <= self.available_bandwidth {
-            self.allocated_bandwidth = bandwidth_limit;
-        } else {
-            println!("Error: Bandwidth limit exceeds available network capacity!");
-        }
+        self.sell_limits.cpu = cpu_limit;
+        self.sell_limits.bandwidth = bandwidth_limit;
+        self.available_cpu = self.available_cpu.min(cpu_limit);
+        self.available_bandwidth = self.available_bandwidth.min(bandwidth_limit);
+    }
+
+    // Set RAM and Storage Limits
+    pub fn set_ram_storage_limits(&mut self, ram_limit: u64, storage_limit: u64) {
+        self.sell_limits.ram = ram_limit;
+        self.sell_limits.storage = storage_limit;
+        self.available_ram = self.available_ram.min(ram_limit);
+        self.available_storage = self.available_storage.min(storage_limit);
+    }
+
+    // Offer what the host can actually spare, capped by the sell limits.
+    // Resources held by our own tasks show up as used on the host, so they are added back.
+    pub fn refresh_capacity(&mut self, metrics: &HostMetrics) {
+        self.available_ram = self.sell_limits.ram.min(metrics.free_ram_mb + self.allocated_ram);
+        self.available_cpu = self.sell_limits.cpu.min(metrics.idle_cpu_percent() + self.allocated_cpu);
+        self.available_storage = self.sell_limits.storage.min(metrics.disk_free_gb + self.allocated_storage);
+        // Without a known link speed the seller's limit is all we have
+        self.available_bandwidth = match metrics.free_bandwidth_mbps() {
+            Some(free) => self.sell_limits.bandwidth.min(free + self.allocated_bandwidth),
+            None => self.sell_limits.bandwidth,
+        };
     }
 
-    // Simulate sending heartbeat
-    pub async fn send_heartbeat(&self) -> Result<(), String> {
+    // Send heartbeats to the controller's Heartbeat RPC until the task is dropped,
+    // moving to whichever replica currently leads
+    pub async fn send_heartbeat(&self, controller: ControllerEndpoint, interval: Duration) {
+        let mut client: Option<(String, ControllerServiceClient<Channel>)> = None;
         loop {
-            // Attempt to send heartbeat and timeout if it takes too long (indicating node failure)
-            match timeout(Duration::from_secs(6), async {
-                println!(
-                    "Node {} sending heartbeat. Resources: RAM: {}MB, CPU: {}%, Bandwidth: {}Mbps",
-                    self.node_id, self.allocated_ram, self.allocated_cpu, self.allocated_bandwidth
-                );
-                // Simulating heartbeat success/failure
-                Ok::<(), String>(()) // Simulating successful heartbeat
-            })
-            .await
-            {
-                Ok(_) => { /* Heartbeat successful */ }
-                Err(_) => {
-                    // Heartbeat timeout means node failed
-                    return Err(format!("Node {} failed to send heartbeat!", self.node_id));
+            let controller_addr = controller.current();
+            if client.as_ref().is_none_or(|(addr, _)| *addr != controller_addr) {
+                client = None;
+                match controller.connect(&controller_addr).await {
+                    Ok(connected) => client = Some((controller_addr.clone(), connected)),
+                    Err(err) => {
+                        warn!("Node {} could not reach controller at {}: {}", self.node_id, controller_addr, err);
+                        controller.rotate();
+                    }
                 }
             }
 
-            // Sleep for 5 seconds before sending the next heartbeat
-            sleep(Duration::from_secs(5)).await;
-        }
-}
-
-    // Send heartbeat with logging
-    pub async fn send_heartbeat(&self) -> Result<(), String> {
-        loop {
-            match timeout(Duration::from_secs(6), async {
-                info!("Node {} sending heartbeat.", self.node_id);
-                Ok::<(), String>(())
-            }).await {
-                Ok(_) => { /* Heartbeat successful */ }
-                Err(_) => {
-                    warn!("Node {} failed to send heartbeat!", self.node_id);
-                    return Err(format!("Node {} failed to send heartbeat!", self.node_id));
+            if let Some((_, connected)) = client.as_mut() {
+                let request = tonic::Request::new(HeartbeatRequest { node_id: self.node_id.clone() });
+                match timeout(interval, connected.heartbeat(request)).await {
+                    Ok(Ok(response)) => {
+                        let response = response.into_inner();
+                        if response.renew_certificate {
+                            if let Some(credentials) = controller.credentials() {
+                                match credentials.renew(connected, &self.node_id).await {
+                                    // Reconnect so the next heartbeat already uses the new certificate
+                                    Ok(()) => client = None,
+                                    Err(err) => warn!("Node {} could not renew its certificate: {}", self.node_id, err),
+                                }
+                            }
+                        }
+                        // A follower answered; retry right away at the leader it named
+                        if !response.leader_url.is_empty() && controller.follow(&response.leader_url) {
+                            continue;
+                        }
+                        info!("Node {} sent heartbeat.", self.node_id)
+                    }
+                    Ok(Err(status)) => {
+                        warn!("Node {} heartbeat rejected: {}", self.node_id, status);
+                        client = None;
+                        controller.rotate();
+                    }
+                    Err(_) => {
+                        warn!("Node {} heartbeat timed out", self.node_id);
+                        client = None;
+                        controller.rotate();
+                    }
                 }
             }
-            sleep(Duration::from_secs(5)).await;
+
+            sleep(interval).await;
         }
     }
 
-    // Execute task with logging
-    pub async fn execute_task(&mut self, task: &mut Task) -> Result<(), String> {
+    // Execute task with the executor it declares
+    pub async fn execute_task(&mut self, task: &mut Task) -> Result<TaskOutput, String> {
         info!(
-            "Node {} executing Task {}: {}MB RAM, {}% CPU, {}Mbps Bandwidth",
-            self.node_id, task.task_id, task.required_ram, task.required_cpu, task.required_bandwidth
+            "Node {} executing Task {} ({} executor): {}MB RAM, {}% CPU, {}Mbps Bandwidth",
+            self.node_id, task.task_id, task.executor.name(), task.required_ram, task.required_cpu, task.required_bandwidth
         );
-        
-        // Simulate task execution
-        sleep(Duration::from_secs(5)).await;
-        let result = simulate_task_execution();
+        self.track_running(task.clone());
+
+        let result = executor_for(&task.executor, self.enforcer.clone()).execute(task).await;
+        self.complete_task(&task.task_id);
 
         match result {
-            Ok(_) => {
-                info!("Task {} completed on Node {}", task.task_id, self.node_id);
-                self.free_resources(task);
-                Ok(())
+            Ok(output) if output.success() => {
+                info!("Task {} completed on Node {} in {:.1}s", task.task_id, self.node_id, output.runtime.as_secs_f64());
+                Ok(output)
+            }
+            Ok(output) => {
+                warn!("Task {} exited with code {} on Node {}", task.task_id, output.exit_code, self.node_id);
+                Err(format!("Task {} exited with code {}", task.task_id, output.exit_code))
             }
             Err(err) => {
                 warn!("Task {} failed on Node {}: {}", task.task_id, self.node_id, err);
@@ -121,25 +187,68 @@ impl Node {
         }
     }
 
+    // Record a task as running (its resources are already allocated)
+    fn track_running(&mut self, task: Task) {
+        self.running_tasks.insert(task.task_id.clone(), RunningTask { task, started_at: Instant::now() });
+    }
+
+    // Allocate resources for a task and start tracking it
+    pub fn start_task(&mut self, task: Task) {
+        self.allocate_resources(&task);
+        self.track_running(task);
+    }
+
+    // Stop tracking a finished task and return its resources
+    pub fn complete_task(&mut self, task_id: &str) -> Option<Task> {
+        let running = self.running_tasks.remove(task_id)?;
+        self.free_resources(&running.task);
+        Some(running.task)
+    }
+
+    // Suspend a running task and return its resources. Executors keep no state between runs,
+    // so the task starts over when placed again and all of its run time is lost.
+    pub fn preempt_task(&mut self, task_id: &str) -> Option<Duration> {
+        let running = self.running_tasks.remove(task_id)?;
+        self.free_resources(&running.task);
+        let lost = running.started_at.elapsed();
+        info!("Task {} preempted on Node {} ({:.1}s of work lost)", task_id, self.node_id, lost.as_secs_f64());
+        Some(lost)
+    }
 
     // Node maintains its own task queue
     pub async fn add_task_to_queue(&mut self, task: Task) {
         if self.can_handle_task(&task) {
-            // Execute the task concurrently using async
-            let node_id = self.node_id.clone();
-            task::spawn(async move {
-                node.execute_task(task).await;
-            });
+            let mut task = task;
+            let _ = self.execute_task(&mut task).await;
         } else {
             println!("Node {} does not have enough resources for Task {}", self.node_id, task.task_id);
         }
     }
 
     // Check if the node has sufficient resources for the task
-    fn can_handle_task(&self, task: &Task) -> bool {
-        self.allocated_ram + task.required_ram <= self.available_ram &&
-        self.allocated_cpu + task.required_cpu <= self.available_cpu &&
-        self.allocated_bandwidth + task.required_bandwidth <= self.available_bandwidth
+    pub fn can_handle_task(&self, task: &Task) -> bool {
+        // A sum that overflows doesn't fit either
+        let fits = |allocated: u64, required: u64, available: u64| allocated.checked_add(required).is_some_and(|total| total <= available);
+        fits(self.allocated_ram, task.required_ram, self.available_ram) &&
+        fits(self.allocated_cpu, task.required_cpu, self.available_cpu) &&
+        fits(self.allocated_bandwidth, task.required_bandwidth, self.available_bandwidth) &&
+        fits(self.allocated_storage, task.required_storage, self.available_storage)
+    }
+
+    // Reserve the task's resources on this node
+    pub fn allocate_resources(&mut self, task: &Task) {
+        self.allocated_ram = self.allocated_ram.saturating_add(task.required_ram);
+        self.allocated_cpu = self.allocated_cpu.saturating_add(task.required_cpu);
+        self.allocated_bandwidth = self.allocated_bandwidth.saturating_add(task.required_bandwidth);
+        self.allocated_storage = self.allocated_storage.saturating_add(task.required_storage);
+    }
+
+    // Return the task's resources to the node
+    pub fn free_resources(&mut self, task: &Task) {
+        self.allocated_ram = self.allocated_ram.saturating_sub(task.required_ram);
+        self.allocated_cpu = self.allocated_cpu.saturating_sub(task.required_cpu);
+        self.allocated_bandwidth = self.allocated_bandwidth.saturating_sub(task.required_bandwidth);
+        self.allocated_storage = self.allocated_storage.saturating_sub(task.required_storage);
     }
 
 // Dynamically adjust resource allocation
@@ -177,24 +286,27 @@ pub async fn send_batched_heartbeat(&self) {
     }
 }
 
-pub fn preemptive_task_scheduling(&mut self, new_task: &Task) {
-    // Check if current tasks can be preempted
-    for current_task in self.running_tasks.iter_mut() {
-        if new_task.priority > current_task.priority {
-            println!(
-                "Preempting Task {} with Task {} on Node {}",
-                current_task.task_id, new_task.task_id, self.node_id
-            );
-            // Preempt the current task
-            self.pause_task(current_task);
-            self.execute_task(new_task.clone());
+// Lower-priority tasks to preempt so `new_task` fits, lowest priority first, out of the tasks
+// holding resources here. None if even preempting all of them would not make room.
+pub fn preemption_victims(&self, new_task: &Task, running: &[Task]) -> Option<Vec<Task>> {
+    // Submitted priorities, so a victim that aged while it waited can't preempt back
+    let mut candidates: Vec<&Task> = running.iter().filter(|task| task.submitted_priority < new_task.submitted_priority).collect();
+    candidates.sort_by_key(|task| task.submitted_priority);
+
+    let mut projected = self.clone();
+    let mut victims = Vec::new();
+    for task in candidates {
+        if projected.can_handle_task(new_task) {
             break;
         }
+        projected.free_resources(task);
+        victims.push(task.clone());
     }
+    projected.can_handle_task(new_task).then_some(victims)
 }
 
 // Allocate resources based on fair share principle
-pub fn allocate_fair_share_resources(&mut self, tasks: &mut Vec<Task>) {
+pub fn allocate_fair_share_resources(&mut self, tasks: &mut [Task]) {
     let total_tasks = tasks.len() as u64;
 
     // Allocate fair share of RAM and CPU to each task
@@ -216,12 +328,61 @@ pub fn calculate_load(&self) -> f64 {
     let bandwidth_load = (self.allocated_bandwidth as f64 / self.available_bandwidth as f64) * 100.0;
     (cpu_load + ram_load + bandwidth_load) / 3.0 // Average load across CPU, RAM, and bandwidth
 }
-
-// Placeholder function to pause a task
-fn pause_task(&self, task: &Task) {
-    println!("Task {} paused on Node {}", task.task_id, self.node_id);
-}
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tasks_fit_within_remaining_capacity() {
+        let mut node = Node::new("node_1", 1024, 10, 100, 10);
+        let task = Task::new("t1", 0, 512, 50, 5, Vec::new());
+        assert!(node.can_handle_task(&task));
+        node.start_task(task.clone());
+        assert!(node.can_handle_task(&Task::new("t2", 0, 512, 50, 5, Vec::new())));
+        assert!(!node.can_handle_task(&Task::new("t3", 0, 513, 50, 5, Vec::new())));
+    }
+
+    #[test]
+    fn overflowing_requirements_never_fit() {
+        let mut node = Node::new("node_1", 1024, 10, 100, 10);
+        node.start_task(Task::new("t1", 0, 1, 1, 1, Vec::new()));
+        assert!(!node.can_handle_task(&Task::new("huge", 0, u64::MAX, 1, 1, Vec::new())));
+        assert!(!node.can_handle_task(&Task::new("huge", 0, 1, u64::MAX, 1, Vec::new())));
+        assert!(!node.can_handle_task(&Task::new("huge", 0, 1, 1, u64::MAX, Vec::new())));
+    }
+
+    #[test]
+    fn capacity_follows_the_host_within_the_sell_limits() {
+        let mut node = Node::new("node_1", 1024, 10, 100, 10);
+        node.allocate_resources(&Task::new("t1", 0, 256, 20, 1, Vec::new()));
+
+        let busy = HostMetrics { free_ram_mb: 512, cpu_count: 1, cpu_utilisation: 90.0, disk_free_gb: 100, ..Default::default() };
+        node.refresh_capacity(&busy);
+        // Our own task's share counts as available; the rest is capped by what the seller sells
+        assert_eq!(node.available_ram, 768);
+        assert_eq!(node.available_cpu, 30);
+        assert_eq!(node.available_storage, 10);
+        assert_eq!(node.available_bandwidth, 10);
+
+        let idle = HostMetrics { free_ram_mb: 64_000, cpu_count: 8, disk_free_gb: 500, link_speed_mbps: Some(1000), ..Default::default() };
+        node.refresh_capacity(&idle);
+        assert_eq!((node.available_ram, node.available_cpu, node.available_bandwidth), (1024, 100, 10));
+    }
 
+    #[test]
+    fn preemption_frees_the_fewest_lowest_priority_tasks() {
+        let mut node = Node::new("node_1", 1024, 10, 100, 10);
+        let running = vec![Task::new("low", 1, 400, 40, 1, Vec::new()), Task::new("mid", 5, 400, 40, 1, Vec::new()), Task::new("top", 9, 200, 20, 1, Vec::new())];
+        running.iter().for_each(|task| node.allocate_resources(task));
 
+        let urgent = Task::new("urgent", 8, 300, 30, 1, Vec::new());
+        let victims = node.preemption_victims(&urgent, &running).unwrap();
+        assert_eq!(victims.iter().map(|task| task.task_id.as_str()).collect::<Vec<_>>(), ["low"]);
+        // Equal or higher priority tasks are never victims
+        assert!(node.preemption_victims(&Task::new("big", 8, 1000, 30, 1, Vec::new()), &running).is_none());
+        assert!(node.preemption_victims(&Task::new("peer", 1, 300, 30, 1, Vec::new()), &running).is_none());
+        assert_eq!(node.allocated_ram, 1000);
+    }
+}
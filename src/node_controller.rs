@@ -1,16 +1,115 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, oneshot};
+use tonic::{Request, Response, Status, Streaming};
+use crate::controller_grpc_client::node::controller_service_server::ControllerService;
+use crate::controller_grpc_client::node::{HeartbeatRequest, HeartbeatResponse, ReportProgressResponse, TaskEvent};
+use crate::controller_grpc_client::node::{TaskResultRequest, TaskResultResponse};
+use crate::controller_grpc_client::node::{DrainNodeRequest, DrainNodeResponse, ListNodesRequest, ListNodesResponse, NodeInfo};
+use crate::controller_grpc_client::node::{ReloadConfigRequest, ReloadConfigResponse};
+use crate::controller_grpc_client::node::{NodeCertificate, RenewCertificateRequest, RevokeNodeRequest, RevokeNodeResponse};
+use crate::controller_grpc_client::node::{CreateJoinTokenRequest, CreateUserRequest, JoinToken, NodeStatusResponse, UserInfo};
+use crate::controller_grpc_client::NodeController as NodeClient;
+use crate::auth::Authenticator;
+use crate::certificate_authority::{CertificateAuthority, SharedRegistry};
+use crate::job_service::JobCommand;
+use crate::leader_election::Leadership;
 use crate::node::Node;
+use crate::policy::{self, Action, Resource};
+use crate::result_store::ResultStore;
+use crate::task_queue::TaskQueue;
+use crate::task_scheduler::SchedulerEvent;
+use crate::task_tracker::TaskTracker;
+use crate::tls::{require_role, PeerIdentity, PeerRole, TlsSettings};
+use crate::user_manager::{UserManager, UserRole};
 
-struct NodeController {
+// How long a node whose RPCs failed gets no new tasks; the failure detector handles longer outages
+const SUSPECT_BACKOFF: Duration = Duration::from_secs(10);
+
+pub struct NodeController {
     nodes: HashMap<String, Node>,  // Map node_id -> Node
     failed_nodes: HashMap<String, Node>,  // Track failed nodes
+    draining: HashSet<String>,  // Nodes finishing their tasks without taking new ones
+    suspected: HashMap<String, Instant>, // Nodes that could not be reached, and since when
+}
+
+// Point-in-time view of a node for operators
+#[derive(Debug, Clone)]
+pub struct NodeSummary {
+    pub node_id: String,
+    pub health: Option<NodeHealth>, // None until the node's first heartbeat
+    pub failed: bool,
+    pub draining: bool,
+    pub phi: f64,
+    pub capacity: (u64, u64, u64, u64),  // RAM (MB), CPU (%), bandwidth (Mbps), storage (GB)
+    pub allocated: (u64, u64, u64, u64),
+    pub running_tasks: usize,
+}
+
+impl NodeSummary {
+    fn from_node(node: &Node, failed: bool, draining: bool) -> Self {
+        NodeSummary {
+            node_id: node.node_id.clone(),
+            health: None,
+            failed,
+            draining,
+            phi: 0.0,
+            capacity: (node.available_ram, node.available_cpu, node.available_bandwidth, node.available_storage),
+            allocated: (node.allocated_ram, node.allocated_cpu, node.allocated_bandwidth, node.allocated_storage),
+            running_tasks: 0,
+        }
+    }
+
+    pub fn to_proto(&self) -> NodeInfo {
+        let health = match (self.failed, self.health) {
+            (true, _) => "failed".to_string(),
+            (false, Some(health)) => format!("{:?}", health).to_lowercase(),
+            (false, None) => "unknown".to_string(),
+        };
+        NodeInfo {
+            node_id: self.node_id.clone(),
+            health,
+            draining: self.draining,
+            phi: self.phi,
+            capacity_ram: self.capacity.0,
+            capacity_cpu: self.capacity.1,
+            capacity_bandwidth: self.capacity.2,
+            capacity_storage: self.capacity.3,
+            allocated_ram: self.allocated.0,
+            allocated_cpu: self.allocated.1,
+            allocated_bandwidth: self.allocated.2,
+            allocated_storage: self.allocated.3,
+            running_tasks: self.running_tasks as u32,
+        }
+    }
+}
+
+// Operator requests about nodes, answered by the scheduler loop
+#[derive(Debug)]
+pub enum NodeCommand {
+    List { reply: oneshot::Sender<Vec<NodeSummary>> },
+    Drain { node_id: String, reply: oneshot::Sender<Result<NodeSummary, String>> },
+    Add { status: Box<NodeStatusResponse>, client: NodeClient }, // A registered node agent came online
+}
+
+impl Default for NodeController {
+    fn default() -> Self {
+        NodeController::new()
+    }
 }
 
 impl NodeController {
+    pub fn new() -> Self {
+        NodeController { nodes: HashMap::new(), failed_nodes: HashMap::new(), draining: HashSet::new(), suspected: HashMap::new() }
+    }
+
     // Register a node with the controller
     pub fn register_node(&mut self, node: Node) {
-        self.nodes.insert(node.node_id.clone(), node);
         println!("Node {} registered.", node.node_id);
+        self.nodes.insert(node.node_id.clone(), node);
     }
 
     // Mark node as failed if it doesn’t send heartbeat
@@ -21,75 +120,660 @@ impl NodeController {
         }
     }
 
+    // Bring a failed node back once its heartbeats resume
+    pub fn recover_node(&mut self, node_id: &str) {
+        if let Some(mut node) = self.failed_nodes.remove(node_id) {
+            // Its tasks were reassigned when it failed, so it comes back empty
+            node.allocated_ram = 0;
+            node.allocated_cpu = 0;
+            node.allocated_bandwidth = 0;
+            node.allocated_storage = 0;
+            node.running_tasks.clear();
+            self.nodes.insert(node_id.to_string(), node);
+            println!("Node {} recovered.", node_id);
+        }
+    }
+
     // Check if a node is available for task assignment
     pub fn is_node_available(&self, node_id: &str) -> bool {
         self.nodes.contains_key(node_id)
     }
 
-    // Get a list of available nodes for task assignment
-    pub fn get_available_nodes(&self) -> Vec<&Node> {
-        self.nodes.values().collect()
+    // Get a snapshot of available nodes for task assignment (draining and suspected nodes excluded)
+    pub fn get_available_nodes(&self) -> Vec<Node> {
+        self.nodes
+            .values()
+            .filter(|node| !self.draining.contains(&node.node_id) && !self.is_suspected(&node.node_id))
+            .cloned()
+            .collect()
+    }
+
+    // Place nothing on a node for a while, e.g. after a dispatch RPC to it failed
+    pub fn suspect_node(&mut self, node_id: &str) {
+        warn!("Node {} is unreachable, placing no tasks on it for {}s", node_id, SUSPECT_BACKOFF.as_secs());
+        self.suspected.insert(node_id.to_string(), Instant::now());
+    }
+
+    pub fn is_suspected(&self, node_id: &str) -> bool {
+        self.suspected.get(node_id).is_some_and(|since| since.elapsed() < SUSPECT_BACKOFF)
+    }
+
+    // Stop placing tasks on a node; its running tasks finish normally
+    pub fn drain_node(&mut self, node_id: &str) -> Result<(), String> {
+        if !self.nodes.contains_key(node_id) && !self.failed_nodes.contains_key(node_id) {
+            return Err(format!("Node {} not found", node_id));
+        }
+        if self.draining.insert(node_id.to_string()) {
+            println!("Node {} is draining.", node_id);
+        }
+        Ok(())
+    }
+
+    pub fn is_draining(&self, node_id: &str) -> bool {
+        self.draining.contains(node_id)
+    }
+
+    // Summaries of every known node, failed ones included
+    pub fn summaries(&self) -> Vec<NodeSummary> {
+        let active = self.nodes.values().map(|node| NodeSummary::from_node(node, false, self.is_draining(&node.node_id)));
+        let failed = self.failed_nodes.values().map(|node| NodeSummary::from_node(node, true, self.is_draining(&node.node_id)));
+        let mut summaries: Vec<NodeSummary> = active.chain(failed).collect();
+        summaries.sort_by(|a, b| a.node_id.cmp(&b.node_id));
+        summaries
+    }
+
+    // Write back node state (e.g. allocations made by the load balancer on a snapshot)
+    pub fn update_nodes(&mut self, nodes: Vec<Node>) {
+        for node in nodes {
+            if let Some(existing) = self.nodes.get_mut(&node.node_id) {
+                *existing = node;
+            }
+        }
+    }
+
+    // Get a mutable handle on a single node
+    pub fn get_node_mut(&mut self, node_id: &str) -> Option<&mut Node> {
+        self.nodes.get_mut(node_id)
+    }
+
+    // Handle node failure: requeue its tasks so the scheduler reassigns them, then mark it failed
+    pub fn handle_node_failure(&mut self, node_id: &str, task_tracker: &mut TaskTracker, task_queue: &mut TaskQueue) {
+        for task_id in task_tracker.tasks_on_node(node_id) {
+            println!("Reassigning Task {} from failed Node {}", task_id, node_id);
+            if let Some(mut task) = task_tracker.take_task(&task_id) {
+                task.assigned_node_id = None;
+                task_queue.enqueue(task);
+            }
+        }
+
+        // Mark the node as failed
+        self.mark_node_as_failed(node_id);
     }
 
-    // Handle node failure and reassign tasks
-    pub fn handle_node_failure(&mut self, node_id: &str, task_tracker: &mut TaskTracker, load_balancer: &mut LoadBalancer, task_queue: &mut TaskQueue) {
-        // Get all tasks assigned to the failed node
-        for (task_id, assigned_node) in task_tracker.task_node_map.clone() {
-            if assigned_node == node_id {
-                println!("Reassigning Task {} from failed Node {}", task_id, node_id);
+    // Add a new node dynamically
+    pub fn add_node(&mut self, node: Node) {
+        println!("Node {} added to the network.", node.node_id);
+        self.nodes.insert(node.node_id.clone(), node);
+    }
 
-                // Retrieve task and push it back to the task queue
-                if let Some(task) = task_queue.get_task_by_id(&task_id) {
+    // Remove a node from the network, requeueing its tasks
+    pub fn remove_node(&mut self, node_id: &str, task_tracker: &mut TaskTracker, task_queue: &mut TaskQueue) {
+        self.draining.remove(node_id);
+        if self.nodes.remove(node_id).is_some() {
+            println!("Node {} removed from the network.", node_id);
+            // Reassign tasks from the removed node
+            for task_id in task_tracker.tasks_on_node(node_id) {
+                if let Some(mut task) = task_tracker.take_task(&task_id) {
+                    task.assigned_node_id = None;
                     task_queue.enqueue(task);
-                    task_tracker.remove_task_assignment(&task_id);
-
-                    // Reassign task to a different node
-                    let available_nodes = self.get_available_nodes();
-                    if let Some(node_id) = load_balancer.assign_task_to_node(&task, &mut available_nodes) {
-                        println!("Task {} reassigned to Node {}", task_id, node_id);
-                    } else {
-                        println!("No available nodes for reassignment of Task {}", task_id);
+                }
+            }
+        } else {
+            println!("Node {} not found in the network.", node_id);
+        }
+    }
+}
+
+// Health of a node as seen by the failure detector
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NodeHealth {
+    Healthy,
+    Suspect,
+    Failed,
+    Recovered, // Heartbeats resumed after a failure
+}
+
+// A node moving between health states
+#[derive(Debug, Clone)]
+pub struct HealthTransition {
+    pub node_id: String,
+    pub from: NodeHealth,
+    pub to: NodeHealth,
+    pub phi: f64,
+}
+
+// Phi-accrual detector thresholds
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FailureDetectorConfig {
+    pub suspect_phi: f64,            // Phi at which a node becomes Suspect
+    pub failed_phi: f64,             // Phi at which a Suspect node becomes Failed
+    pub expected_interval_secs: f64, // Heartbeat interval assumed before any history exists
+    pub min_std_dev_secs: f64,       // Floor on the interval deviation so jitter-free nodes aren't hair-trigger
+    pub window: usize,               // Number of intervals kept per node
+}
+
+impl Default for FailureDetectorConfig {
+    fn default() -> Self {
+        FailureDetectorConfig {
+            suspect_phi: 3.0,
+            failed_phi: 8.0,
+            expected_interval_secs: 5.0,
+            min_std_dev_secs: 0.5,
+            window: 100,
+        }
+    }
+}
+
+struct HeartbeatHistory {
+    last_seen: Instant,
+    intervals: VecDeque<f64>, // Seconds between consecutive heartbeats
+    health: NodeHealth,
+}
+
+// Records the last-seen time of each node and runs a phi-accrual failure detector over it
+pub struct HeartbeatTracker {
+    config: FailureDetectorConfig,
+    nodes: HashMap<String, HeartbeatHistory>,
+}
+
+impl HeartbeatTracker {
+    pub fn new(config: FailureDetectorConfig) -> Self {
+        HeartbeatTracker { config, nodes: HashMap::new() }
+    }
+
+    // Update thresholds without losing heartbeat history
+    pub fn set_config(&mut self, config: FailureDetectorConfig) {
+        self.config = config;
+    }
+
+    // Record a heartbeat; a failed node that reports again becomes Recovered
+    pub fn record_heartbeat(&mut self, node_id: &str) {
+        let now = Instant::now();
+        let window = self.config.window;
+
+        match self.nodes.get_mut(node_id) {
+            Some(history) => {
+                if history.health == NodeHealth::Failed {
+                    // The outage is not a normal interval, keep it out of the history
+                    info!("Node {} is sending heartbeats again", node_id);
+                    history.health = NodeHealth::Recovered;
+                } else {
+                    history.intervals.push_back((now - history.last_seen).as_secs_f64());
+                    if history.intervals.len() > window {
+                        history.intervals.pop_front();
                     }
                 }
+                history.last_seen = now;
             }
-            // Mark the node as failed
-        self.mark_node_as_failed(node_id);
+            None => {
+                self.nodes.insert(
+                    node_id.to_string(),
+                    HeartbeatHistory { last_seen: now, intervals: VecDeque::new(), health: NodeHealth::Healthy },
+                );
+            }
+        }
+    }
+
+    pub fn health(&self, node_id: &str) -> Option<NodeHealth> {
+        self.nodes.get(node_id).map(|history| history.health)
+    }
+
+    pub fn last_seen(&self, node_id: &str) -> Option<Instant> {
+        self.nodes.get(node_id).map(|history| history.last_seen)
+    }
+
+    // Suspicion level of a node right now
+    pub fn phi(&self, node_id: &str) -> Option<f64> {
+        self.nodes.get(node_id).map(|history| Self::compute_phi(&self.config, history, Instant::now()))
+    }
+
+    // Re-evaluate every node and return the state changes
+    pub fn evaluate(&mut self) -> Vec<HealthTransition> {
+        let now = Instant::now();
+        let mut transitions = Vec::new();
+
+        for (node_id, history) in self.nodes.iter_mut() {
+            let phi = Self::compute_phi(&self.config, history, now);
+            let next = match history.health {
+                NodeHealth::Healthy | NodeHealth::Recovered if phi >= self.config.suspect_phi => NodeHealth::Suspect,
+                NodeHealth::Recovered => NodeHealth::Healthy,
+                NodeHealth::Suspect if phi >= self.config.failed_phi => NodeHealth::Failed,
+                NodeHealth::Suspect if phi < self.config.suspect_phi => NodeHealth::Healthy,
+                current => current,
+            };
+
+            // Recovered is reported once so the controller can re-add the node
+            let reported = if history.health == NodeHealth::Recovered && next == NodeHealth::Healthy {
+                Some(NodeHealth::Recovered)
+            } else if next != history.health {
+                Some(next)
+            } else {
+                None
+            };
+
+            if let Some(to) = reported {
+                match to {
+                    NodeHealth::Failed => warn!("Node {} failed (phi {:.1})", node_id, phi),
+                    NodeHealth::Suspect => warn!("Node {} suspected (phi {:.1})", node_id, phi),
+                    _ => info!("Node {} is {:?} (phi {:.1})", node_id, to, phi),
+                }
+                transitions.push(HealthTransition { node_id: node_id.clone(), from: history.health, to, phi });
+            }
+            history.health = next;
+        }
+
+        transitions
+    }
+
+    // phi = -log10(P(heartbeat arrives later than now)), assuming normally distributed intervals
+    fn compute_phi(config: &FailureDetectorConfig, history: &HeartbeatHistory, now: Instant) -> f64 {
+        let elapsed = (now - history.last_seen).as_secs_f64();
+        let (mean, std_dev) = if history.intervals.is_empty() {
+            (config.expected_interval_secs, config.expected_interval_secs / 4.0)
+        } else {
+            let n = history.intervals.len() as f64;
+            let mean = history.intervals.iter().sum::<f64>() / n;
+            let variance = history.intervals.iter().map(|i| (i - mean).powi(2)).sum::<f64>() / n;
+            (mean, variance.sqrt())
+        };
+        let std_dev = std_dev.max(config.min_std_dev_secs);
+
+        // Logistic approximation of the normal CDF
+        let y = (elapsed - mean) / std_dev;
+        let e = (-y * (1.5976 + 0.070566 * y * y)).exp();
+        let p_later = if elapsed > mean { e / (1.0 + e) } else { 1.0 - 1.0 / (1.0 + e) };
+        -p_later.max(f64::MIN_POSITIVE).log10()
     }
 }
 
-// Add a new node dynamically
-pub fn add_node(&mut self, node: Node) {
-    self.nodes.insert(node.node_id.clone(), node);
-    println!("Node {} added to the network.", node.node_id);
+// Controller-side RPCs: heartbeats feed the failure detector, pushed task events
+// are forwarded to the scheduler, and clients collect task results.
+// On a follower replica only heartbeats are answered, with the leader's URL.
+// With TLS, nodes may only speak for the node_id in their certificate and operator
+// RPCs need an operator certificate. Without TLS, operator RPCs go by the caller's token.
+pub struct ControllerNodeService {
+    tracker: Arc<Mutex<HeartbeatTracker>>,
+    events: mpsc::Sender<SchedulerEvent>,
+    results: Arc<Mutex<ResultStore>>,
+    leadership: Leadership,
+    authority: Option<Arc<Mutex<CertificateAuthority>>>, // Renews and revokes node certificates
+    shared_registry: Option<SharedRegistry>,             // Where issued and revoked certificates go for other replicas
+    accounts: Option<Arc<Mutex<UserManager>>>,           // Users, join tokens and registered nodes
+    node_tls: Option<TlsSettings>,                       // For dialing registered nodes
+    join_token_ttl: Duration,
+    joined: Arc<Mutex<HashSet<String>>>, // Registered nodes handed to the scheduler (or being dialed)
+    auth: Option<Authenticator>,         // Checks tokens on operator RPCs made without a certificate
 }
 
-// Remove a node from the network
-pub fn remove_node(&mut self, node_id: &str) {
-    if let Some(node) = self.nodes.remove(node_id) {
-        println!("Node {} removed from the network.", node_id);
-        // Reassign tasks from the removed node
-        self.reassign_tasks_from_node(&node);
-    } else {
-        println!("Node {} not found in the network.", node_id);
+impl ControllerNodeService {
+    pub fn new(
+        tracker: Arc<Mutex<HeartbeatTracker>>,
+        events: mpsc::Sender<SchedulerEvent>,
+        results: Arc<Mutex<ResultStore>>,
+    ) -> Self {
+        ControllerNodeService {
+            tracker,
+            events,
+            results,
+            leadership: Leadership::always(),
+            authority: None,
+            shared_registry: None,
+            accounts: None,
+            node_tls: None,
+            join_token_ttl: Duration::from_secs(86400),
+            joined: Arc::new(Mutex::new(HashSet::new())),
+            auth: None,
+        }
+    }
+
+    pub fn with_leadership(mut self, leadership: Leadership) -> Self {
+        self.leadership = leadership;
+        self
+    }
+
+    pub fn with_authority(mut self, authority: Arc<Mutex<CertificateAuthority>>) -> Self {
+        self.authority = Some(authority);
+        self
+    }
+
+    pub fn with_shared_registry(mut self, registry: SharedRegistry) -> Self {
+        self.shared_registry = Some(registry);
+        self
+    }
+
+    async fn publish_certificates(&self, authority: &Mutex<CertificateAuthority>) -> Result<(), Status> {
+        match &self.shared_registry {
+            Some(registry) => registry.publish(authority).await.map_err(Status::unavailable),
+            None => Ok(()),
+        }
+    }
+
+    // Accept CreateUser and CreateJoinToken, and bring registered nodes into the cluster
+    // when they first heartbeat. `tls` is what the controller dials node agents with.
+    pub fn with_accounts(mut self, accounts: Arc<Mutex<UserManager>>, tls: Option<TlsSettings>, join_token_ttl: Duration) -> Self {
+        self.accounts = Some(accounts);
+        self.node_tls = tls;
+        self.join_token_ttl = join_token_ttl;
+        self
+    }
+
+    pub fn with_auth(mut self, auth: Authenticator) -> Self {
+        self.auth = Some(auth);
+        self
+    }
+
+    // An operator certificate may do anything; otherwise the caller's token must allow `action`
+    fn authorize<T>(&self, request: &Request<T>, action: Action, resource: Resource) -> Result<(), Status> {
+        if require_role(request, &[PeerRole::Operator])?.is_some() {
+            return Ok(());
+        }
+        match &self.auth {
+            Some(auth) => policy::enforce(auth.authenticate(request)?.as_ref(), action, resource),
+            None => Ok(()),
+        }
+    }
+
+    // Owner of the job a task result belongs to; None for tasks not submitted as jobs
+    async fn job_owner(&self, task_id: &str) -> Result<Option<String>, Status> {
+        let (reply, response) = oneshot::channel();
+        self.events
+            .send(SchedulerEvent::Job(JobCommand::Get { job_id: task_id.to_string(), reply }))
+            .await
+            .map_err(|_| Status::unavailable("Scheduler is shut down"))?;
+        let record = response.await.map_err(|_| Status::internal("Scheduler dropped the request"))?;
+        Ok(record.map(|record| record.owner))
+    }
+
+    // Dial a registered node the first time it heartbeats and hand it to the scheduler
+    fn join_registered(&self, node_id: &str) {
+        let accounts = match &self.accounts {
+            Some(accounts) => accounts,
+            None => return,
+        };
+        let node_url = match accounts.lock().unwrap().node(node_id) {
+            Some(registration) => registration.node_url.clone(),
+            None => return, // Listed in network.nodes, or unknown
+        };
+        if !self.joined.lock().unwrap().insert(node_id.to_string()) {
+            return;
+        }
+
+        let (node_id, tls, events, joined) = (node_id.to_string(), self.node_tls.clone(), self.events.clone(), self.joined.clone());
+        tokio::spawn(async move {
+            let joining = async {
+                let mut client = NodeClient::connect(node_url.clone(), tls.as_ref()).await.map_err(|e| e.to_string())?;
+                let status = client.get_node_status().await.map_err(|e| e.message().to_string())?;
+                if status.node_id != node_id {
+                    return Err(format!("{} answers as node {}", node_url, status.node_id));
+                }
+                info!("Node {} at {} sells {}MB RAM, {}% CPU, {}Mbps", node_id, node_url, status.available_ram, status.available_cpu, status.available_bandwidth);
+                events.send(SchedulerEvent::Node(NodeCommand::Add { status: Box::new(status), client })).await.map_err(|_| "Scheduler is shut down".to_string())?;
+                Ok::<(), String>(())
+            };
+            if let Err(err) = joining.await {
+                // Try again on the next heartbeat
+                warn!("Could not add registered node {}: {}", node_id, err);
+                joined.lock().unwrap().remove(&node_id);
+            }
+        });
+    }
+
+    // Check that `peer`'s certificate names `node_id`, without asking the CA about it
+    fn check_node_identity(peer: Option<&PeerIdentity>, node_id: &str) -> Result<(), Status> {
+        match peer {
+            Some(peer) if peer.role != PeerRole::Node || peer.name != node_id => {
+                warn!("{} certificate for {} tried to act as node {}", peer.role.as_str(), peer.name, node_id);
+                Err(Status::permission_denied(format!("Certificate is not valid for node {}", node_id)))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Check that `peer` holds a valid certificate for `node_id`; true once it should be renewed
+    fn authenticate_node(&self, peer: Option<&PeerIdentity>, node_id: &str) -> Result<bool, Status> {
+        Self::check_node_identity(peer, node_id)?;
+        let peer = match peer {
+            Some(peer) => peer,
+            None => return Ok(false), // Plaintext: TLS is off
+        };
+        match &self.authority {
+            Some(authority) => {
+                let authority = authority.lock().unwrap();
+                authority.verify(peer).map_err(Status::permission_denied)?;
+                Ok(authority.needs_renewal(peer))
+            }
+            None => Ok(false),
+        }
     }
 }
 
-// Reassign tasks when a node is removed
-fn reassign_tasks_from_node(&mut self, node: &Node) {
-    let mut tasks_to_reassign = vec![];
+#[tonic::async_trait]
+impl ControllerService for ControllerNodeService {
+    async fn heartbeat(
+        &self,
+        request: Request<HeartbeatRequest>,
+    ) -> Result<Response<HeartbeatResponse>, Status> {
+        let peer = PeerIdentity::of(&request)?;
+        let node_id = request.into_inner().node_id;
+        if !self.leadership.is_leader() {
+            // Send the node agent to the leader (empty while there is none; it retries here).
+            // Only the leader's registry knows every certificate, so it alone checks revocation.
+            Self::check_node_identity(peer.as_ref(), &node_id)?;
+            let leader_url = self.leadership.leader_url().unwrap_or_default();
+            return Ok(Response::new(HeartbeatResponse { healthy: false, leader_url, renew_certificate: false }));
+        }
+        let renew_certificate = self.authenticate_node(peer.as_ref(), &node_id)?;
+        self.tracker.lock().unwrap().record_heartbeat(&node_id);
+        self.join_registered(&node_id);
+        Ok(Response::new(HeartbeatResponse { healthy: true, leader_url: String::new(), renew_certificate }))
+    }
 
-    for (task_id, assigned_node) in task_tracker.task_node_map.iter() {
-        if assigned_node == &node.node_id {
-            tasks_to_reassign.push(task_id.clone());
+    async fn report_progress(
+        &self,
+        request: Request<Streaming<TaskEvent>>,
+    ) -> Result<Response<ReportProgressResponse>, Status> {
+        self.leadership.require_leader()?;
+        let peer = PeerIdentity::of(&request)?;
+        let mut stream = request.into_inner();
+        let mut events_received = 0;
+        while let Some(event) = stream.message().await? {
+            self.authenticate_node(peer.as_ref(), &event.node_id)?;
+            events_received += 1;
+            // A pushed event also proves the node is alive
+            self.tracker.lock().unwrap().record_heartbeat(&event.node_id);
+            if self.events.send(SchedulerEvent::Progress(event)).await.is_err() {
+                return Err(Status::unavailable("Scheduler is shut down"));
+            }
         }
+        Ok(Response::new(ReportProgressResponse { events_received }))
     }
 
-    // Reassign the tasks
-    for task_id in tasks_to_reassign {
-        if let Some(task) = task_queue.get_task_by_id(&task_id) {
-            let available_nodes = self.get_available_nodes();
-            load_balancer.assign_task_to_node(task, available_nodes);
+    async fn get_task_result(
+        &self,
+        request: Request<TaskResultRequest>,
+    ) -> Result<Response<TaskResultResponse>, Status> {
+        self.leadership.require_leader()?;
+        // Buyers may read the output of their own jobs
+        let owner = self.job_owner(&request.get_ref().task_id).await?;
+        let resource = owner.as_deref().map_or(Resource::Cluster, |owner| Resource::Job { owner });
+        self.authorize(&request, Action::ReadJob, resource)?;
+        let task_id = request.into_inner().task_id;
+        match self.results.lock().unwrap().get(&task_id) {
+            Some(result) => Ok(Response::new(result.to_response())),
+            None => Err(Status::not_found(format!("No result for Task {}", task_id))),
         }
     }
+
+    async fn list_nodes(
+        &self,
+        request: Request<ListNodesRequest>,
+    ) -> Result<Response<ListNodesResponse>, Status> {
+        self.leadership.require_leader()?;
+        self.authorize(&request, Action::ListNodes, Resource::Cluster)?;
+        let (reply, response) = oneshot::channel();
+        self.events
+            .send(SchedulerEvent::Node(NodeCommand::List { reply }))
+            .await
+            .map_err(|_| Status::unavailable("Scheduler is shut down"))?;
+        let nodes = response.await.map_err(|_| Status::internal("Scheduler dropped the request"))?;
+        Ok(Response::new(ListNodesResponse { nodes: nodes.iter().map(NodeSummary::to_proto).collect() }))
+    }
+
+    async fn drain_node(
+        &self,
+        request: Request<DrainNodeRequest>,
+    ) -> Result<Response<DrainNodeResponse>, Status> {
+        self.leadership.require_leader()?;
+        self.authorize(&request, Action::DrainNode, Resource::Cluster)?;
+        let node_id = request.into_inner().node_id;
+        let (reply, response) = oneshot::channel();
+        self.events
+            .send(SchedulerEvent::Node(NodeCommand::Drain { node_id, reply }))
+            .await
+            .map_err(|_| Status::unavailable("Scheduler is shut down"))?;
+        let summary = response
+            .await
+            .map_err(|_| Status::internal("Scheduler dropped the request"))?
+            .map_err(Status::not_found)?;
+        Ok(Response::new(DrainNodeResponse { node: Some(summary.to_proto()) }))
+    }
+
+    async fn reload_config(
+        &self,
+        request: Request<ReloadConfigRequest>,
+    ) -> Result<Response<ReloadConfigResponse>, Status> {
+        self.leadership.require_leader()?;
+        self.authorize(&request, Action::ChangeConfig, Resource::Cluster)?;
+        let (reply, response) = oneshot::channel();
+        self.events
+            .send(SchedulerEvent::ReloadConfig { reply })
+            .await
+            .map_err(|_| Status::unavailable("Scheduler is shut down"))?;
+        let diff = response
+            .await
+            .map_err(|_| Status::internal("Scheduler dropped the request"))?
+            .map_err(Status::invalid_argument)?;
+        Ok(Response::new(diff.to_response()))
+    }
+
+    async fn renew_certificate(
+        &self,
+        request: Request<RenewCertificateRequest>,
+    ) -> Result<Response<NodeCertificate>, Status> {
+        self.leadership.require_leader()?;
+        let authority = self.authority.as_ref().ok_or_else(|| Status::failed_precondition("TLS is not enabled on this controller"))?;
+        // The node is whoever the current, unrevoked certificate says it is
+        let peer = PeerIdentity::of(&request)?.ok_or_else(|| Status::unauthenticated("A client certificate is required"))?;
+        self.authenticate_node(Some(&peer), &peer.name)?;
+        let issued = authority
+            .lock()
+            .unwrap()
+            .issue_node(&peer.name, &request.into_inner().csr_pem)
+            .map_err(Status::invalid_argument)?;
+        self.publish_certificates(authority).await?;
+        info!("Renewed the certificate of node {}", peer.name);
+        Ok(Response::new(issued))
+    }
+
+    async fn revoke_node(
+        &self,
+        request: Request<RevokeNodeRequest>,
+    ) -> Result<Response<RevokeNodeResponse>, Status> {
+        self.leadership.require_leader()?;
+        self.authorize(&request, Action::RevokeNode, Resource::Cluster)?;
+        let authority = self.authority.as_ref().ok_or_else(|| Status::failed_precondition("TLS is not enabled on this controller"))?;
+        let node_id = request.into_inner().node_id;
+        let revoked = authority.lock().unwrap().revoke_node(&node_id).map_err(Status::internal)?;
+        // Not done until a replica taking over would refuse the node too
+        self.publish_certificates(authority).await?;
+        if revoked == 0 {
+            return Err(Status::not_found(format!("No valid certificate issued to node {}", node_id)));
+        }
+        Ok(Response::new(RevokeNodeResponse { revoked }))
+    }
+
+    async fn create_user(
+        &self,
+        request: Request<CreateUserRequest>,
+    ) -> Result<Response<UserInfo>, Status> {
+        self.authorize(&request, Action::ManageUsers, Resource::Cluster)?;
+        let accounts = self.accounts.as_ref().ok_or_else(|| Status::failed_precondition("Accounts are not enabled on this controller"))?;
+        let request = request.into_inner();
+        let role = UserRole::parse(&request.role).map_err(Status::invalid_argument)?;
+        let user = accounts.lock().unwrap().add_user(&request.email, role, &request.password).map_err(Status::invalid_argument)?;
+        Ok(Response::new(UserInfo { user_id: user.user_id, email: user.email, role: user.role.as_str().to_string() }))
+    }
+
+    async fn create_join_token(
+        &self,
+        request: Request<CreateJoinTokenRequest>,
+    ) -> Result<Response<JoinToken>, Status> {
+        self.authorize(&request, Action::CreateJoinToken, Resource::Account { user_id: &request.get_ref().owner_id })?;
+        let accounts = self.accounts.as_ref().ok_or_else(|| Status::failed_precondition("Accounts are not enabled on this controller"))?;
+        let request = request.into_inner();
+        let ttl = if request.ttl_secs == 0 { self.join_token_ttl } else { Duration::from_secs(request.ttl_secs) };
+        let (token, expires_at) = accounts
+            .lock()
+            .unwrap()
+            .create_join_token(&request.owner_id, ttl)
+            .map_err(Status::failed_precondition)?;
+        info!("Created a join token for user {}", request.owner_id);
+        Ok(Response::new(JoinToken { token, owner_id: request.owner_id, expires_at: expires_at as i64 }))
+    }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history(intervals: &[f64]) -> HeartbeatHistory {
+        HeartbeatHistory { last_seen: Instant::now(), intervals: intervals.iter().copied().collect(), health: NodeHealth::Healthy }
+    }
+
+    #[test]
+    fn phi_grows_with_silence() {
+        let config = FailureDetectorConfig::default();
+        let steady = history(&[1.0; 10]);
+        let phi = |after: f64| HeartbeatTracker::compute_phi(&config, &steady, steady.last_seen + Duration::from_secs_f64(after));
+        assert!(phi(0.5) < 1.0);
+        assert!(phi(1.0) < phi(2.0) && phi(2.0) < phi(3.0));
+        assert!(phi(3.0) > config.suspect_phi);
+        assert!(phi(10.0) > config.failed_phi);
+
+        // Jittery nodes get more slack for the same silence
+        let jittery = history(&[0.2, 1.8, 0.5, 1.5, 1.0, 0.1, 1.9]);
+        assert!(HeartbeatTracker::compute_phi(&config, &jittery, jittery.last_seen + Duration::from_secs(3)) < phi(3.0));
+    }
+
+    #[test]
+    fn silent_nodes_go_suspect_then_failed_and_recover() {
+        let config = FailureDetectorConfig { expected_interval_secs: 0.01, min_std_dev_secs: 0.001, ..FailureDetectorConfig::default() };
+        let mut tracker = HeartbeatTracker::new(config);
+        tracker.record_heartbeat("node_1");
+        assert!(tracker.evaluate().is_empty());
+
+        std::thread::sleep(Duration::from_millis(100));
+        let to = |transitions: Vec<HealthTransition>| transitions.into_iter().map(|t| t.to).collect::<Vec<_>>();
+        assert_eq!(to(tracker.evaluate()), [NodeHealth::Suspect]);
+        assert_eq!(to(tracker.evaluate()), [NodeHealth::Failed]);
+        assert!(tracker.evaluate().is_empty());
+
+        tracker.record_heartbeat("node_1");
+        assert_eq!(tracker.health("node_1"), Some(NodeHealth::Recovered));
+        assert_eq!(to(tracker.evaluate()), [NodeHealth::Recovered]);
+        assert_eq!(tracker.health("node_1"), Some(NodeHealth::Healthy));
+        // The outage is not taken as a normal interval
+        assert!(tracker.nodes["node_1"].intervals.is_empty());
+    }
 }
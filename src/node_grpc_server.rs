@@ -1,13 +1,159 @@
-use tonic::{transport::Server, Request, Response, Status};
-use node::node_service_server::{NodeService, NodeServiceServer};
-use node::{HeartbeatRequest, HeartbeatResponse, TaskRequest, TaskResponse, NodeStatusRequest, NodeStatusResponse};
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
+use tokio::task::AbortHandle;
+use tokio::time::interval;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::{Stream, StreamExt};
+use log::{info, warn};
+use tonic::{Request, Response, Status};
+use crate::controller_grpc_client::node::node_service_server::NodeService;
+use crate::controller_grpc_client::node::{HeartbeatRequest, HeartbeatResponse, TaskRequest, TaskResponse, NodeStatusRequest, NodeStatusResponse};
+use crate::controller_grpc_client::node::{CancelTaskRequest, PreemptTaskRequest, PreemptTaskResponse, TaskEvent, TaskState, TaskStatusRequest, TaskStatusResponse, WatchTasksRequest};
+use crate::controller_grpc_client::node::{ReloadConfigRequest, ReloadConfigResponse};
+use crate::config::{Config, ConfigDiff, ConfigSource, NODE_AGENT_KEYS};
+use crate::executor::{executor_for, ExecutorKind};
+use crate::host_metrics::{HostMetrics, HostSampler};
+use crate::job_service::valid_job_id;
+use crate::leader_election::ControllerEndpoint;
+use crate::node::Node;
+use crate::resource_enforcer::CgroupEnforcer;
+use crate::task::Task;
 
-pub mod node {
-    tonic::include_proto!("node");
+// Events buffered per subscriber before slow watchers start missing them
+const EVENT_BUFFER: usize = 256;
+// How often a running task's usage is published
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(2);
+
+// NodeService as run by the node agent on a seller's machine
+pub struct MyNodeService {
+    node_id: String,
+    node: Arc<Mutex<Node>>, // Sold capacity and what running tasks hold of it
+    host: Arc<Mutex<HostMetrics>>, // Latest host sample
+    tasks: Arc<Mutex<HashMap<String, TaskStatusResponse>>>, // Task ID -> latest status
+    enforcer: Option<Arc<CgroupEnforcer>>,
+    events: broadcast::Sender<TaskEvent>, // Task state transitions, fanned out to watchers
+    running: Arc<Mutex<HashMap<String, AbortHandle>>>, // Task ID -> handle used to cancel it
+    config: Arc<Mutex<Option<(ConfigSource, Config)>>>, // Where the running configuration came from, for reloads
+}
+
+impl MyNodeService {
+    pub fn new(node: Node) -> Self {
+        let (events, _) = broadcast::channel(EVENT_BUFFER);
+        MyNodeService {
+            node_id: node.node_id.clone(),
+            enforcer: node.enforcer.clone(),
+            node: Arc::new(Mutex::new(node)),
+            host: Arc::new(Mutex::new(HostMetrics::default())),
+            tasks: Arc::new(Mutex::new(HashMap::new())),
+            events,
+            running: Arc::new(Mutex::new(HashMap::new())),
+            config: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    // Allow ReloadConfig to re-read `source`; `config` is what the node was started with
+    pub fn with_config(self, source: ConfigSource, config: Config) -> Self {
+        *self.config.lock().unwrap() = Some((source, config));
+        self
+    }
+
+    // Reload whenever the config file changes
+    pub fn watch_config(&self, every: Duration) {
+        let mut changes = match self.config.lock().unwrap().as_ref() {
+            Some((source, _)) => source.watch(every),
+            None => return,
+        };
+        let node = self.node.clone();
+        let config = self.config.clone();
+        tokio::spawn(async move {
+            while changes.recv().await.is_some() {
+                if let Err(err) = reload_config(&node, &config) {
+                    warn!("Keeping the current configuration: {}", err);
+                }
+            }
+        });
+    }
+
+    // Sample host resources periodically and offer only what the host can spare
+    pub fn monitor_host(&self, mut sampler: HostSampler, every: Duration) {
+        let node = self.node.clone();
+        let host = self.host.clone();
+        // Sample right away so the first GetNodeStatus is already accurate
+        match sampler.sample() {
+            Ok(metrics) => {
+                node.lock().unwrap().refresh_capacity(&metrics);
+                *host.lock().unwrap() = metrics;
+            }
+            Err(err) => warn!("Could not sample host resources: {}", err),
+        }
+
+        tokio::spawn(async move {
+            let mut ticker = interval(every);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                match sampler.sample() {
+                    Ok(metrics) => {
+                        node.lock().unwrap().refresh_capacity(&metrics);
+                        *host.lock().unwrap() = metrics;
+                    }
+                    Err(err) => warn!("Could not sample host resources: {}", err),
+                }
+            }
+        });
+    }
+
+    // Whether subprocess tasks may run `command`; nothing is allowed without a config
+    fn allows_command(&self, command: &str) -> bool {
+        self.config.lock().unwrap().as_ref().is_some_and(|(_, config)| config.executor.allowed_commands.iter().any(|allowed| allowed == command))
+    }
+
+    // Sender for this node's task events, e.g. to push them to the controller
+    pub fn events(&self) -> broadcast::Sender<TaskEvent> {
+        self.events.clone()
+    }
 }
 
-#[derive(Default)]
-pub struct MyNodeService;
+// Re-read the config file and apply new sell limits. Running tasks keep what they hold;
+// lowered limits only stop new placements, raised ones are offered from the next host sample.
+fn reload_config(node: &Mutex<Node>, config: &Mutex<Option<(ConfigSource, Config)>>) -> Result<ConfigDiff, String> {
+    // Held throughout so concurrent reloads apply one after the other
+    let mut config = config.lock().unwrap();
+    let (source, current) = config.as_ref().ok_or("Node agent was not started from a config file")?;
+    let new = source.load()?;
+    let diff = ConfigDiff::between(current, &new, NODE_AGENT_KEYS);
+
+    if diff.touches("sell_limits") {
+        let limits = &new.sell_limits;
+        let mut node = node.lock().unwrap();
+        node.set_ram_storage_limits(limits.ram_mb, limits.storage_gb);
+        node.set_cpu_bandwidth_limits(limits.cpu_percent, limits.bandwidth_mbps);
+    }
+
+    if !diff.applied.is_empty() {
+        info!("Reloaded {}: applied {}", source.path().display(), diff.applied.join(", "));
+    }
+    if !diff.restart_required.is_empty() {
+        warn!("Changes to {} need a node agent restart", diff.restart_required.join(", "));
+    }
+    let source = source.clone();
+    *config = Some((source, new));
+    Ok(diff)
+}
+
+// Build a task event stamped with the node's clock
+fn task_event(node_id: &str, task_id: &str, state: TaskState) -> TaskEvent {
+    TaskEvent {
+        task_id: task_id.to_string(),
+        node_id: node_id.to_string(),
+        state: state as i32,
+        timestamp_ms: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64),
+        ..Default::default()
+    }
+}
 
 #[tonic::async_trait]
 impl NodeService for MyNodeService {
@@ -18,17 +164,133 @@ impl NodeService for MyNodeService {
         let node_id = request.into_inner().node_id;
         println!("Heartbeat received from Node: {}", node_id);
 
-        Ok(Response::new(HeartbeatResponse { healthy: true }))
+        Ok(Response::new(HeartbeatResponse { healthy: true, leader_url: String::new(), renew_certificate: false }))
     }
 
     async fn assign_task(
         &self,
         request: Request<TaskRequest>,
     ) -> Result<Response<TaskResponse>, Status> {
-        let task_id = request.into_inner().task_id;
-        println!("Task {} assigned", task_id);
+        let request = request.into_inner();
+        // Task IDs name the task's cgroup, so never accept one that could leave it
+        if !valid_job_id(&request.task_id) {
+            return Err(Status::invalid_argument(format!("Invalid task ID: {:?}", request.task_id)));
+        }
+        let executor = ExecutorKind::from_request(&request.executor, request.command, request.args)
+            .map_err(Status::invalid_argument)?;
+        if let ExecutorKind::Subprocess { command, .. } = &executor {
+            if !self.allows_command(command) {
+                warn!("Rejecting Task {}: {} is not in executor.allowed_commands", request.task_id, command);
+                return Ok(Response::new(TaskResponse {
+                    success: false,
+                    message: format!("Node {} does not run {}", self.node_id, command),
+                }));
+            }
+        }
+        let task = Task::new(&request.task_id, 0, request.required_ram, request.required_cpu, request.required_bandwidth, request.data)
+            .with_executor(executor);
+
+        // Only accept what fits in the capacity this node sells
+        {
+            let mut node = self.node.lock().unwrap();
+            // A retried assignment must not allocate twice or replace the first run's abort handle
+            if node.running_tasks.contains_key(&task.task_id) {
+                info!("Task {} is already running; ignoring the repeated assignment", task.task_id);
+                return Ok(Response::new(TaskResponse {
+                    success: true,
+                    message: "Task already running".to_string(),
+                }));
+            }
+            if !node.can_handle_task(&task) {
+                warn!("Rejecting Task {}: exceeds remaining sold capacity", task.task_id);
+                return Ok(Response::new(TaskResponse {
+                    success: false,
+                    message: "Insufficient capacity on node".to_string(),
+                }));
+            }
+            node.start_task(task.clone());
+        }
+        info!("Task {} assigned ({} executor)", task.task_id, task.executor.name());
+
+        self.tasks.lock().unwrap().insert(
+            task.task_id.clone(),
+            TaskStatusResponse { task_id: task.task_id.clone(), state: TaskState::Queued as i32, ..Default::default() },
+        );
+        // Sending only fails when nobody is watching
+        let _ = self.events.send(task_event(&self.node_id, &task.task_id, TaskState::Queued));
+
+        // Run the task in the background, publishing its transitions as it goes
+        let node_id = self.node_id.clone();
+        let node = self.node.clone();
+        let tasks = self.tasks.clone();
+        let enforcer = self.enforcer.clone();
+        let events = self.events.clone();
+        let running = self.running.clone();
+        let task_id = task.task_id.clone();
+        // Hold the lock until the handle is registered so a fast task can't finish first
+        let mut running_tasks = self.running.lock().unwrap();
+        let handle = tokio::spawn(async move {
+            if let Some(status) = tasks.lock().unwrap().get_mut(&task.task_id) {
+                status.state = TaskState::Running as i32;
+            }
+            let _ = events.send(task_event(&node_id, &task.task_id, TaskState::Running));
+
+            let executor = executor_for(&task.executor, enforcer.clone());
+            let execution = executor.execute(&task);
+            tokio::pin!(execution);
+            let mut ticker = interval(PROGRESS_INTERVAL);
+            ticker.tick().await;
+            let result = loop {
+                tokio::select! {
+                    result = &mut execution => break result,
+                    _ = ticker.tick() => {
+                        // Usage is only measured for tasks running under the enforcer
+                        let mut event = task_event(&node_id, &task.task_id, TaskState::Running);
+                        if let Some(usage) = enforcer.as_ref().and_then(|e| e.latest_usage(&task.task_id)) {
+                            event.memory_mb = usage.memory_mb;
+                            event.cpu_percent = usage.cpu_percent;
+                        }
+                        let _ = events.send(event);
+                    }
+                }
+            };
+
+            let status = match result {
+                Ok(output) => TaskStatusResponse {
+                    task_id: task.task_id.clone(),
+                    state: if output.success() { TaskState::Succeeded } else { TaskState::Failed } as i32,
+                    exit_code: output.exit_code,
+                    stdout: output.stdout,
+                    stderr: output.stderr,
+                    runtime_ms: output.runtime.as_millis() as u64,
+                    error: String::new(),
+                    peak_memory_mb: output.usage.as_ref().map_or(0, |u| u.peak_memory_mb),
+                    cpu_time_ms: output.usage.as_ref().map_or(0, |u| u.cpu_usage_usec / 1000),
+                },
+                Err(err) => TaskStatusResponse {
+                    task_id: task.task_id.clone(),
+                    state: TaskState::Failed as i32,
+                    exit_code: -1,
+                    error: err,
+                    ..Default::default()
+                },
+            };
+
+            let mut event = task_event(&node_id, &task.task_id, TaskState::from_i32(status.state).unwrap_or(TaskState::Failed));
+            event.exit_code = status.exit_code;
+            event.memory_mb = status.peak_memory_mb;
+            event.message = status.error.clone();
+            if status.state == TaskState::Succeeded as i32 {
+                event.progress_percent = 100;
+            }
+            tasks.lock().unwrap().insert(task.task_id.clone(), status);
+            running.lock().unwrap().remove(&task.task_id);
+            node.lock().unwrap().complete_task(&task.task_id);
+            let _ = events.send(event);
+        });
+        running_tasks.insert(task_id, handle.abort_handle());
+        drop(running_tasks);
 
-        // Simulate task processing (In reality, you'd assign this task to the node's queue)
         Ok(Response::new(TaskResponse {
             success: true,
             message: "Task assigned successfully".to_string(),
@@ -39,27 +301,230 @@ impl NodeService for MyNodeService {
         &self,
         _: Request<NodeStatusRequest>,
     ) -> Result<Response<NodeStatusResponse>, Status> {
-        // Respond with node status (in a real scenario, use actual node resource data)
+        // Capacity this node can sell right now (host resources capped by sell limits)
+        let node = self.node.lock().unwrap();
+        let host = self.host.lock().unwrap();
         Ok(Response::new(NodeStatusResponse {
-            node_id: "node_1".to_string(),
-            available_ram: 4096,
-            available_cpu: 80,
-            available_bandwidth: 50,
+            node_id: node.node_id.clone(),
+            available_ram: node.available_ram,
+            available_cpu: node.available_cpu,
+            available_bandwidth: node.available_bandwidth,
+            available_storage: node.available_storage,
+            allocated_ram: node.allocated_ram,
+            allocated_cpu: node.allocated_cpu,
+            allocated_bandwidth: node.allocated_bandwidth,
+            allocated_storage: node.allocated_storage,
+            running_tasks: node.running_tasks.len() as u32,
+            host_total_ram: host.total_ram_mb,
+            host_free_ram: host.free_ram_mb,
+            host_cpu_count: host.cpu_count,
+            host_cpu_utilisation: host.cpu_utilisation,
+            host_disk_free: host.disk_free_gb,
+            host_network_rx_mbps: host.network_rx_mbps,
+            host_network_tx_mbps: host.network_tx_mbps,
         }))
     }
+
+    async fn get_task_status(
+        &self,
+        request: Request<TaskStatusRequest>,
+    ) -> Result<Response<TaskStatusResponse>, Status> {
+        let task_id = request.into_inner().task_id;
+        let status = self.tasks.lock().unwrap().get(&task_id).cloned().unwrap_or(TaskStatusResponse {
+            task_id,
+            state: TaskState::Unknown as i32,
+            ..Default::default()
+        });
+        Ok(Response::new(status))
+    }
+
+    async fn cancel_task(
+        &self,
+        request: Request<CancelTaskRequest>,
+    ) -> Result<Response<TaskResponse>, Status> {
+        let task_id = request.into_inner().task_id;
+        let handle = match self.running.lock().unwrap().remove(&task_id) {
+            Some(handle) => handle,
+            None => {
+                return Ok(Response::new(TaskResponse { success: false, message: format!("Task {} is not running", task_id) }));
+            }
+        };
+
+        // Dropping the executor future kills its subprocess (kill_on_drop)
+        handle.abort();
+        self.node.lock().unwrap().complete_task(&task_id);
+        info!("Task {} cancelled", task_id);
+        let message = "Cancelled by the controller".to_string();
+        if let Some(status) = self.tasks.lock().unwrap().get_mut(&task_id) {
+            status.state = TaskState::Failed as i32;
+            status.exit_code = -1;
+            status.error = message.clone();
+        }
+        let mut event = task_event(&self.node_id, &task_id, TaskState::Failed);
+        event.exit_code = -1;
+        event.message = message.clone();
+        let _ = self.events.send(event);
+
+        Ok(Response::new(TaskResponse { success: true, message }))
+    }
+
+    async fn preempt_task(
+        &self,
+        request: Request<PreemptTaskRequest>,
+    ) -> Result<Response<PreemptTaskResponse>, Status> {
+        let PreemptTaskRequest { task_id, reason } = request.into_inner();
+        let handle = match self.running.lock().unwrap().remove(&task_id) {
+            Some(handle) => handle,
+            None => {
+                let message = format!("Task {} is not running", task_id);
+                return Ok(Response::new(PreemptTaskResponse { success: false, message, wasted_work_ms: 0 }));
+            }
+        };
+
+        // Executors keep no state between runs, so the task starts over wherever it is placed next
+        handle.abort();
+        let wasted_work_ms = self.node.lock().unwrap().preempt_task(&task_id).map_or(0, |lost| lost.as_millis() as u64);
+        if let Some(status) = self.tasks.lock().unwrap().get_mut(&task_id) {
+            status.state = TaskState::Preempted as i32;
+            status.error = reason.clone();
+        }
+        let mut event = task_event(&self.node_id, &task_id, TaskState::Preempted);
+        event.message = reason;
+        event.wasted_work_ms = wasted_work_ms;
+        let _ = self.events.send(event);
+
+        Ok(Response::new(PreemptTaskResponse { success: true, message: format!("Task {} preempted", task_id), wasted_work_ms }))
+    }
+
+    async fn reload_config(
+        &self,
+        _: Request<ReloadConfigRequest>,
+    ) -> Result<Response<ReloadConfigResponse>, Status> {
+        let diff = reload_config(&self.node, &self.config).map_err(Status::invalid_argument)?;
+        Ok(Response::new(diff.to_response()))
+    }
+
+    type WatchTasksStream = Pin<Box<dyn Stream<Item = Result<TaskEvent, Status>> + Send + 'static>>;
+
+    async fn watch_tasks(
+        &self,
+        request: Request<WatchTasksRequest>,
+    ) -> Result<Response<Self::WatchTasksStream>, Status> {
+        let task_ids = request.into_inner().task_ids;
+        let stream = BroadcastStream::new(self.events.subscribe()).filter_map(move |event| match event {
+            Ok(event) if task_ids.is_empty() || task_ids.contains(&event.task_id) => Some(Ok(event)),
+            Ok(_) => None,
+            // A lagging watcher must resync through GetTaskStatus
+            Err(err) => Some(Err(Status::data_loss(err.to_string()))),
+        });
+        Ok(Response::new(Box::pin(stream)))
+    }
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let addr = "[::1]:50051".parse().unwrap();
-    let node_service = MyNodeService::default();
+// Push this node's task events to the controller, reconnecting when the stream breaks
+pub async fn report_progress(controller: ControllerEndpoint, events: broadcast::Sender<TaskEvent>, retry: Duration) {
+    loop {
+        // Heartbeats keep `controller` pointed at the current leader
+        let controller_addr = controller.current();
+        match controller.connect(&controller_addr).await {
+            Ok(mut client) => {
+                let outbound = BroadcastStream::new(events.subscribe()).filter_map(|event| event.ok());
+                match client.report_progress(outbound).await {
+                    Ok(response) => info!("Progress stream closed after {} events", response.into_inner().events_received),
+                    Err(status) => warn!("Progress stream to {} failed: {}", controller_addr, status),
+                }
+            }
+            Err(err) => warn!("Could not reach controller at {}: {}", controller_addr, err),
+        }
+        tokio::time::sleep(retry).await;
+    }
+}
 
-    println!("Node gRPC Server listening on {}", addr);
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    Server::builder()
-        .add_service(NodeServiceServer::new(node_service))
-        .serve(addr)
-        .await?;
+    fn service(allowed_commands: &[&str]) -> MyNodeService {
+        let mut config = Config::default();
+        config.executor.allowed_commands = allowed_commands.iter().map(|command| command.to_string()).collect();
+        let source = ConfigSource::new("does/not/exist.toml", Vec::new());
+        MyNodeService::new(Node::new("node_1", 1024, 10, 100, 10)).with_config(source, config)
+    }
 
-    Ok(())
+    fn task_request(task_id: &str, executor: &str, command: &str) -> Request<TaskRequest> {
+        Request::new(TaskRequest {
+            task_id: task_id.to_string(),
+            required_ram: 64,
+            required_cpu: 10,
+            required_bandwidth: 1,
+            executor: executor.to_string(),
+            command: command.to_string(),
+            ..Default::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn subprocess_tasks_need_an_allowed_command() {
+        let node = service(&[]);
+        let response = node.assign_task(task_request("t1", "subprocess", "/bin/sh")).await.unwrap().into_inner();
+        assert!(!response.success);
+        assert!(node.tasks.lock().unwrap().is_empty());
+
+        let response = node.assign_task(task_request("t2", "in_process", "")).await.unwrap().into_inner();
+        assert!(response.success);
+    }
+
+    #[tokio::test]
+    async fn allowed_commands_match_exactly() {
+        let node = service(&["/bin/true"]);
+        assert!(node.allows_command("/bin/true"));
+        assert!(!node.allows_command("/bin/true2"));
+        assert!(!node.allows_command("true"));
+        assert!(!MyNodeService::new(Node::new("node_2", 1024, 10, 100, 10)).allows_command("/bin/true"));
+    }
+
+    #[tokio::test]
+    async fn repeated_assignments_of_a_running_task_are_ignored() {
+        let node = service(&["sleep"]);
+        let assign = || {
+            let mut request = task_request("t1", "subprocess", "sleep");
+            request.get_mut().args = vec!["30".to_string()];
+            request
+        };
+        assert!(node.assign_task(assign()).await.unwrap().into_inner().success);
+        let response = node.assign_task(assign()).await.unwrap().into_inner();
+        assert!(response.success);
+        assert_eq!(response.message, "Task already running");
+        assert_eq!(node.node.lock().unwrap().allocated_ram, 64);
+
+        // The first run can still be stopped
+        let preempt = Request::new(PreemptTaskRequest { task_id: "t1".to_string(), reason: "done".to_string() });
+        assert!(node.preempt_task(preempt).await.unwrap().into_inner().success);
+        assert_eq!(node.node.lock().unwrap().allocated_ram, 0);
+    }
+
+    #[tokio::test]
+    async fn preempted_tasks_release_their_resources() {
+        let node = service(&["sleep"]);
+        let mut events = node.events.subscribe();
+        let mut request = task_request("t1", "subprocess", "sleep");
+        request.get_mut().args = vec!["30".to_string()];
+        assert!(node.assign_task(request).await.unwrap().into_inner().success);
+        assert_eq!(node.node.lock().unwrap().allocated_ram, 64);
+
+        let preempt = |reason: &str| Request::new(PreemptTaskRequest { task_id: "t1".to_string(), reason: reason.to_string() });
+        let response = node.preempt_task(preempt("room for t2")).await.unwrap().into_inner();
+        assert!(response.success);
+        assert_eq!(node.node.lock().unwrap().allocated_ram, 0);
+        assert_eq!(node.tasks.lock().unwrap()["t1"].state, TaskState::Preempted as i32);
+        let event = loop {
+            let event = events.recv().await.unwrap();
+            if event.state == TaskState::Preempted as i32 {
+                break event;
+            }
+        };
+        assert_eq!(event.message, "room for t2");
+        assert_eq!(event.wasted_work_ms, response.wasted_work_ms);
+        assert!(!node.preempt_task(preempt("again")).await.unwrap().into_inner().success);
+    }
 }
@@ -0,0 +1,177 @@
+use log::warn;
+use tonic::Status;
+use crate::user_manager::{Principal, Scope, UserRole};
+
+// Something a request wants to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    // Buyers, on their own jobs
+    SubmitJob,
+    ReadJob, // Includes the job's output
+    CancelJob,
+    RunCommand, // Submit a subprocess job, which runs a program on the node's host
+    // Sellers, on their own nodes
+    CreateJoinToken,
+    OwnNode, // Be the owner a join token registers nodes for
+    SetSellLimits,
+    ReadEarnings,
+    // Operators, on the cluster
+    ListNodes,
+    DrainNode,
+    RevokeNode,
+    ChangeConfig,
+    ManageUsers,
+}
+
+impl Action {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Action::SubmitJob => "submit jobs",
+            Action::ReadJob => "read jobs",
+            Action::CancelJob => "cancel jobs",
+            Action::RunCommand => "run host commands",
+            Action::CreateJoinToken => "create join tokens",
+            Action::OwnNode => "own nodes",
+            Action::SetSellLimits => "set sell limits",
+            Action::ReadEarnings => "read earnings",
+            Action::ListNodes => "list nodes",
+            Action::DrainNode => "drain nodes",
+            Action::RevokeNode => "revoke nodes",
+            Action::ChangeConfig => "change the configuration",
+            Action::ManageUsers => "manage users",
+        }
+    }
+
+    // Roles that may do this to what they own; operators may do it to anything
+    fn roles(&self) -> &'static [UserRole] {
+        match self {
+            Action::SubmitJob | Action::CancelJob => &[UserRole::Buyer, UserRole::Operator],
+            Action::ReadJob => &[UserRole::Buyer, UserRole::Seller, UserRole::Operator],
+            Action::CreateJoinToken | Action::OwnNode | Action::SetSellLimits | Action::ReadEarnings => &[UserRole::Seller, UserRole::Operator],
+            Action::RunCommand | Action::ListNodes | Action::DrainNode | Action::RevokeNode | Action::ChangeConfig | Action::ManageUsers => &[UserRole::Operator],
+        }
+    }
+
+    // Scope an API token needs for this; None for actions that take a login session
+    fn scope(&self) -> Option<Scope> {
+        match self {
+            Action::SubmitJob | Action::RunCommand => Some(Scope::JobsSubmit),
+            Action::ReadJob => Some(Scope::JobsRead),
+            Action::CancelJob => Some(Scope::JobsCancel),
+            _ => None,
+        }
+    }
+}
+
+// What an action is done to, with the user it belongs to
+#[derive(Debug, Clone, Copy)]
+pub enum Resource<'a> {
+    Cluster, // The scheduler, the configuration, every node and account
+    Job { owner: &'a str },
+    Node { owner: &'a str },
+    Account { user_id: &'a str }, // E.g. the account a join token is for
+}
+
+impl<'a> Resource<'a> {
+    fn owner(&self) -> Option<&'a str> {
+        match self {
+            Resource::Cluster => None,
+            Resource::Job { owner } | Resource::Node { owner } => Some(*owner),
+            Resource::Account { user_id } => Some(*user_id),
+        }
+    }
+}
+
+// Whether `principal` may do `action` to `resource`: the role must allow the action, an API
+// token must carry its scope, and everyone but operators may only touch what they own
+pub fn check(principal: &Principal, action: Action, resource: Resource) -> Result<(), String> {
+    if !action.roles().contains(&principal.role) {
+        return Err(format!("A {} may not {}", principal.role.as_str(), action.as_str()));
+    }
+    match (action.scope(), &principal.token_id) {
+        (Some(scope), _) if !principal.has_scope(scope) => {
+            return Err(format!("The token lacks {} and may not {}", scope.as_str(), action.as_str()));
+        }
+        (None, Some(token_id)) => return Err(format!("API token {} may not {}; log in instead", token_id, action.as_str())),
+        _ => {}
+    }
+    if principal.role != UserRole::Operator && resource.owner() != Some(principal.user_id.as_str()) {
+        return Err(format!("{} may not {} for another user", principal.user_id, action.as_str()));
+    }
+    Ok(())
+}
+
+// `check` for an RPC. No principal means authentication is off and anything goes.
+pub fn enforce(principal: Option<&Principal>, action: Action, resource: Resource) -> Result<(), Status> {
+    match principal {
+        Some(principal) => check(principal, action, resource).map_err(|err| {
+            warn!("Denied: {}", err);
+            Status::permission_denied(err)
+        }),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(user_id: &str, role: UserRole) -> Principal {
+        Principal { user_id: user_id.to_string(), role, scopes: vec![Scope::JobsRead, Scope::JobsSubmit, Scope::JobsCancel], token_id: None }
+    }
+
+    fn api_token(user_id: &str, role: UserRole, scopes: Vec<Scope>) -> Principal {
+        Principal { user_id: user_id.to_string(), role, scopes, token_id: Some("tok_1".to_string()) }
+    }
+
+    #[test]
+    fn buyers_only_touch_their_own_jobs() {
+        let buyer = session("alice", UserRole::Buyer);
+        assert!(check(&buyer, Action::SubmitJob, Resource::Job { owner: "alice" }).is_ok());
+        assert!(check(&buyer, Action::CancelJob, Resource::Job { owner: "alice" }).is_ok());
+        assert!(check(&buyer, Action::ReadJob, Resource::Job { owner: "bob" }).is_err());
+        assert!(check(&buyer, Action::ReadJob, Resource::Cluster).is_err());
+    }
+
+    #[test]
+    fn roles_limit_actions() {
+        let seller = session("sam", UserRole::Seller);
+        assert!(check(&seller, Action::SubmitJob, Resource::Job { owner: "sam" }).is_err());
+        assert!(check(&seller, Action::ReadEarnings, Resource::Node { owner: "sam" }).is_ok());
+        assert!(check(&seller, Action::SetSellLimits, Resource::Node { owner: "other" }).is_err());
+        assert!(check(&session("alice", UserRole::Buyer), Action::CreateJoinToken, Resource::Account { user_id: "alice" }).is_err());
+        assert!(check(&seller, Action::DrainNode, Resource::Node { owner: "sam" }).is_err());
+    }
+
+    #[test]
+    fn operators_act_on_anything() {
+        let operator = session("root", UserRole::Operator);
+        assert!(check(&operator, Action::ReadJob, Resource::Job { owner: "alice" }).is_ok());
+        assert!(check(&operator, Action::RevokeNode, Resource::Cluster).is_ok());
+        assert!(check(&operator, Action::OwnNode, Resource::Account { user_id: "sam" }).is_ok());
+    }
+
+    #[test]
+    fn only_operators_run_host_commands() {
+        assert!(check(&session("alice", UserRole::Buyer), Action::RunCommand, Resource::Cluster).is_err());
+        assert!(check(&session("root", UserRole::Operator), Action::RunCommand, Resource::Cluster).is_ok());
+        let token = api_token("root", UserRole::Operator, vec![Scope::JobsRead]);
+        assert!(check(&token, Action::RunCommand, Resource::Cluster).is_err());
+    }
+
+    #[test]
+    fn api_tokens_need_the_scope_and_only_carry_job_scopes() {
+        let reader = api_token("alice", UserRole::Buyer, vec![Scope::JobsRead]);
+        assert!(check(&reader, Action::ReadJob, Resource::Job { owner: "alice" }).is_ok());
+        assert!(check(&reader, Action::SubmitJob, Resource::Job { owner: "alice" }).is_err());
+        let operator = api_token("root", UserRole::Operator, vec![Scope::JobsRead, Scope::JobsSubmit, Scope::JobsCancel]);
+        assert!(check(&operator, Action::ManageUsers, Resource::Cluster).is_err());
+    }
+
+    #[test]
+    fn enforce_allows_everything_without_auth() {
+        assert!(enforce(None, Action::ManageUsers, Resource::Cluster).is_ok());
+        let denied = enforce(Some(&session("alice", UserRole::Buyer)), Action::ManageUsers, Resource::Cluster).unwrap_err();
+        assert_eq!(denied.code(), tonic::Code::PermissionDenied);
+    }
+}
@@ -1,90 +1,614 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use log::{info, warn};
+use redis::aio::ConnectionManager;
 use redis::AsyncCommands;
+use tokio::sync::mpsc;
+use crate::certificate_authority::IssuedCert;
+use crate::config::RedisConfig;
+use crate::controller_grpc_client::NodeController as NodeClient;
+use crate::job_service::JobRecord;
+use crate::leader_election::Leadership;
+use crate::node_controller::NodeSummary;
+use crate::state_store::{ControllerState, JournalEntry};
+use crate::task::Task;
 
+// Compare-and-set scripts, so a replica never extends or drops a lease someone else took over.
+// Taking a lease bumps its epoch, which fences off writes from the holder's earlier terms.
+const ACQUIRE_LEASE: &str = r#"
+if redis.call("SET", KEYS[1], ARGV[1], "NX", "PX", ARGV[2]) then
+    return redis.call("INCR", KEYS[2])
+end
+return 0
+"#;
+const RENEW_LEASE: &str = r#"
+if redis.call("GET", KEYS[1]) == ARGV[1] then
+    return redis.call("PEXPIRE", KEYS[1], ARGV[2])
+end
+return 0
+"#;
+const RELEASE_LEASE: &str = r#"
+if redis.call("GET", KEYS[1]) == ARGV[1] then
+    return redis.call("DEL", KEYS[1])
+end
+return 0
+"#;
+// Run a batch of commands only while the lease is held in the same term. Each command is
+// passed as its argument count followed by its arguments.
+const FENCED_WRITE: &str = r#"
+if redis.call("GET", KEYS[1]) ~= ARGV[1] or redis.call("GET", KEYS[2]) ~= ARGV[2] then
+    return 0
+end
+local i = 3
+while i <= #ARGV do
+    local count = tonumber(ARGV[i])
+    redis.call(unpack(ARGV, i + 1, i + count))
+    i = i + count + 1
+end
+return 1
+"#;
+
+// Why a Redis operation failed
+#[derive(Debug)]
+pub enum RedisStoreError {
+    InvalidUrl(String),
+    Connection(redis::RedisError), // Server unreachable or connection dropped; worth retrying
+    Command(redis::RedisError),
+    Corrupt { key: String, reason: String }, // Stored value could not be decoded
+    Fenced { lease: String },                // The lease behind a fenced client moved on; nothing was written
+}
+
+impl fmt::Display for RedisStoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RedisStoreError::InvalidUrl(message) => write!(f, "invalid Redis URL: {}", message),
+            RedisStoreError::Connection(err) => write!(f, "Redis connection error: {}", err),
+            RedisStoreError::Command(err) => write!(f, "Redis command failed: {}", err),
+            RedisStoreError::Corrupt { key, reason } => write!(f, "corrupt value at {}: {}", key, reason),
+            RedisStoreError::Fenced { lease } => write!(f, "no longer holding lease {}", lease),
+        }
+    }
+}
+
+impl std::error::Error for RedisStoreError {}
+
+impl From<redis::RedisError> for RedisStoreError {
+    fn from(err: redis::RedisError) -> Self {
+        if err.is_io_error() || err.is_connection_refusal() || err.is_connection_dropped() || err.is_timeout() {
+            RedisStoreError::Connection(err)
+        } else {
+            RedisStoreError::Command(err)
+        }
+    }
+}
+
+// Node status as shared through Redis
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeStatus {
+    pub node_id: String,
+    pub health: String,
+    pub draining: bool,
+    pub capacity: (u64, u64, u64, u64), // RAM (MB), CPU (%), bandwidth (Mbps), storage (GB)
+    pub allocated: (u64, u64, u64, u64),
+    pub running_tasks: u64,
+}
+
+impl NodeStatus {
+    pub fn from_summary(summary: &NodeSummary) -> Self {
+        let info = summary.to_proto();
+        NodeStatus {
+            node_id: info.node_id,
+            health: info.health,
+            draining: info.draining,
+            capacity: summary.capacity,
+            allocated: summary.allocated,
+            running_tasks: summary.running_tasks as u64,
+        }
+    }
+
+    fn to_fields(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("health", self.health.clone()),
+            ("draining", self.draining.to_string()),
+            ("capacity_ram", self.capacity.0.to_string()),
+            ("capacity_cpu", self.capacity.1.to_string()),
+            ("capacity_bandwidth", self.capacity.2.to_string()),
+            ("capacity_storage", self.capacity.3.to_string()),
+            ("allocated_ram", self.allocated.0.to_string()),
+            ("allocated_cpu", self.allocated.1.to_string()),
+            ("allocated_bandwidth", self.allocated.2.to_string()),
+            ("allocated_storage", self.allocated.3.to_string()),
+            ("running_tasks", self.running_tasks.to_string()),
+        ]
+    }
+
+    fn from_fields(node_id: &str, key: &str, fields: &HashMap<String, String>) -> Result<Self, RedisStoreError> {
+        let text = |name: &str| {
+            fields.get(name).cloned().ok_or_else(|| RedisStoreError::Corrupt { key: key.to_string(), reason: format!("missing field {}", name) })
+        };
+        let number = |name: &str| {
+            text(name)?.parse::<u64>().map_err(|e| RedisStoreError::Corrupt { key: key.to_string(), reason: format!("{}: {}", name, e) })
+        };
+        Ok(NodeStatus {
+            node_id: node_id.to_string(),
+            health: text("health")?,
+            draining: text("draining")? == "true",
+            capacity: (number("capacity_ram")?, number("capacity_cpu")?, number("capacity_bandwidth")?, number("capacity_storage")?),
+            allocated: (number("allocated_ram")?, number("allocated_cpu")?, number("allocated_bandwidth")?, number("allocated_storage")?),
+            running_tasks: number("running_tasks")?,
+        })
+    }
+}
+
+// A lease held in one term. Writes made under it are refused once the lease is gone, even if
+// the same holder takes it again later.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fence {
+    pub lease: String,
+    pub holder: String,
+    pub epoch: u64, // Bumped every time the lease is taken
+}
+
+// Changes the controller pushes to Redis
+#[derive(Debug)]
+pub enum SharedUpdate {
+    Journal(Box<JournalEntry>),
+    Nodes(Vec<NodeSummary>), // Latest view of every node, refreshing their TTLs
+}
+
+// Shared controller state in Redis. Key layout, under `key_prefix`:
+//   nodes              set of registered node IDs
+//   node:<id>          hash of the node's status, expires unless refreshed
+//   tasks              set of unfinished task IDs
+//   task:<id>          unfinished task (JSON)
+//   task:<id>:node     node a task is assigned to
+//   node:<id>:tasks    set of tasks assigned to a node
+//   queue              sorted set of queued task IDs, scored by priority
+//   jobs               set of job IDs
+//   job:<id>           job record (JSON)
+//   lease:<name>       lease held by one replica (see leader_election)
+//   lease:<name>:epoch how often the lease was taken
+//   certificates       hash of certificates the CA issued (JSON), by serial number
+#[derive(Clone)]
 pub struct RedisClient {
-    client: redis::Client,
+    pool: Vec<ConnectionManager>, // Multiplexed, self-reconnecting connections, used round-robin
+    next: Arc<AtomicUsize>,
+    prefix: String,
+    node_ttl_secs: usize,
+    fence: Option<Fence>, // Writes only go through while this lease is held
 }
 
 impl RedisClient {
-    pub async fn new() -> Self {
-        let client = redis::Client::open("redis://127.0.0.1/").unwrap();
-        Self { client }
-    }
-
-    // Store node status
-    pub async fn set_node_status(
-        &self,
-        node_id: String,
-        ram: u64,
-        cpu: u64,
-        bandwidth: u64,
-    ) {
-        let mut conn = self.client.get_async_connection().await.unwrap();
-        let _: () = conn.hset_multiple(
-            format!("node_status:{}", node_id),
-            &[
-                ("available_ram", ram.to_string()),
-                ("available_cpu", cpu.to_string()),
-                ("available_bandwidth", bandwidth.to_string()),
-            ],
+    pub async fn connect(config: &RedisConfig) -> Result<Self, RedisStoreError> {
+        let client = redis::Client::open(config.url.as_str()).map_err(|e| RedisStoreError::InvalidUrl(e.to_string()))?;
+        let mut pool = Vec::with_capacity(config.pool_size);
+        for _ in 0..config.pool_size.max(1) {
+            pool.push(ConnectionManager::new(client.clone()).await?);
+        }
+        info!("Connected to Redis at {} ({} connections)", config.url, pool.len());
+        Ok(RedisClient {
+            pool,
+            next: Arc::new(AtomicUsize::new(0)),
+            prefix: config.key_prefix.clone(),
+            node_ttl_secs: config.node_ttl_secs as usize,
+            fence: None,
+        })
+    }
+
+    // Only write while `fence` still holds, e.g. while this replica leads
+    pub fn with_fence(mut self, fence: Fence) -> Self {
+        self.fence = Some(fence);
+        self
+    }
+
+    fn conn(&self) -> ConnectionManager {
+        let index = self.next.fetch_add(1, Ordering::Relaxed) % self.pool.len();
+        self.pool[index].clone()
+    }
+
+    fn key(&self, suffix: &str) -> String {
+        format!("{}{}", self.prefix, suffix)
+    }
+
+    // Run a batch of writes atomically, checking the fence first if there is one
+    async fn write(&self, pipe: &mut redis::Pipeline) -> Result<(), RedisStoreError> {
+        let fence = match &self.fence {
+            Some(fence) => fence,
+            None => return Ok(pipe.atomic().query_async(&mut self.conn()).await?),
+        };
+        let script = redis::Script::new(FENCED_WRITE);
+        let mut invocation = script.prepare_invoke();
+        invocation
+            .key(self.key(&format!("lease:{}", fence.lease)))
+            .key(self.key(&format!("lease:{}:epoch", fence.lease)))
+            .arg(&fence.holder)
+            .arg(fence.epoch);
+        for arg in fenced_args(pipe) {
+            invocation.arg(arg);
+        }
+        let written: i64 = invocation.invoke_async(&mut self.conn()).await?;
+        if written == 0 {
+            return Err(RedisStoreError::Fenced { lease: fence.lease.clone() });
+        }
+        Ok(())
+    }
+
+    // Register a node and store its status; the status expires unless refreshed within the TTL
+    pub async fn set_node_status(&self, status: &NodeStatus) -> Result<(), RedisStoreError> {
+        let key = self.key(&format!("node:{}", status.node_id));
+        self.write(
+            redis::pipe()
+                .sadd(self.key("nodes"), &status.node_id)
+                .ignore()
+                .hset_multiple(&key, &status.to_fields())
+                .ignore()
+                .expire(&key, self.node_ttl_secs)
+                .ignore(),
         )
         .await
-        .unwrap();
-    }
-
-    // Get node status
-    pub async fn get_node_status(&self, node_id: String) -> Option<(u64, u64, u64)> {
-        let mut conn = self.client.get_async_connection().await.unwrap();
-        let ram: u64 = conn.hget(format!("node_status:{}", node_id), "available_ram").await.unwrap();
-        let cpu: u64 = conn.hget(format!("node_status:{}", node_id), "available_cpu").await.unwrap();
-        let bandwidth: u64 = conn.hget(format!("node_status:{}", node_id), "available_bandwidth").await.unwrap();
-        Some((ram, cpu, bandwidth))
-    }
-
-    // Store task assignment
-    pub async fn assign_task_to_node(&self, task_id: String, node_id: String) {
-        let mut conn = self.client.get_async_connection().await.unwrap();
-        let _: () = conn.set(format!("task_assignment:{}", task_id), node_id).await.unwrap();
-    }
-
-    // Get task assignment
-    pub async fn get_task_assignment(&self, task_id: String) -> Option<String> {
-        let mut conn = self.client.get_async_connection().await.unwrap();
-        conn.get(format!("task_assignment:{}", task_id)).await.unwrap()
-    }
-
-    async fn distribute_tasks(
-        redis_client: &RedisClient,
-        controller: &mut NodeController,
-        task_id: String,
-        task_ram: u64,
-        task_cpu: u64,
-        task_bandwidth: u64,
-        task_data: Vec<u8>,
-    ) {
-        // Retrieve available nodes from Redis
-        let node_ids = vec!["node_1".to_string(), "node_2".to_string()];
-        
+    }
+
+    // None once the node's status has expired
+    pub async fn get_node_status(&self, node_id: &str) -> Result<Option<NodeStatus>, RedisStoreError> {
+        let key = self.key(&format!("node:{}", node_id));
+        let fields: HashMap<String, String> = self.conn().hgetall(&key).await?;
+        if fields.is_empty() {
+            return Ok(None);
+        }
+        NodeStatus::from_fields(node_id, &key, &fields).map(Some)
+    }
+
+    // Registered nodes whose status is still fresh; expired ones are dropped from the set
+    pub async fn live_nodes(&self) -> Result<Vec<NodeStatus>, RedisStoreError> {
+        let node_ids: Vec<String> = self.conn().smembers(self.key("nodes")).await?;
+        let mut live = Vec::new();
         for node_id in node_ids {
-            if let Some((available_ram, available_cpu, available_bandwidth)) = redis_client.get_node_status(node_id.clone()).await {
-                if available_ram >= task_ram && available_cpu >= task_cpu && available_bandwidth >= task_bandwidth {
-                    // Assign the task via gRPC
-                    let success = controller.assign_task_to_node(
-                        task_id.clone(),
-                        task_ram,
-                        task_cpu,
-                        task_bandwidth,
-                        task_data.clone(),
-                    ).await;
-    
-                    if success {
-                        // Store task assignment in Redis
-                        redis_client.assign_task_to_node(task_id.clone(), node_id.clone()).await;
-                        println!("Task {} assigned to Node {}", task_id, node_id);
+            match self.get_node_status(&node_id).await? {
+                Some(status) => live.push(status),
+                None => {
+                    info!("Node {} status expired, unregistering it", node_id);
+                    self.write(redis::pipe().srem(self.key("nodes"), &node_id).ignore()).await?;
+                }
+            }
+        }
+        Ok(live)
+    }
+
+    pub async fn remove_node(&self, node_id: &str) -> Result<(), RedisStoreError> {
+        self.write(redis::pipe().srem(self.key("nodes"), node_id).ignore().del(self.key(&format!("node:{}", node_id))).ignore()).await
+    }
+
+    // Store a task and queue it, dropping any earlier assignment
+    pub async fn enqueue_task(&self, task: &Task) -> Result<(), RedisStoreError> {
+        let data = serde_json::to_string(task).map_err(|e| RedisStoreError::Corrupt { key: task.task_id.clone(), reason: e.to_string() })?;
+        let previous = self.get_task_assignment(&task.task_id).await?;
+        let mut pipe = redis::pipe();
+        pipe.sadd(self.key("tasks"), &task.task_id)
+            .ignore()
+            .set(self.key(&format!("task:{}", task.task_id)), data)
+            .ignore()
+            .del(self.key(&format!("task:{}:node", task.task_id)))
+            .ignore()
+            .zadd(self.key("queue"), &task.task_id, task.priority as u32)
+            .ignore();
+        if let Some(node_id) = previous {
+            pipe.srem(self.key(&format!("node:{}:tasks", node_id)), &task.task_id).ignore();
+        }
+        self.write(&mut pipe).await
+    }
+
+    // Take the highest priority queued task; safe to call from several replicas at once
+    pub async fn dequeue_task(&self) -> Result<Option<Task>, RedisStoreError> {
+        let popped: Vec<(String, f64)> = self.conn().zpopmax(self.key("queue"), 1).await?;
+        match popped.into_iter().next() {
+            Some((task_id, _)) => self.get_task(&task_id).await,
+            None => Ok(None),
+        }
+    }
+
+    // Every queued task, highest priority first
+    pub async fn queued_tasks(&self) -> Result<Vec<Task>, RedisStoreError> {
+        let task_ids: Vec<String> = self.conn().zrevrange(self.key("queue"), 0, -1).await?;
+        let mut tasks = Vec::with_capacity(task_ids.len());
+        for task_id in task_ids {
+            if let Some(task) = self.get_task(&task_id).await? {
+                tasks.push(task);
+            }
+        }
+        Ok(tasks)
+    }
+
+    pub async fn get_task(&self, task_id: &str) -> Result<Option<Task>, RedisStoreError> {
+        let key = self.key(&format!("task:{}", task_id));
+        let data: Option<String> = self.conn().get(&key).await?;
+        data.map(|data| serde_json::from_str(&data).map_err(|e| RedisStoreError::Corrupt { key, reason: e.to_string() }))
+            .transpose()
+    }
+
+    // Record that a node accepted a task
+    pub async fn assign_task_to_node(&self, task_id: &str, node_id: &str) -> Result<(), RedisStoreError> {
+        self.write(
+            redis::pipe()
+                .zrem(self.key("queue"), task_id)
+                .ignore()
+                .set(self.key(&format!("task:{}:node", task_id)), node_id)
+                .ignore()
+                .sadd(self.key(&format!("node:{}:tasks", node_id)), task_id)
+                .ignore(),
+        )
+        .await
+    }
+
+    pub async fn get_task_assignment(&self, task_id: &str) -> Result<Option<String>, RedisStoreError> {
+        Ok(self.conn().get(self.key(&format!("task:{}:node", task_id))).await?)
+    }
+
+    pub async fn tasks_on_node(&self, node_id: &str) -> Result<Vec<String>, RedisStoreError> {
+        Ok(self.conn().smembers(self.key(&format!("node:{}:tasks", node_id))).await?)
+    }
+
+    // Forget a finished task
+    pub async fn complete_task(&self, task_id: &str) -> Result<(), RedisStoreError> {
+        let node_id = self.get_task_assignment(task_id).await?;
+        let mut pipe = redis::pipe();
+        pipe.srem(self.key("tasks"), task_id)
+            .ignore()
+            .zrem(self.key("queue"), task_id)
+            .ignore()
+            .del(self.key(&format!("task:{}", task_id)))
+            .ignore()
+            .del(self.key(&format!("task:{}:node", task_id)))
+            .ignore();
+        if let Some(node_id) = node_id {
+            pipe.srem(self.key(&format!("node:{}:tasks", node_id)), task_id).ignore();
+        }
+        self.write(&mut pipe).await
+    }
+
+    // Put every task of a failed node back in the queue
+    pub async fn requeue_node_tasks(&self, node_id: &str) -> Result<Vec<String>, RedisStoreError> {
+        let task_ids = self.tasks_on_node(node_id).await?;
+        for task_id in &task_ids {
+            if let Some(task) = self.get_task(task_id).await? {
+                self.enqueue_task(&task).await?;
+            }
+        }
+        self.write(redis::pipe().del(self.key(&format!("node:{}:tasks", node_id))).ignore()).await?;
+        Ok(task_ids)
+    }
+
+    pub async fn set_job(&self, record: &JobRecord) -> Result<(), RedisStoreError> {
+        let data = serde_json::to_string(record).map_err(|e| RedisStoreError::Corrupt { key: record.job_id.clone(), reason: e.to_string() })?;
+        self.write(redis::pipe().sadd(self.key("jobs"), &record.job_id).ignore().set(self.key(&format!("job:{}", record.job_id)), data).ignore()).await
+    }
+
+    pub async fn get_job(&self, job_id: &str) -> Result<Option<JobRecord>, RedisStoreError> {
+        let key = self.key(&format!("job:{}", job_id));
+        let data: Option<String> = self.conn().get(&key).await?;
+        data.map(|data| serde_json::from_str(&data).map_err(|e| RedisStoreError::Corrupt { key, reason: e.to_string() }))
+            .transpose()
+    }
+
+    // Everything a controller taking over needs: unfinished tasks, their assignments and all jobs
+    pub async fn load_state(&self) -> Result<ControllerState, RedisStoreError> {
+        let mut state = ControllerState::default();
+        let task_ids: Vec<String> = self.conn().smembers(self.key("tasks")).await?;
+        for task_id in task_ids {
+            let task = match self.get_task(&task_id).await? {
+                Some(task) => task,
+                None => continue,
+            };
+            if let Some(node_id) = self.get_task_assignment(&task_id).await? {
+                state.assigned.insert(task_id.clone(), node_id);
+            }
+            state.tasks.insert(task_id, task);
+        }
+        let job_ids: Vec<String> = self.conn().smembers(self.key("jobs")).await?;
+        for job_id in job_ids {
+            if let Some(record) = self.get_job(&job_id).await? {
+                state.jobs.insert(job_id, record);
+            }
+        }
+        Ok(state)
+    }
+
+    // Replace the shared record of issued certificates with the leader's
+    pub async fn share_certificates(&self, issued: &BTreeMap<u64, IssuedCert>) -> Result<(), RedisStoreError> {
+        let key = self.key("certificates");
+        let mut pipe = redis::pipe();
+        pipe.del(&key).ignore();
+        for (serial, cert) in issued {
+            let data = serde_json::to_string(cert).map_err(|e| RedisStoreError::Corrupt { key: key.clone(), reason: e.to_string() })?;
+            pipe.hset(&key, serial, data).ignore();
+        }
+        self.write(&mut pipe).await
+    }
+
+    pub async fn load_certificates(&self) -> Result<BTreeMap<u64, IssuedCert>, RedisStoreError> {
+        let key = self.key("certificates");
+        let fields: HashMap<u64, String> = self.conn().hgetall(&key).await?;
+        fields
+            .into_iter()
+            .map(|(serial, data)| {
+                let cert = serde_json::from_str(&data).map_err(|e| RedisStoreError::Corrupt { key: format!("{}[{}]", key, serial), reason: e.to_string() })?;
+                Ok((serial, cert))
+            })
+            .collect()
+    }
+
+    // Take the lease if nobody holds it, starting a new term
+    pub async fn acquire_lease(&self, name: &str, holder: &str, ttl: Duration) -> Result<Option<Fence>, RedisStoreError> {
+        let epoch: u64 = redis::Script::new(ACQUIRE_LEASE)
+            .key(self.key(&format!("lease:{}", name)))
+            .key(self.key(&format!("lease:{}:epoch", name)))
+            .arg(holder)
+            .arg(ttl.as_millis() as u64)
+            .invoke_async(&mut self.conn())
+            .await?;
+        Ok((epoch > 0).then(|| Fence { lease: name.to_string(), holder: holder.to_string(), epoch }))
+    }
+
+    // Extend the lease, only if `holder` still has it
+    pub async fn renew_lease(&self, name: &str, holder: &str, ttl: Duration) -> Result<bool, RedisStoreError> {
+        let renewed: i64 = redis::Script::new(RENEW_LEASE)
+            .key(self.key(&format!("lease:{}", name)))
+            .arg(holder)
+            .arg(ttl.as_millis() as u64)
+            .invoke_async(&mut self.conn())
+            .await?;
+        Ok(renewed == 1)
+    }
+
+    // Give the lease up early, only if `holder` still has it
+    pub async fn release_lease(&self, name: &str, holder: &str) -> Result<(), RedisStoreError> {
+        let _: i64 = redis::Script::new(RELEASE_LEASE)
+            .key(self.key(&format!("lease:{}", name)))
+            .arg(holder)
+            .invoke_async(&mut self.conn())
+            .await?;
+        Ok(())
+    }
+
+    // Current holder of the lease, if any
+    pub async fn lease_holder(&self, name: &str) -> Result<Option<String>, RedisStoreError> {
+        Ok(self.conn().get(self.key(&format!("lease:{}", name))).await?)
+    }
+
+    // Dispatch a task to the first live node with room for it, recording the assignment
+    pub async fn distribute_task(&self, clients: &mut HashMap<String, NodeClient>, task: &Task) -> Result<Option<String>, RedisStoreError> {
+        for node in self.live_nodes().await? {
+            let fits = node.capacity.0 >= node.allocated.0 + task.required_ram
+                && node.capacity.1 >= node.allocated.1 + task.required_cpu
+                && node.capacity.2 >= node.allocated.2 + task.required_bandwidth;
+            if node.draining || node.health == "failed" || !fits {
+                continue;
+            }
+            let client = match clients.get_mut(&node.node_id) {
+                Some(client) => client,
+                None => continue,
+            };
+            let accepted = client
+                .assign_task_to_node(task.task_id.clone(), task.required_ram, task.required_cpu, task.required_bandwidth, task.data.clone(), &task.executor)
+                .await;
+            if let Err(status) = &accepted {
+                warn!("Could not dispatch Task {} to Node {}: {}", task.task_id, node.node_id, status);
+            }
+            if accepted.unwrap_or(false) {
+                self.assign_task_to_node(&task.task_id, &node.node_id).await?;
+                println!("Task {} assigned to Node {}", task.task_id, node.node_id);
+                return Ok(Some(node.node_id));
+            }
+        }
+        Ok(None)
+    }
+
+    // Apply one controller state change
+    pub async fn apply(&self, update: SharedUpdate) -> Result<(), RedisStoreError> {
+        match update {
+            SharedUpdate::Journal(entry) => match *entry {
+                JournalEntry::Enqueued { task } => self.enqueue_task(&task).await,
+                JournalEntry::Assigned { task_id, node_id } => self.assign_task_to_node(&task_id, &node_id).await,
+                JournalEntry::Finished { task_id } => self.complete_task(&task_id).await,
+                JournalEntry::NodeFailed { node_id } => self.requeue_node_tasks(&node_id).await.map(|_| ()),
+                JournalEntry::Job { record } => self.set_job(&record).await,
+            },
+            SharedUpdate::Nodes(summaries) => {
+                // Failed nodes are no longer refreshed and expire on their own
+                for summary in summaries.iter().filter(|summary| !summary.failed) {
+                    self.set_node_status(&NodeStatus::from_summary(summary)).await?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    // Mirror controller state changes into Redis in order, without making the scheduler wait.
+    // Unbounded so that a slow Redis delays the mirror instead of losing updates. With replicas,
+    // writes are fenced by the leader lease and the mirror stops once this replica loses it.
+    pub fn spawn_mirror(self, mut leadership: Leadership) -> mpsc::UnboundedSender<SharedUpdate> {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let client = match leadership.fence() {
+            Some(fence) => self.with_fence(fence),
+            None => self,
+        };
+        tokio::spawn(async move {
+            loop {
+                let update = tokio::select! {
+                    update = rx.recv() => match update {
+                        Some(update) => update,
+                        None => break,
+                    },
+                    _ = leadership.lost() => {
+                        warn!("Stopped mirroring state to Redis, no longer the leader");
                         break;
                     }
+                };
+                match client.apply(update).await {
+                    Ok(()) => {}
+                    Err(RedisStoreError::Fenced { lease }) => {
+                        warn!("Stopped mirroring state to Redis, lease {} moved to another replica", lease);
+                        break;
+                    }
+                    Err(err) => warn!("Could not mirror state to Redis: {}", err),
                 }
             }
-        }
+        });
+        tx
+    }
+}
+
+// Arguments for FENCED_WRITE: each command's argument count, then its arguments
+fn fenced_args(pipe: &redis::Pipeline) -> Vec<Vec<u8>> {
+    let mut args = Vec::new();
+    for cmd in pipe.cmd_iter() {
+        args.push(cmd.args_iter().len().to_string().into_bytes());
+        args.extend(cmd.args_iter().map(|arg| match arg {
+            redis::Arg::Simple(bytes) => bytes.to_vec(),
+            redis::Arg::Cursor => b"0".to_vec(), // No SCANs are fenced
+        }));
+    }
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fenced_writes_pass_each_command_with_its_length() {
+        let mut pipe = redis::pipe();
+        pipe.sadd("dc:jobs", "job-1").ignore().set("dc:job:job-1", "{}").ignore().del("dc:queue").ignore();
+        let args: Vec<String> = fenced_args(&pipe).into_iter().map(|arg| String::from_utf8(arg).unwrap()).collect();
+        assert_eq!(args, ["3", "SADD", "dc:jobs", "job-1", "3", "SET", "dc:job:job-1", "{}", "2", "DEL", "dc:queue"]);
+    }
+
+    #[test]
+    fn node_status_round_trips_through_hash_fields() {
+        let status = NodeStatus {
+            node_id: "node_1".to_string(),
+            health: "suspect".to_string(),
+            draining: true,
+            capacity: (4096, 400, 100, 50),
+            allocated: (1024, 150, 10, 5),
+            running_tasks: 3,
+        };
+        let fields: HashMap<String, String> = status.to_fields().into_iter().map(|(name, value)| (name.to_string(), value)).collect();
+        assert_eq!(NodeStatus::from_fields("node_1", "dc:node:node_1", &fields).unwrap(), status);
+
+        let mut corrupt = fields.clone();
+        corrupt.insert("allocated_cpu".to_string(), "lots".to_string());
+        assert!(matches!(NodeStatus::from_fields("node_1", "dc:node:node_1", &corrupt), Err(RedisStoreError::Corrupt { .. })));
+        let mut partial = fields;
+        partial.remove("running_tasks");
+        assert!(matches!(NodeStatus::from_fields("node_1", "dc:node:node_1", &partial), Err(RedisStoreError::Corrupt { .. })));
+    }
+
+    #[tokio::test]
+    async fn bad_urls_fail_before_connecting() {
+        let invalid = RedisConfig { url: "http://127.0.0.1/".to_string(), ..RedisConfig::default() };
+        assert!(matches!(RedisClient::connect(&invalid).await, Err(RedisStoreError::InvalidUrl(_))));
     }
-    
 }
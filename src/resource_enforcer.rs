@@ -0,0 +1,330 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::io::Write;
+use std::time::{Duration, Instant};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use crate::task::Task;
+
+// cgroup v2 mount point
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+// cpu.max period in microseconds; a task asking for 100% CPU gets one full core
+const CPU_PERIOD_USEC: u64 = 100_000;
+// Tries at removing a task cgroup whose processes are still being killed
+const REMOVE_ATTEMPTS: u32 = 50;
+const REMOVE_RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
+// Why a task was stopped by the enforcer
+#[derive(Debug, Clone)]
+pub enum EnforcementError {
+    OutOfMemory { task_id: String, limit_mb: u64, peak_mb: u64 },
+    CpuOverQuota { task_id: String, limit_percent: u64, throttled_percent: f64 },
+    Cgroup(String),
+}
+
+impl fmt::Display for EnforcementError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EnforcementError::OutOfMemory { task_id, limit_mb, peak_mb } => write!(
+                f, "Task {} killed: out of memory (limit {}MB, peak {}MB)", task_id, limit_mb, peak_mb
+            ),
+            EnforcementError::CpuOverQuota { task_id, limit_percent, throttled_percent } => write!(
+                f, "Task {} killed: over CPU quota (limit {}%, throttled {:.0}% of the time)", task_id, limit_percent, throttled_percent
+            ),
+            EnforcementError::Cgroup(message) => write!(f, "cgroup error: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for EnforcementError {}
+
+// Actual usage of a task as measured by its cgroup
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ResourceUsage {
+    pub memory_mb: u64,
+    pub peak_memory_mb: u64,
+    pub cpu_percent: f64,        // Since the previous sample
+    pub cpu_usage_usec: u64,     // Total CPU time consumed
+    pub throttled_percent: f64,  // Share of periods the task was throttled since the previous sample
+}
+
+// Puts each task in its own cgroup v2 with cpu.max and memory.max from its requirements
+pub struct CgroupEnforcer {
+    root: PathBuf,
+    max_throttled_percent: f64, // Kill tasks throttled more than this (100 = never kill for CPU)
+    usage: Mutex<HashMap<String, ResourceUsage>>, // Task ID -> latest sample
+}
+
+// Handle on a single task's cgroup
+pub struct TaskCgroup {
+    task_id: String,
+    path: PathBuf,
+    limit_mb: u64,
+    limit_cpu: u64,
+    last_sample: Option<(Instant, u64, u64, u64)>, // (time, usage_usec, nr_periods, nr_throttled)
+    removed: bool, // Set by release; otherwise drop cleans up
+}
+
+impl CgroupEnforcer {
+    // Set up a parent cgroup for tasks; fails if cgroup v2 with cpu and memory controllers is unavailable
+    pub fn new(name: &str, max_throttled_percent: f64) -> Result<Self, EnforcementError> {
+        let controllers = read_string(&Path::new(CGROUP_ROOT).join("cgroup.controllers"))
+            .map_err(|_| EnforcementError::Cgroup("cgroup v2 is not mounted at /sys/fs/cgroup".to_string()))?;
+        if !controllers.contains("cpu") || !controllers.contains("memory") {
+            return Err(EnforcementError::Cgroup("cpu and memory controllers are not available".to_string()));
+        }
+
+        let root = Path::new(CGROUP_ROOT).join(name);
+        fs::create_dir_all(&root).map_err(|e| cgroup_error(&root, e))?;
+        // Let child cgroups use the cpu and memory controllers
+        write_string(&root.join("cgroup.subtree_control"), "+cpu +memory")?;
+
+        info!("Resource enforcement enabled under {}", root.display());
+        Ok(CgroupEnforcer { root, max_throttled_percent, usage: Mutex::new(HashMap::new()) })
+    }
+
+    // Create the cgroup for a task with limits derived from its requirements
+    pub async fn create_task_cgroup(&self, task: &Task) -> Result<TaskCgroup, EnforcementError> {
+        let path = self.root.join(format!("task_{}", task.task_id));
+        // A leftover from an earlier attempt would carry its processes and OOM count over
+        if path.exists() {
+            warn!("Removing leftover cgroup {}", path.display());
+            remove_cgroup(&path, REMOVE_ATTEMPTS).await.map_err(|e| cgroup_error(&path, e))?;
+        }
+        fs::create_dir(&path).map_err(|e| cgroup_error(&path, e))?;
+
+        let quota = task.required_cpu.max(1) * CPU_PERIOD_USEC / 100;
+        write_string(&path.join("cpu.max"), &format!("{} {}", quota, CPU_PERIOD_USEC))?;
+        write_string(&path.join("memory.max"), &(task.required_ram * 1024 * 1024).to_string())?;
+        // No swap, so exceeding memory.max is an OOM rather than a slowdown
+        write_string(&path.join("memory.swap.max"), "0")?;
+
+        Ok(TaskCgroup {
+            task_id: task.task_id.clone(),
+            path,
+            limit_mb: task.required_ram,
+            limit_cpu: task.required_cpu,
+            last_sample: None,
+            removed: false,
+        })
+    }
+
+    // Sample a task's usage, record it, and report a violation if it exceeded its limits
+    pub fn check(&self, cgroup: &mut TaskCgroup) -> Result<ResourceUsage, EnforcementError> {
+        let usage = cgroup.sample()?;
+        self.usage.lock().unwrap().insert(cgroup.task_id.clone(), usage.clone());
+
+        if cgroup.oom_killed()? {
+            return Err(EnforcementError::OutOfMemory {
+                task_id: cgroup.task_id.clone(),
+                limit_mb: cgroup.limit_mb,
+                peak_mb: usage.peak_memory_mb.max(cgroup.limit_mb),
+            });
+        }
+        if usage.throttled_percent > self.max_throttled_percent {
+            return Err(EnforcementError::CpuOverQuota {
+                task_id: cgroup.task_id.clone(),
+                limit_percent: cgroup.limit_cpu,
+                throttled_percent: usage.throttled_percent,
+            });
+        }
+        Ok(usage)
+    }
+
+    // Latest measured usage of a task, if it is enforced by this node
+    pub fn latest_usage(&self, task_id: &str) -> Option<ResourceUsage> {
+        self.usage.lock().unwrap().get(task_id).cloned()
+    }
+
+    // Forget the task's usage, then kill everything in its cgroup and remove it once the
+    // killed processes have exited
+    pub async fn release(&self, mut cgroup: TaskCgroup) {
+        self.usage.lock().unwrap().remove(&cgroup.task_id);
+        if let Err(e) = remove_cgroup(&cgroup.path, REMOVE_ATTEMPTS).await {
+            warn!("Could not remove cgroup {}: {}", cgroup.path.display(), e);
+        }
+        cgroup.removed = true;
+    }
+}
+
+// A cancelled or failed execute never gets to release; nothing may outlive the task either way.
+// The removal is retried in the background, as dropping cannot wait for processes to exit.
+impl Drop for TaskCgroup {
+    fn drop(&mut self) {
+        if self.removed {
+            return;
+        }
+        let path = self.path.clone();
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => {
+                runtime.spawn(async move {
+                    if let Err(e) = remove_cgroup(&path, REMOVE_ATTEMPTS).await {
+                        warn!("Could not remove cgroup {}: {}", path.display(), e);
+                    }
+                });
+            }
+            Err(_) => {
+                if let Err(e) = try_remove_cgroup(&path) {
+                    warn!("Could not remove cgroup {}: {}", path.display(), e);
+                }
+            }
+        }
+    }
+}
+
+// Kill a cgroup's processes and remove it. rmdir fails while killed processes are still
+// exiting, so it is tried `attempts` times.
+async fn remove_cgroup(path: &Path, attempts: u32) -> std::io::Result<()> {
+    let mut attempt = 1;
+    loop {
+        match try_remove_cgroup(path) {
+            Err(_) if attempt < attempts => {
+                attempt += 1;
+                tokio::time::sleep(REMOVE_RETRY_INTERVAL).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+fn try_remove_cgroup(path: &Path) -> std::io::Result<()> {
+    // cgroup.kill is only there on kernels 5.14 and later; never create it
+    if let Ok(mut kill) = fs::OpenOptions::new().write(true).open(path.join("cgroup.kill")) {
+        let _ = kill.write_all(b"1");
+    }
+    match fs::remove_dir(path) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+impl TaskCgroup {
+    // cgroup.procs file; writing a PID (or 0 for the writer itself) moves it into the cgroup
+    pub fn procs_path(&self) -> PathBuf {
+        self.path.join("cgroup.procs")
+    }
+
+    // Open cgroup.procs ahead of a spawn, so the child only has to write "0" to it before exec
+    // (pre_exec runs after fork, where only async-signal-safe calls are allowed)
+    pub fn open_procs(&self) -> Result<fs::File, EnforcementError> {
+        let path = self.procs_path();
+        fs::OpenOptions::new().write(true).open(&path).map_err(|e| cgroup_error(&path, e))
+    }
+
+    // Move an already running process into the cgroup
+    pub fn add_process(&self, pid: u32) -> Result<(), EnforcementError> {
+        write_string(&self.procs_path(), &pid.to_string())
+    }
+
+    // Kill every process in the cgroup
+    pub fn kill(&self) {
+        if let Err(e) = write_string(&self.path.join("cgroup.kill"), "1") {
+            warn!("Could not kill cgroup for Task {}: {}", self.task_id, e);
+        }
+    }
+
+    fn oom_killed(&self) -> Result<bool, EnforcementError> {
+        let events = read_keyed(&self.path.join("memory.events"))?;
+        Ok(events.get("oom_kill").copied().unwrap_or(0) > 0)
+    }
+
+    fn sample(&mut self) -> Result<ResourceUsage, EnforcementError> {
+        let now = Instant::now();
+        let memory = read_string(&self.path.join("memory.current"))?.trim().parse::<u64>().unwrap_or(0);
+        // memory.peak is only available on newer kernels
+        let peak = read_string(&self.path.join("memory.peak")).ok().and_then(|s| s.trim().parse::<u64>().ok()).unwrap_or(memory);
+
+        let cpu_stat = read_keyed(&self.path.join("cpu.stat"))?;
+        let usage_usec = cpu_stat.get("usage_usec").copied().unwrap_or(0);
+        let nr_periods = cpu_stat.get("nr_periods").copied().unwrap_or(0);
+        let nr_throttled = cpu_stat.get("nr_throttled").copied().unwrap_or(0);
+
+        let (cpu_percent, throttled_percent) = match self.last_sample {
+            Some((at, last_usage, last_periods, last_throttled)) => {
+                let elapsed_usec = (now - at).as_micros().max(1) as f64;
+                let periods = nr_periods.saturating_sub(last_periods);
+                let throttled = nr_throttled.saturating_sub(last_throttled);
+                (
+                    usage_usec.saturating_sub(last_usage) as f64 / elapsed_usec * 100.0,
+                    if periods > 0 { throttled as f64 / periods as f64 * 100.0 } else { 0.0 },
+                )
+            }
+            None => (0.0, 0.0),
+        };
+        self.last_sample = Some((now, usage_usec, nr_periods, nr_throttled));
+
+        Ok(ResourceUsage {
+            memory_mb: memory / (1024 * 1024),
+            peak_memory_mb: peak / (1024 * 1024),
+            cpu_percent,
+            cpu_usage_usec: usage_usec,
+            throttled_percent,
+        })
+    }
+}
+
+fn cgroup_error(path: &Path, e: std::io::Error) -> EnforcementError {
+    EnforcementError::Cgroup(format!("{}: {}", path.display(), e))
+}
+
+fn read_string(path: &Path) -> Result<String, EnforcementError> {
+    fs::read_to_string(path).map_err(|e| cgroup_error(path, e))
+}
+
+fn write_string(path: &Path, value: &str) -> Result<(), EnforcementError> {
+    fs::write(path, value).map_err(|e| cgroup_error(path, e))
+}
+
+// Parse "key value" lines (cpu.stat, memory.events)
+fn read_keyed(path: &Path) -> Result<HashMap<String, u64>, EnforcementError> {
+    Ok(read_string(path)?
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            Some((parts.next()?.to_string(), parts.next()?.parse().ok()?))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cgroup_at(path: PathBuf) -> TaskCgroup {
+        TaskCgroup { task_id: "t1".to_string(), path, limit_mb: 64, limit_cpu: 10, last_sample: None, removed: false }
+    }
+
+    #[test]
+    fn dropping_a_task_cgroup_removes_it() {
+        let path = std::env::temp_dir().join(format!("dc_cgroup_test_{}", std::process::id()));
+        fs::create_dir_all(&path).unwrap();
+        drop(cgroup_at(path.clone()));
+        assert!(!path.exists());
+        // Already gone is fine too
+        drop(cgroup_at(path));
+    }
+
+    #[tokio::test]
+    async fn dropping_inside_the_runtime_retries_until_the_cgroup_is_empty() {
+        let path = std::env::temp_dir().join(format!("dc_cgroup_drop_{}", std::process::id()));
+        fs::create_dir_all(path.join("exiting")).unwrap();
+        drop(cgroup_at(path.clone()));
+        // The "processes" leave after the drop returned
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        fs::remove_dir(path.join("exiting")).unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn removal_gives_up_on_a_busy_directory() {
+        let path = std::env::temp_dir().join(format!("dc_cgroup_busy_{}", std::process::id()));
+        fs::create_dir_all(path.join("child")).unwrap();
+        assert!(remove_cgroup(&path, 3).await.is_err());
+        assert!(!path.join("cgroup.kill").exists());
+        fs::remove_dir_all(&path).unwrap();
+    }
+}
@@ -10,6 +10,19 @@ impl ResourceManager {
         println!("Allocated Storage: {}GB out of {}GB", node.allocated_storage, node.available_storage);
         println!("Allocated CPU: {}% out of {}%", node.allocated_cpu, node.available_cpu);
         println!("Allocated Bandwidth: {}Mbps out of {}Mbps", node.allocated_bandwidth, node.available_bandwidth);
+
+        // Actual usage of enforced tasks against what they were sold
+        if let Some(enforcer) = &node.enforcer {
+            for (task_id, running) in &node.running_tasks {
+                if let Some(usage) = enforcer.latest_usage(task_id) {
+                    println!(
+                        "Task {}: RAM {}MB (peak {}MB) of {}MB, CPU {:.1}% of {}%, throttled {:.1}%",
+                        task_id, usage.memory_mb, usage.peak_memory_mb, running.task.required_ram,
+                        usage.cpu_percent, running.task.required_cpu, usage.throttled_percent
+                    );
+                }
+            }
+        }
     }
 
     // Release a percentage of CPU usage when node is idle
@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use log::{info, warn};
+use crate::controller_grpc_client::node::{TaskResultResponse, TaskState};
+use crate::executor::TaskOutput;
+use crate::ipfs_storage::IpfsClient;
+
+// Outputs up to this size are kept inline on the controller; larger ones go to IPFS
+const DEFAULT_INLINE_LIMIT: usize = 64 * 1024;
+// Bytes of stdout/stderr kept as a tail for quick inspection
+const DEFAULT_TAIL_BYTES: usize = 4 * 1024;
+const DEFAULT_IPFS_API: &str = "http://localhost:5001";
+
+// Where a stream of task output lives
+#[derive(Debug, Clone)]
+pub enum StoredOutput {
+    Inline(Vec<u8>),
+    Ipfs { cid: String, size: usize },
+}
+
+// Final outcome of a task as recorded by the controller
+#[derive(Debug, Clone)]
+pub struct TaskResult {
+    pub task_id: String,
+    pub node_id: String,
+    pub succeeded: bool,
+    pub exit_code: i32,
+    pub error: String, // Executor or enforcement error, empty if the task ran to completion
+    pub stdout: StoredOutput,
+    pub stderr: StoredOutput,
+    pub stdout_tail: Vec<u8>,
+    pub stderr_tail: Vec<u8>,
+    pub runtime: Duration,
+    pub peak_memory_mb: u64,
+    pub cpu_time_ms: u64,
+    pub completed_at: SystemTime,
+}
+
+impl TaskResult {
+    // Build a result from a task's output, uploading large streams to IPFS
+    pub async fn from_output(
+        task_id: &str,
+        node_id: &str,
+        output: TaskOutput,
+        error: String,
+        ipfs: &IpfsClient,
+        inline_limit: usize,
+        tail_bytes: usize,
+    ) -> Self {
+        let stdout_tail = tail(&output.stdout, tail_bytes);
+        let stderr_tail = tail(&output.stderr, tail_bytes);
+        let usage = output.usage.unwrap_or_default();
+
+        TaskResult {
+            task_id: task_id.to_string(),
+            node_id: node_id.to_string(),
+            succeeded: error.is_empty() && output.exit_code == 0,
+            exit_code: output.exit_code,
+            error,
+            stdout: store_output(ipfs, task_id, "stdout", output.stdout, inline_limit).await,
+            stderr: store_output(ipfs, task_id, "stderr", output.stderr, inline_limit).await,
+            stdout_tail,
+            stderr_tail,
+            runtime: output.runtime,
+            peak_memory_mb: usage.peak_memory_mb,
+            cpu_time_ms: usage.cpu_usage_usec / 1000,
+            completed_at: SystemTime::now(),
+        }
+    }
+
+    pub fn to_response(&self) -> TaskResultResponse {
+        let (stdout, stdout_cid, output_size) = match &self.stdout {
+            StoredOutput::Inline(data) => (data.clone(), String::new(), data.len()),
+            StoredOutput::Ipfs { cid, size } => (Vec::new(), cid.clone(), *size),
+        };
+        let (stderr, stderr_cid) = match &self.stderr {
+            StoredOutput::Inline(data) => (data.clone(), String::new()),
+            StoredOutput::Ipfs { cid, .. } => (Vec::new(), cid.clone()),
+        };
+
+        TaskResultResponse {
+            task_id: self.task_id.clone(),
+            node_id: self.node_id.clone(),
+            state: if self.succeeded { TaskState::Succeeded } else { TaskState::Failed } as i32,
+            exit_code: self.exit_code,
+            error: self.error.clone(),
+            stdout,
+            stdout_cid,
+            stderr,
+            stderr_cid,
+            stdout_tail: self.stdout_tail.clone(),
+            stderr_tail: self.stderr_tail.clone(),
+            output_size: output_size as u64,
+            runtime_ms: self.runtime.as_millis() as u64,
+            peak_memory_mb: self.peak_memory_mb,
+            cpu_time_ms: self.cpu_time_ms,
+            completed_at_ms: self.completed_at.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64),
+        }
+    }
+}
+
+// Keep large outputs out of controller memory; fall back to inline if IPFS is unavailable
+async fn store_output(ipfs: &IpfsClient, task_id: &str, stream: &str, data: Vec<u8>, inline_limit: usize) -> StoredOutput {
+    if data.len() <= inline_limit {
+        return StoredOutput::Inline(data);
+    }
+
+    let size = data.len();
+    match ipfs.add(data.clone()).await {
+        Ok(cid) => {
+            info!("Stored {} bytes of {} for Task {} in IPFS as {}", size, stream, task_id, cid);
+            StoredOutput::Ipfs { cid, size }
+        }
+        Err(err) => {
+            warn!("Could not upload {} of Task {} to IPFS, keeping it inline: {}", stream, task_id, err);
+            StoredOutput::Inline(data)
+        }
+    }
+}
+
+fn tail(data: &[u8], bytes: usize) -> Vec<u8> {
+    data[data.len().saturating_sub(bytes)..].to_vec()
+}
+
+// Results of finished tasks, kept on the controller for clients to collect
+pub struct ResultStore {
+    results: HashMap<String, TaskResult>, // Task ID -> latest result
+    pub inline_limit: usize,
+    pub tail_bytes: usize,
+    pub ipfs: IpfsClient, // Where outputs above inline_limit go
+}
+
+impl Default for ResultStore {
+    fn default() -> Self {
+        ResultStore::new()
+    }
+}
+
+impl ResultStore {
+    pub fn new() -> Self {
+        ResultStore::with_limits(DEFAULT_INLINE_LIMIT, DEFAULT_TAIL_BYTES)
+    }
+
+    pub fn with_limits(inline_limit: usize, tail_bytes: usize) -> Self {
+        ResultStore { results: HashMap::new(), inline_limit, tail_bytes, ipfs: IpfsClient::new(DEFAULT_IPFS_API) }
+    }
+
+    pub fn with_ipfs(mut self, api_url: &str) -> Self {
+        self.ipfs = IpfsClient::new(api_url);
+        self
+    }
+
+    // Record a result; a retried task's latest attempt replaces the earlier one
+    pub fn insert(&mut self, result: TaskResult) {
+        self.results.insert(result.task_id.clone(), result);
+    }
+
+    pub fn get(&self, task_id: &str) -> Option<&TaskResult> {
+        self.results.get(task_id)
+    }
+
+    // Drop a result once the client has collected it
+    pub fn remove(&mut self, task_id: &str) -> Option<TaskResult> {
+        self.results.remove(task_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(exit_code: i32, stdout: &[u8], stderr: &[u8]) -> TaskOutput {
+        TaskOutput { exit_code, stdout: stdout.to_vec(), stderr: stderr.to_vec(), runtime: Duration::from_millis(1500), usage: None }
+    }
+
+    #[tokio::test]
+    async fn small_outputs_stay_inline_with_a_tail() {
+        let store = ResultStore::with_limits(16, 4);
+        let result = TaskResult::from_output("t1", "node_1", output(0, b"hello world", b"oops"), String::new(), &store.ipfs, store.inline_limit, store.tail_bytes).await;
+        assert!(result.succeeded);
+        assert_eq!(result.stdout_tail, b"orld");
+
+        let response = result.to_response();
+        assert_eq!(response.stdout, b"hello world");
+        assert!(response.stdout_cid.is_empty());
+        assert_eq!(response.output_size, 11);
+        assert_eq!(response.runtime_ms, 1500);
+        assert_eq!(response.state, TaskState::Succeeded as i32);
+    }
+
+    #[tokio::test]
+    async fn large_outputs_are_kept_inline_when_ipfs_is_down() {
+        // Nothing listens on the discard port
+        let store = ResultStore::with_limits(4, 2).with_ipfs("http://127.0.0.1:9");
+        let result = TaskResult::from_output("t1", "node_1", output(0, b"too long for inline", b""), String::new(), &store.ipfs, store.inline_limit, store.tail_bytes).await;
+        assert!(matches!(&result.stdout, StoredOutput::Inline(data) if data == b"too long for inline"));
+        assert_eq!(result.stdout_tail, b"ne");
+    }
+
+    #[tokio::test]
+    async fn errors_and_exit_codes_fail_the_task_and_retries_replace_it() {
+        let mut store = ResultStore::new();
+        let killed = TaskResult::from_output("t1", "node_1", output(0, b"", b""), "Memory limit exceeded".to_string(), &store.ipfs, store.inline_limit, store.tail_bytes).await;
+        assert!(!killed.succeeded);
+        store.insert(killed);
+        let failed = TaskResult::from_output("t1", "node_2", output(3, b"", b""), String::new(), &store.ipfs, store.inline_limit, store.tail_bytes).await;
+        assert_eq!(failed.to_response().state, TaskState::Failed as i32);
+        store.insert(failed);
+
+        assert_eq!(store.get("t1").unwrap().node_id, "node_2");
+        assert!(store.remove("t1").is_some());
+        assert!(store.get("t1").is_none());
+    }
+}
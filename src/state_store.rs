@@ -0,0 +1,257 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use crate::job_service::JobRecord;
+use crate::task::Task;
+
+const WAL_FILE: &str = "journal.wal";
+const SNAPSHOT_FILE: &str = "snapshot.json";
+
+// One durable change to the scheduler's state
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum JournalEntry {
+    Enqueued { task: Task },                       // Submitted or requeued; replaces any earlier copy
+    Assigned { task_id: String, node_id: String }, // Dispatched and accepted by the node
+    Finished { task_id: String },                  // Succeeded, failed for good or cancelled
+    NodeFailed { node_id: String },                // Its tasks went back to the queue
+    Job { record: JobRecord },                     // Job state change, as served by JobService
+}
+
+// WAL line: entries carry a sequence number so a snapshot knows which ones it already covers
+#[derive(Serialize, Deserialize)]
+struct Record {
+    seq: u64,
+    #[serde(flatten)]
+    entry: JournalEntry,
+}
+
+// Everything the controller needs to rebuild its queue, task-to-node map and job records
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ControllerState {
+    pub tasks: HashMap<String, Task>,      // Every unfinished task, queued or dispatched
+    pub assigned: HashMap<String, String>, // Task ID -> node it was dispatched to
+    pub jobs: HashMap<String, JobRecord>,  // Every job, including finished ones
+    last_seq: u64,                         // Last journal entry applied
+}
+
+impl ControllerState {
+    fn apply(&mut self, seq: u64, entry: JournalEntry) {
+        self.last_seq = seq;
+        match entry {
+            JournalEntry::Enqueued { task } => {
+                self.assigned.remove(&task.task_id);
+                self.tasks.insert(task.task_id.clone(), task);
+            }
+            JournalEntry::Assigned { task_id, node_id } => {
+                if self.tasks.contains_key(&task_id) {
+                    self.assigned.insert(task_id, node_id);
+                }
+            }
+            JournalEntry::Finished { task_id } => {
+                self.assigned.remove(&task_id);
+                self.tasks.remove(&task_id);
+            }
+            JournalEntry::NodeFailed { node_id } => self.assigned.retain(|_, assigned| *assigned != node_id),
+            JournalEntry::Job { record } => {
+                self.jobs.insert(record.job_id.clone(), record);
+            }
+        }
+    }
+
+    // Tasks waiting to be placed
+    pub fn queued(&self) -> impl Iterator<Item = &Task> {
+        self.tasks.values().filter(|task| !self.assigned.contains_key(&task.task_id))
+    }
+}
+
+// Write-ahead log of scheduler state changes, compacted into a snapshot every `snapshot_every` entries
+pub struct StateStore {
+    dir: PathBuf,
+    wal: File,
+    state: ControllerState, // What a restart would recover, kept to write snapshots
+    snapshot_every: u64,
+    since_snapshot: u64,
+    fsync: bool, // Sync every entry to disk before the change takes effect
+}
+
+impl StateStore {
+    // Load the latest snapshot, replay the log on top of it and start a fresh log.
+    // Returns the store together with the recovered state.
+    pub fn open(dir: impl Into<PathBuf>, snapshot_every: u64, fsync: bool) -> Result<(Self, ControllerState), String> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir).map_err(|e| format!("Could not create {}: {}", dir.display(), e))?;
+
+        let snapshot_path = dir.join(SNAPSHOT_FILE);
+        let mut state = match fs::read_to_string(&snapshot_path) {
+            Ok(contents) => serde_json::from_str(&contents).map_err(|e| format!("Corrupt snapshot {}: {}", snapshot_path.display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => ControllerState::default(),
+            Err(e) => return Err(format!("Could not read {}: {}", snapshot_path.display(), e)),
+        };
+        let replayed = replay(&dir.join(WAL_FILE), &mut state)?;
+
+        let wal = open_wal(&dir, false)?;
+        let mut store = StateStore { dir, wal, state: state.clone(), snapshot_every: snapshot_every.max(1), since_snapshot: 0, fsync };
+        // Compacting right away also drops a torn last entry from a crash mid-write
+        store.snapshot()?;
+        info!(
+            "Recovered {} unfinished tasks ({} dispatched) and {} jobs from {} ({} log entries replayed)",
+            state.tasks.len(),
+            state.assigned.len(),
+            state.jobs.len(),
+            store.dir.display(),
+            replayed
+        );
+        Ok((store, state))
+    }
+
+    // Durably record a change; the caller applies it in memory afterwards
+    pub fn append(&mut self, entry: JournalEntry) -> Result<(), String> {
+        let seq = self.state.last_seq + 1;
+        let record = Record { seq, entry };
+        let mut line = serde_json::to_string(&record).map_err(|e| e.to_string())?;
+        line.push('\n');
+        self.wal.write_all(line.as_bytes()).map_err(|e| format!("Could not write to the journal: {}", e))?;
+        if self.fsync {
+            self.wal.sync_data().map_err(|e| format!("Could not sync the journal: {}", e))?;
+        }
+        self.state.apply(seq, record.entry);
+
+        self.since_snapshot += 1;
+        if self.since_snapshot >= self.snapshot_every {
+            self.snapshot()?;
+        }
+        Ok(())
+    }
+
+    // Replace the local state with one taken over from elsewhere (e.g. Redis on failover)
+    pub fn reset(&mut self, mut state: ControllerState) -> Result<(), String> {
+        // Keep sequence numbers increasing so stale log entries are still skipped on replay
+        state.last_seq = self.state.last_seq;
+        self.state = state;
+        self.snapshot()
+    }
+
+    // Write the state to a new snapshot and truncate the log it covers
+    fn snapshot(&mut self) -> Result<(), String> {
+        let path = self.dir.join(SNAPSHOT_FILE);
+        let tmp = self.dir.join(format!("{}.tmp", SNAPSHOT_FILE));
+        let contents = serde_json::to_vec(&self.state).map_err(|e| e.to_string())?;
+        let mut file = File::create(&tmp).map_err(|e| format!("Could not create {}: {}", tmp.display(), e))?;
+        file.write_all(&contents).and_then(|_| file.sync_all()).map_err(|e| format!("Could not write {}: {}", tmp.display(), e))?;
+        // Rename is atomic, so a crash leaves either the old or the new snapshot
+        fs::rename(&tmp, &path).map_err(|e| format!("Could not replace {}: {}", path.display(), e))?;
+
+        // Entries left behind by a crash right here are skipped on replay by their sequence number
+        self.wal = open_wal(&self.dir, true)?;
+        self.since_snapshot = 0;
+        Ok(())
+    }
+}
+
+fn open_wal(dir: &Path, truncate: bool) -> Result<File, String> {
+    let path = dir.join(WAL_FILE);
+    let mut options = OpenOptions::new();
+    if truncate {
+        options.write(true).create(true).truncate(true);
+    } else {
+        options.append(true).create(true);
+    }
+    options.open(&path).map_err(|e| format!("Could not open {}: {}", path.display(), e))
+}
+
+// Apply every log entry newer than the snapshot; stops at the first unreadable line
+fn replay(path: &Path, state: &mut ControllerState) -> Result<usize, String> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(format!("Could not open {}: {}", path.display(), e)),
+    };
+
+    let mut replayed = 0;
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record: Record = match serde_json::from_str(&line) {
+            Ok(record) => record,
+            Err(e) => {
+                // Only the last write can be torn; anything after it was never acknowledged
+                warn!("Ignoring the rest of {} from line {}: {}", path.display(), number + 1, e);
+                break;
+            }
+        };
+        if record.seq > state.last_seq {
+            state.apply(record.seq, record.entry);
+            replayed += 1;
+        }
+    }
+    Ok(replayed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    // Fresh state directory for each test
+    fn state_dir() -> PathBuf {
+        static NEXT: AtomicU32 = AtomicU32::new(0);
+        let dir = std::env::temp_dir().join(format!("dc_state_{}_{}", std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed)));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn enqueued(task_id: &str) -> JournalEntry {
+        JournalEntry::Enqueued { task: Task::new(task_id, 0, 64, 10, 1, Vec::new()) }
+    }
+
+    #[test]
+    fn a_torn_last_entry_is_dropped_on_replay() {
+        let dir = state_dir();
+        let (mut store, _) = StateStore::open(&dir, 100, false).unwrap();
+        store.append(enqueued("t1")).unwrap();
+        store.append(JournalEntry::Assigned { task_id: "t1".to_string(), node_id: "node_1".to_string() }).unwrap();
+        store.append(enqueued("t2")).unwrap();
+        drop(store);
+        // Crash in the middle of writing the next entry
+        let mut wal = OpenOptions::new().append(true).open(dir.join(WAL_FILE)).unwrap();
+        wal.write_all(br#"{"seq":4,"op":"finished","task_"#).unwrap();
+
+        let (mut store, state) = StateStore::open(&dir, 100, false).unwrap();
+        assert_eq!(state.tasks.len(), 2);
+        assert_eq!(state.assigned["t1"], "node_1");
+        assert_eq!(state.queued().map(|task| task.task_id.as_str()).collect::<Vec<_>>(), ["t2"]);
+
+        // The log goes on after the torn entry was compacted away
+        store.append(JournalEntry::Finished { task_id: "t1".to_string() }).unwrap();
+        drop(store);
+        let (_, state) = StateStore::open(&dir, 100, false).unwrap();
+        assert!(!state.tasks.contains_key("t1") && !state.assigned.contains_key("t1"));
+        assert!(state.tasks.contains_key("t2"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn entries_covered_by_the_snapshot_are_not_replayed_twice() {
+        let dir = state_dir();
+        let (mut store, _) = StateStore::open(&dir, 2, false).unwrap();
+        store.append(enqueued("t1")).unwrap();
+        store.append(JournalEntry::Assigned { task_id: "t1".to_string(), node_id: "node_1".to_string() }).unwrap();
+        store.append(JournalEntry::NodeFailed { node_id: "node_1".to_string() }).unwrap();
+        drop(store);
+        // A crash between writing the snapshot and truncating the log leaves old entries behind
+        let stale = Record { seq: 2, entry: JournalEntry::Assigned { task_id: "t1".to_string(), node_id: "node_1".to_string() } };
+        let mut wal = OpenOptions::new().append(true).open(dir.join(WAL_FILE)).unwrap();
+        wal.write_all(format!("{}\n", serde_json::to_string(&stale).unwrap()).as_bytes()).unwrap();
+
+        let (_, state) = StateStore::open(&dir, 2, false).unwrap();
+        assert!(state.tasks.contains_key("t1"));
+        assert!(state.assigned.is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,250 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use crate::load_balancer::LoadBalancer;
+use crate::node::Node;
+use crate::task::Task;
+
+// Snapshot of the cluster used to pick a placement strategy
+#[derive(Debug, Clone)]
+pub struct ClusterConditions {
+    pub queue_depth: usize,
+    pub min_deadline_slack: Option<u64>, // Seconds until the most urgent deadline, if any task has one
+    pub average_load: f64,               // Mean of Node::calculate_load across available nodes
+}
+
+impl ClusterConditions {
+    pub fn observe(queued_tasks: &[Task], nodes: &[Node]) -> Self {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+
+        let min_deadline_slack = queued_tasks
+            .iter()
+            .filter(|task| task.deadline != u64::MAX)
+            .map(|task| task.deadline.saturating_sub(now))
+            .min();
+
+        let average_load = if nodes.is_empty() {
+            0.0
+        } else {
+            nodes.iter().map(|node| node.calculate_load()).sum::<f64>() / nodes.len() as f64
+        };
+
+        ClusterConditions { queue_depth: queued_tasks.len(), min_deadline_slack, average_load }
+    }
+}
+
+// Condition that triggers a rule
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "when", content = "value", rename_all = "snake_case")]
+pub enum SwitchCondition {
+    QueueDepthAbove(usize),
+    DeadlineSlackBelow(u64),
+    AverageLoadAbove(f64),
+    AverageLoadBelow(f64),
+    Always,
+}
+
+impl SwitchCondition {
+    fn matches(&self, conditions: &ClusterConditions) -> bool {
+        match self {
+            SwitchCondition::QueueDepthAbove(depth) => conditions.queue_depth > *depth,
+            SwitchCondition::DeadlineSlackBelow(secs) => conditions.min_deadline_slack.is_some_and(|slack| slack < *secs),
+            SwitchCondition::AverageLoadAbove(load) => conditions.average_load > *load,
+            SwitchCondition::AverageLoadBelow(load) => conditions.average_load < *load,
+            SwitchCondition::Always => true,
+        }
+    }
+
+    fn describe(&self, conditions: &ClusterConditions) -> String {
+        match self {
+            SwitchCondition::QueueDepthAbove(depth) => format!("queue depth {} > {}", conditions.queue_depth, depth),
+            SwitchCondition::DeadlineSlackBelow(secs) => {
+                format!("deadline slack {}s < {}s", conditions.min_deadline_slack.unwrap_or(0), secs)
+            }
+            SwitchCondition::AverageLoadAbove(load) => format!("average load {:.1}% > {:.1}%", conditions.average_load, load),
+            SwitchCondition::AverageLoadBelow(load) => format!("average load {:.1}% < {:.1}%", conditions.average_load, load),
+            SwitchCondition::Always => "default rule".to_string(),
+        }
+    }
+}
+
+// Use `strategy` when `condition` holds
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SwitchRule {
+    pub strategy: String,
+    #[serde(flatten)]
+    pub condition: SwitchCondition,
+}
+
+// Rules are evaluated in order, first match wins
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SwitchingRules {
+    pub rules: Vec<SwitchRule>,
+    pub hysteresis_rounds: u32, // Consecutive evaluations a new strategy must win before switching
+    pub min_dwell_secs: u64,    // Minimum time to keep a strategy after switching
+}
+
+impl Default for SwitchingRules {
+    fn default() -> Self {
+        SwitchingRules {
+            rules: vec![
+                SwitchRule { strategy: "deadline".to_string(), condition: SwitchCondition::DeadlineSlackBelow(60) },
+                SwitchRule { strategy: "least_loaded".to_string(), condition: SwitchCondition::AverageLoadAbove(75.0) },
+                SwitchRule { strategy: "priority".to_string(), condition: SwitchCondition::QueueDepthAbove(50) },
+                SwitchRule { strategy: "weight".to_string(), condition: SwitchCondition::AverageLoadBelow(25.0) },
+                SwitchRule { strategy: "priority".to_string(), condition: SwitchCondition::Always },
+            ],
+            hysteresis_rounds: 3,
+            min_dwell_secs: 30,
+        }
+    }
+}
+
+// A strategy change decided by the switcher
+#[derive(Debug, Clone)]
+pub struct StrategySwitch {
+    pub from: String,
+    pub to: String,
+    pub reason: String,
+}
+
+pub struct StrategySwitcher {
+    rules: SwitchingRules,
+    current: String,
+    pending: Option<(String, u32)>, // Candidate strategy and how many rounds in a row it has won
+    last_switch: Instant,
+}
+
+impl StrategySwitcher {
+    pub fn new(rules: SwitchingRules, initial_strategy: &str) -> Self {
+        StrategySwitcher {
+            rules,
+            current: initial_strategy.to_string(),
+            pending: None,
+            last_switch: Instant::now(),
+        }
+    }
+
+    pub fn current_strategy(&self) -> &str {
+        &self.current
+    }
+
+    // Replace the rules without resetting the current strategy
+    pub fn set_rules(&mut self, rules: SwitchingRules) {
+        self.rules = rules;
+        self.pending = None;
+    }
+
+    // Adopt a strategy chosen outside the rules (e.g. by a config reload)
+    pub fn set_strategy(&mut self, strategy: &str) {
+        self.current = strategy.to_string();
+        self.pending = None;
+        self.last_switch = Instant::now();
+    }
+
+    // Decide whether conditions justify a switch, applying hysteresis
+    pub fn evaluate(&mut self, conditions: &ClusterConditions) -> Option<StrategySwitch> {
+        let rule = self.rules.rules.iter().find(|rule| rule.condition.matches(conditions))?;
+
+        if rule.strategy == self.current {
+            self.pending = None;
+            return None;
+        }
+
+        let rounds = match &self.pending {
+            Some((candidate, rounds)) if *candidate == rule.strategy => rounds + 1,
+            _ => 1,
+        };
+        self.pending = Some((rule.strategy.clone(), rounds));
+
+        let dwell = Duration::from_secs(self.rules.min_dwell_secs);
+        if rounds < self.rules.hysteresis_rounds || self.last_switch.elapsed() < dwell {
+            return None;
+        }
+
+        let switch = StrategySwitch {
+            from: self.current.clone(),
+            to: rule.strategy.clone(),
+            reason: rule.condition.describe(conditions),
+        };
+        self.current = rule.strategy.clone();
+        self.pending = None;
+        self.last_switch = Instant::now();
+        Some(switch)
+    }
+
+    // Evaluate conditions and switch the load balancer's strategy if needed
+    pub fn apply(&mut self, load_balancer: &mut LoadBalancer, conditions: &ClusterConditions) -> Option<StrategySwitch> {
+        let switch = self.evaluate(conditions)?;
+
+        if let Err(err) = load_balancer.set_strategy(&switch.to) {
+            // Unknown strategy in config; stay on the old one
+            warn!("Strategy switch to {} rejected: {}", switch.to, err);
+            self.current = switch.from;
+            return None;
+        }
+
+        info!("Switching placement strategy {} -> {} ({})", switch.from, switch.to, switch.reason);
+        Some(switch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load_balancer::StrategyRegistry;
+
+    fn rules(hysteresis_rounds: u32, min_dwell_secs: u64) -> SwitchingRules {
+        SwitchingRules {
+            rules: vec![
+                SwitchRule { strategy: "deadline".to_string(), condition: SwitchCondition::QueueDepthAbove(10) },
+                SwitchRule { strategy: "priority".to_string(), condition: SwitchCondition::Always },
+            ],
+            hysteresis_rounds,
+            min_dwell_secs,
+        }
+    }
+
+    fn queue(depth: usize) -> ClusterConditions {
+        ClusterConditions { queue_depth: depth, min_deadline_slack: None, average_load: 0.0 }
+    }
+
+    #[test]
+    fn a_strategy_must_win_several_rounds_in_a_row() {
+        let mut switcher = StrategySwitcher::new(rules(3, 0), "priority");
+        assert!(switcher.evaluate(&queue(20)).is_none());
+        assert!(switcher.evaluate(&queue(20)).is_none());
+        // A round for the current strategy resets the streak
+        assert!(switcher.evaluate(&queue(0)).is_none());
+        assert!(switcher.evaluate(&queue(20)).is_none());
+        assert!(switcher.evaluate(&queue(20)).is_none());
+        let switch = switcher.evaluate(&queue(20)).unwrap();
+        assert_eq!((switch.from.as_str(), switch.to.as_str()), ("priority", "deadline"));
+        assert_eq!(switch.reason, "queue depth 20 > 10");
+        assert_eq!(switcher.current_strategy(), "deadline");
+    }
+
+    #[test]
+    fn strategies_are_kept_for_the_minimum_dwell_time() {
+        let mut switcher = StrategySwitcher::new(rules(1, 3600), "priority");
+        for _ in 0..5 {
+            assert!(switcher.evaluate(&queue(20)).is_none());
+        }
+        assert_eq!(switcher.current_strategy(), "priority");
+    }
+
+    #[test]
+    fn switches_to_unknown_strategies_are_rejected() {
+        let mut unknown = rules(1, 0);
+        unknown.rules[0].strategy = "no_such_strategy".to_string();
+        let mut switcher = StrategySwitcher::new(unknown, "priority");
+        let mut balancer = LoadBalancer::new(StrategyRegistry::with_defaults(), "priority").unwrap();
+        assert!(switcher.apply(&mut balancer, &queue(20)).is_none());
+        assert_eq!((switcher.current_strategy(), balancer.strategy_name()), ("priority", "priority"));
+
+        let mut switcher = StrategySwitcher::new(rules(1, 0), "priority");
+        assert!(switcher.apply(&mut balancer, &queue(20)).is_some());
+        assert_eq!(balancer.strategy_name(), "deadline");
+    }
+}
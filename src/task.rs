@@ -1,45 +1,81 @@
 use flate2::{write::GzEncoder, Compression};
 use std::io::prelude::*;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use crate::executor::ExecutorKind;
 
-struct Task {
-    task_id: String,
+// Retry limit for tasks created without one (config: retries.max_retries)
+pub const DEFAULT_MAX_RETRIES: u32 = 3;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Task {
+    pub task_id: String,
+    pub priority: u8,          // Higher value = more important
+    pub submitted_priority: u8, // Priority as submitted; queue aging raises `priority` only
     pub deadline: u64,      // Deadline timestamp (UNIX time)
-    required_ram: u64,     // RAM needed for the task (in MB)
-    required_cpu: u64,     // CPU usage required (in %)
-    required_bandwidth: u64, // Bandwidth required (in Mbps)
-    data: Vec<u8>,         // Task data (payload)
-    assigned_node_id: Option<String>,  // Node to which this task is assigned
-    retries: u32,          // Number of retries attempted
-    max_retries: u32,      // Maximum retries allowed
+    pub required_ram: u64,     // RAM needed for the task (in MB)
+    pub required_cpu: u64,     // CPU usage required (in %)
+    pub required_bandwidth: u64, // Bandwidth required (in Mbps)
+    pub required_storage: u64, // Storage required (in GB)
+    pub owner: String,         // User who submitted the task
+    pub data: Vec<u8>,         // Task data (payload)
+    pub executor: ExecutorKind, // How the node runs the task
+    pub assigned_node_id: Option<String>,  // Node to which this task is assigned
+    pub retries: u32,          // Number of retries attempted
+    pub max_retries: u32,      // Maximum retries allowed
+    pub preemptions: u32,      // Number of times the task was preempted
+    pub wasted_work: Duration, // Run time lost to preemption; preempted tasks start over
 }
 
 impl Task {
     // Constructor
-    fn new(task_id: &str, ram: u64, cpu: u64, bandwidth: u64, data: Vec<u8>) -> Task {
+    pub fn new(task_id: &str, priority: u8, ram: u64, cpu: u64, bandwidth: u64, data: Vec<u8>) -> Self {
         Task {
             task_id: task_id.to_string(),
+            priority,
+            submitted_priority: priority,
+            deadline: u64::MAX, // No deadline unless one is set
             required_ram: ram,
             required_cpu: cpu,
             required_bandwidth: bandwidth,
+            required_storage: 0,
+            owner: String::new(),
             data,
+            executor: ExecutorKind::InProcess,
             assigned_node_id: None,
             retries: 0,
-            max_retries: 3,  // Default retry limit of 3
+            max_retries: DEFAULT_MAX_RETRIES,
+            preemptions: 0,
+            wasted_work: Duration::ZERO,
         }
     }
-    pub fn new(task_id: &str, priority: u8, ram: u64, cpu: u64, bandwidth: u64, data: Vec<u8>) -> Self {
-        Task {
-            task_id: task_id.to_string(),
-            priority,
-            required_ram: ram,
-            required_cpu: cpu,
-            required_bandwidth: bandwidth,
-            data,
-        }
+
+    // Set a deadline (UNIX time)
+    pub fn with_deadline(mut self, deadline: u64) -> Self {
+        self.deadline = deadline;
+        self
+    }
+
+    // Set the storage requirement (in GB)
+    pub fn with_storage(mut self, storage: u64) -> Self {
+        self.required_storage = storage;
+        self
+    }
+
+    // Set the submitting user
+    pub fn with_owner(mut self, owner: &str) -> Self {
+        self.owner = owner.to_string();
+        self
+    }
+
+    // Set how the task is run on the node
+    pub fn with_executor(mut self, executor: ExecutorKind) -> Self {
+        self.executor = executor;
+        self
     }
 
     // Check if the task has exceeded its retry limit
-    fn exceeded_retry_limit(&self) -> bool {
+    pub fn exceeded_retry_limit(&self) -> bool {
         self.retries >= self.max_retries
     }
 
@@ -51,7 +87,7 @@ impl Task {
        
         
         // Compress task data before sending to nodes
-        fn compress_task_data(data: &[u8]) -> Vec<u8> {
+        pub fn compress_task_data(data: &[u8]) -> Vec<u8> {
             let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
             encoder.write_all(data).unwrap();
             encoder.finish().unwrap()
@@ -1,17 +1,252 @@
-use std::collections::VecDeque;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use log::info;
+use serde::{Deserialize, Serialize};
+use crate::task::Task;
 
-struct TaskQueue {
-    queue: VecDeque<Task>,
+// Raise the priority of tasks that have waited too long
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AgingPolicy {
+    pub interval_secs: u64, // Waiting time that earns one promotion step
+    pub step: u8,           // Priority added per interval waited
+    pub max_priority: u8,   // Aging never promotes past this priority
+}
+
+impl Default for AgingPolicy {
+    fn default() -> Self {
+        AgingPolicy { interval_secs: 30, step: 1, max_priority: u8::MAX }
+    }
+}
+
+impl AgingPolicy {
+    // Priority after waiting `waited`
+    fn effective_priority(&self, priority: u8, waited: Duration) -> u8 {
+        if self.interval_secs == 0 || priority >= self.max_priority {
+            return priority;
+        }
+        let steps = waited.as_secs() / self.interval_secs;
+        let boost = steps.saturating_mul(self.step as u64).min(u8::MAX as u64) as u8;
+        priority.saturating_add(boost).min(self.max_priority)
+    }
+}
+
+struct QueuedTask {
+    task: Task,
+    enqueued_at: Instant,
+    sequence: u64, // Arrival order
+}
+
+pub struct TaskQueue {
+    queue: Vec<QueuedTask>,
+    aging: AgingPolicy,
+    next_sequence: u64,
+}
+
+impl Default for TaskQueue {
+    fn default() -> Self {
+        TaskQueue::new()
+    }
 }
 
 impl TaskQueue {
+    pub fn new() -> Self {
+        TaskQueue::with_aging(AgingPolicy::default())
+    }
+
+    pub fn with_aging(aging: AgingPolicy) -> Self {
+        TaskQueue { queue: Vec::new(), aging, next_sequence: 0 }
+    }
+
+    // Change the aging policy for tasks already queued and future ones
+    pub fn set_aging(&mut self, aging: AgingPolicy) {
+        self.aging = aging;
+    }
+
     // Add a task to the queue
-    fn enqueue(&mut self, task: Task) {
-        self.queue.push_back(task);
+    pub fn enqueue(&mut self, task: Task) {
+        self.queue.push(QueuedTask { task, enqueued_at: Instant::now(), sequence: self.next_sequence });
+        self.next_sequence += 1;
+    }
+
+    // Dequeue the next task: highest (aged) priority, then earliest deadline, then arrival
+    pub fn dequeue(&mut self) -> Option<Task> {
+        let index = self.next_index()?;
+        let entry = self.queue.remove(index);
+        Some(self.promote(entry))
+    }
+
+    // Look at the next task without removing it
+    pub fn peek(&self) -> Option<&Task> {
+        self.next_index().map(|index| &self.queue[index].task)
+    }
+
+    // Find a queued task by ID
+    pub fn get_task_by_id(&self, task_id: &str) -> Option<&Task> {
+        self.queue.iter().map(|entry| &entry.task).find(|task| task.task_id == task_id)
+    }
+
+    // Remove a queued task by ID (e.g. on cancellation)
+    pub fn remove(&mut self, task_id: &str) -> Option<Task> {
+        let index = self.queue.iter().position(|entry| entry.task.task_id == task_id)?;
+        Some(self.queue.remove(index).task)
+    }
+
+    // Drain every queued task as a batch, in dequeue order
+    pub fn get_all_tasks(&mut self) -> Vec<Task> {
+        let now = Instant::now();
+        let mut entries: Vec<QueuedTask> = self.queue.drain(..).collect();
+        entries.sort_by(|a, b| self.compare(a, b, now));
+        entries.into_iter().map(|entry| self.promote(entry)).collect()
+    }
+
+    // Every queued task in dequeue order, with its aged priority, leaving the queue as it is
+    pub fn snapshot(&self) -> Vec<Task> {
+        let now = Instant::now();
+        let mut entries: Vec<&QueuedTask> = self.queue.iter().collect();
+        entries.sort_by(|a, b| self.compare(a, b, now));
+        entries
+            .into_iter()
+            .map(|entry| {
+                let mut task = entry.task.clone();
+                task.priority = self.aging.effective_priority(task.priority, now - entry.enqueued_at);
+                task
+            })
+            .collect()
+    }
+
+    // Remove the tasks placed on a node (task_id -> node_id), in dequeue order. The rest keep
+    // their place and waiting time, so they go on aging.
+    pub fn take_placeable(&mut self, assignments: &HashMap<String, String>) -> Vec<Task> {
+        let now = Instant::now();
+        let (mut placed, waiting): (Vec<QueuedTask>, Vec<QueuedTask>) =
+            self.queue.drain(..).partition(|entry| assignments.contains_key(&entry.task.task_id));
+        self.queue = waiting;
+        placed.sort_by(|a, b| self.compare(a, b, now));
+        placed.into_iter().map(|entry| self.promote(entry)).collect()
+    }
+
+    // Number of tasks waiting
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    fn next_index(&self) -> Option<usize> {
+        let now = Instant::now();
+        self.queue
+            .iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| self.compare(a, b, now))
+            .map(|(index, _)| index)
+    }
+
+    // Ordering used by the queue: Less = dequeued first
+    fn compare(&self, a: &QueuedTask, b: &QueuedTask, now: Instant) -> Ordering {
+        let priority_a = self.aging.effective_priority(a.task.priority, now - a.enqueued_at);
+        let priority_b = self.aging.effective_priority(b.task.priority, now - b.enqueued_at);
+        priority_b
+            .cmp(&priority_a)
+            .then_with(|| a.task.deadline.cmp(&b.task.deadline))
+            .then_with(|| a.sequence.cmp(&b.sequence))
+    }
+
+    // Apply the aged priority to a task leaving the queue
+    fn promote(&self, entry: QueuedTask) -> Task {
+        let mut task = entry.task;
+        let waited = entry.enqueued_at.elapsed();
+        let aged = self.aging.effective_priority(task.priority, waited);
+        if aged > task.priority {
+            info!("Task {} promoted from priority {} to {} after waiting {}s", task.task_id, task.priority, aged, waited.as_secs());
+            task.priority = aged;
+        }
+        task
+    }
+}
+
+#[cfg(test)]
+impl TaskQueue {
+    // Pretend a queued task has been waiting `by` longer
+    pub(crate) fn backdate(&mut self, task_id: &str, by: Duration) {
+        for entry in self.queue.iter_mut().filter(|entry| entry.task.task_id == task_id) {
+            entry.enqueued_at -= by;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task(task_id: &str, priority: u8) -> Task {
+        Task::new(task_id, priority, 64, 10, 0, Vec::new())
+    }
+
+    fn ids(tasks: &[Task]) -> Vec<&str> {
+        tasks.iter().map(|task| task.task_id.as_str()).collect()
+    }
+
+    #[test]
+    fn higher_priority_first_then_arrival() {
+        let mut queue = TaskQueue::new();
+        queue.enqueue(task("low", 1));
+        queue.enqueue(task("high", 5));
+        queue.enqueue(task("low2", 1));
+        assert_eq!(ids(&queue.snapshot()), ["high", "low", "low2"]);
+        assert_eq!(queue.dequeue().unwrap().task_id, "high");
+        assert_eq!(queue.dequeue().unwrap().task_id, "low");
+    }
+
+    #[test]
+    fn waiting_tasks_age_up_to_the_cap() {
+        let aging = AgingPolicy { interval_secs: 30, step: 2, max_priority: 5 };
+        assert_eq!(aging.effective_priority(1, Duration::from_secs(29)), 1);
+        assert_eq!(aging.effective_priority(1, Duration::from_secs(61)), 5);
+        assert_eq!(aging.effective_priority(1, Duration::from_secs(45)), 3);
+        assert_eq!(aging.effective_priority(9, Duration::from_secs(600)), 9);
+        let disabled = AgingPolicy { interval_secs: 0, ..aging };
+        assert_eq!(disabled.effective_priority(1, Duration::from_secs(600)), 1);
+    }
+
+    #[test]
+    fn an_old_task_overtakes_newer_higher_priority_ones() {
+        let mut queue = TaskQueue::with_aging(AgingPolicy { interval_secs: 30, step: 1, max_priority: u8::MAX });
+        queue.enqueue(task("old", 1));
+        queue.enqueue(task("new", 3));
+        queue.backdate("old", Duration::from_secs(95));
+        let snapshot = queue.snapshot();
+        assert_eq!(ids(&snapshot), ["old", "new"]);
+        assert_eq!(snapshot[0].priority, 4);
+        // The snapshot leaves the queue alone
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.get_task_by_id("old").unwrap().priority, 1);
+    }
+
+    #[test]
+    fn unplaced_tasks_keep_their_waiting_time() {
+        let mut queue = TaskQueue::with_aging(AgingPolicy { interval_secs: 30, step: 1, max_priority: u8::MAX });
+        queue.enqueue(task("a", 1));
+        queue.enqueue(task("b", 1));
+        queue.enqueue(task("c", 1));
+        queue.backdate("a", Duration::from_secs(60));
+
+        let assignments = HashMap::from([("b".to_string(), "node_1".to_string())]);
+        assert_eq!(ids(&queue.take_placeable(&assignments)), ["b"]);
+        assert_eq!(ids(&queue.snapshot()), ["a", "c"]);
+        assert_eq!(queue.snapshot()[0].priority, 3);
     }
 
-    // Dequeue the next task from the queue
-    fn dequeue(&mut self) -> Option<Task> {
-        self.queue.pop_front()
+    #[test]
+    fn placed_tasks_leave_with_their_aged_priority() {
+        let mut queue = TaskQueue::with_aging(AgingPolicy { interval_secs: 30, step: 1, max_priority: u8::MAX });
+        queue.enqueue(task("a", 1));
+        queue.backdate("a", Duration::from_secs(30));
+        let assignments = HashMap::from([("a".to_string(), "node_1".to_string())]);
+        assert_eq!(queue.take_placeable(&assignments)[0].priority, 2);
+        assert!(queue.is_empty());
     }
 }
@@ -0,0 +1,989 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration as StdDuration, SystemTime};
+use log::{info, warn, error};
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::time::{interval, Duration};
+use crate::config::{Config, ConfigDiff, ConfigSource, CONTROLLER_KEYS};
+use crate::controller_grpc_client::NodeController as NodeClient;
+use crate::controller_grpc_client::node::{TaskEvent, TaskState};
+use crate::executor::TaskOutput;
+use crate::job_service::{JobCommand, JobRecord, JobState};
+use crate::leader_election::Leadership;
+use crate::resource_enforcer::ResourceUsage;
+use crate::redis_client::SharedUpdate;
+use crate::result_store::{ResultStore, TaskResult};
+use crate::state_store::{ControllerState, JournalEntry, StateStore};
+use crate::load_balancer::LoadBalancer;
+use crate::node::Node;
+use crate::node_controller::{HeartbeatTracker, NodeCommand, NodeController, NodeHealth, NodeSummary};
+use crate::strategy_switcher::{ClusterConditions, StrategySwitcher};
+use crate::task::{Task, DEFAULT_MAX_RETRIES};
+use crate::task_queue::{AgingPolicy, TaskQueue};
+use crate::task_tracker::{TaskProgress, TaskStatus, TaskTracker};
+
+// Events the scheduler reacts to
+#[derive(Debug)]
+pub enum SchedulerEvent {
+    Submit(Task),
+    Progress(TaskEvent), // Streamed from nodes over WatchTasks or ReportProgress
+    Job(JobCommand),     // From the client-facing JobService
+    Node(NodeCommand),   // Operator requests (dcctl nodes ...)
+    TaskCompleted { task_id: String, node_id: String, output: TaskOutput },
+    TaskFailed { task_id: String, node_id: String, error: String },
+    NodeFailed { node_id: String },
+    ReloadConfig { reply: oneshot::Sender<Result<ConfigDiff, String>> },
+}
+
+pub struct TaskScheduler {
+    task_queue: TaskQueue,
+    task_tracker: TaskTracker,
+    controller: NodeController,
+    load_balancer: LoadBalancer,
+    switcher: StrategySwitcher,
+    node_clients: HashMap<String, NodeClient>, // node_id -> gRPC client
+    heartbeats: Option<Arc<Mutex<HeartbeatTracker>>>, // Failure detector fed by node heartbeats
+    results: Arc<Mutex<ResultStore>>, // Outcomes of finished tasks, served by GetTaskResult
+    jobs: HashMap<String, JobRecord>, // Every submitted task, including finished ones
+    job_updates: broadcast::Sender<JobRecord>,
+    events_tx: mpsc::Sender<SchedulerEvent>,
+    events_rx: mpsc::Receiver<SchedulerEvent>,
+    tick: Duration,
+    default_max_retries: u32, // For jobs submitted without their own limit
+    config: Option<(ConfigSource, Config)>, // Where the running configuration came from, for reloads
+    journal: Option<StateStore>, // Write-ahead log of state changes, replayed after a crash
+    shared: Option<mpsc::UnboundedSender<SharedUpdate>>, // Mirror of state changes in Redis
+    leadership: Leadership, // The scheduler stops once this replica is no longer the leader
+}
+
+impl TaskScheduler {
+    pub fn new(controller: NodeController, load_balancer: LoadBalancer, switcher: StrategySwitcher) -> Self {
+        let (events_tx, events_rx) = mpsc::channel(1024);
+        TaskScheduler {
+            task_queue: TaskQueue::new(),
+            task_tracker: TaskTracker::new(),
+            controller,
+            load_balancer,
+            switcher,
+            node_clients: HashMap::new(),
+            heartbeats: None,
+            results: Arc::new(Mutex::new(ResultStore::new())),
+            jobs: HashMap::new(),
+            job_updates: broadcast::channel(256).0,
+            events_tx,
+            events_rx,
+            tick: Duration::from_secs(1),
+            default_max_retries: DEFAULT_MAX_RETRIES,
+            config: None,
+            journal: None,
+            shared: None,
+            leadership: Leadership::always(),
+        }
+    }
+
+    // How often queued tasks are placed
+    pub fn with_tick(mut self, tick: Duration) -> Self {
+        self.tick = tick;
+        self
+    }
+
+    // Priority aging for queued tasks
+    pub fn with_aging(mut self, aging: AgingPolicy) -> Self {
+        self.task_queue.set_aging(aging);
+        self
+    }
+
+    // Where finished task results are kept (and how much output stays inline)
+    pub fn with_results(mut self, results: ResultStore) -> Self {
+        self.results = Arc::new(Mutex::new(results));
+        self
+    }
+
+    // Retry limit for jobs that don't set their own
+    pub fn with_max_retries(mut self, max_retries: u32) -> Self {
+        self.default_max_retries = max_retries;
+        self
+    }
+
+    // Allow ReloadConfig to re-read `source`; `config` is what the scheduler was built from
+    pub fn with_config(mut self, source: ConfigSource, config: Config) -> Self {
+        self.config = Some((source, config));
+        self
+    }
+
+    // Journal every state change so a restarted controller can recover
+    pub fn with_journal(mut self, journal: StateStore) -> Self {
+        self.journal = Some(journal);
+        self
+    }
+
+    // Mirror state changes and node status into Redis (see RedisClient::spawn_mirror)
+    pub fn with_shared_state(mut self, shared: mpsc::UnboundedSender<SharedUpdate>) -> Self {
+        self.shared = Some(shared);
+        self
+    }
+
+    pub fn with_leadership(mut self, leadership: Leadership) -> Self {
+        self.leadership = leadership;
+        self
+    }
+
+    // Fail over nodes whose heartbeats stop arriving
+    pub fn with_failure_detector(mut self, heartbeats: Arc<Mutex<HeartbeatTracker>>) -> Self {
+        self.heartbeats = Some(heartbeats);
+        self
+    }
+
+    // Shared handle on the results of finished tasks
+    pub fn result_store(&self) -> Arc<Mutex<ResultStore>> {
+        self.results.clone()
+    }
+
+    // Job state changes, for JobService watchers
+    pub fn job_updates(&self) -> broadcast::Sender<JobRecord> {
+        self.job_updates.clone()
+    }
+
+    // Sender used by gRPC handlers and nodes to report events
+    pub fn event_sender(&self) -> mpsc::Sender<SchedulerEvent> {
+        self.events_tx.clone()
+    }
+
+    // Reload whenever the config file changes; does nothing without with_config
+    pub fn watch_config(&self, every: Duration) {
+        let mut changes = match &self.config {
+            Some((source, _)) => source.watch(every),
+            None => return,
+        };
+        let events = self.events_tx.clone();
+        tokio::spawn(async move {
+            while changes.recv().await.is_some() {
+                let (reply, response) = oneshot::channel();
+                if events.send(SchedulerEvent::ReloadConfig { reply }).await.is_err() {
+                    break;
+                }
+                if let Ok(Err(err)) = response.await {
+                    warn!("Keeping the current configuration: {}", err);
+                }
+            }
+        });
+    }
+
+    // Register the gRPC client used to dispatch tasks to a node and subscribe to its task events
+    pub fn register_node_client(&mut self, node_id: &str, client: NodeClient) {
+        let mut watcher = client.clone();
+        let events = self.events_tx.clone();
+        let watched_node = node_id.to_string();
+        tokio::spawn(async move {
+            let mut stream = match watcher.watch_tasks().await {
+                Ok(stream) => stream,
+                Err(err) => {
+                    warn!("Could not watch tasks on Node {}: {}", watched_node, err);
+                    return;
+                }
+            };
+            while let Ok(Some(event)) = stream.message().await {
+                if events.send(SchedulerEvent::Progress(event)).await.is_err() {
+                    break;
+                }
+            }
+            warn!("Task event stream from Node {} closed", watched_node);
+        });
+
+        self.node_clients.insert(node_id.to_string(), client);
+    }
+
+    // Add a node to the cluster together with its gRPC client
+    pub fn add_node(&mut self, node: Node, client: NodeClient) {
+        let node_id = node.node_id.clone();
+        self.controller.add_node(node);
+        self.register_node_client(&node_id, client);
+    }
+
+    // Queue a task for scheduling
+    pub fn submit(&mut self, mut task: Task) {
+        if task.max_retries == 0 {
+            task.max_retries = self.default_max_retries;
+        }
+        info!("Task {} queued (priority {})", task.task_id, task.priority);
+        let record = JobRecord::from_task(&task);
+        self.journal(JournalEntry::Enqueued { task: task.clone() });
+        self.journal(JournalEntry::Job { record: record.clone() });
+        let _ = self.job_updates.send(record.clone());
+        self.jobs.insert(task.task_id.clone(), record);
+        self.task_queue.enqueue(task);
+    }
+
+    // Rebuild the queue, task-to-node map and job records from the journal, then ask each node
+    // what became of the tasks dispatched to it while the controller was down.
+    // Call after the nodes are added and before run().
+    pub async fn recover(&mut self, state: ControllerState) {
+        for task in state.queued() {
+            self.task_queue.enqueue(task.clone());
+        }
+        self.jobs = state.jobs;
+        for (task_id, node_id) in state.assigned {
+            if let Some(task) = state.tasks.get(&task_id) {
+                self.reconcile(task.clone(), node_id).await;
+            }
+        }
+        info!("Recovered {} queued and {} running tasks", self.task_queue.len(), self.task_tracker.task_node_map.len());
+    }
+
+    // Resume tracking a task the journal places on `node_id`, or requeue it if the node lost it
+    async fn reconcile(&mut self, mut task: Task, node_id: String) {
+        let status = match self.node_clients.get_mut(&node_id) {
+            Some(client) => client.get_task_status(task.task_id.clone()).await.map_err(|e| e.to_string()),
+            None => Err("node is not connected".to_string()),
+        };
+        let state = status.as_ref().map_or(TaskState::Unknown, |status| TaskState::from_i32(status.state).unwrap_or(TaskState::Unknown));
+
+        match (state, status) {
+            (TaskState::Queued | TaskState::Running | TaskState::Succeeded | TaskState::Failed, Ok(status)) => {
+                if let Some(node) = self.controller.get_node_mut(&node_id) {
+                    node.allocate_resources(&task);
+                }
+                task.assigned_node_id = Some(node_id.clone());
+                self.task_tracker.track_task(task, &node_id);
+                if state == TaskState::Succeeded || state == TaskState::Failed {
+                    // Finished while the controller was down: handle it as if the event just arrived
+                    let event = TaskEvent {
+                        task_id: status.task_id,
+                        node_id,
+                        state: status.state,
+                        exit_code: status.exit_code,
+                        message: status.error,
+                        ..Default::default()
+                    };
+                    self.handle_progress(event).await;
+                }
+            }
+            (_, status) => {
+                let reason = status.err().unwrap_or_else(|| format!("node reports {:?}", state));
+                warn!("Task {} is no longer on Node {} ({}), requeueing", task.task_id, node_id, reason);
+                task.assigned_node_id = None;
+                self.journal(JournalEntry::Enqueued { task: task.clone() });
+                self.update_job(&task.task_id, |job| {
+                    job.state = JobState::Pending;
+                    job.node_id = None;
+                    job.message = format!("Lost by Node {} during a controller restart", node_id);
+                });
+                self.task_queue.enqueue(task);
+            }
+        }
+    }
+
+    // Change a job's record, journal it and publish it to watchers
+    fn update_job(&mut self, job_id: &str, update: impl FnOnce(&mut JobRecord)) {
+        if let Some(record) = self.jobs.get_mut(job_id) {
+            update(record);
+            record.updated_at = SystemTime::now();
+            let record = record.clone();
+            self.journal(JournalEntry::Job { record: record.clone() });
+            // Sending only fails when nobody is watching
+            let _ = self.job_updates.send(record);
+        }
+    }
+
+    // Publish a running job's progress; too frequent (and too cheap to lose) to journal
+    fn update_job_progress(&mut self, job_id: &str, progress_percent: u32) {
+        if let Some(record) = self.jobs.get_mut(job_id) {
+            record.progress_percent = progress_percent;
+            record.updated_at = SystemTime::now();
+            let _ = self.job_updates.send(record.clone());
+        }
+    }
+
+    // Append to the journal and mirror to Redis; a failed write is logged and the change
+    // still applied in memory
+    fn journal(&mut self, entry: JournalEntry) {
+        if let Some(shared) = &self.shared {
+            let _ = shared.send(SharedUpdate::Journal(Box::new(entry.clone())));
+        }
+        if let Some(journal) = &mut self.journal {
+            if let Err(err) = journal.append(entry) {
+                error!("State change not persisted: {}", err);
+            }
+        }
+    }
+
+    // Run the scheduling service (runs indefinitely)
+    pub async fn run(mut self) {
+        let mut ticker = interval(self.tick);
+        let mut leadership = self.leadership.clone();
+        loop {
+            tokio::select! {
+                _ = leadership.lost() => {
+                    // Another replica may already be scheduling; stop before we double-dispatch
+                    warn!("No longer the leader, stopping the scheduler");
+                    break;
+                }
+                _ = ticker.tick() => {
+                    self.check_node_health();
+                    if let Some(shared) = &self.shared {
+                        let _ = shared.send(SharedUpdate::Nodes(self.node_summaries()));
+                    }
+                    self.schedule_pending().await;
+                }
+                event = self.events_rx.recv() => match event {
+                    Some(SchedulerEvent::Progress(event)) => self.handle_progress(event).await,
+                    Some(SchedulerEvent::Job(command)) => self.handle_job_command(command).await,
+                    Some(event) => self.handle_event(event),
+                    None => break,
+                },
+            }
+            // A config reload may have changed the tick
+            if ticker.period() != self.tick {
+                ticker = interval(self.tick);
+            }
+        }
+        info!("Task scheduler stopped");
+    }
+
+    // Place every queued task and dispatch it to its node
+    async fn schedule_pending(&mut self) {
+        if self.task_queue.is_empty() {
+            return;
+        }
+
+        // Tasks stay queued until placed so they keep aging while they wait
+        let mut tasks = self.task_queue.snapshot();
+        let mut nodes = self.controller.get_available_nodes();
+
+        let conditions = ClusterConditions::observe(&tasks, &nodes);
+        self.switcher.apply(&mut self.load_balancer, &conditions);
+
+        let assignments: HashMap<String, String> = self.load_balancer.assign_tasks(&mut tasks, &mut nodes).into_iter().collect();
+        self.controller.update_nodes(nodes);
+
+        // Unplaced tasks wait for capacity on a later tick
+        for task in self.task_queue.take_placeable(&assignments) {
+            let node_id = assignments[&task.task_id].clone();
+            self.dispatch(task, node_id).await;
+        }
+
+        // The most urgent task that fits nowhere may take the place of lower-priority ones
+        let waiting = tasks
+            .iter()
+            .filter(|task| !assignments.contains_key(&task.task_id))
+            .min_by_key(|task| std::cmp::Reverse(task.submitted_priority));
+        if let Some(task) = waiting {
+            self.preempt_for(task).await;
+        }
+    }
+
+    // Make room for a task by preempting lower-priority tasks on the node where that takes the
+    // fewest of them. The victims go back to the queue; the task is placed on a later tick.
+    async fn preempt_for(&mut self, task: &Task) {
+        let plan = self
+            .controller
+            .get_available_nodes()
+            .iter()
+            .filter_map(|node| {
+                let running: Vec<Task> = self
+                    .task_tracker
+                    .tasks_on_node(&node.node_id)
+                    .iter()
+                    .filter_map(|task_id| self.task_tracker.get_task(task_id).cloned())
+                    .collect();
+                let victims = node.preemption_victims(task, &running)?;
+                (!victims.is_empty()).then(|| (node.node_id.clone(), victims))
+            })
+            .min_by_key(|(_, victims)| victims.len());
+        let (node_id, victims) = match plan {
+            Some(plan) => plan,
+            None => return,
+        };
+
+        let reason = format!("making room for Task {} (priority {})", task.task_id, task.submitted_priority);
+        for victim in victims {
+            let client = match self.node_clients.get_mut(&node_id) {
+                Some(client) => client,
+                None => return,
+            };
+            match client.preempt_task(victim.task_id.clone(), reason.clone()).await {
+                Ok(Some(wasted)) => self.requeue_preempted(&victim.task_id, &node_id, &reason, wasted),
+                // It finished in the meantime; its own event frees the resources
+                Ok(None) => warn!("Node {} had already stopped Task {}", node_id, victim.task_id),
+                Err(status) => {
+                    warn!("Could not preempt Task {} on Node {}: {}", victim.task_id, node_id, status);
+                    return;
+                }
+            }
+        }
+    }
+
+    // Put a task its node suspended back in the queue; it starts over, losing `wasted` of work
+    fn requeue_preempted(&mut self, task_id: &str, node_id: &str, reason: &str, wasted: StdDuration) {
+        if let Some(mut task) = self.finish(task_id, node_id) {
+            info!("Task {} preempted on Node {} ({}, {:.1}s of work lost), requeueing", task.task_id, node_id, reason, wasted.as_secs_f64());
+            task.preemptions += 1;
+            task.wasted_work += wasted;
+            task.assigned_node_id = None;
+            self.journal(JournalEntry::Enqueued { task: task.clone() });
+            let (preemptions, wasted_work) = (task.preemptions, task.wasted_work);
+            self.update_job(&task.task_id, |job| {
+                job.state = JobState::Pending;
+                job.node_id = None;
+                job.preemptions = preemptions;
+                job.wasted_work = wasted_work;
+                job.message = format!("Preempted: {}", reason);
+            });
+            self.task_queue.enqueue(task);
+        }
+    }
+
+    // Send a placed task to its node over gRPC
+    async fn dispatch(&mut self, mut task: Task, node_id: String) {
+        let client = match self.node_clients.get_mut(&node_id) {
+            Some(client) => client,
+            None => {
+                warn!("No gRPC client for Node {}, requeueing Task {}", node_id, task.task_id);
+                self.release(&task, &node_id);
+                self.task_queue.enqueue(task);
+                return;
+            }
+        };
+
+        let accepted = match client
+            .assign_task_to_node(
+                task.task_id.clone(),
+                task.required_ram,
+                task.required_cpu,
+                task.required_bandwidth,
+                task.data.clone(),
+                &task.executor,
+            )
+            .await
+        {
+            Ok(accepted) => accepted,
+            Err(status) if is_transport_error(&status) => {
+                // Not the task's fault: keep its retries, and try other nodes for a while
+                warn!("Could not reach Node {} to dispatch Task {}: {}", node_id, task.task_id, status);
+                self.release(&task, &node_id);
+                self.controller.suspect_node(&node_id);
+                self.task_queue.enqueue(task);
+                return;
+            }
+            Err(status) => {
+                warn!("Node {} refused Task {}: {}", node_id, task.task_id, status);
+                false
+            }
+        };
+
+        if accepted {
+            info!("Task {} dispatched to Node {}", task.task_id, node_id);
+            task.assigned_node_id = Some(node_id.clone());
+            self.journal(JournalEntry::Assigned { task_id: task.task_id.clone(), node_id: node_id.clone() });
+            self.update_job(&task.task_id, |job| {
+                job.state = JobState::Running;
+                job.node_id = Some(node_id.clone());
+                job.progress_percent = 0;
+            });
+            self.task_tracker.track_task(task, &node_id);
+        } else {
+            warn!("Node {} rejected Task {}", node_id, task.task_id);
+            self.release(&task, &node_id);
+            self.retry_or_drop(task);
+        }
+    }
+
+    // Apply failure detector transitions: fail over failed nodes, bring recovered ones back
+    fn check_node_health(&mut self) {
+        let transitions = match &self.heartbeats {
+            Some(heartbeats) => heartbeats.lock().unwrap().evaluate(),
+            None => return,
+        };
+
+        for transition in transitions {
+            match transition.to {
+                NodeHealth::Failed => self.handle_event(SchedulerEvent::NodeFailed { node_id: transition.node_id }),
+                NodeHealth::Recovered => self.controller.recover_node(&transition.node_id),
+                _ => {}
+            }
+        }
+    }
+
+    // Apply a task state transition pushed by a node
+    async fn handle_progress(&mut self, event: TaskEvent) {
+        // Nodes may both push and be watched, so the same transition can arrive twice
+        if self.task_tracker.get_task(&event.task_id).is_none() {
+            return;
+        }
+        // Only the node running the task may report on it; anything else would free its resources there
+        match self.task_tracker.get_assigned_node(&event.task_id) {
+            Some(node_id) if node_id == event.node_id => {}
+            assigned => {
+                warn!(
+                    "Ignoring event for Task {} from Node {}; it is assigned to {}",
+                    event.task_id, event.node_id, assigned.as_deref().unwrap_or("no node")
+                );
+                return;
+            }
+        }
+        let state = TaskState::from_i32(event.state).unwrap_or(TaskState::Unknown);
+        let status = match state {
+            TaskState::Queued => TaskStatus::Queued,
+            TaskState::Running => TaskStatus::Running,
+            TaskState::Succeeded => TaskStatus::Succeeded,
+            TaskState::Failed => TaskStatus::Failed,
+            TaskState::Preempted => TaskStatus::Preempted,
+            TaskState::Unknown => return,
+        };
+        self.task_tracker.update_progress(
+            &event.task_id,
+            TaskProgress {
+                node_id: event.node_id.clone(),
+                status,
+                progress_percent: event.progress_percent,
+                memory_mb: event.memory_mb,
+                cpu_percent: event.cpu_percent,
+                updated_at: std::time::Instant::now(),
+            },
+        );
+
+        if status == TaskStatus::Running {
+            self.update_job_progress(&event.task_id, event.progress_percent);
+        }
+
+        match status {
+            TaskStatus::Succeeded => {
+                let output = self.fetch_output(&event).await;
+                self.record_result(&event, output.clone());
+                self.handle_event(SchedulerEvent::TaskCompleted { task_id: event.task_id, node_id: event.node_id, output });
+            }
+            TaskStatus::Failed => {
+                let output = self.fetch_output(&event).await;
+                self.record_result(&event, output);
+                let error = if event.message.is_empty() { format!("exit code {}", event.exit_code) } else { event.message };
+                self.handle_event(SchedulerEvent::TaskFailed { task_id: event.task_id, node_id: event.node_id, error });
+            }
+            TaskStatus::Preempted => {
+                self.requeue_preempted(&event.task_id, &event.node_id, &event.message, StdDuration::from_millis(event.wasted_work_ms))
+            }
+            TaskStatus::Queued | TaskStatus::Running => {}
+        }
+    }
+
+    // Fetch the full output of a finished task; falls back to what the event carried
+    async fn fetch_output(&mut self, event: &TaskEvent) -> TaskOutput {
+        let fallback = TaskOutput { exit_code: event.exit_code, ..Default::default() };
+        let client = match self.node_clients.get_mut(&event.node_id) {
+            Some(client) => client,
+            None => return fallback,
+        };
+
+        match client.get_task_status(event.task_id.clone()).await {
+            Ok(status) => TaskOutput {
+                exit_code: status.exit_code,
+                stdout: status.stdout,
+                stderr: status.stderr,
+                runtime: StdDuration::from_millis(status.runtime_ms),
+                usage: (status.peak_memory_mb > 0).then(|| ResourceUsage {
+                    peak_memory_mb: status.peak_memory_mb,
+                    cpu_usage_usec: status.cpu_time_ms * 1000,
+                    ..Default::default()
+                }),
+            },
+            Err(err) => {
+                warn!("Could not fetch output of Task {} from Node {}: {}", event.task_id, event.node_id, err);
+                fallback
+            }
+        }
+    }
+
+    // Answer a JobService request
+    async fn handle_job_command(&mut self, command: JobCommand) {
+        match command {
+            JobCommand::Submit { task, reply } => {
+                let result = match self.jobs.get(&task.task_id) {
+                    Some(existing) if !existing.state.is_terminal() => Err(format!("Job {} already exists", task.task_id)),
+                    _ => {
+                        let job_id = task.task_id.clone();
+                        self.submit(*task);
+                        Ok(self.jobs[&job_id].clone())
+                    }
+                };
+                let _ = reply.send(result);
+            }
+            JobCommand::Cancel { job_id, reply } => {
+                let _ = reply.send(self.cancel_job(&job_id).await);
+            }
+            JobCommand::Get { job_id, reply } => {
+                let _ = reply.send(self.jobs.get(&job_id).cloned());
+            }
+            JobCommand::List { owner, state, reply } => {
+                let mut jobs: Vec<JobRecord> = self
+                    .jobs
+                    .values()
+                    .filter(|job| owner.as_ref().is_none_or(|owner| &job.owner == owner))
+                    .filter(|job| state.is_none_or(|state| job.state == state))
+                    .cloned()
+                    .collect();
+                jobs.sort_by_key(|job| job.submitted_at);
+                let _ = reply.send(jobs);
+            }
+        }
+    }
+
+    // Remove a pending job from the queue, or stop it on its node
+    async fn cancel_job(&mut self, job_id: &str) -> Result<JobRecord, String> {
+        match self.jobs.get(job_id) {
+            None => return Err(format!("Job {} not found", job_id)),
+            Some(job) if job.state.is_terminal() => return Err(format!("Job {} already finished", job_id)),
+            Some(_) => {}
+        }
+
+        if self.task_queue.remove(job_id).is_none() {
+            let node_id = match self.task_tracker.get_assigned_node(job_id) {
+                Some(node_id) => node_id,
+                None => return Err(format!("Job {} is not queued or running", job_id)),
+            };
+            // Forget the task first so the node's resulting FAILED event is ignored
+            self.finish(job_id, &node_id);
+            if let Some(client) = self.node_clients.get_mut(&node_id) {
+                match client.cancel_task(job_id.to_string()).await {
+                    Ok(true) => {}
+                    Ok(false) => warn!("Node {} had already stopped Task {}", node_id, job_id),
+                    Err(err) => warn!("Could not cancel Task {} on Node {}: {}", job_id, node_id, err),
+                }
+            }
+        }
+
+        info!("Job {} cancelled", job_id);
+        self.journal(JournalEntry::Finished { task_id: job_id.to_string() });
+        self.update_job(job_id, |job| {
+            job.state = JobState::Cancelled;
+            job.message = "Cancelled by user".to_string();
+        });
+        Ok(self.jobs[job_id].clone())
+    }
+
+    // Store a finished task's result in the background; large outputs are uploaded to IPFS
+    fn record_result(&self, event: &TaskEvent, output: TaskOutput) {
+        let (ipfs, inline_limit, tail_bytes) = {
+            let results = self.results.lock().unwrap();
+            (results.ipfs.clone(), results.inline_limit, results.tail_bytes)
+        };
+        let results = self.results.clone();
+        let (task_id, node_id, error) = (event.task_id.clone(), event.node_id.clone(), event.message.clone());
+        tokio::spawn(async move {
+            let result = TaskResult::from_output(&task_id, &node_id, output, error, &ipfs, inline_limit, tail_bytes).await;
+            results.lock().unwrap().insert(result);
+        });
+    }
+
+    fn handle_event(&mut self, event: SchedulerEvent) {
+        match event {
+            SchedulerEvent::Submit(task) => self.submit(task),
+            SchedulerEvent::Progress(event) => {
+                // Progress needs the node clients; it is handled asynchronously in run()
+                warn!("Unexpected synchronous progress event for Task {}", event.task_id);
+            }
+            SchedulerEvent::Job(command) => {
+                warn!("Unexpected synchronous job command: {:?}", command);
+            }
+            SchedulerEvent::TaskCompleted { task_id, node_id, output } => {
+                if let Some(task) = self.finish(&task_id, &node_id) {
+                    info!(
+                        "Task {} completed on Node {} with exit code {} in {:.1}s ({} bytes of output)",
+                        task.task_id, node_id, output.exit_code, output.runtime.as_secs_f64(), output.stdout.len()
+                    );
+                    self.journal(JournalEntry::Finished { task_id: task_id.clone() });
+                    self.update_job(&task_id, |job| {
+                        job.state = JobState::Succeeded;
+                        job.progress_percent = 100;
+                    });
+                }
+            }
+            SchedulerEvent::TaskFailed { task_id, node_id, error } => {
+                if let Some(task) = self.finish(&task_id, &node_id) {
+                    warn!("Task {} failed on Node {}: {}", task.task_id, node_id, error);
+                    self.update_job(&task_id, |job| job.message = error);
+                    self.retry_or_drop(task);
+                }
+            }
+            SchedulerEvent::Node(NodeCommand::List { reply }) => {
+                let _ = reply.send(self.node_summaries());
+            }
+            SchedulerEvent::Node(NodeCommand::Drain { node_id, reply }) => {
+                let result = self.controller.drain_node(&node_id).and_then(|_| {
+                    self.node_summaries()
+                        .into_iter()
+                        .find(|summary| summary.node_id == node_id)
+                        .ok_or_else(|| format!("Node {} not found", node_id))
+                });
+                let _ = reply.send(result);
+            }
+            SchedulerEvent::Node(NodeCommand::Add { status, client }) => {
+                if !self.node_clients.contains_key(&status.node_id) {
+                    info!("Node {} joined", status.node_id);
+                    let node = Node::new(&status.node_id, status.available_ram, status.available_storage, status.available_cpu, status.available_bandwidth);
+                    self.add_node(node, client);
+                }
+            }
+            SchedulerEvent::ReloadConfig { reply } => {
+                let _ = reply.send(self.reload_config());
+            }
+            SchedulerEvent::NodeFailed { node_id } => {
+                let requeued = self.task_tracker.tasks_on_node(&node_id);
+                self.journal(JournalEntry::NodeFailed { node_id: node_id.clone() });
+                self.controller.handle_node_failure(&node_id, &mut self.task_tracker, &mut self.task_queue);
+                for task_id in requeued {
+                    self.update_job(&task_id, |job| {
+                        job.state = JobState::Pending;
+                        job.node_id = None;
+                        job.message = format!("Node {} failed", node_id);
+                    });
+                }
+            }
+        }
+    }
+
+    // Re-read the config file and apply what can change at runtime. Runs between events,
+    // so queued and running tasks are untouched and no placement sees a half-applied config.
+    fn reload_config(&mut self) -> Result<ConfigDiff, String> {
+        let (source, current) = self.config.as_ref().ok_or("Controller was not started from a config file")?;
+        let source = source.clone();
+        let new = source.load()?;
+        let diff = ConfigDiff::between(current, &new, CONTROLLER_KEYS);
+
+        // The only step that can fail goes first, so a rejected reload changes nothing
+        if diff.touches("scheduler.strategy") {
+            self.load_balancer.set_strategy(&new.scheduler.strategy)?;
+            self.switcher.set_strategy(&new.scheduler.strategy);
+        }
+        if diff.touches("scheduler.switching") {
+            self.switcher.set_rules(new.scheduler.switching.clone());
+        }
+        if diff.touches("scheduler.aging") {
+            self.task_queue.set_aging(new.scheduler.aging.clone());
+        }
+        self.tick = Duration::from_millis(new.scheduler.tick_ms);
+        if let Some(heartbeats) = &self.heartbeats {
+            heartbeats.lock().unwrap().set_config(new.heartbeat.failure_detector.clone());
+        }
+        {
+            let mut results = self.results.lock().unwrap();
+            results.inline_limit = new.ipfs.inline_output_limit;
+            results.tail_bytes = new.ipfs.output_tail_bytes;
+        }
+        self.default_max_retries = new.retries.max_retries;
+
+        if !diff.applied.is_empty() {
+            info!("Reloaded {}: applied {}", source.path().display(), diff.applied.join(", "));
+        }
+        if !diff.restart_required.is_empty() {
+            warn!("Changes to {} need a controller restart", diff.restart_required.join(", "));
+        }
+        self.config = Some((source, new));
+        Ok(diff)
+    }
+
+    // Node summaries with failure detector state and task counts filled in
+    fn node_summaries(&self) -> Vec<NodeSummary> {
+        let heartbeats = self.heartbeats.as_ref().map(|heartbeats| heartbeats.lock().unwrap());
+        self.controller
+            .summaries()
+            .into_iter()
+            .map(|mut summary| {
+                if let Some(heartbeats) = &heartbeats {
+                    summary.health = heartbeats.health(&summary.node_id);
+                    summary.phi = heartbeats.phi(&summary.node_id).unwrap_or(0.0);
+                }
+                summary.running_tasks = self.task_tracker.tasks_on_node(&summary.node_id).len();
+                summary
+            })
+            .collect()
+    }
+
+    // Stop tracking a finished task and return its resources to the node
+    fn finish(&mut self, task_id: &str, node_id: &str) -> Option<Task> {
+        let task = self.task_tracker.take_task(task_id)?;
+        self.release(&task, node_id);
+        Some(task)
+    }
+
+    fn release(&mut self, task: &Task, node_id: &str) {
+        if let Some(node) = self.controller.get_node_mut(node_id) {
+            node.free_resources(task);
+        }
+    }
+
+    fn retry_or_drop(&mut self, mut task: Task) {
+        task.retries += 1;
+        task.assigned_node_id = None;
+        if task.exceeded_retry_limit() {
+            error!("Task {} permanently failed after {} retries", task.task_id, task.max_retries);
+            self.journal(JournalEntry::Finished { task_id: task.task_id.clone() });
+            self.update_job(&task.task_id, |job| {
+                job.state = JobState::Failed;
+                job.retries = task.retries;
+            });
+        } else {
+            warn!("Retrying Task {} (Attempt #{}/{})", task.task_id, task.retries, task.max_retries);
+            self.journal(JournalEntry::Enqueued { task: task.clone() });
+            self.update_job(&task.task_id, |job| {
+                job.state = JobState::Pending;
+                job.node_id = None;
+                job.retries = task.retries;
+            });
+            self.task_queue.enqueue(task);
+        }
+    }
+}
+
+// The RPC never got an answer from the node, as opposed to the node rejecting the request
+fn is_transport_error(status: &tonic::Status) -> bool {
+    matches!(status.code(), tonic::Code::Unavailable | tonic::Code::DeadlineExceeded | tonic::Code::Cancelled | tonic::Code::Unknown)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tonic::transport::Server;
+    use crate::controller_grpc_client::node::node_service_server::NodeServiceServer;
+    use crate::executor::ExecutorKind;
+    use crate::load_balancer::StrategyRegistry;
+    use crate::node_grpc_server::MyNodeService;
+    use crate::strategy_switcher::SwitchingRules;
+
+    fn scheduler() -> TaskScheduler {
+        let load_balancer = LoadBalancer::new(StrategyRegistry::with_defaults(), "priority").unwrap();
+        let switcher = StrategySwitcher::new(SwitchingRules::default(), load_balancer.strategy_name());
+        TaskScheduler::new(NodeController::new(), load_balancer, switcher)
+    }
+
+    // A task already dispatched to `node_id`, holding its resources there
+    fn running(scheduler: &mut TaskScheduler, task: Task, node_id: &str) {
+        scheduler.controller.get_node_mut(node_id).unwrap().allocate_resources(&task);
+        scheduler.submit(task.clone());
+        scheduler.task_queue.get_all_tasks();
+        scheduler.task_tracker.track_task(task, node_id);
+    }
+
+    // A node agent serving NodeService on a local port, allowed to run sleep
+    async fn node_agent(node: Node) -> NodeClient {
+        let mut config = Config::default();
+        config.executor.allowed_commands = vec!["sleep".to_string()];
+        let service = MyNodeService::new(node).with_config(ConfigSource::new("does/not/exist.toml", Vec::new()), config);
+        let addr = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        tokio::spawn(Server::builder().add_service(NodeServiceServer::new(service)).serve(addr));
+        for _ in 0..50 {
+            if let Ok(client) = NodeClient::connect(format!("http://{}", addr), None).await {
+                return client;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("node agent at {} did not come up", addr);
+    }
+
+    fn sleeping(task_id: &str, priority: u8, ram: u64) -> Task {
+        Task::new(task_id, priority, ram, 10, 1, Vec::new())
+            .with_executor(ExecutorKind::Subprocess { command: "sleep".to_string(), args: vec!["30".to_string()] })
+    }
+
+    fn event(task_id: &str, node_id: &str, state: TaskState) -> TaskEvent {
+        TaskEvent { task_id: task_id.to_string(), node_id: node_id.to_string(), state: state as i32, ..Default::default() }
+    }
+
+    #[tokio::test]
+    async fn unreachable_nodes_get_tasks_back_without_spending_retries() {
+        let mut scheduler = scheduler();
+        scheduler.controller.add_node(Node::new("node_1", 1024, 10, 100, 10));
+        scheduler.node_clients.insert("node_1".to_string(), NodeClient::unreachable());
+        scheduler.submit(Task::new("t1", 0, 512, 50, 1, Vec::new()));
+
+        scheduler.schedule_pending().await;
+        let queued = scheduler.task_queue.get_all_tasks();
+        assert_eq!(queued.len(), 1);
+        assert_eq!(queued[0].retries, 0);
+        assert!(scheduler.task_tracker.get_task("t1").is_none());
+        assert_eq!(scheduler.controller.get_node_mut("node_1").unwrap().allocated_ram, 0);
+        assert!(scheduler.controller.is_suspected("node_1"));
+        assert!(scheduler.controller.get_available_nodes().is_empty());
+    }
+
+    #[tokio::test]
+    async fn tasks_waiting_for_capacity_keep_aging() {
+        let mut scheduler = scheduler().with_aging(AgingPolicy { interval_secs: 30, step: 1, max_priority: u8::MAX });
+        scheduler.controller.add_node(Node::new("node_1", 256, 10, 100, 10));
+        scheduler.submit(Task::new("big", 1, 512, 50, 1, Vec::new()));
+        scheduler.task_queue.backdate("big", StdDuration::from_secs(60));
+
+        scheduler.schedule_pending().await;
+        scheduler.schedule_pending().await;
+        let queued = scheduler.task_queue.snapshot();
+        assert_eq!(queued.len(), 1);
+        assert_eq!(queued[0].priority, 3);
+    }
+
+    #[tokio::test]
+    async fn events_from_another_node_are_ignored() {
+        let mut scheduler = scheduler();
+        scheduler.controller.add_node(Node::new("node_1", 1024, 10, 100, 10));
+        scheduler.controller.add_node(Node::new("node_2", 1024, 10, 100, 10));
+        scheduler.controller.get_node_mut("node_2").unwrap().allocate_resources(&Task::new("other", 0, 256, 20, 1, Vec::new()));
+        running(&mut scheduler, Task::new("t1", 0, 512, 50, 1, Vec::new()), "node_1");
+
+        scheduler.handle_progress(event("t1", "node_2", TaskState::Succeeded)).await;
+        assert!(scheduler.task_tracker.get_task("t1").is_some());
+        assert_eq!(scheduler.controller.get_node_mut("node_1").unwrap().allocated_ram, 512);
+        assert_eq!(scheduler.controller.get_node_mut("node_2").unwrap().allocated_ram, 256);
+        assert_eq!(scheduler.jobs["t1"].state, JobState::Pending);
+
+        scheduler.handle_progress(event("t1", "node_1", TaskState::Succeeded)).await;
+        assert!(scheduler.task_tracker.get_task("t1").is_none());
+        assert_eq!(scheduler.controller.get_node_mut("node_1").unwrap().allocated_ram, 0);
+        assert_eq!(scheduler.jobs["t1"].state, JobState::Succeeded);
+    }
+
+    #[tokio::test]
+    async fn urgent_tasks_preempt_lower_priority_ones() {
+        let mut scheduler = scheduler();
+        let client = node_agent(Node::new("node_1", 1024, 10, 100, 10)).await;
+        scheduler.add_node(Node::new("node_1", 1024, 10, 100, 10), client);
+        scheduler.submit(sleeping("batch", 1, 800));
+        scheduler.schedule_pending().await;
+        assert_eq!(scheduler.task_tracker.get_assigned_node("batch").as_deref(), Some("node_1"));
+
+        scheduler.submit(sleeping("urgent", 9, 600));
+        scheduler.schedule_pending().await;
+        assert!(scheduler.task_tracker.get_task("batch").is_none());
+        assert_eq!(scheduler.jobs["batch"].state, JobState::Pending);
+        assert_eq!(scheduler.task_queue.get_task_by_id("batch").unwrap().preemptions, 1);
+        assert_eq!(scheduler.jobs["batch"].preemptions, 1);
+        assert_eq!(scheduler.jobs["batch"].wasted_work, scheduler.task_queue.get_task_by_id("batch").unwrap().wasted_work);
+
+        // The urgent task takes the freed room; the batch task waits instead of preempting back
+        scheduler.schedule_pending().await;
+        assert_eq!(scheduler.task_tracker.get_assigned_node("urgent").as_deref(), Some("node_1"));
+        assert!(scheduler.task_queue.get_task_by_id("batch").is_some());
+        scheduler.schedule_pending().await;
+        assert_eq!(scheduler.task_tracker.get_assigned_node("urgent").as_deref(), Some("node_1"));
+        assert!(scheduler.task_queue.get_task_by_id("batch").is_some());
+    }
+
+    #[tokio::test]
+    async fn aged_tasks_do_not_preempt_the_tasks_that_preempted_them() {
+        let mut scheduler = scheduler();
+        let client = node_agent(Node::new("node_1", 1024, 10, 100, 10)).await;
+        scheduler.add_node(Node::new("node_1", 1024, 10, 100, 10), client);
+        scheduler.submit(sleeping("batch", 1, 800));
+        scheduler.schedule_pending().await;
+        scheduler.submit(sleeping("urgent", 9, 600));
+        scheduler.schedule_pending().await;
+        scheduler.schedule_pending().await;
+        assert_eq!(scheduler.task_tracker.get_assigned_node("urgent").as_deref(), Some("node_1"));
+
+        // Waiting ages the batch task far above the urgent one, tick after tick
+        for _ in 0..5 {
+            scheduler.task_queue.backdate("batch", Duration::from_secs(600));
+            assert!(scheduler.task_queue.snapshot()[0].priority > 9);
+            scheduler.schedule_pending().await;
+            assert_eq!(scheduler.task_tracker.get_assigned_node("urgent").as_deref(), Some("node_1"));
+            let batch = scheduler.task_queue.get_task_by_id("batch").unwrap();
+            assert_eq!((batch.preemptions, batch.submitted_priority), (1, 1));
+        }
+    }
+}
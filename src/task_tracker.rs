@@ -1,10 +1,72 @@
 use std::collections::HashMap;
+use std::time::Instant;
+use crate::task::Task;
 
-struct TaskTracker {
-    task_node_map: HashMap<String, String>,  // Map task_id -> node_id
+// Lifecycle state of a task as reported by its node
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TaskStatus {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+    Preempted,
+}
+
+// Latest progress report for a task
+#[derive(Debug, Clone)]
+pub struct TaskProgress {
+    pub node_id: String,
+    pub status: TaskStatus,
+    pub progress_percent: u32,
+    pub memory_mb: u64,
+    pub cpu_percent: f64,
+    pub updated_at: Instant,
+}
+
+pub struct TaskTracker {
+    pub task_node_map: HashMap<String, String>,  // Map task_id -> node_id
+    tasks: HashMap<String, Task>,  // Map task_id -> dispatched task (kept for reassignment)
+    progress: HashMap<String, TaskProgress>,  // Map task_id -> latest progress report
+}
+
+impl Default for TaskTracker {
+    fn default() -> Self {
+        TaskTracker::new()
+    }
 }
 
 impl TaskTracker {
+    pub fn new() -> Self {
+        TaskTracker { task_node_map: HashMap::new(), tasks: HashMap::new(), progress: HashMap::new() }
+    }
+
+    // Record a state transition reported by a node
+    pub fn update_progress(&mut self, task_id: &str, progress: TaskProgress) {
+        self.progress.insert(task_id.to_string(), progress);
+    }
+
+    // Get the latest progress report for a task
+    pub fn get_progress(&self, task_id: &str) -> Option<&TaskProgress> {
+        self.progress.get(task_id)
+    }
+
+    // Track a dispatched task so it can be requeued if its node fails
+    pub fn track_task(&mut self, task: Task, node_id: &str) {
+        self.assign_task_to_node(&task.task_id, node_id);
+        self.tasks.insert(task.task_id.clone(), task);
+    }
+
+    // Stop tracking a task and hand it back
+    pub fn take_task(&mut self, task_id: &str) -> Option<Task> {
+        self.task_node_map.remove(task_id);
+        self.tasks.remove(task_id)
+    }
+
+    // Get a dispatched task
+    pub fn get_task(&self, task_id: &str) -> Option<&Task> {
+        self.tasks.get(task_id)
+    }
+
     // Assign a task to a node
     pub fn assign_task_to_node(&mut self, task_id: &str, node_id: &str) {
         self.task_node_map.insert(task_id.to_string(), node_id.to_string());
@@ -18,5 +80,15 @@ impl TaskTracker {
     // Remove task assignment when task is completed or node fails
     pub fn remove_task_assignment(&mut self, task_id: &str) {
         self.task_node_map.remove(task_id);
+        self.tasks.remove(task_id);
+    }
+
+    // Get every task currently assigned to a node
+    pub fn tasks_on_node(&self, node_id: &str) -> Vec<String> {
+        self.task_node_map
+            .iter()
+            .filter(|(_, assigned_node)| assigned_node.as_str() == node_id)
+            .map(|(task_id, _)| task_id.clone())
+            .collect()
     }
 }
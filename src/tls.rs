@@ -0,0 +1,292 @@
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use log::info;
+use rcgen::{CertificateParams, DistinguishedName, DnType};
+use serde::{Deserialize, Serialize};
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint, Identity, ServerTlsConfig};
+use tonic::{Request, Status};
+use crate::controller_grpc_client::node::controller_service_client::ControllerServiceClient;
+use crate::controller_grpc_client::node::enrollment_service_client::EnrollmentServiceClient;
+use crate::controller_grpc_client::node::{EnrollRequest, NodeCertificate, RenewCertificateRequest};
+
+// Names certificates are issued for. Clients check these instead of the host they dial,
+// so controllers and node agents can sit behind any address.
+pub const CONTROLLER_SERVER_NAME: &str = "controller.distributed-computing";
+pub const NODE_SERVER_NAME: &str = "node.distributed-computing";
+
+const CA_FILE: &str = "ca.pem";
+const CERT_FILE: &str = "cert.pem";
+const KEY_FILE: &str = "key.pem";
+
+// What a certificate was issued for, recorded in its organizational unit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PeerRole {
+    Controller,
+    Node,
+    Operator, // dcctl
+}
+
+impl PeerRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PeerRole::Controller => "controller",
+            PeerRole::Node => "node",
+            PeerRole::Operator => "operator",
+        }
+    }
+
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "controller" => Some(PeerRole::Controller),
+            "node" => Some(PeerRole::Node),
+            "operator" => Some(PeerRole::Operator),
+            _ => None,
+        }
+    }
+
+    // Server name a certificate with this role is valid for
+    pub fn server_name(&self) -> &'static str {
+        match self {
+            PeerRole::Controller => CONTROLLER_SERVER_NAME,
+            _ => NODE_SERVER_NAME,
+        }
+    }
+}
+
+// Who is on the other end, as proven by their client certificate
+#[derive(Debug, Clone)]
+pub struct PeerIdentity {
+    pub name: String, // node_id for nodes
+    pub role: PeerRole,
+    pub serial: u64,
+    pub not_after: i64, // UNIX time
+}
+
+impl PeerIdentity {
+    pub fn from_der(der: &[u8]) -> Result<Self, String> {
+        let (_, cert) = x509_parser::parse_x509_certificate(der).map_err(|e| format!("Invalid certificate: {}", e))?;
+        let name = cert
+            .subject()
+            .iter_common_name()
+            .next()
+            .and_then(|cn| cn.as_str().ok())
+            .ok_or("Certificate has no common name")?;
+        let role = cert
+            .subject()
+            .iter_organizational_unit()
+            .next()
+            .and_then(|ou| ou.as_str().ok())
+            .and_then(PeerRole::parse)
+            .ok_or("Certificate has no known role")?;
+        let serial = cert.raw_serial().iter().fold(0u64, |serial, byte| serial << 8 | *byte as u64);
+        Ok(PeerIdentity { name: name.to_string(), role, serial, not_after: cert.validity().not_after.timestamp() })
+    }
+
+    // The client certificate a request came with; None over plaintext
+    pub fn of<T>(request: &Request<T>) -> Result<Option<Self>, Status> {
+        match request.peer_certs().as_ref().and_then(|certs| certs.first().cloned()) {
+            Some(cert) => PeerIdentity::from_der(cert.get_ref()).map(Some).map_err(Status::unauthenticated),
+            None => Ok(None),
+        }
+    }
+}
+
+// Reject callers whose certificate has none of `roles`. Plaintext requests pass: the server only
+// accepts them when TLS is disabled.
+pub fn require_role<T>(request: &Request<T>, roles: &[PeerRole]) -> Result<Option<PeerIdentity>, Status> {
+    let peer = PeerIdentity::of(request)?;
+    match &peer {
+        Some(peer) if !roles.contains(&peer.role) => Err(Status::permission_denied(format!(
+            "{} certificate for {} may not call this",
+            peer.role.as_str(),
+            peer.name
+        ))),
+        _ => Ok(peer),
+    }
+}
+
+// The CA to trust and, for mutual TLS, our own certificate and key
+#[derive(Clone)]
+pub struct TlsSettings {
+    ca_pem: String,
+    identity: Option<(String, String)>, // Certificate and private key, PEM
+}
+
+impl TlsSettings {
+    pub fn new(ca_pem: String, identity: Option<(String, String)>) -> Self {
+        TlsSettings { ca_pem, identity }
+    }
+
+    // Load ca.pem and, when both exist, cert.pem and key.pem from `dir`
+    pub fn load(dir: &Path) -> Result<Self, String> {
+        let ca_path = dir.join(CA_FILE);
+        let ca_pem = fs::read_to_string(&ca_path).map_err(|e| format!("Could not read {}: {}", ca_path.display(), e))?;
+        let (cert_path, key_path) = (dir.join(CERT_FILE), dir.join(KEY_FILE));
+        let identity = if cert_path.exists() && key_path.exists() {
+            let cert = fs::read_to_string(&cert_path).map_err(|e| format!("Could not read {}: {}", cert_path.display(), e))?;
+            let key = fs::read_to_string(&key_path).map_err(|e| format!("Could not read {}: {}", key_path.display(), e))?;
+            Some((cert, key))
+        } else {
+            None
+        };
+        Ok(TlsSettings { ca_pem, identity })
+    }
+
+    // Write the CA certificate and our own certificate and key to `dir`
+    pub fn save(&self, dir: &Path) -> Result<(), String> {
+        fs::create_dir_all(dir).map_err(|e| format!("Could not create {}: {}", dir.display(), e))?;
+        write_file(&dir.join(CA_FILE), &self.ca_pem, 0o644)?;
+        if let Some((cert, key)) = &self.identity {
+            write_file(&dir.join(KEY_FILE), key, 0o600)?;
+            write_file(&dir.join(CERT_FILE), cert, 0o644)?;
+        }
+        Ok(())
+    }
+
+    pub fn ca_pem(&self) -> &str {
+        &self.ca_pem
+    }
+
+    // Identity our own certificate carries
+    pub fn peer_identity(&self) -> Result<PeerIdentity, String> {
+        let (cert, _) = self.identity.as_ref().ok_or("No certificate")?;
+        let pem = x509_parser::pem::parse_x509_pem(cert.as_bytes()).map_err(|e| format!("Invalid certificate: {}", e))?.1;
+        PeerIdentity::from_der(&pem.contents)
+    }
+
+    pub fn client_config(&self, server_name: &str) -> ClientTlsConfig {
+        let mut config = ClientTlsConfig::new().ca_certificate(Certificate::from_pem(&self.ca_pem)).domain_name(server_name);
+        if let Some((cert, key)) = &self.identity {
+            config = config.identity(Identity::from_pem(cert, key));
+        }
+        config
+    }
+
+    // Serve with our certificate and only accept clients with one signed by the same CA
+    pub fn server_config(&self) -> Result<ServerTlsConfig, String> {
+        Ok(self.public_server_config()?.client_ca_root(Certificate::from_pem(&self.ca_pem)))
+    }
+
+    // Serve with our certificate to clients without one (JobService, enrollment)
+    pub fn public_server_config(&self) -> Result<ServerTlsConfig, String> {
+        let (cert, key) = self.identity.as_ref().ok_or("No certificate to serve with")?;
+        Ok(ServerTlsConfig::new().identity(Identity::from_pem(cert, key)))
+    }
+}
+
+// Open a channel to `url`, over TLS when `tls` is set
+pub async fn connect(url: &str, tls: Option<&TlsSettings>, server_name: &str) -> Result<Channel, tonic::transport::Error> {
+    let mut endpoint = Endpoint::from_shared(url.to_string())?;
+    if let Some(tls) = tls {
+        endpoint = endpoint.tls_config(tls.client_config(server_name))?;
+    }
+    endpoint.connect().await
+}
+
+// A node agent's key and certificate, kept in a directory and renewed in place
+#[derive(Clone)]
+pub struct NodeCredentials {
+    dir: PathBuf,
+    settings: Arc<Mutex<TlsSettings>>,
+}
+
+impl NodeCredentials {
+    // Load the credentials in `dir`, enrolling with the controller at `enroll_url` first if
+    // there are none yet. `dir` must already hold the controller's ca.pem.
+    pub async fn load_or_enroll(dir: impl Into<PathBuf>, node_id: &str, enroll_url: &str, token: &str) -> Result<Self, String> {
+        let dir = dir.into();
+        let settings = TlsSettings::load(&dir).map_err(|e| format!("{} (copy the controller's CA certificate there first)", e))?;
+        if settings.identity.is_some() {
+            let identity = settings.peer_identity()?;
+            if identity.role != PeerRole::Node || identity.name != node_id {
+                return Err(format!(
+                    "{} holds a certificate for {} {}, not node {}",
+                    dir.display(),
+                    identity.role.as_str(),
+                    identity.name,
+                    node_id
+                ));
+            }
+            return Ok(NodeCredentials { dir, settings: Arc::new(Mutex::new(settings)) });
+        }
+
+        if token.is_empty() {
+            return Err(format!("No certificate in {} and tls.enrollment_token is not set", dir.display()));
+        }
+        let (csr_pem, key_pem) = certificate_request(node_id)?;
+        let channel = connect(enroll_url, Some(&settings), CONTROLLER_SERVER_NAME)
+            .await
+            .map_err(|e| format!("Could not reach {} to enroll: {}", enroll_url, e))?;
+        let request = EnrollRequest { node_id: node_id.to_string(), csr_pem, enrollment_token: token.to_string() };
+        let issued = EnrollmentServiceClient::new(channel)
+            .enroll(request)
+            .await
+            .map_err(|status| format!("Enrollment rejected: {}", status.message()))?
+            .into_inner();
+        info!("Node {} enrolled, certificate valid until {}", node_id, issued.not_after);
+
+        let credentials = NodeCredentials { dir, settings: Arc::new(Mutex::new(settings)) };
+        credentials.store(issued, key_pem)?;
+        Ok(credentials)
+    }
+
+    // Credentials for a certificate the controller just issued, saved to `dir`
+    pub fn from_issued(dir: impl Into<PathBuf>, issued: NodeCertificate, key_pem: String) -> Result<Self, String> {
+        let dir = dir.into();
+        let settings = TlsSettings::new(issued.ca_certificate_pem.clone(), None);
+        let credentials = NodeCredentials { dir, settings: Arc::new(Mutex::new(settings)) };
+        credentials.store(issued, key_pem)?;
+        Ok(credentials)
+    }
+
+    pub fn settings(&self) -> TlsSettings {
+        self.settings.lock().unwrap().clone()
+    }
+
+    // Swap our certificate for a fresh one over an already authenticated connection
+    pub async fn renew(&self, client: &mut ControllerServiceClient<Channel>, node_id: &str) -> Result<(), String> {
+        let (csr_pem, key_pem) = certificate_request(node_id)?;
+        let issued = client
+            .renew_certificate(RenewCertificateRequest { csr_pem })
+            .await
+            .map_err(|status| format!("Renewal rejected: {}", status.message()))?
+            .into_inner();
+        info!("Node {} renewed its certificate, now valid until {}", node_id, issued.not_after);
+        self.store(issued, key_pem)
+    }
+
+    fn store(&self, issued: NodeCertificate, key_pem: String) -> Result<(), String> {
+        let settings = TlsSettings::new(issued.ca_certificate_pem, Some((issued.certificate_pem, key_pem)));
+        settings.save(&self.dir)?;
+        *self.settings.lock().unwrap() = settings;
+        Ok(())
+    }
+}
+
+// A new private key and a signing request for it; the CA fills in everything but the key
+pub(crate) fn certificate_request(node_id: &str) -> Result<(String, String), String> {
+    let mut params = CertificateParams::new(vec![NODE_SERVER_NAME.to_string()]);
+    params.distinguished_name = DistinguishedName::new();
+    params.distinguished_name.push(DnType::CommonName, node_id);
+    let cert = rcgen::Certificate::from_params(params).map_err(|e| format!("Could not generate a key: {}", e))?;
+    let csr = cert.serialize_request_pem().map_err(|e| format!("Could not create a signing request: {}", e))?;
+    Ok((csr, cert.serialize_private_key_pem()))
+}
+
+// Replace `path` atomically, creating it with `mode`
+pub(crate) fn write_file(path: &Path, contents: &str, mode: u32) -> Result<(), String> {
+    let tmp = path.with_extension("tmp");
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(mode)
+        .open(&tmp)
+        .map_err(|e| format!("Could not create {}: {}", tmp.display(), e))?;
+    file.write_all(contents.as_bytes()).and_then(|_| file.sync_all()).map_err(|e| format!("Could not write {}: {}", tmp.display(), e))?;
+    fs::rename(&tmp, path).map_err(|e| format!("Could not replace {}: {}", path.display(), e))
+}
@@ -1,13 +1,80 @@
+use std::path::Path;
+use tonic::transport::Channel;
+use distributed_computing::auth::with_token;
+use distributed_computing::config::{Config, ConfigSource};
+use distributed_computing::enrollment::Registration;
+use distributed_computing::job_service::job::auth_service_client::AuthServiceClient;
+use distributed_computing::job_service::job::{LoginRequest, WhoAmIRequest};
+use distributed_computing::policy::{self, Action, Resource};
+use distributed_computing::tls::{self, TlsSettings, CONTROLLER_SERVER_NAME};
+use distributed_computing::user_manager::{Principal, Scope, UserRole};
+
+// Sign-in goes to the controller's public port, where this node registered
+async fn auth_client(config: &Config) -> Result<AuthServiceClient<Channel>, String> {
+    let ca = if config.tls.enabled { Some(TlsSettings::load(Path::new(&config.tls.dir))?) } else { None };
+    let url = &config.network.enroll_url;
+    let channel = tls::connect(url, ca.as_ref(), CONTROLLER_SERVER_NAME).await.map_err(|e| format!("Could not reach {}: {}", url, e))?;
+    Ok(AuthServiceClient::new(channel))
+}
+
+// Who `token` belongs to, as the controller sees it
+async fn principal(config: &Config, token: &str) -> Result<Principal, String> {
+    let identity = auth_client(config)
+        .await?
+        .who_am_i(with_token(WhoAmIRequest {}, Some(token))?)
+        .await
+        .map_err(|status| status.message().to_string())?
+        .into_inner();
+    Ok(Principal {
+        user_id: identity.user_id,
+        role: UserRole::parse(&identity.role)?,
+        scopes: identity.scopes.iter().map(|scope| Scope::parse(scope)).collect::<Result<_, _>>()?,
+        token_id: if identity.token_id.is_empty() { None } else { Some(identity.token_id) },
+    })
+}
+
+// Returns a session token for the other commands
 #[tauri::command]
-fn set_resource_limits(ram_limit: u64, storage_limit: u64, cpu_limit: u64, bandwidth_limit: u64) {
+async fn login(email: String, password: String) -> Result<String, String> {
+    let config = ConfigSource::new(Config::default_path(), Vec::new()).load()?;
+    let session = auth_client(&config)
+        .await?
+        .login(LoginRequest { email, password })
+        .await
+        .map_err(|status| status.message().to_string())?
+        .into_inner();
+    Ok(session.token)
+}
+
+// Change what this node sells. Only its owner and operators may; the node agent applies
+// the edited config file within a few seconds.
+#[tauri::command]
+async fn set_resource_limits(token: String, ram_limit: u64, storage_limit: u64, cpu_limit: u64, bandwidth_limit: u64) -> Result<(), String> {
+    let source = ConfigSource::new(Config::default_path(), Vec::new());
+    let config = source.load()?;
+    let registration = Registration::load(Path::new(&config.node.registration_file))?;
+    let principal = principal(&config, &token).await?;
+    // A node with an operator-assigned node.id has no owner on record
+    let resource = match &registration {
+        Some(registration) => Resource::Node { owner: &registration.owner_id },
+        None => Resource::Cluster,
+    };
+    policy::check(&principal, Action::SetSellLimits, resource)?;
+
     println!("Setting limits: RAM: {}MB, Storage: {}GB, CPU: {}%, Bandwidth: {}Mbps",
         ram_limit, storage_limit, cpu_limit, bandwidth_limit);
-    // Call the Node and ResourceManager functions here
+    source.update(&[
+        ("sell_limits.ram_mb", ram_limit),
+        ("sell_limits.storage_gb", storage_limit),
+        ("sell_limits.cpu_percent", cpu_limit),
+        ("sell_limits.bandwidth_mbps", bandwidth_limit),
+    ])?;
+    Ok(())
 }
 
 fn main() {
     tauri::Builder::default()
-        .invoke_handler(tauri::generate_handler![set_resource_limits])
+        .invoke_handler(tauri::generate_handler![login, set_resource_limits])
         .run(tauri::generate_context!())
         .expect("error while running Tauri application");
 }
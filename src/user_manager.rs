@@ -1,26 +1,515 @@
-struct User {
-    user_id: String,
-    email: String,
-    password_hash: String,
-    session_token: Option<String>,
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use log::{info, warn};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::oidc::ExternalIdentity;
+use crate::policy::{self, Action, Resource};
+use crate::tls::write_file;
+
+// API tokens start with this, session tokens are JWTs
+const API_TOKEN_PREFIX: &str = "dca_";
+
+// What an account may do on the marketplace
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UserRole {
+    Buyer,    // Submits jobs
+    Seller,   // Owns nodes that sell capacity
+    Operator, // Runs the cluster
+}
+
+impl UserRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UserRole::Buyer => "buyer",
+            UserRole::Seller => "seller",
+            UserRole::Operator => "operator",
+        }
+    }
+
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "buyer" => Ok(UserRole::Buyer),
+            "seller" => Ok(UserRole::Seller),
+            "operator" => Ok(UserRole::Operator),
+            other => Err(format!("Unknown role {} (buyer, seller or operator)", other)),
+        }
+    }
+
+    // Most a session or API token of this role may do on the job API
+    pub fn scopes(&self) -> &'static [Scope] {
+        match self {
+            UserRole::Buyer | UserRole::Operator => &[Scope::JobsRead, Scope::JobsSubmit, Scope::JobsCancel],
+            UserRole::Seller => &[Scope::JobsRead],
+        }
+    }
+}
+
+// Permission on the job API a token carries
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Scope {
+    #[serde(rename = "jobs:read")]
+    JobsRead, // GetJob, ListJobs, WatchJob
+    #[serde(rename = "jobs:submit")]
+    JobsSubmit,
+    #[serde(rename = "jobs:cancel")]
+    JobsCancel,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::JobsRead => "jobs:read",
+            Scope::JobsSubmit => "jobs:submit",
+            Scope::JobsCancel => "jobs:cancel",
+        }
+    }
+
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "jobs:read" => Ok(Scope::JobsRead),
+            "jobs:submit" => Ok(Scope::JobsSubmit),
+            "jobs:cancel" => Ok(Scope::JobsCancel),
+            other => Err(format!("Unknown scope {} (jobs:read, jobs:submit or jobs:cancel)", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct User {
+    pub user_id: String,
+    pub email: String,
+    pub role: UserRole,
+    #[serde(default)]
+    password_hash: String, // Argon2 PHC string; empty = no password login
+    #[serde(default)]
+    pub identities: Vec<LinkedIdentity>, // Single sign-on accounts that log in as this user
+}
+
+impl User {
+    // The user acting through a login session
+    pub fn principal(&self) -> Principal {
+        Principal { user_id: self.user_id.clone(), role: self.role, scopes: self.role.scopes().to_vec(), token_id: None }
+    }
+}
+
+// An account at an OpenID Connect provider
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LinkedIdentity {
+    pub issuer: String,
+    pub subject: String,
+}
+
+// Who a request is authenticated as, and what it may do
+#[derive(Debug, Clone)]
+pub struct Principal {
+    pub user_id: String,
+    pub role: UserRole,
+    pub scopes: Vec<Scope>,
+    pub token_id: Option<String>, // API token used, None for a session
+}
+
+impl Principal {
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        self.scopes.contains(&scope)
+    }
+}
+
+// Claims of a session token
+#[derive(Debug, Serialize, Deserialize)]
+struct SessionClaims {
+    sub: String, // User ID
+    jti: String, // Session ID, listed in revoked_sessions after logout
+    iat: u64,
+    exp: u64,
+}
+
+// Long-lived token for scripts; like join tokens, stored by hash
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiToken {
+    pub token_id: String,
+    pub user_id: String,
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    pub created_at: u64, // UNIX time
+    pub expires_at: u64, // UNIX time, 0 = never
+}
+
+// Unredeemed join token; the token itself is only shown to its creator
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct JoinToken {
+    owner_id: String,
+    expires_at: u64, // UNIX time
+}
+
+// What a node agent declared when it registered
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodeRegistration {
+    pub node_id: String,
+    pub owner_id: String,
+    pub node_url: String,       // NodeService endpoint the controller dials
+    pub executors: Vec<String>, // Executor kinds the node runs
+    pub enforced: bool,         // Sold limits are enforced with cgroups
+    pub ram_mb: u64,
+    pub cpu_percent: u64,
+    pub bandwidth_mbps: u64,
+    pub storage_gb: u64,
+    pub registered_at: u64, // UNIX time
+}
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+struct Accounts {
+    users: HashMap<String, User>,             // By user ID
+    join_tokens: HashMap<String, JoinToken>,  // By SHA-256 of the token
+    nodes: HashMap<String, NodeRegistration>, // By node ID
+    session_key: String,                      // HMAC key for session tokens, generated on first start
+    api_tokens: HashMap<String, ApiToken>,    // By SHA-256 of the token
+    revoked_sessions: HashMap<String, u64>,   // Logged out session ID -> its expiry
+}
+
+// Accounts of buyers, sellers and operators, and the nodes sellers registered, kept in one JSON file
+pub struct UserManager {
+    path: PathBuf,
+    accounts: Accounts,
+    session_ttl: Duration,
 }
 
 impl UserManager {
-    // Login function using OAuth2
-    fn login(email: String, password: String) -> Result<User, Error> {
-        // Verify credentials and generate session token
-        let user = authenticate_user(email, password)?;
-        user.session_token = Some(generate_session_token());
-        return Ok(user);
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, String> {
+        let path = path.into();
+        let accounts = match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).map_err(|e| format!("Corrupt {}: {}", path.display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Accounts::default(),
+            Err(e) => return Err(format!("Could not read {}: {}", path.display(), e)),
+        };
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).map_err(|e| format!("Could not create {}: {}", dir.display(), e))?;
+        }
+        let mut manager = UserManager { path, accounts, session_ttl: Duration::from_secs(12 * 3600) };
+        if manager.accounts.session_key.is_empty() {
+            manager.accounts.session_key = random_hex(32);
+            manager.save()?;
+        }
+        Ok(manager)
+    }
+
+    pub fn with_session_ttl(mut self, ttl: Duration) -> Self {
+        self.session_ttl = ttl;
+        self
     }
 
-    // Logout function to invalidate session
-    fn logout(user: &mut User) {
-        user.session_token = None; // Invalidate session
+    // `password` may be empty for accounts that never log in
+    pub fn add_user(&mut self, email: &str, role: UserRole, password: &str) -> Result<User, String> {
+        let email = email.trim().to_lowercase();
+        if !email.contains('@') {
+            return Err(format!("Not an email address: {}", email));
+        }
+        if self.find_by_email(&email).is_some() {
+            return Err(format!("A user with email {} already exists", email));
+        }
+        let password_hash = if password.is_empty() { String::new() } else { hash_password(password)? };
+        let user = User { user_id: format!("user_{}", random_hex(8)), email, role, password_hash, identities: Vec::new() };
+        self.accounts.users.insert(user.user_id.clone(), user.clone());
+        self.save()?;
+        info!("Added {} {} as {}", user.role.as_str(), user.email, user.user_id);
+        Ok(user)
     }
-    
-    // Session validation
-    fn validate_session(user: &User) -> bool {
-        return user.session_token.is_some(); // Check if session is valid
+
+    pub fn get_user(&self, user_id: &str) -> Option<&User> {
+        self.accounts.users.get(user_id)
     }
+
+    pub fn find_by_email(&self, email: &str) -> Option<&User> {
+        let email = email.trim().to_lowercase();
+        self.accounts.users.values().find(|user| user.email == email)
+    }
+
+    pub fn has_operator(&self) -> bool {
+        self.accounts.users.values().any(|user| user.role == UserRole::Operator)
+    }
+
+    // Create the first operator; fails once any operator exists
+    pub fn add_first_operator(&mut self, email: &str, password: &str) -> Result<User, String> {
+        if self.has_operator() {
+            return Err("An operator account already exists".to_string());
+        }
+        if password.is_empty() {
+            return Err("The first operator needs a password".to_string());
+        }
+        self.add_user(email, UserRole::Operator, password)
+    }
+
+    pub fn set_password(&mut self, user_id: &str, password: &str) -> Result<(), String> {
+        let password_hash = hash_password(password)?;
+        let user = self.accounts.users.get_mut(user_id).ok_or_else(|| format!("User {} not found", user_id))?;
+        user.password_hash = password_hash;
+        self.save()
+    }
+
+    // Check the password and start a session
+    pub fn login(&mut self, email: &str, password: &str) -> Result<(String, User, u64), String> {
+        let password_hash = self.password_hash_for(email);
+        let matched = verify_password(password, &password_hash);
+        self.finish_login(email, &password_hash, matched)
+    }
+
+    // Hash a login's password is checked against. Unknown emails and accounts without a
+    // password get a dummy one, so they take as long to turn away as a wrong password.
+    pub fn password_hash_for(&self, email: &str) -> String {
+        match self.find_by_email(email) {
+            Some(user) if !user.password_hash.is_empty() => user.password_hash.clone(),
+            _ => dummy_password_hash().to_string(),
+        }
+    }
+
+    // Start a session once the password was checked against `checked_hash` (which is slow, so
+    // callers may do it without holding the accounts). Fails unless it matched and the hash is
+    // still the user's.
+    pub fn finish_login(&mut self, email: &str, checked_hash: &str, matched: bool) -> Result<(String, User, u64), String> {
+        let user = match self.find_by_email(email) {
+            Some(user) if matched && !user.password_hash.is_empty() && user.password_hash == checked_hash => user.clone(),
+            _ => {
+                warn!("Failed login for {}", email.trim());
+                return Err("Wrong email or password".to_string());
+            }
+        };
+        self.start_session(user)
+    }
+
+    // Log in with an identity the OIDC provider vouched for. Unknown identities are linked to the
+    // user with the same verified email, or get a new account with `sign_up_role` if that is set.
+    // Both only happen for identities from `issuer`, the configured provider, and an operator
+    // account is never taken over by email: it logs in with its password.
+    pub fn login_external(&mut self, identity: &ExternalIdentity, issuer: &str, sign_up_role: Option<UserRole>) -> Result<(String, User, u64), String> {
+        let linked = LinkedIdentity { issuer: identity.issuer.clone(), subject: identity.subject.clone() };
+        if let Some(user) = self.accounts.users.values().find(|user| user.identities.contains(&linked)) {
+            return self.start_session(user.clone());
+        }
+
+        let name = identity.email.as_deref().unwrap_or(&identity.subject);
+        if identity.issuer != issuer {
+            warn!("Not linking {} from {}, which is not the configured provider {}", name, identity.issuer, issuer);
+            return Err("No account for this identity; ask an operator to add you".to_string());
+        }
+        let email = identity.email.as_deref().filter(|_| identity.email_verified);
+        let user_id = match email.and_then(|email| self.find_by_email(email)) {
+            Some(user) if user.role == UserRole::Operator => {
+                warn!("Not linking {} at {} to operator {} by email", identity.subject, identity.issuer, user.user_id);
+                return Err("Operator accounts are not linked by email; log in with a password".to_string());
+            }
+            Some(user) => user.user_id.clone(),
+            None => match (email, sign_up_role) {
+                (Some(email), Some(role)) => self.add_user(email, role, "")?.user_id,
+                _ => {
+                    warn!("No account for {} at {}", name, identity.issuer);
+                    return Err("No account for this identity; ask an operator to add you".to_string());
+                }
+            },
+        };
+        let user = self.accounts.users.get_mut(&user_id).expect("user found or added above");
+        user.identities.push(linked);
+        let user = user.clone();
+        self.save()?;
+        info!("Linked {} at {} to user {}", identity.subject, identity.issuer, user.user_id);
+        self.start_session(user)
+    }
+
+    // Sign a session token for `user`. Returns the token and its expiry.
+    fn start_session(&self, user: User) -> Result<(String, User, u64), String> {
+        let now = unix_now();
+        let claims = SessionClaims { sub: user.user_id.clone(), jti: random_hex(12), iat: now, exp: now + self.session_ttl.as_secs() };
+        let token = jsonwebtoken::encode(&Header::new(Algorithm::HS256), &claims, &EncodingKey::from_secret(self.accounts.session_key.as_bytes()))
+            .map_err(|e| format!("Could not sign a session token: {}", e))?;
+        info!("User {} logged in", user.user_id);
+        Ok((token, user, claims.exp))
+    }
+
+    // End a session before it expires
+    pub fn logout(&mut self, token: &str) -> Result<(), String> {
+        let claims = self.session_claims(token)?;
+        let now = unix_now();
+        self.accounts.revoked_sessions.retain(|_, exp| *exp > now);
+        self.accounts.revoked_sessions.insert(claims.jti, claims.exp);
+        self.save()
+    }
+
+    // Resolve a bearer token (session or API token) to the user it acts for
+    pub fn authenticate(&self, token: &str) -> Result<Principal, String> {
+        if token.starts_with(API_TOKEN_PREFIX) {
+            let api_token = self.accounts.api_tokens.get(&token_hash(token)).ok_or("Unknown or revoked API token")?;
+            if api_token.expires_at != 0 && api_token.expires_at <= unix_now() {
+                return Err("API token has expired".to_string());
+            }
+            let user = self.get_user(&api_token.user_id).ok_or("The token's user no longer exists")?;
+            // Tokens never outgrow the role, even if it was lowered after they were created
+            let scopes = api_token.scopes.iter().copied().filter(|scope| user.role.scopes().contains(scope)).collect();
+            return Ok(Principal { user_id: user.user_id.clone(), role: user.role, scopes, token_id: Some(api_token.token_id.clone()) });
+        }
+        let claims = self.session_claims(token)?;
+        let user = self.get_user(&claims.sub).ok_or("The session's user no longer exists")?;
+        Ok(user.principal())
+    }
+
+    fn session_claims(&self, token: &str) -> Result<SessionClaims, String> {
+        let key = DecodingKey::from_secret(self.accounts.session_key.as_bytes());
+        let claims = jsonwebtoken::decode::<SessionClaims>(token, &key, &Validation::new(Algorithm::HS256))
+            .map_err(|e| format!("Invalid session token: {}", e))?
+            .claims;
+        if self.accounts.revoked_sessions.contains_key(&claims.jti) {
+            return Err("Session has ended".to_string());
+        }
+        Ok(claims)
+    }
+
+    // New API token for `user_id`; empty `scopes` means everything the role allows. Returns the token once.
+    pub fn create_api_token(&mut self, user_id: &str, name: &str, scopes: Vec<Scope>, ttl: Option<Duration>) -> Result<(String, ApiToken), String> {
+        let user = self.get_user(user_id).ok_or_else(|| format!("User {} not found", user_id))?;
+        let allowed = user.role.scopes();
+        let scopes = if scopes.is_empty() { allowed.to_vec() } else { scopes };
+        if let Some(scope) = scopes.iter().find(|scope| !allowed.contains(scope)) {
+            return Err(format!("A {} cannot hold {}", user.role.as_str(), scope.as_str()));
+        }
+        let now = unix_now();
+        let token = format!("{}{}", API_TOKEN_PREFIX, random_hex(24));
+        let api_token = ApiToken {
+            token_id: format!("tok_{}", random_hex(6)),
+            user_id: user_id.to_string(),
+            name: name.to_string(),
+            scopes,
+            created_at: now,
+            expires_at: ttl.map_or(0, |ttl| now + ttl.as_secs()),
+        };
+        self.accounts.api_tokens.retain(|_, token| token.expires_at == 0 || token.expires_at > now);
+        self.accounts.api_tokens.insert(token_hash(&token), api_token.clone());
+        self.save()?;
+        info!("Created API token {} for user {}", api_token.token_id, user_id);
+        Ok((token, api_token))
+    }
+
+    pub fn api_tokens_of(&self, user_id: &str) -> Vec<&ApiToken> {
+        self.accounts.api_tokens.values().filter(|token| token.user_id == user_id).collect()
+    }
+
+    pub fn revoke_api_token(&mut self, user_id: &str, token_id: &str) -> Result<ApiToken, String> {
+        let hash = self
+            .accounts
+            .api_tokens
+            .iter()
+            .find(|(_, token)| token.token_id == token_id && token.user_id == user_id)
+            .map(|(hash, _)| hash.clone())
+            .ok_or_else(|| format!("API token {} not found", token_id))?;
+        let revoked = self.accounts.api_tokens.remove(&hash).expect("token found above");
+        self.save()?;
+        info!("Revoked API token {} of user {}", token_id, user_id);
+        Ok(revoked)
+    }
+
+    // New one-time token a node agent of `owner_id` registers with. Returns the token and its expiry.
+    // Whether the caller may create it is up to the RPC; this checks the owner may own nodes.
+    pub fn create_join_token(&mut self, owner_id: &str, ttl: Duration) -> Result<(String, u64), String> {
+        let owner = self.get_user(owner_id).ok_or_else(|| format!("User {} not found", owner_id))?;
+        policy::check(&owner.principal(), Action::OwnNode, Resource::Account { user_id: owner_id })?;
+        let token = format!("dcj_{}", random_hex(24));
+        let expires_at = unix_now() + ttl.as_secs();
+        self.accounts.join_tokens.retain(|_, token| token.expires_at > unix_now());
+        self.accounts.join_tokens.insert(token_hash(&token), JoinToken { owner_id: owner_id.to_string(), expires_at });
+        self.save()?;
+        Ok((token, expires_at))
+    }
+
+    // Redeem `token` and record a node for its owner under a new, controller-assigned ID
+    pub fn register_node(&mut self, token: &str, mut registration: NodeRegistration) -> Result<NodeRegistration, String> {
+        let hash = token_hash(token);
+        let join_token = match self.accounts.join_tokens.get(&hash) {
+            Some(join_token) if join_token.expires_at > unix_now() => join_token.clone(),
+            Some(_) => return Err("Join token has expired".to_string()),
+            None => return Err("Unknown or already used join token".to_string()),
+        };
+        let owner = self.get_user(&join_token.owner_id).ok_or_else(|| format!("Owner {} of the join token no longer exists", join_token.owner_id))?;
+        // The owner's role may have changed since the token was created
+        policy::check(&owner.principal(), Action::OwnNode, Resource::Account { user_id: &owner.user_id })?;
+
+        registration.node_id = loop {
+            let node_id = format!("node-{}", random_hex(6));
+            if !self.accounts.nodes.contains_key(&node_id) {
+                break node_id;
+            }
+        };
+        registration.owner_id = join_token.owner_id;
+        registration.registered_at = unix_now();
+        self.accounts.join_tokens.remove(&hash);
+        self.accounts.nodes.insert(registration.node_id.clone(), registration.clone());
+        self.save()?;
+        info!("Registered node {} for user {}", registration.node_id, registration.owner_id);
+        Ok(registration)
+    }
+
+    pub fn node(&self, node_id: &str) -> Option<&NodeRegistration> {
+        self.accounts.nodes.get(node_id)
+    }
+
+    // The user a registered node belongs to
+    pub fn node_owner(&self, node_id: &str) -> Option<&User> {
+        self.node(node_id).and_then(|node| self.get_user(&node.owner_id))
+    }
+
+    pub fn nodes_of(&self, user_id: &str) -> Vec<&NodeRegistration> {
+        self.accounts.nodes.values().filter(|node| node.owner_id == user_id).collect()
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let contents = serde_json::to_string_pretty(&self.accounts).map_err(|e| e.to_string())?;
+        write_file(&self.path, &contents, 0o600)
+    }
+}
+
+fn hash_password(password: &str) -> Result<String, String> {
+    if password.len() < 8 {
+        return Err("Passwords must be at least 8 characters".to_string());
+    }
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default().hash_password(password.as_bytes(), &salt).map_err(|e| format!("Could not hash the password: {}", e))?;
+    Ok(hash.to_string())
+}
+
+pub fn verify_password(password: &str, password_hash: &str) -> bool {
+    match PasswordHash::new(password_hash) {
+        Ok(parsed) => Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok(),
+        Err(_) => false,
+    }
+}
+
+// Hash of a random password nobody knows, made with the same parameters as real ones
+fn dummy_password_hash() -> &'static str {
+    static DUMMY: OnceLock<String> = OnceLock::new();
+    DUMMY.get_or_init(|| hash_password(&random_hex(16)).expect("hashing a random password"))
+}
+
+// Tokens are stored hashed so a leaked accounts file can't be used to join nodes or call the API
+fn token_hash(token: &str) -> String {
+    hex(&Sha256::digest(token.as_bytes()))
+}
+
+pub(crate) fn random_hex(bytes: usize) -> String {
+    let mut buf = vec![0u8; bytes];
+    OsRng.fill_bytes(&mut buf);
+    hex(&buf)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
 }
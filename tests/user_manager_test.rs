@@ -0,0 +1,223 @@
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+use distributed_computing::oidc::ExternalIdentity;
+use distributed_computing::user_manager::{verify_password, NodeRegistration, Scope, UserManager, UserRole};
+
+// Fresh accounts file for each test
+fn accounts_path() -> PathBuf {
+    static NEXT: AtomicU32 = AtomicU32::new(0);
+    let name = format!("dc_accounts_{}_{}.json", std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed));
+    let path = std::env::temp_dir().join(name);
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+fn accounts() -> UserManager {
+    UserManager::open(accounts_path()).unwrap()
+}
+
+#[test]
+fn login_checks_the_password() {
+    let mut accounts = accounts();
+    let user = accounts.add_user("Alice@Example.com", UserRole::Buyer, "correct horse").unwrap();
+
+    let (token, logged_in, _) = accounts.login("alice@example.com", "correct horse").unwrap();
+    assert_eq!(logged_in.user_id, user.user_id);
+    assert_eq!(accounts.authenticate(&token).unwrap().user_id, user.user_id);
+    assert!(accounts.login("alice@example.com", "wrong horse").is_err());
+    assert!(accounts.login("nobody@example.com", "correct horse").is_err());
+}
+
+#[test]
+fn unknown_emails_are_checked_against_a_real_hash() {
+    let mut accounts = accounts();
+    accounts.add_user("nopass@example.com", UserRole::Seller, "").unwrap();
+    for email in ["nobody@example.com", "nopass@example.com"] {
+        let hash = accounts.password_hash_for(email);
+        assert!(hash.starts_with("$argon2"), "{}", hash);
+        assert!(!verify_password("", &hash));
+        assert!(accounts.finish_login(email, &hash, true).is_err());
+    }
+}
+
+#[test]
+fn a_password_changed_during_the_check_fails_the_login() {
+    let mut accounts = accounts();
+    let user = accounts.add_user("bob@example.com", UserRole::Buyer, "first password").unwrap();
+    let hash = accounts.password_hash_for("bob@example.com");
+    assert!(verify_password("first password", &hash));
+
+    accounts.set_password(&user.user_id, "second password").unwrap();
+    assert!(accounts.finish_login("bob@example.com", &hash, true).is_err());
+    assert!(accounts.finish_login("bob@example.com", &accounts.password_hash_for("bob@example.com"), false).is_err());
+}
+
+#[test]
+fn only_the_first_operator_is_bootstrapped() {
+    let mut accounts = accounts();
+    assert!(accounts.add_first_operator("admin@example.com", "").is_err());
+    let admin = accounts.add_first_operator("admin@example.com", "operator password").unwrap();
+    assert_eq!(admin.role, UserRole::Operator);
+    assert!(accounts.add_first_operator("mallory@example.com", "operator password").is_err());
+    assert!(accounts.find_by_email("mallory@example.com").is_none());
+}
+
+const ISSUER: &str = "https://sso.example.com";
+
+fn identity(issuer: &str, subject: &str, email: &str) -> ExternalIdentity {
+    ExternalIdentity { issuer: issuer.to_string(), subject: subject.to_string(), email: Some(email.to_string()), email_verified: true }
+}
+
+#[test]
+fn external_identities_link_to_the_user_with_their_email() {
+    let mut accounts = accounts();
+    let user = accounts.add_user("carol@example.com", UserRole::Seller, "").unwrap();
+
+    let (_, linked, _) = accounts.login_external(&identity(ISSUER, "sub-1", "carol@example.com"), ISSUER, None).unwrap();
+    assert_eq!(linked.user_id, user.user_id);
+    // Later logins go by issuer and subject, even if the email changed at the provider
+    let (_, again, _) = accounts.login_external(&identity(ISSUER, "sub-1", "carol@elsewhere.com"), ISSUER, None).unwrap();
+    assert_eq!(again.user_id, user.user_id);
+}
+
+#[test]
+fn operators_are_never_linked_by_email() {
+    let mut accounts = accounts();
+    let operator = accounts.add_user("root@example.com", UserRole::Operator, "operator password").unwrap();
+
+    assert!(accounts.login_external(&identity(ISSUER, "sub-2", "root@example.com"), ISSUER, Some(UserRole::Buyer)).is_err());
+    assert!(accounts.get_user(&operator.user_id).unwrap().identities.is_empty());
+    assert!(accounts.find_by_email("root@example.com").unwrap().role == UserRole::Operator);
+}
+
+#[test]
+fn identities_from_other_issuers_are_not_linked_or_signed_up() {
+    let mut accounts = accounts();
+    let user = accounts.add_user("dave@example.com", UserRole::Buyer, "").unwrap();
+
+    let foreign = identity("https://evil.example.net", "sub-3", "dave@example.com");
+    assert!(accounts.login_external(&foreign, ISSUER, Some(UserRole::Buyer)).is_err());
+    assert!(accounts.get_user(&user.user_id).unwrap().identities.is_empty());
+    let stranger = identity("https://evil.example.net", "sub-4", "erin@example.com");
+    assert!(accounts.login_external(&stranger, ISSUER, Some(UserRole::Buyer)).is_err());
+    assert!(accounts.find_by_email("erin@example.com").is_none());
+}
+
+#[test]
+fn unverified_emails_neither_link_nor_sign_up() {
+    let mut accounts = accounts();
+    accounts.add_user("frank@example.com", UserRole::Buyer, "").unwrap();
+    let unverified = ExternalIdentity { email_verified: false, ..identity(ISSUER, "sub-5", "frank@example.com") };
+    assert!(accounts.login_external(&unverified, ISSUER, Some(UserRole::Buyer)).is_err());
+
+    let (_, signed_up, _) = accounts.login_external(&identity(ISSUER, "sub-6", "grace@example.com"), ISSUER, Some(UserRole::Buyer)).unwrap();
+    assert_eq!(signed_up.role, UserRole::Buyer);
+    assert_eq!(signed_up.email, "grace@example.com");
+}
+
+fn registration(node_url: &str) -> NodeRegistration {
+    NodeRegistration {
+        node_id: String::new(),
+        owner_id: String::new(),
+        node_url: node_url.to_string(),
+        executors: vec!["wasm".to_string()],
+        enforced: false,
+        ram_mb: 1024,
+        cpu_percent: 100,
+        bandwidth_mbps: 10,
+        storage_gb: 10,
+        registered_at: 0,
+    }
+}
+
+#[test]
+fn join_tokens_register_one_node_for_their_owner() {
+    let mut accounts = accounts();
+    let seller = accounts.add_user("seller@example.com", UserRole::Seller, "").unwrap();
+    let (token, _) = accounts.create_join_token(&seller.user_id, Duration::from_secs(600)).unwrap();
+
+    // The agent's own node_id and owner are ignored
+    let mut declared = registration("https://node.example.com:50051");
+    declared.owner_id = "someone_else".to_string();
+    declared.node_id = "node_1".to_string();
+    let node = accounts.register_node(&token, declared).unwrap();
+    assert_eq!(node.owner_id, seller.user_id);
+    assert_ne!(node.node_id, "node_1");
+    assert_eq!(accounts.node_owner(&node.node_id).unwrap().user_id, seller.user_id);
+
+    assert!(accounts.register_node(&token, registration("https://other.example.com:50051")).is_err());
+    assert_eq!(accounts.nodes_of(&seller.user_id).len(), 1);
+}
+
+#[test]
+fn expired_join_tokens_and_buyers_register_nothing() {
+    let mut accounts = accounts();
+    let seller = accounts.add_user("seller@example.com", UserRole::Seller, "").unwrap();
+    let (expired, _) = accounts.create_join_token(&seller.user_id, Duration::ZERO).unwrap();
+    assert!(accounts.register_node(&expired, registration("https://node.example.com:50051")).is_err());
+
+    let buyer = accounts.add_user("buyer@example.com", UserRole::Buyer, "").unwrap();
+    assert!(accounts.create_join_token(&buyer.user_id, Duration::from_secs(600)).is_err());
+    assert!(accounts.register_node("dcj_made_up", registration("https://node.example.com:50051")).is_err());
+    assert!(accounts.nodes_of(&seller.user_id).is_empty());
+}
+
+#[test]
+fn logout_ends_only_that_session_and_survives_a_restart() {
+    let path = accounts_path();
+    let mut accounts = UserManager::open(&path).unwrap();
+    accounts.add_user("heidi@example.com", UserRole::Buyer, "heidi password").unwrap();
+    let (laptop, _, _) = accounts.login("heidi@example.com", "heidi password").unwrap();
+    let (phone, _, _) = accounts.login("heidi@example.com", "heidi password").unwrap();
+
+    accounts.logout(&laptop).unwrap();
+    assert!(accounts.authenticate(&laptop).is_err());
+    assert!(accounts.logout(&laptop).is_err());
+    assert!(accounts.authenticate(&phone).is_ok());
+
+    let reopened = UserManager::open(&path).unwrap();
+    assert!(reopened.authenticate(&laptop).is_err());
+    assert!(reopened.authenticate(&phone).is_ok());
+}
+
+#[test]
+fn tampered_and_foreign_session_tokens_are_refused() {
+    let mut accounts = accounts();
+    accounts.add_user("ivan@example.com", UserRole::Buyer, "ivan password").unwrap();
+    let (token, _, _) = accounts.login("ivan@example.com", "ivan password").unwrap();
+
+    // Flip the first character of the signature
+    let at = token.rfind('.').unwrap() + 1;
+    let flipped = if &token[at..at + 1] == "A" { "B" } else { "A" };
+    let tampered = format!("{}{}{}", &token[..at], flipped, &token[at + 1..]);
+    assert!(accounts.authenticate(&tampered).is_err());
+
+    // Signed with another controller's key
+    let mut other = self::accounts();
+    other.add_user("ivan@example.com", UserRole::Buyer, "ivan password").unwrap();
+    let (foreign, _, _) = other.login("ivan@example.com", "ivan password").unwrap();
+    assert!(accounts.authenticate(&foreign).is_err());
+    assert!(accounts.authenticate("not a token").is_err());
+}
+
+#[test]
+fn api_tokens_are_capped_by_role_and_revocable() {
+    let mut accounts = accounts();
+    let seller = accounts.add_user("judy@example.com", UserRole::Seller, "").unwrap();
+    assert!(accounts.create_api_token(&seller.user_id, "ci", vec![Scope::JobsSubmit], None).is_err());
+    let (token, created) = accounts.create_api_token(&seller.user_id, "ci", Vec::new(), None).unwrap();
+    assert_eq!(created.scopes, [Scope::JobsRead]);
+
+    let principal = accounts.authenticate(&token).unwrap();
+    assert_eq!(principal.user_id, seller.user_id);
+    assert_eq!(principal.scopes, [Scope::JobsRead]);
+    assert_eq!(principal.token_id.as_deref(), Some(created.token_id.as_str()));
+
+    // Only the owner revokes it
+    let buyer = accounts.add_user("ken@example.com", UserRole::Buyer, "").unwrap();
+    assert!(accounts.revoke_api_token(&buyer.user_id, &created.token_id).is_err());
+    accounts.revoke_api_token(&seller.user_id, &created.token_id).unwrap();
+    assert!(accounts.authenticate(&token).is_err());
+    assert!(accounts.api_tokens_of(&seller.user_id).is_empty());
+}